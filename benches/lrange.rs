@@ -0,0 +1,43 @@
+// LRANGE 0 9 on a 1M-element list, the way reads used to work, copying the
+// whole value out of the store with `get`, and through `lrange`, which reads
+// the list in place and copies only the requested elements.
+//
+// Run with `cargo bench --bench lrange`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use redis::store::{RedisStore, RedisValueType};
+
+const ELEMENTS: usize = 1_000_000;
+
+// Average time of `f` over `rounds` runs
+fn time(rounds: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..rounds {
+        f();
+    }
+    start.elapsed() / rounds
+}
+
+fn main() {
+    let store = RedisStore::new();
+    store.rpush("list", (0..ELEMENTS).map(|i| format!("element:{}", i)).collect()).unwrap();
+
+    let cloned = time(10, || {
+        let value = store.get("list").unwrap();
+        let first: Vec<String> = match &value.data {
+            RedisValueType::List(list) => list.iter().take(10).map(str::to_string).collect(),
+            _ => unreachable!(),
+        };
+        black_box(first);
+    });
+    let in_place = time(100_000, || {
+        black_box(store.lrange("list", 0, 9).unwrap());
+    });
+
+    println!("LRANGE 0 9 on a {}-element list:", ELEMENTS);
+    println!("  copying the list: {:>12.3?}", cloned);
+    println!("  in place:         {:>12.3?}", in_place);
+    println!("  {:.0}x faster", cloned.as_secs_f64() / in_place.as_secs_f64());
+}
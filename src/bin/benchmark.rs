@@ -0,0 +1,328 @@
+// A small redis-benchmark: runs each selected command a number of times over
+// several concurrent connections, optionally pipelined, and reports
+// throughput and latency percentiles per command. It only speaks the
+// protocol, so it can load-test any server, this one included.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process::exit;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use redis::resp::{parse_resp, serialize_resp, RespData};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+
+const USAGE: &str = "\
+Usage: bench [OPTIONS]
+  -h <hostname>      Server hostname (default 127.0.0.1)
+  -p <port>          Server port (default 6379)
+  -s <socket>        Server socket (overrides host and port)
+  -c <clients>       Number of parallel connections (default 50)
+  -n <requests>      Total number of requests (default 100000)
+  -d <size>          Data size of SET/LPUSH value in bytes (default 3)
+  -P <numreq>        Pipeline <numreq> requests. Default 1 (no pipeline).
+  -r <keyspacelen>   Use random keys for SET/GET/INCR: the __rand_int__
+                     placeholder in keys is replaced by a random number
+                     in [0, keyspacelen).
+  -t <tests>         Only run the comma separated list of tests (default:
+                     ping,set,get,incr,lpush,lrange)
+  -q                 Quiet. Just show query/sec values
+  --csv              Output in CSV format
+  --help             Output this help and exit";
+
+const PLACEHOLDER: &str = "__rand_int__";
+
+// The commands each test sends, as in redis-benchmark. LRANGE reads the
+// list the LPUSH test fills.
+const TESTS: &[(&str, &str, &[&str])] = &[
+    ("ping", "PING", &["PING"]),
+    ("set", "SET", &["SET", "key:__rand_int__", "<data>"]),
+    ("get", "GET", &["GET", "key:__rand_int__"]),
+    ("incr", "INCR", &["INCR", "counter:__rand_int__"]),
+    ("lpush", "LPUSH", &["LPUSH", "mylist", "<data>"]),
+    ("lrange", "LRANGE_100 (first 100 elements)", &["LRANGE", "mylist", "0", "99"]),
+];
+
+#[derive(Clone)]
+struct Options {
+    host: String,
+    port: u16,
+    socket: Option<String>,
+    clients: usize,
+    requests: u64,
+    data_size: usize,
+    pipeline: usize,
+    keyspace: Option<u64>,
+    tests: Vec<String>,
+    quiet: bool,
+    csv: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    exit(1);
+}
+
+fn parse_options(args: Vec<String>) -> Options {
+    let mut options = Options {
+        host: "127.0.0.1".to_string(),
+        port: 6379,
+        socket: None,
+        clients: 50,
+        requests: 100_000,
+        data_size: 3,
+        pipeline: 1,
+        keyspace: None,
+        tests: TESTS.iter().map(|(name, _, _)| name.to_string()).collect(),
+        quiet: false,
+        csv: false,
+    };
+    fn number<T: std::str::FromStr>(value: String, what: &str) -> T {
+        value.parse().unwrap_or_else(|_| usage_error(&format!("Invalid {}", what)))
+    }
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().unwrap_or_else(|| usage_error(&format!("Missing value for {}", name)));
+        match arg.as_str() {
+            "-h" => options.host = value("-h"),
+            "-p" => options.port = number(value("-p"), "port"),
+            "-s" => options.socket = Some(value("-s")),
+            "-c" => options.clients = number::<usize>(value("-c"), "number of clients").max(1),
+            "-n" => options.requests = number(value("-n"), "number of requests"),
+            "-d" => options.data_size = number(value("-d"), "data size"),
+            "-P" => options.pipeline = number::<usize>(value("-P"), "pipeline depth").max(1),
+            "-r" => options.keyspace = Some(number::<u64>(value("-r"), "keyspace length").max(1)),
+            "-t" => {
+                options.tests = value("-t").split(',').map(|test| test.trim().to_lowercase()).collect();
+                if let Some(unknown) = options.tests.iter().find(|test| !TESTS.iter().any(|(name, _, _)| name == test)) {
+                    usage_error(&format!("Unknown test '{}'", unknown));
+                }
+            }
+            "-q" => options.quiet = true,
+            "--csv" => options.csv = true,
+            "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ => usage_error(&format!("Unrecognized option '{}'", arg)),
+        }
+    }
+    options
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+async fn connect(options: &Options) -> std::io::Result<Box<dyn Stream>> {
+    Ok(match &options.socket {
+        Some(path) => Box::new(UnixStream::connect(path).await?),
+        None => {
+            let stream = TcpStream::connect((options.host.as_str(), options.port)).await?;
+            stream.set_nodelay(true)?;
+            Box::new(stream)
+        }
+    })
+}
+
+// xorshift64*, seeded per connection; plenty for picking keys
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Rng {
+        Rng(RandomState::new().build_hasher().finish() | 1)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % n
+    }
+}
+
+// The command for one request, with the placeholders filled in
+fn build_command(template: &[&str], data: &str, keyspace: Option<u64>, rng: &mut Rng) -> Vec<u8> {
+    let args = template
+        .iter()
+        .map(|arg| {
+            let arg = if *arg == "<data>" { data } else { arg };
+            let arg = match keyspace {
+                Some(keyspace) => arg.replace(PLACEHOLDER, &format!("{:012}", rng.below(keyspace))),
+                None => arg.replace(PLACEHOLDER, "000000000000"),
+            };
+            RespData::BulkString(arg)
+        })
+        .collect();
+    serialize_resp(&RespData::Array(args))
+}
+
+#[derive(Default)]
+struct ClientResult {
+    latencies: Vec<Duration>,
+    errors: u64,
+    first_error: Option<String>,
+}
+
+// One connection's share of a test: claims up to `pipeline` requests at a
+// time from `remaining`, sends them in one write and times each reply from
+// that write
+async fn run_client(
+    options: Arc<Options>,
+    template: &'static [&'static str],
+    remaining: Arc<AtomicU64>,
+) -> std::io::Result<ClientResult> {
+    let mut stream = connect(&options).await?;
+    let data = "x".repeat(options.data_size);
+    let mut rng = Rng::new();
+    let mut result = ClientResult::default();
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    let pipeline = options.pipeline as u64;
+    loop {
+        let claimed = remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| (left > 0).then(|| left.saturating_sub(pipeline)))
+            .map_or(0, |left| left.min(pipeline));
+        if claimed == 0 {
+            return Ok(result);
+        }
+        let mut batch = Vec::new();
+        for _ in 0..claimed {
+            batch.extend_from_slice(&build_command(template, &data, options.keyspace, &mut rng));
+        }
+        let sent = Instant::now();
+        stream.write_all(&batch).await?;
+        let mut received = 0;
+        while received < claimed {
+            match parse_resp(&buffer)? {
+                Some((consumed, reply)) => {
+                    buffer.drain(..consumed);
+                    received += 1;
+                    result.latencies.push(sent.elapsed());
+                    if let RespData::Error(e) = reply {
+                        result.errors += 1;
+                        result.first_error.get_or_insert(e);
+                    }
+                }
+                None => {
+                    let n = stream.read(&mut chunk).await?;
+                    if n == 0 {
+                        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Server closed the connection"));
+                    }
+                    buffer.extend_from_slice(&chunk[..n]);
+                }
+            }
+        }
+    }
+}
+
+struct Report {
+    title: &'static str,
+    requests: usize,
+    elapsed: Duration,
+    errors: u64,
+    first_error: Option<String>,
+    // Sorted
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn rps(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    // Latencies in milliseconds: average, min, p50, p95, p99 and max
+    fn latency_summary(&self) -> [f64; 6] {
+        let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        if self.latencies.is_empty() {
+            return [0.0; 6];
+        }
+        let percentile = |p: f64| ms(self.latencies[((self.latencies.len() as f64 * p).ceil() as usize).clamp(1, self.latencies.len()) - 1]);
+        let total: Duration = self.latencies.iter().sum();
+        [
+            ms(total) / self.latencies.len() as f64,
+            ms(self.latencies[0]),
+            percentile(0.50),
+            percentile(0.95),
+            percentile(0.99),
+            ms(self.latencies[self.latencies.len() - 1]),
+        ]
+    }
+}
+
+async fn run_test(options: &Arc<Options>, title: &'static str, template: &'static [&'static str]) -> std::io::Result<Report> {
+    let remaining = Arc::new(AtomicU64::new(options.requests));
+    let start = Instant::now();
+    let clients: Vec<_> = (0..options.clients)
+        .map(|_| tokio::spawn(run_client(Arc::clone(options), template, Arc::clone(&remaining))))
+        .collect();
+    let mut report = Report { title, requests: 0, elapsed: Duration::ZERO, errors: 0, first_error: None, latencies: Vec::new() };
+    for client in clients {
+        let result = client.await.map_err(std::io::Error::other)??;
+        report.errors += result.errors;
+        report.first_error = report.first_error.or(result.first_error);
+        report.latencies.extend(result.latencies);
+    }
+    report.elapsed = start.elapsed();
+    report.requests = report.latencies.len();
+    report.latencies.sort_unstable();
+    Ok(report)
+}
+
+fn print_report(report: &Report, options: &Options) {
+    let [avg, min, p50, p95, p99, max] = report.latency_summary();
+    if options.csv {
+        println!(
+            "\"{}\",\"{:.2}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\"",
+            report.title,
+            report.rps(),
+            avg,
+            min,
+            p50,
+            p95,
+            p99,
+            max
+        );
+    } else if options.quiet {
+        println!("{}: {:.2} requests per second, p50={:.3} msec", report.title, report.rps(), p50);
+    } else {
+        println!("====== {} ======", report.title);
+        println!("  {} requests completed in {:.2} seconds", report.requests, report.elapsed.as_secs_f64());
+        println!("  {} parallel clients", options.clients);
+        println!("  {} bytes payload", options.data_size);
+        println!("  pipeline depth {}", options.pipeline);
+        println!();
+        println!("Summary:");
+        println!("  throughput summary: {:.2} requests per second", report.rps());
+        println!("  latency summary (msec):");
+        println!("  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}", "avg", "min", "p50", "p95", "p99", "max");
+        println!("  {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}", avg, min, p50, p95, p99, max);
+        println!();
+    }
+    if let Some(error) = &report.first_error {
+        eprintln!("{}: {} requests failed, the first with: {}", report.title, report.errors, error);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = Arc::new(parse_options(std::env::args().skip(1).collect()));
+    if options.csv {
+        println!("\"test\",\"rps\",\"avg_latency_ms\",\"min_latency_ms\",\"p50_latency_ms\",\"p95_latency_ms\",\"p99_latency_ms\",\"max_latency_ms\"");
+    }
+    for (name, title, template) in TESTS {
+        if !options.tests.iter().any(|test| test == name) {
+            continue;
+        }
+        match run_test(&options, title, template).await {
+            Ok(report) => print_report(&report, &options),
+            Err(e) => {
+                let target = options.socket.clone().unwrap_or_else(|| format!("{}:{}", options.host, options.port));
+                eprintln!("{}: error talking to {}: {}", title, target, e);
+                exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+// A minimal redis-cli: runs the command given as arguments, or reads
+// commands from stdin (with a prompt when it's a terminal), and prints
+// replies the way redis-cli does. --pipe sends raw protocol from stdin for
+// bulk loading. Built on the crate's RESP codec rather than a client
+// library, so it also exercises the codec from the client side.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::process::exit;
+use std::time::Duration;
+
+use redis::resp::{parse_resp, serialize_resp, split_inline_args, RespData};
+
+const USAGE: &str = "\
+Usage: cli [OPTIONS] [cmd [arg [arg ...]]]
+  -h <hostname>      Server hostname (default: 127.0.0.1).
+  -p <port>          Server port (default: 6379).
+  -s <socket>        Server socket (overrides hostname and port).
+  -r <repeat>        Execute specified command N times (-1 for ever).
+  -i <interval>      When -r is used, waits <interval> seconds per command.
+                     It is possible to specify sub-second times like -i 0.1.
+  -3                 Start session in RESP3 protocol mode.
+  --pipe             Transfer raw Redis protocol from stdin to server.
+  --help             Output this help and exit.";
+
+struct Options {
+    host: String,
+    port: u16,
+    socket: Option<String>,
+    repeat: i64,
+    interval: Duration,
+    resp3: bool,
+    pipe: bool,
+    command: Vec<String>,
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    exit(1);
+}
+
+fn parse_options(args: Vec<String>) -> Options {
+    let mut options = Options {
+        host: "127.0.0.1".to_string(),
+        port: 6379,
+        socket: None,
+        repeat: 1,
+        interval: Duration::ZERO,
+        resp3: false,
+        pipe: false,
+        command: Vec::new(),
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().unwrap_or_else(|| usage_error(&format!("Missing value for {}", name)));
+        match arg.as_str() {
+            "-h" => options.host = value("-h"),
+            "-p" => options.port = value("-p").parse().unwrap_or_else(|_| usage_error("Invalid port")),
+            "-s" => options.socket = Some(value("-s")),
+            "-r" => options.repeat = value("-r").parse().unwrap_or_else(|_| usage_error("Invalid repeat count")),
+            "-i" => {
+                options.interval = value("-i")
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .unwrap_or_else(|| usage_error("Invalid interval"))
+            }
+            "-3" => options.resp3 = true,
+            "--pipe" => options.pipe = true,
+            "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => usage_error(&format!("Unrecognized option '{}'", arg)),
+            _ => {
+                options.command.push(arg);
+                options.command.extend(args.by_ref());
+            }
+        }
+    }
+    options
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn try_clone(&self) -> std::io::Result<Stream> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+struct Connection {
+    stream: Stream,
+    // Bytes read past the last parsed reply
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn open(options: &Options) -> std::io::Result<Connection> {
+        let stream = match &options.socket {
+            Some(path) => Stream::Unix(UnixStream::connect(path)?),
+            None => Stream::Tcp(TcpStream::connect((options.host.as_str(), options.port))?),
+        };
+        Ok(Connection { stream, buffer: Vec::new() })
+    }
+
+    fn send(&mut self, args: &[Vec<u8>]) -> std::io::Result<()> {
+        let command = RespData::Array(args.iter().cloned().map(bulk).collect());
+        self.stream.write_all(&serialize_resp(&command))
+    }
+
+    fn read_reply(&mut self) -> std::io::Result<RespData> {
+        loop {
+            if let Some((consumed, reply)) = parse_resp(&self.buffer)? {
+                self.buffer.drain(..consumed);
+                return Ok(reply);
+            }
+            let mut chunk = [0u8; 16 * 1024];
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Server closed the connection"));
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    // Sends a command and returns its reply, printing any out-of-band push
+    // messages that arrive first
+    fn call(&mut self, args: &[Vec<u8>]) -> std::io::Result<RespData> {
+        self.send(args)?;
+        loop {
+            match self.read_reply()? {
+                RespData::Push(items) => print!("{}", format_reply(&RespData::Push(items), "")),
+                reply => return Ok(reply),
+            }
+        }
+    }
+}
+
+fn bulk(arg: Vec<u8>) -> RespData {
+    match String::from_utf8(arg) {
+        Ok(arg) => RespData::BulkString(arg),
+        Err(e) => RespData::BulkBytes(e.into_bytes()),
+    }
+}
+
+// A bulk string as redis-cli shows it: quoted, with anything unprintable
+// escaped
+fn quote(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Formats a reply like redis-cli on a terminal. Elements of aggregates are
+// numbered, and nested ones indented under their number; `prefix` is the
+// indentation of the aggregate being formatted.
+fn format_reply(reply: &RespData, prefix: &str) -> String {
+    let (items, map) = match reply {
+        RespData::SimpleString(s) => return format!("{}\n", s),
+        RespData::Error(e) => return format!("(error) {}\n", e),
+        RespData::Integer(n) => return format!("(integer) {}\n", n),
+        RespData::BulkString(s) => return format!("{}\n", quote(s.as_bytes())),
+        RespData::BulkBytes(b) => return format!("{}\n", quote(b)),
+        RespData::Null | RespData::NullArray => return "(nil)\n".to_string(),
+        RespData::Array(items) | RespData::Push(items) if items.is_empty() => return "(empty array)\n".to_string(),
+        RespData::Map(pairs) if pairs.is_empty() => return "(empty hash)\n".to_string(),
+        RespData::Array(items) | RespData::Push(items) => (items.iter().map(|item| (item, None)).collect::<Vec<_>>(), false),
+        RespData::Map(pairs) => (pairs.iter().map(|(key, value)| (key, Some(value))).collect(), true),
+    };
+    let width = items.len().to_string().len();
+    let nested = format!("{}{}", prefix, " ".repeat(width + 2));
+    let mut out = String::new();
+    for (i, (item, value)) in items.into_iter().enumerate() {
+        // The caller already wrote the prefix of the first line
+        let indent = if i == 0 { "" } else { prefix };
+        out.push_str(&format!("{}{:>width$}{} ", indent, i + 1, if map { '#' } else { ')' }));
+        out.push_str(&format_reply(item, &nested));
+        if let Some(value) = value {
+            out.pop();
+            out.push_str(" => ");
+            out.push_str(&format_reply(value, &nested));
+        }
+    }
+    out
+}
+
+fn connect(options: &Options) -> Connection {
+    let mut connection = Connection::open(options).unwrap_or_else(|e| {
+        let target = options.socket.clone().unwrap_or_else(|| format!("{}:{}", options.host, options.port));
+        eprintln!("Could not connect to Redis at {}: {}", target, e);
+        exit(1);
+    });
+    if options.resp3 {
+        match connection.call(&[b"HELLO".to_vec(), b"3".to_vec()]) {
+            Ok(RespData::Error(e)) => eprintln!("HELLO 3 failed: {}", e),
+            Ok(_) => {}
+            Err(e) => fail(e),
+        }
+    }
+    connection
+}
+
+fn fail(e: std::io::Error) -> ! {
+    eprintln!("Error: {}", e);
+    exit(1);
+}
+
+// Runs one command, printing its reply. After SUBSCRIBE or MONITOR the
+// server keeps sending, so those print everything until the connection
+// closes.
+fn run_command(connection: &mut Connection, args: &[Vec<u8>]) -> std::io::Result<()> {
+    let reply = connection.call(args)?;
+    print!("{}", format_reply(&reply, ""));
+    let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+    if matches!(name.as_str(), "subscribe" | "psubscribe" | "monitor") && !matches!(reply, RespData::Error(_)) {
+        if name != "monitor" {
+            println!("Reading messages... (press Ctrl-C to quit)");
+        }
+        loop {
+            std::io::stdout().flush()?;
+            print!("{}", format_reply(&connection.read_reply()?, ""));
+        }
+    }
+    std::io::stdout().flush()
+}
+
+fn run_repeated(connection: &mut Connection, args: &[Vec<u8>], options: &Options) -> std::io::Result<()> {
+    let mut done = 0;
+    while options.repeat < 0 || done < options.repeat {
+        run_command(connection, args)?;
+        done += 1;
+        if !options.interval.is_zero() {
+            std::thread::sleep(options.interval);
+        }
+    }
+    Ok(())
+}
+
+// Reads commands a line at a time, prompting when stdin is a terminal
+fn run_interactive(connection: &mut Connection, options: &Options) -> std::io::Result<()> {
+    let interactive = std::io::stdin().is_terminal();
+    let prompt = options.socket.clone().unwrap_or_else(|| format!("{}:{}", options.host, options.port));
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("{}> ", prompt);
+            std::io::stdout().flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        let args = match split_inline_args(line.as_bytes()) {
+            Some(args) if args.is_empty() => continue,
+            Some(args) => args,
+            None => {
+                println!("Invalid argument(s)");
+                continue;
+            }
+        };
+        let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+        if name == "quit" || name == "exit" {
+            return Ok(());
+        }
+        run_repeated(connection, &args, options)?;
+    }
+}
+
+// Random bytes for the ECHO that marks the end of a --pipe transfer
+fn pipe_marker() -> String {
+    let mut marker = String::new();
+    while marker.len() < 20 {
+        marker.push_str(&format!("{:016x}", RandomState::new().build_hasher().finish()));
+    }
+    marker.truncate(20);
+    marker
+}
+
+// Streams stdin to the server as is, then ECHOes a marker; its reply means
+// every earlier command has been answered. Exits non-zero if any of them
+// failed.
+fn run_pipe(connection: &mut Connection) -> std::io::Result<()> {
+    let marker = pipe_marker();
+    let mut writer = connection.stream.try_clone()?;
+    let ending = serialize_resp(&RespData::Array(vec![bulk(b"ECHO".to_vec()), bulk(marker.clone().into_bytes())]));
+    let sender = std::thread::spawn(move || -> std::io::Result<()> {
+        std::io::copy(&mut std::io::stdin().lock(), &mut writer)?;
+        writer.write_all(&ending)?;
+        println!("All data transferred. Waiting for the last reply...");
+        Ok(())
+    });
+
+    let (mut errors, mut replies) = (0, 0);
+    loop {
+        match connection.read_reply()? {
+            RespData::Error(e) => {
+                eprintln!("{}", e);
+                errors += 1;
+            }
+            RespData::BulkString(s) if s == marker => break,
+            _ => {}
+        }
+        replies += 1;
+    }
+    sender.join().expect("pipe writer")?;
+    println!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+    if errors > 0 {
+        exit(1);
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = parse_options(std::env::args().skip(1).collect());
+    let mut connection = connect(&options);
+    let result = if options.pipe {
+        run_pipe(&mut connection)
+    } else if options.command.is_empty() {
+        run_interactive(&mut connection, &options)
+    } else {
+        let args: Vec<Vec<u8>> = options.command.iter().map(|arg| arg.clone().into_bytes()).collect();
+        run_repeated(&mut connection, &args, &options)
+    };
+    if let Err(e) = result {
+        fail(e);
+    }
+}
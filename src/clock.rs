@@ -0,0 +1,63 @@
+//! Wall-clock time as seen by the store.
+//!
+//! Every expiry decision reads "now" through the store's [`Clock`], so tests
+//! can swap in a [`ManualClock`] and move time instead of sleeping.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::util::current_time_ms;
+
+/// A source of the current time as a Unix timestamp in milliseconds.
+pub trait Clock: Send + Sync {
+    fn now_ms(&self) -> u64;
+}
+
+/// The system clock, used unless another one is given.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        current_time_ms()
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use redis::clock::ManualClock;
+/// use redis::config::ServerConfig;
+/// use redis::store::{RedisStore, RedisValueType, SetOptions};
+///
+/// let clock = Arc::new(ManualClock::new(1_000_000));
+/// let store = RedisStore::with_clock(ServerConfig::default(), clock.clone());
+/// store.set_with_options("session".to_string(), RedisValueType::String("abc".to_string()), SetOptions::EX(10));
+/// clock.advance(Duration::from_secs(10));
+/// assert!(!store.exists("session"));
+/// ```
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    /// A clock stopped at `now_ms`.
+    pub fn new(now_ms: u64) -> Self {
+        ManualClock { now: AtomicU64::new(now_ms) }
+    }
+
+    pub fn set(&self, now_ms: u64) {
+        self.now.store(now_ms, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
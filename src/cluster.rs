@@ -0,0 +1,98 @@
+//! Cluster introspection for a single node running without cluster mode.
+//!
+//! Cluster-aware clients probe CLUSTER INFO, SLOTS, SHARDS and MYID when they
+//! connect, and sharding libraries compute hash slots themselves. This node
+//! serves no slots, but answers those probes and hashes keys to slots the
+//! way a Redis cluster would.
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::util::random_hex;
+
+/// Number of hash slots keys are spread over in a Redis cluster.
+pub const CLUSTER_SLOTS: u16 = 16384;
+
+// Holds the node id across restarts, in `dir`
+pub(crate) const NODE_ID_FILE: &str = "node-id";
+
+pub(crate) const NODE_ID_LEN: usize = 40;
+
+// CRC16-CCITT (XMODEM) by byte, polynomial 0x1021
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The CRC16 variant Redis Cluster hashes keys with (XMODEM: polynomial
+/// 0x1021, initial value 0, no reflection).
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize])
+}
+
+/// The hash slot of `key`. If the key contains a non-empty hash tag, the
+/// part between the first `{` and the next `}`, only the tag is hashed, so
+/// keys sharing a tag land in the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = match key.iter().position(|&b| b == b'{') {
+        Some(start) => match key[start + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[start + 1..start + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(hashed) & (CLUSTER_SLOTS - 1)
+}
+
+// The node id saved in `dir`, or a new one, saved there for the next start
+pub(crate) fn load_node_id(dir: &Path) -> std::io::Result<String> {
+    let path = dir.join(NODE_ID_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            let id = contents.trim();
+            if id.len() == NODE_ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Ok(id.to_ascii_lowercase());
+            }
+            Err(std::io::Error::new(ErrorKind::InvalidData, format!("{} doesn't hold a node id", path.display())))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let id = random_hex(NODE_ID_LEN);
+            std::fs::write(&path, format!("{}\n", id))?;
+            Ok(id)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// CLUSTER INFO, for a node outside any cluster
+pub(crate) fn cluster_info() -> String {
+    [
+        "cluster_enabled:0",
+        "cluster_state:ok",
+        "cluster_slots_assigned:0",
+        "cluster_slots_ok:0",
+        "cluster_slots_pfail:0",
+        "cluster_slots_fail:0",
+        "cluster_known_nodes:1",
+        "cluster_size:0",
+        "cluster_current_epoch:0",
+        "cluster_my_epoch:0",
+        "cluster_stats_messages_sent:0",
+        "cluster_stats_messages_received:0",
+        "total_cluster_links_buffer_limit_exceeded:0",
+    ]
+    .iter()
+    .map(|line| format!("{}\r\n", line))
+    .collect()
+}
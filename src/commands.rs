@@ -0,0 +1,2107 @@
+// Command table and handlers. Adding a command means writing its handler
+// and adding one row to COMMANDS; the dispatcher in execute_command takes
+// care of lookup, arity, stats, eviction and propagation.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::cluster::{cluster_info, key_hash_slot, CLUSTER_SLOTS};
+use crate::config::ServerConfig;
+use crate::connection::{ConnectionState, Transaction};
+use crate::errors;
+use crate::hashes::{self, ExpiryCondition, FieldExpiry, MAX_FIELD_EXPIRY_MS};
+use crate::info::{build_info, memory_doctor, memory_stats};
+use crate::lcs::{longest_common_subsequence, table_size};
+use crate::logging;
+use crate::migrate::{migrate_keys, remove_migrated, MigrateOptions};
+use crate::replication::{start_replication, wait_for_replicas};
+use crate::reply::{HashEntries, KeyNames, ListRange, Reply, STREAMED_REPLY_MIN};
+use crate::resp::RespData;
+use crate::store::{
+    estimate_value_size_sampled, list_range_bounds, object_encoding, HashValue, RedisStore, RedisValueType, SetOptions, ValueKind, ENTRY_OVERHEAD,
+};
+use crate::sort::{has_store_option, sort_elements, SortOptions};
+use crate::streams::{self, Fields, GroupStart, NewId, ReadFrom, StreamId, StreamValue, Trim};
+use crate::tracking::{invalidate_keys, TrackingOptions};
+use crate::util::glob_match;
+
+// Modifies the dataset: serialized with other writes and propagated to replicas
+pub const WRITE: u32 = 1 << 0;
+// May grow memory usage, so refused when over maxmemory
+pub const DENYOOM: u32 = 1 << 1;
+// Refused between MULTI and EXEC, since it blocks or takes over the connection
+pub const NO_MULTI: u32 = 1 << 2;
+// Gets arguments that aren't valid UTF-8 as they were sent, rather than
+// converted to text like every other command's (RESTORE's payload)
+pub const BINARY: u32 = 1 << 3;
+// A write that sends replicas the commands its effects amount to itself,
+// rather than being propagated as called (MIGRATE sends DEL). It also takes
+// the write lock itself, only around those effects, so it can wait on the
+// network without holding up every other write.
+pub const PROPAGATES_ITSELF: u32 = 1 << 4;
+
+pub type BoxFuture<'a> = Pin<Box<dyn Future<Output = RespData> + Send + 'a>>;
+
+// Handlers get the full command including its name, already arity-checked
+pub enum Handler {
+    Sync(fn(&[RespData], &Arc<RedisStore>) -> RespData),
+    Async(for<'a> fn(&'a [RespData], &'a Arc<RedisStore>) -> BoxFuture<'a>),
+    // For commands that read or change the state of the calling connection
+    Connection(fn(&[RespData], &Arc<RedisStore>, &mut ConnectionState) -> RespData),
+    // Connection commands that wait on something else, such as WAIT on replica
+    // acknowledgements. Replies queued before them are sent first.
+    AsyncConnection(for<'a> fn(&'a [RespData], &'a Arc<RedisStore>, &'a mut ConnectionState) -> BoxFuture<'a>),
+    // For commands whose reply can be too large to build whole
+    Streamed(fn(&[RespData], &Arc<RedisStore>) -> Reply),
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub handler: Handler,
+    // As in Redis' command table, counting the command name: positive means
+    // exactly that many arguments, negative means at least that many
+    pub arity: i64,
+    pub flags: u32,
+    // Where the keys are, also as in Redis: the index of the first and last
+    // key (negative counting from the end) and the step between keys. A
+    // first key of 0 means the command takes no keys.
+    pub first_key: usize,
+    pub last_key: i64,
+    pub key_step: usize,
+}
+
+impl Command {
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity >= 0 { argc == self.arity } else { argc >= -self.arity }
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.flags & WRITE != 0
+    }
+
+    // Whether a call of the command writes. Only SORT depends on its
+    // arguments; it writes with STORE alone.
+    pub fn writes(&self, args: &[RespData]) -> bool {
+        match self.name {
+            "sort" => has_store_option(args),
+            _ => self.is_write(),
+        }
+    }
+
+    pub fn is_denyoom(&self) -> bool {
+        self.flags & DENYOOM != 0
+    }
+
+    pub fn is_no_multi(&self) -> bool {
+        self.flags & NO_MULTI != 0
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.flags & BINARY != 0
+    }
+
+    pub fn propagates_itself(&self) -> bool {
+        self.flags & PROPAGATES_ITSELF != 0
+    }
+
+    // The key arguments of an arity-checked call
+    pub fn keys<'a>(&self, args: &'a [RespData]) -> impl Iterator<Item = &'a RespData> {
+        let last = if self.last_key < 0 { args.len() as i64 + self.last_key } else { self.last_key };
+        let keys = match self.first_key {
+            0 => &[][..],
+            first => args.get(first..(last + 1).max(0) as usize).unwrap_or(&[]),
+        };
+        keys.iter().step_by(self.key_step.max(1))
+    }
+}
+
+const fn command(
+    name: &'static str,
+    handler: Handler,
+    arity: i64,
+    flags: u32,
+    first_key: usize,
+    last_key: i64,
+    key_step: usize,
+) -> Command {
+    Command { name, handler, arity, flags, first_key, last_key, key_step }
+}
+
+pub static COMMANDS: &[Command] = &[
+    command("ping", Handler::Connection(ping), -1, 0, 0, 0, 0),
+    command("echo", Handler::Sync(echo), 2, 0, 0, 0, 0),
+    command("set", Handler::Sync(set), -3, WRITE | DENYOOM, 1, 1, 1),
+    command("get", Handler::Sync(get), 2, 0, 1, 1, 1),
+    command("lcs", Handler::Connection(lcs), -3, 0, 1, 2, 1),
+    command("exists", Handler::Sync(exists), -2, 0, 1, -1, 1),
+    command("del", Handler::Sync(del), -2, WRITE, 1, -1, 1),
+    command("ttl", Handler::Sync(ttl), 2, 0, 1, 1, 1),
+    command("pttl", Handler::Sync(pttl), 2, 0, 1, 1, 1),
+    command("incr", Handler::Sync(incr), 2, WRITE | DENYOOM, 1, 1, 1),
+    command("decr", Handler::Sync(decr), 2, WRITE | DENYOOM, 1, 1, 1),
+    command("lpush", Handler::Sync(lpush), -3, WRITE | DENYOOM, 1, 1, 1),
+    command("rpush", Handler::Sync(rpush), -3, WRITE | DENYOOM, 1, 1, 1),
+    command("llen", Handler::Sync(llen), 2, 0, 1, 1, 1),
+    command("lrange", Handler::Streamed(lrange), 4, 0, 1, 1, 1),
+    command("dump", Handler::Sync(dump), 2, 0, 1, 1, 1),
+    command("restore", Handler::Sync(restore), -4, WRITE | DENYOOM | BINARY, 1, 1, 1),
+    command("migrate", Handler::AsyncConnection(migrate), -6, WRITE | PROPAGATES_ITSELF, 0, 0, 0),
+    command("sort", Handler::Sync(sort), -2, WRITE | DENYOOM, 1, 1, 1),
+    command("hset", Handler::Sync(hset), -4, WRITE | DENYOOM, 1, 1, 1),
+    command("hget", Handler::Sync(hget), 3, 0, 1, 1, 1),
+    command("hgetall", Handler::Streamed(hgetall), 2, 0, 1, 1, 1),
+    command("hlen", Handler::Sync(hlen), 2, 0, 1, 1, 1),
+    command("hexists", Handler::Sync(hexists), 3, 0, 1, 1, 1),
+    command("hdel", Handler::Sync(hdel), -3, WRITE, 1, 1, 1),
+    command("hexpire", Handler::Sync(hexpire), -6, WRITE, 1, 1, 1),
+    command("hpexpire", Handler::Sync(hpexpire), -6, WRITE, 1, 1, 1),
+    command("httl", Handler::Sync(httl), -5, 0, 1, 1, 1),
+    command("hpttl", Handler::Sync(hpttl), -5, 0, 1, 1, 1),
+    command("hpersist", Handler::Sync(hpersist), -5, WRITE, 1, 1, 1),
+    command("hgetex", Handler::Sync(hgetex), -5, WRITE, 1, 1, 1),
+    command("hgetdel", Handler::Sync(hgetdel), -5, WRITE, 1, 1, 1),
+    command("xadd", Handler::Connection(xadd), -5, WRITE | DENYOOM, 1, 1, 1),
+    command("xlen", Handler::Sync(xlen), 2, 0, 1, 1, 1),
+    command("xrange", Handler::Sync(xrange), -4, 0, 1, 1, 1),
+    command("xdel", Handler::Sync(xdel), -3, WRITE, 1, 1, 1),
+    command("xgroup", Handler::Sync(xgroup), -2, WRITE | DENYOOM, 2, 2, 1),
+    // Its keys follow STREAMS, so they can't be given by position
+    command("xreadgroup", Handler::Connection(xreadgroup), -7, WRITE, 0, 0, 0),
+    command("xack", Handler::Sync(xack), -4, WRITE, 1, 1, 1),
+    command("xinfo", Handler::Connection(xinfo), -2, 0, 2, 2, 1),
+    command("replconf", Handler::Sync(replconf), -1, 0, 0, 0, 0),
+    command("replicaof", Handler::Sync(replicaof), 3, 0, 0, 0, 0),
+    command("slaveof", Handler::Sync(replicaof), 3, 0, 0, 0, 0),
+    command("readonly", Handler::Sync(readwrite), 1, 0, 0, 0, 0),
+    command("readwrite", Handler::Sync(readwrite), 1, 0, 0, 0, 0),
+    command("psync", Handler::Sync(connection_only), -3, NO_MULTI, 0, 0, 0),
+    command("sync", Handler::Sync(connection_only), 1, NO_MULTI, 0, 0, 0),
+    command("wait", Handler::AsyncConnection(wait), 3, NO_MULTI, 0, 0, 0),
+    command("multi", Handler::Connection(multi), 1, 0, 0, 0, 0),
+    command("exec", Handler::Sync(connection_only), 1, 0, 0, 0, 0),
+    command("discard", Handler::Connection(discard), 1, 0, 0, 0, 0),
+    command("subscribe", Handler::Connection(subscribe), -2, 0, 0, 0, 0),
+    command("unsubscribe", Handler::Connection(unsubscribe), -1, 0, 0, 0, 0),
+    command("psubscribe", Handler::Connection(psubscribe), -2, 0, 0, 0, 0),
+    command("punsubscribe", Handler::Connection(punsubscribe), -1, 0, 0, 0, 0),
+    command("publish", Handler::Sync(publish), 3, 0, 0, 0, 0),
+    command("client", Handler::Connection(client), -2, 0, 0, 0, 0),
+    command("hello", Handler::Connection(hello), -1, 0, 0, 0, 0),
+    command("config", Handler::Sync(config), -2, 0, 0, 0, 0),
+    command("object", Handler::Sync(object), -2, 0, 0, 0, 0),
+    command("memory", Handler::Sync(memory), -2, 0, 0, 0, 0),
+    command("cluster", Handler::Sync(cluster), -2, 0, 0, 0, 0),
+    command("info", Handler::Sync(info), -1, 0, 0, 0, 0),
+    command("command", Handler::Sync(commands_info), -1, 0, 0, 0, 0),
+    command("keys", Handler::Streamed(keys), 2, 0, 0, 0, 0),
+    command("dbsize", Handler::Sync(dbsize), 1, 0, 0, 0, 0),
+    command("debug", Handler::Sync(debug), -2, 0, 0, 0, 0),
+    command("save", Handler::Async(save), 1, 0, 0, 0, 0),
+];
+
+// The commands clients can call, under the names they call them by: their
+// own, unless rename-command gave them another or disabled them
+pub struct CommandTable(Vec<(String, &'static Command)>);
+
+impl CommandTable {
+    pub fn new(renamed: &HashMap<String, String>) -> Self {
+        let commands = COMMANDS
+            .iter()
+            .filter_map(|command| match renamed.get(command.name) {
+                Some(name) if name.is_empty() => None,
+                Some(name) => Some((name.clone(), command)),
+                None => Some((command.name.to_string(), command)),
+            })
+            .collect();
+        CommandTable(commands)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&'static Command> {
+        self.0.iter().find(|(called, _)| called.eq_ignore_ascii_case(name)).map(|(_, command)| *command)
+    }
+
+    // Every callable command, with the name it's called by
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &'static Command)> {
+        self.0.iter().map(|(called, command)| (called.as_str(), *command))
+    }
+}
+
+fn ok() -> RespData {
+    RespData::SimpleString("OK".to_string())
+}
+
+fn help(lines: &[&str]) -> RespData {
+    RespData::Array(lines.iter().map(|l| RespData::SimpleString(l.to_string())).collect())
+}
+
+fn bulk_args(args: &[RespData]) -> Vec<String> {
+    args.iter()
+        .filter_map(|x| match x {
+            RespData::BulkString(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// PSYNC and SYNC take over the client connection, so the connection loop
+// intercepts them before they reach the dispatcher. EXEC runs other
+// commands, so the dispatcher handles it itself.
+fn connection_only(args: &[RespData], _store: &Arc<RedisStore>) -> RespData {
+    let name = match args.first() {
+        Some(RespData::BulkString(name)) => name.to_lowercase(),
+        _ => String::new(),
+    };
+    RespData::Error(format!("ERR '{}' is only valid on a client connection", name))
+}
+
+fn ping(args: &[RespData], _store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    // A subscribed client gets an array, like the messages it is waiting for
+    if state.in_subscribe_mode() {
+        let message = args.get(1).cloned().unwrap_or_else(|| RespData::BulkString(String::new()));
+        return RespData::Array(vec![RespData::BulkString("pong".to_string()), message]);
+    }
+    RespData::SimpleString("PONG".to_string())
+}
+
+fn echo(args: &[RespData], _store: &Arc<RedisStore>) -> RespData {
+    args[1].clone()
+}
+
+// What SET was asked to do besides storing the value. Without KEEPTTL, SET
+// is the one write that clears an existing TTL.
+struct SetParams {
+    expiry: SetOptions,
+}
+
+// Parses the options after SET's key and value. Like Redis, all of them are
+// checked for syntax before the expire time is validated.
+fn parse_set_params(options: &[RespData], now_ms: u64) -> Result<SetParams, String> {
+    let mut expire: Option<(String, &str)> = None;
+    let mut keep_ttl = false;
+    let mut i = 0;
+    while i < options.len() {
+        let option = match &options[i] {
+            RespData::BulkString(option) => option.to_uppercase(),
+            _ => return Err(errors::SYNTAX.to_string()),
+        };
+        match option.as_str() {
+            "EX" | "PX" | "EXAT" | "PXAT" => {
+                let value = match options.get(i + 1) {
+                    Some(RespData::BulkString(value)) => value,
+                    _ => return Err(errors::SYNTAX.to_string()),
+                };
+                // Only one kind of expire time, though it may be repeated
+                if keep_ttl || expire.as_ref().is_some_and(|(previous, _)| *previous != option) {
+                    return Err(errors::SYNTAX.to_string());
+                }
+                expire = Some((option, value));
+                i += 2;
+            }
+            "KEEPTTL" if expire.is_none() => {
+                keep_ttl = true;
+                i += 1;
+            }
+            _ => return Err(errors::SYNTAX.to_string()),
+        }
+    }
+
+    let expiry = match expire {
+        None if keep_ttl => SetOptions::KEEPTTL,
+        None => SetOptions::None,
+        Some((unit, value)) => {
+            let amount: i64 = value.parse().map_err(|_| errors::NOT_INTEGER.to_string())?;
+            let relative = unit == "EX" || unit == "PX";
+            let millis = if unit.starts_with("EX") { amount.checked_mul(1000) } else { Some(amount) };
+            // Relative times must still fit once turned absolute
+            let valid = millis.is_some_and(|millis| millis > 0 && (!relative || millis.checked_add(now_ms as i64).is_some()));
+            if !valid {
+                return Err(errors::invalid_expire_time("set"));
+            }
+            match unit.as_str() {
+                "EX" => SetOptions::EX(amount as u64),
+                "PX" => SetOptions::PX(amount as u64),
+                "EXAT" => SetOptions::EXAT(amount as u64),
+                _ => SetOptions::PXAT(amount as u64),
+            }
+        }
+    };
+    Ok(SetParams { expiry })
+}
+
+fn set(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    if let (RespData::BulkString(key), RespData::BulkString(value)) = (&args[1], &args[2]) {
+        let params = match parse_set_params(&args[3..], store.now_ms()) {
+            Ok(params) => params,
+            Err(e) => return RespData::Error(e),
+        };
+        store.set_with_options(key.clone(), RedisValueType::String(value.clone()), params.expiry);
+        ok()
+    } else {
+        RespData::Error(errors::wrong_arity("set"))
+    }
+}
+
+fn get(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    if let RespData::BulkString(key) = &args[1] {
+        let value = store.read_typed(key, ValueKind::String, |value| match value {
+            Some(RedisValueType::String(s)) => RespData::BulkString(s.clone()),
+            Some(RedisValueType::Integer(n)) => RespData::BulkString(n.to_string()),
+            _ => RespData::Null,
+        });
+        value.unwrap_or_else(RespData::Error)
+    } else {
+        RespData::Error(errors::wrong_arity("get"))
+    }
+}
+
+// LCS key1 key2 [LEN] [IDX] [MINMATCHLEN len] [WITHMATCHLEN]. Missing keys
+// count as empty strings.
+fn lcs(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let keys = match (args.get(1), args.get(2)) {
+        (Some(RespData::BulkString(a)), Some(RespData::BulkString(b))) => [a, b],
+        _ => return RespData::Error(errors::wrong_arity("lcs")),
+    };
+    let args = bulk_args(&args[3..]);
+    let (mut len_only, mut idx, mut with_match_len, mut min_match_len) = (false, false, false, 0);
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "LEN" => len_only = true,
+            "IDX" => idx = true,
+            "WITHMATCHLEN" => with_match_len = true,
+            "MINMATCHLEN" if i + 1 < args.len() => {
+                match args[i + 1].parse::<i64>() {
+                    Ok(n) => min_match_len = n.max(0) as usize,
+                    Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+                }
+                i += 1;
+            }
+            _ => return RespData::Error(errors::SYNTAX.to_string()),
+        }
+        i += 1;
+    }
+    if len_only && idx {
+        return RespData::Error("ERR If you want both the length and indexes, please just use IDX.".to_string());
+    }
+
+    let mut values = Vec::with_capacity(2);
+    for key in keys {
+        let value = store.read_typed(key, ValueKind::String, |value| match value {
+            Some(RedisValueType::String(s)) => s.clone(),
+            Some(RedisValueType::Integer(n)) => n.to_string(),
+            _ => String::new(),
+        });
+        match value {
+            Ok(value) => values.push(value),
+            Err(e) => return RespData::Error(e),
+        }
+    }
+    let (a, b) = (values[0].as_bytes(), values[1].as_bytes());
+    // The table is quadratic in the input, so it gets the same cap as a
+    // single bulk string rather than whatever the strings ask for
+    let max = store.config.read().proto_max_bulk_len;
+    if table_size(a.len(), b.len()).is_none_or(|size| size as u64 > max) {
+        return RespData::Error("ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".to_string());
+    }
+
+    let result = longest_common_subsequence(a, b);
+    if len_only {
+        return RespData::Integer(result.subsequence.len() as i64);
+    }
+    if !idx {
+        return RespData::BulkBytes(result.subsequence);
+    }
+    let range = |(start, end): (usize, usize)| RespData::Array(vec![RespData::Integer(start as i64), RespData::Integer(end as i64)]);
+    let matches = result
+        .matches
+        .iter()
+        .filter(|m| m.len >= min_match_len)
+        .map(|m| {
+            let mut entry = vec![range(m.a), range(m.b)];
+            if with_match_len {
+                entry.push(RespData::Integer(m.len as i64));
+            }
+            RespData::Array(entry)
+        })
+        .collect();
+    let fields = vec![("matches", RespData::Array(matches)), ("len", RespData::Integer(result.subsequence.len() as i64))];
+    fields_reply(fields, state.protocol)
+}
+
+// Counts every argument, so a key given twice counts twice
+fn exists(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let keys = bulk_args(&args[1..]);
+    RespData::Integer(keys.iter().filter(|key| store.exists(key)).count() as i64)
+}
+
+fn del(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    RespData::Integer(store.del(&bulk_args(&args[1..])) as i64)
+}
+
+// -2 for a missing key, -1 for one without an expiry, otherwise the time
+// left in the given unit, rounded like Redis
+fn time_to_live(command: &str, args: &[RespData], store: &Arc<RedisStore>, unit_ms: u128) -> RespData {
+    if let RespData::BulkString(key) = &args[1] {
+        RespData::Integer(match store.ttl(key) {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(ttl)) => ((ttl.as_millis() + unit_ms / 2) / unit_ms) as i64,
+        })
+    } else {
+        RespData::Error(errors::wrong_arity(command))
+    }
+}
+
+fn ttl(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    time_to_live("ttl", args, store, 1000)
+}
+
+fn pttl(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    time_to_live("pttl", args, store, 1)
+}
+
+fn incr(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(key) => match store.incr(key) {
+            Ok(n) => RespData::Integer(n),
+            Err(e) => RespData::Error(e),
+        },
+        _ => RespData::Error(errors::wrong_arity("incr")),
+    }
+}
+
+fn decr(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(key) => match store.decr(key) {
+            Ok(n) => RespData::Integer(n),
+            Err(e) => RespData::Error(e),
+        },
+        _ => RespData::Error(errors::wrong_arity("decr")),
+    }
+}
+
+fn lpush(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(key) => match store.lpush(key, bulk_args(&args[2..])) {
+            Ok(len) => RespData::Integer(len as i64),
+            Err(e) => RespData::Error(e),
+        },
+        _ => RespData::Error(errors::wrong_arity("lpush")),
+    }
+}
+
+fn rpush(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(key) => match store.rpush(key, bulk_args(&args[2..])) {
+            Ok(len) => RespData::Integer(len as i64),
+            Err(e) => RespData::Error(e),
+        },
+        _ => RespData::Error(errors::wrong_arity("rpush")),
+    }
+}
+
+fn llen(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(key) => match store.llen(key) {
+            Ok(len) => RespData::Integer(len as i64),
+            Err(e) => RespData::Error(e),
+        },
+        _ => RespData::Error(errors::wrong_arity("llen")),
+    }
+}
+
+fn lrange(args: &[RespData], store: &Arc<RedisStore>) -> Reply {
+    let (key, start, stop) = match (&args[1], &args[2], &args[3]) {
+        (RespData::BulkString(key), RespData::BulkString(start), RespData::BulkString(stop)) => (key, start, stop),
+        _ => return RespData::Error(errors::wrong_arity("lrange")).into(),
+    };
+    let (start, stop) = match (start.parse::<i64>(), stop.parse::<i64>()) {
+        (Ok(start), Ok(stop)) => (start, stop),
+        _ => return RespData::Error(errors::NOT_INTEGER.to_string()).into(),
+    };
+    let reply = store.read_typed(key, ValueKind::List, |value| {
+        let list = match value {
+            Some(RedisValueType::List(list)) => list,
+            _ => return RespData::Array(Vec::new()).into(),
+        };
+        match list_range_bounds(list.len(), start, stop) {
+            Some((start, end)) if end - start >= STREAMED_REPLY_MIN => Reply::Stream(Box::new(ListRange::new(list, start, end))),
+            Some((start, end)) => RespData::Array(list.range(start, end).map(|item| RespData::BulkString(item.to_string())).collect()).into(),
+            None => RespData::Array(Vec::new()).into(),
+        }
+    });
+    reply.unwrap_or_else(|e| RespData::Error(e).into())
+}
+
+fn dump(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(key) => match store.dump(key) {
+            Some(payload) => RespData::BulkBytes(payload),
+            None => RespData::Null,
+        },
+        _ => RespData::Error(errors::wrong_arity("dump")),
+    }
+}
+
+fn restore(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    // Only the payload is binary; the key is text like everywhere else
+    let key = match &args[1] {
+        RespData::BulkString(key) => key.clone(),
+        RespData::BulkBytes(key) => String::from_utf8_lossy(key).into_owned(),
+        _ => return RespData::Error(errors::wrong_arity("restore")),
+    };
+    let ttl = match &args[2] {
+        RespData::BulkString(ttl) => match ttl.parse::<i64>() {
+            Ok(ttl) if ttl >= 0 => ttl as u64,
+            Ok(_) => return RespData::Error("ERR Invalid TTL value, must be >= 0".to_string()),
+            Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+        },
+        _ => return RespData::Error(errors::wrong_arity("restore")),
+    };
+    let payload = match &args[3] {
+        RespData::BulkString(s) => s.as_bytes(),
+        RespData::BulkBytes(b) => b.as_slice(),
+        _ => return RespData::Error(errors::wrong_arity("restore")),
+    };
+
+    let mut replace = false;
+    let mut absttl = false;
+    for arg in &args[4..] {
+        match arg {
+            RespData::BulkString(opt) if opt.eq_ignore_ascii_case("REPLACE") => replace = true,
+            RespData::BulkString(opt) if opt.eq_ignore_ascii_case("ABSTTL") => absttl = true,
+            _ => return RespData::Error(errors::SYNTAX.to_string()),
+        }
+    }
+
+    match store.restore(&key, ttl, payload, replace, absttl) {
+        Ok(()) => ok(),
+        Err(e) => RespData::Error(e),
+    }
+}
+
+fn sort(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let key = match args.first() {
+        Some(key) => key,
+        None => return RespData::Error(errors::wrong_arity("sort")),
+    };
+    let mut options = SortOptions { by: None, limit: None, get: Vec::new(), desc: false, alpha: false };
+    let mut destination = None;
+    let mut i = 1;
+    while i < args.len() {
+        let remaining = args.len() - i - 1;
+        match args[i].to_uppercase().as_str() {
+            "ASC" => options.desc = false,
+            "DESC" => options.desc = true,
+            "ALPHA" => options.alpha = true,
+            "LIMIT" if remaining >= 2 => {
+                match (args[i + 1].parse::<i64>(), args[i + 2].parse::<i64>()) {
+                    (Ok(offset), Ok(count)) => options.limit = Some((offset, count)),
+                    _ => return RespData::Error(errors::NOT_INTEGER.to_string()),
+                }
+                i += 2;
+            }
+            "BY" if remaining >= 1 => {
+                options.by = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "GET" if remaining >= 1 => {
+                options.get.push(args[i + 1].clone());
+                i += 1;
+            }
+            "STORE" if remaining >= 1 => {
+                destination = Some(args[i + 1].clone());
+                i += 1;
+            }
+            _ => return RespData::Error(errors::SYNTAX.to_string()),
+        }
+        i += 1;
+    }
+
+    let sorted = match sort_elements(store, key, &options) {
+        Ok(sorted) => sorted,
+        Err(e) => return RespData::Error(e),
+    };
+    match destination {
+        // Missing GET values are stored as empty strings, and an empty
+        // result deletes the destination
+        Some(destination) => {
+            let len = sorted.len();
+            if sorted.is_empty() {
+                store.del(std::slice::from_ref(&destination));
+            } else {
+                let list = sorted.into_iter().map(Option::unwrap_or_default).collect();
+                store.set_with_options(destination.clone(), RedisValueType::List(list), SetOptions::None);
+            }
+            // The dispatcher only knows about the sorted key
+            invalidate_keys(store, &[&destination], None);
+            RespData::Integer(len as i64)
+        }
+        None => optional_bulks(sorted),
+    }
+}
+
+fn optional_bulks(values: Vec<Option<String>>) -> RespData {
+    RespData::Array(values.into_iter().map(|value| value.map_or(RespData::Null, RespData::BulkString)).collect())
+}
+
+fn integers(values: Vec<i64>) -> RespData {
+    RespData::Array(values.into_iter().map(RespData::Integer).collect())
+}
+
+// Reads a hash using its live fields
+fn read_hash(store: &RedisStore, key: &str, f: impl FnOnce(Option<&HashValue>) -> RespData) -> RespData {
+    let reply = store.read_typed(key, ValueKind::Hash, |value| match value {
+        Some(RedisValueType::Hash(hash)) => f(Some(hash)),
+        _ => f(None),
+    });
+    reply.unwrap_or_else(RespData::Error)
+}
+
+// The field names of the hash field TTL commands: "FIELDS numfields field
+// [field ...]", which must be all of `args`
+fn parse_fields(args: &[String]) -> Result<&[String], String> {
+    if !args.first().is_some_and(|arg| arg.eq_ignore_ascii_case("FIELDS")) {
+        return Err("ERR Mandatory argument FIELDS is missing or not at the right position".to_string());
+    }
+    match args.get(1).map(|n| n.parse::<i64>()) {
+        Some(Ok(n)) if n > 0 => {
+            if n as usize != args.len() - 2 {
+                return Err("ERR The `numfields` parameter must match the number of arguments".to_string());
+            }
+            Ok(&args[2..])
+        }
+        _ => Err("ERR Parameter `numFields` should be greater than 0".to_string()),
+    }
+}
+
+// A field expiry as given to HEXPIRE or HGETEX, as Unix milliseconds.
+// Relative times are added to `now`.
+fn parse_field_expiry(command: &str, value: &str, seconds: bool, absolute: bool, now: u64) -> Result<u64, String> {
+    let value: i64 = value.parse().map_err(|_| errors::NOT_INTEGER.to_string())?;
+    if value < 0 {
+        return Err("ERR invalid expire time, must be >= 0".to_string());
+    }
+    let millis = if seconds { (value as u64).checked_mul(1000) } else { Some(value as u64) };
+    let at = millis.and_then(|millis| if absolute { Some(millis) } else { millis.checked_add(now) });
+    at.filter(|at| *at <= MAX_FIELD_EXPIRY_MS).ok_or_else(|| errors::invalid_expire_time(command))
+}
+
+fn hset(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    if args.len().is_multiple_of(2) {
+        return RespData::Error(errors::wrong_arity("hset"));
+    }
+    let pairs = args[1..].chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+    match hashes::hset(store, &args[0], pairs) {
+        Ok(added) => RespData::Integer(added as i64),
+        Err(e) => RespData::Error(e),
+    }
+}
+
+fn hget(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    read_hash(store, &args[0], |hash| {
+        match hash.and_then(|hash| hash.get(&args[1])) {
+            Some(value) => RespData::BulkString(value.to_string()),
+            None => RespData::Null,
+        }
+    })
+}
+
+fn hgetall(args: &[RespData], store: &Arc<RedisStore>) -> Reply {
+    let args = bulk_args(&args[1..]);
+    let reply = store.read_typed(&args[0], ValueKind::Hash, |value| match value {
+        Some(RedisValueType::Hash(hash)) if 2 * hash.len() >= STREAMED_REPLY_MIN => Reply::Stream(Box::new(HashEntries::new(hash))),
+        Some(RedisValueType::Hash(hash)) => RespData::Array(
+            hash.iter()
+                .flat_map(|(name, value, _)| [RespData::BulkString(name.to_string()), RespData::BulkString(value.to_string())])
+                .collect(),
+        )
+        .into(),
+        _ => RespData::Array(Vec::new()).into(),
+    });
+    reply.unwrap_or_else(|e| RespData::Error(e).into())
+}
+
+fn hlen(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    read_hash(store, &args[0], |hash| RespData::Integer(hash.map_or(0, |hash| hash.len()) as i64))
+}
+
+fn hexists(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    read_hash(store, &args[0], |hash| RespData::Integer(hash.is_some_and(|hash| hash.get(&args[1]).is_some()) as i64))
+}
+
+fn hdel(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    match hashes::hdel(store, &args[0], &args[1..]) {
+        Ok(deleted) => RespData::Integer(deleted as i64),
+        Err(e) => RespData::Error(e),
+    }
+}
+
+fn field_expire(command: &str, args: &[RespData], store: &Arc<RedisStore>, seconds: bool) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let at = match parse_field_expiry(command, &args[1], seconds, false, store.now_ms()) {
+        Ok(at) => at,
+        Err(e) => return RespData::Error(e),
+    };
+    let (condition, rest) = match args[2].to_uppercase().as_str() {
+        "NX" => (ExpiryCondition::Nx, &args[3..]),
+        "XX" => (ExpiryCondition::Xx, &args[3..]),
+        "GT" => (ExpiryCondition::Gt, &args[3..]),
+        "LT" => (ExpiryCondition::Lt, &args[3..]),
+        _ => (ExpiryCondition::Always, &args[2..]),
+    };
+    let result = parse_fields(rest).and_then(|fields| hashes::hexpire(store, &args[0], at, condition, fields));
+    result.map_or_else(RespData::Error, integers)
+}
+
+fn hexpire(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    field_expire("hexpire", args, store, true)
+}
+
+fn hpexpire(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    field_expire("hpexpire", args, store, false)
+}
+
+// -2 for a missing field, -1 for one without an expiry, otherwise the time
+// left, in seconds rounded up or in milliseconds
+fn field_time_to_live(args: &[RespData], store: &Arc<RedisStore>, seconds: bool) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let fields = match parse_fields(&args[1..]) {
+        Ok(fields) => fields,
+        Err(e) => return RespData::Error(e),
+    };
+    let now = store.now_ms();
+    read_hash(store, &args[0], |hash| {
+        integers(
+            fields
+                .iter()
+                .map(|field| match hash.and_then(|hash| hash.field_expiry(field)) {
+                    None => hashes::NO_SUCH_FIELD,
+                    Some(None) => hashes::NO_EXPIRY,
+                    Some(Some(at)) if seconds => at.saturating_sub(now).div_ceil(1000) as i64,
+                    Some(Some(at)) => at.saturating_sub(now) as i64,
+                })
+                .collect(),
+        )
+    })
+}
+
+fn httl(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    field_time_to_live(args, store, true)
+}
+
+fn hpttl(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    field_time_to_live(args, store, false)
+}
+
+fn hpersist(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let result = parse_fields(&args[1..]).and_then(|fields| hashes::hpersist(store, &args[0], fields));
+    result.map_or_else(RespData::Error, integers)
+}
+
+fn hgetex(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let now = store.now_ms();
+    let option = args[1].to_uppercase();
+    let (expiry, rest) = match option.as_str() {
+        "PERSIST" => (FieldExpiry::Persist, &args[2..]),
+        "EX" | "PX" | "EXAT" | "PXAT" => {
+            let absolute = option.ends_with("AT");
+            let at = match args.get(2).map(|value| parse_field_expiry("hgetex", value, option.starts_with("EX"), absolute, now)) {
+                Some(Ok(at)) => at,
+                Some(Err(e)) => return RespData::Error(e),
+                None => return RespData::Error(errors::SYNTAX.to_string()),
+            };
+            (FieldExpiry::At(at), &args[3..])
+        }
+        _ => (FieldExpiry::Keep, &args[1..]),
+    };
+    let result = parse_fields(rest).and_then(|fields| hashes::hgetex(store, &args[0], expiry, fields));
+    result.map_or_else(RespData::Error, optional_bulks)
+}
+
+fn hgetdel(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let result = parse_fields(&args[1..]).and_then(|fields| hashes::hgetdel(store, &args[0], fields));
+    result.map_or_else(RespData::Error, optional_bulks)
+}
+
+fn migrate<'a>(args: &'a [RespData], store: &'a Arc<RedisStore>, state: &'a mut ConnectionState) -> BoxFuture<'a> {
+    Box::pin(async move {
+        let args: Vec<String> = match args[1..].iter()
+            .map(|x| match x {
+                RespData::BulkString(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect()
+        {
+            Some(args) => args,
+            None => return RespData::Error(errors::wrong_arity("migrate")),
+        };
+
+        let (db, timeout) = match (args[3].parse::<u64>(), args[4].parse::<u64>()) {
+            (Ok(db), Ok(timeout)) => (db, timeout),
+            _ => return RespData::Error(errors::NOT_INTEGER.to_string()),
+        };
+        let mut options = MigrateOptions {
+            db,
+            timeout: std::time::Duration::from_millis(if timeout == 0 { 1000 } else { timeout }),
+            copy: false,
+            replace: false,
+        };
+
+        let mut keys = vec![args[2].clone()];
+        let mut i = 5;
+        while i < args.len() {
+            match args[i].to_uppercase().as_str() {
+                "COPY" => options.copy = true,
+                "REPLACE" => options.replace = true,
+                "KEYS" => {
+                    if !args[2].is_empty() {
+                        return RespData::Error("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".to_string());
+                    }
+                    keys = args[i + 1..].to_vec();
+                    break;
+                }
+                _ => return RespData::Error(errors::SYNTAX.to_string()),
+            }
+            i += 1;
+        }
+
+        let addr = format!("{}:{}", args[0], args[1]);
+        let (reply, acknowledged) = migrate_keys(store, &addr, &keys, &options).await;
+        if !acknowledged.is_empty() {
+            // EXEC already holds the write lock for its queued commands
+            let _write_guard = if state.in_exec { None } else { Some(store.replication.lock_write().await) };
+            remove_migrated(store, acknowledged);
+        }
+        reply
+    })
+}
+
+fn multi(_args: &[RespData], _store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    if state.transaction.is_some() {
+        return RespData::Error("ERR MULTI calls can not be nested".to_string());
+    }
+    state.transaction = Some(Transaction::default());
+    ok()
+}
+
+fn discard(_args: &[RespData], _store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    match state.transaction.take() {
+        Some(_) => ok(),
+        None => RespData::Error("ERR DISCARD without MULTI".to_string()),
+    }
+}
+
+// The (P)SUBSCRIBE and (P)UNSUBSCRIBE confirmation for `channel`, carrying
+// the client's subscription count
+fn subscription_reply(kind: &str, channel: Option<&str>, state: &ConnectionState) -> RespData {
+    state.out_of_band(vec![
+        RespData::BulkString(kind.to_string()),
+        channel.map_or(RespData::Null, |channel| RespData::BulkString(channel.to_string())),
+        RespData::Integer(state.subscriptions() as i64),
+    ])
+}
+
+// These commands answer once per channel: all but the last confirmation go
+// out ahead of the reply
+fn reply_each(mut replies: Vec<RespData>, state: &ConnectionState) -> RespData {
+    let last = replies.pop().unwrap_or(RespData::Null);
+    for reply in replies {
+        state.push(reply);
+    }
+    last
+}
+
+fn subscribe_to(args: &[RespData], store: &RedisStore, state: &mut ConnectionState, pattern: bool) -> RespData {
+    let kind = if pattern { "psubscribe" } else { "subscribe" };
+    let mut replies = Vec::new();
+    for channel in bulk_args(&args[1..]) {
+        let subscribed = if pattern { &mut state.patterns } else { &mut state.channels };
+        if subscribed.insert(channel.clone()) {
+            store.clients.subscribe(state.id, &channel, pattern);
+        }
+        replies.push(subscription_reply(kind, Some(&channel), state));
+    }
+    reply_each(replies, state)
+}
+
+// Without arguments, drops every subscription of the kind
+fn unsubscribe_from(args: &[RespData], store: &RedisStore, state: &mut ConnectionState, pattern: bool) -> RespData {
+    let kind = if pattern { "punsubscribe" } else { "unsubscribe" };
+    let mut channels = bulk_args(&args[1..]);
+    if channels.is_empty() {
+        channels = if pattern { &state.patterns } else { &state.channels }.iter().cloned().collect();
+        channels.sort_unstable();
+    }
+    if channels.is_empty() {
+        return subscription_reply(kind, None, state);
+    }
+    let mut replies = Vec::new();
+    for channel in channels {
+        let subscribed = if pattern { &mut state.patterns } else { &mut state.channels };
+        if subscribed.remove(&channel) {
+            store.clients.unsubscribe(state.id, &channel, pattern);
+        }
+        replies.push(subscription_reply(kind, Some(&channel), state));
+    }
+    reply_each(replies, state)
+}
+
+fn subscribe(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    subscribe_to(args, store, state, false)
+}
+
+fn psubscribe(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    subscribe_to(args, store, state, true)
+}
+
+fn unsubscribe(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    unsubscribe_from(args, store, state, false)
+}
+
+fn punsubscribe(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    unsubscribe_from(args, store, state, true)
+}
+
+fn publish(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let channel = bulk_args(&args[1..2]).remove(0);
+    RespData::Integer(store.clients.publish(&channel, &args[2]) as i64)
+}
+
+// Names show up in CLIENT LIST, which is split on spaces and newlines
+fn check_client_name(name: &str) -> Result<(), String> {
+    if name.bytes().all(|b| (b'!'..=b'~').contains(&b)) {
+        Ok(())
+    } else {
+        Err("ERR Client names cannot contain spaces, newlines or special characters.".to_string())
+    }
+}
+
+fn client(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let sub = match &args[1] {
+        RespData::BulkString(sub) => sub,
+        _ => return RespData::Error(errors::wrong_arity("client")),
+    };
+    // As in the command table: negative means at least that many arguments
+    let arity: i64 = match sub.to_uppercase().as_str() {
+        "HELP" | "ID" | "GETNAME" | "LIST" | "INFO" | "GETREDIR" => 2,
+        "SETNAME" | "CACHING" => 3,
+        "TRACKING" => -3,
+        _ => return RespData::Error(errors::unknown_subcommand("client", sub)),
+    };
+    let argc = args.len() as i64;
+    if (arity >= 0 && argc != arity) || argc < arity.abs() {
+        return RespData::Error(errors::wrong_arity(&format!("client|{}", sub)));
+    }
+    let args = bulk_args(&args[2..]);
+    match sub.to_uppercase().as_str() {
+        "HELP" => help(&[
+            "CLIENT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CACHING (YES|NO)",
+            "    Enable/disable tracking of the keys for next command in OPTIN/OPTOUT modes.",
+            "GETNAME",
+            "    Return the name of the current connection.",
+            "GETREDIR",
+            "    Return the client ID we are redirecting to when tracking is enabled.",
+            "ID",
+            "    Return the ID of the current connection.",
+            "INFO",
+            "    Return information about the current client connection.",
+            "LIST",
+            "    Return information about client connections.",
+            "SETNAME <name>",
+            "    Assign the name <name> to the current connection.",
+            "TRACKING (ON|OFF) [REDIRECT <id>] [BCAST] [PREFIX <prefix> [...]]",
+            "         [OPTIN] [OPTOUT] [NOLOOP]",
+            "    Control server assisted client side caching.",
+        ]),
+        "ID" => RespData::Integer(state.id as i64),
+        "GETNAME" => state.name.clone().map_or(RespData::Null, RespData::BulkString),
+        "SETNAME" => {
+            let name = args[0].clone();
+            if let Err(e) = check_client_name(&name) {
+                return RespData::Error(e);
+            }
+            state.name = (!name.is_empty()).then_some(name);
+            ok()
+        }
+        "LIST" => {
+            let mut clients = store.clients.list(state.id);
+            clients.push((state.id, state.info()));
+            clients.sort_unstable_by_key(|(id, _)| *id);
+            RespData::BulkString(clients.into_iter().map(|(_, info)| info + "\n").collect())
+        }
+        "INFO" => RespData::BulkString(state.info() + "\n"),
+        "TRACKING" => client_tracking(&args, store, state),
+        "CACHING" => {
+            let (optin, optout) = match &state.tracking {
+                Some(tracking) if tracking.optin || tracking.optout => (tracking.optin, tracking.optout),
+                _ => {
+                    return RespData::Error(
+                        "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled"
+                            .to_string(),
+                    )
+                }
+            };
+            match args[0].to_uppercase().as_str() {
+                "YES" if optin => state.caching = Some(true),
+                "NO" if optout => state.caching = Some(false),
+                "YES" => return RespData::Error("ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.".to_string()),
+                "NO" => return RespData::Error("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.".to_string()),
+                _ => return RespData::Error(errors::SYNTAX.to_string()),
+            }
+            ok()
+        }
+        _ => match &state.tracking {
+            Some(tracking) => RespData::Integer(tracking.redirect.map_or(0, |id| id as i64)),
+            None => RespData::Integer(-1),
+        },
+    }
+}
+
+// CLIENT TRACKING (ON|OFF) [options]. Turning it on again adds prefixes and
+// changes options, but not the mode.
+fn client_tracking(args: &[String], store: &RedisStore, state: &mut ConnectionState) -> RespData {
+    let on = match args[0].to_uppercase().as_str() {
+        "ON" => true,
+        "OFF" => false,
+        _ => return RespData::Error(errors::SYNTAX.to_string()),
+    };
+    let mut options = TrackingOptions::default();
+    let mut i = 1;
+    while i < args.len() {
+        let has_value = i + 1 < args.len();
+        match args[i].to_uppercase().as_str() {
+            "REDIRECT" if has_value => {
+                i += 1;
+                match args[i].parse::<u64>() {
+                    Ok(id) => options.redirect = Some(id),
+                    Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+                }
+            }
+            "PREFIX" if has_value => {
+                i += 1;
+                options.prefixes.push(args[i].clone());
+            }
+            "BCAST" => options.bcast = true,
+            "OPTIN" => options.optin = true,
+            "OPTOUT" => options.optout = true,
+            "NOLOOP" => options.noloop = true,
+            _ => return RespData::Error(errors::SYNTAX.to_string()),
+        }
+        i += 1;
+    }
+
+    if !on {
+        store.tracking.disable(state.id);
+        state.tracking = None;
+        state.caching = None;
+        return ok();
+    }
+    if !options.bcast && !options.prefixes.is_empty() {
+        return RespData::Error("ERR PREFIX option requires BCAST mode to be enabled".to_string());
+    }
+    if options.optin && options.optout {
+        return RespData::Error("ERR You can't use both OPTIN and OPTOUT".to_string());
+    }
+    if options.bcast && (options.optin || options.optout) {
+        return RespData::Error("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string());
+    }
+    if let Some(current) = &state.tracking {
+        if current.bcast != options.bcast {
+            return RespData::Error(
+                "ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.".to_string(),
+            );
+        }
+        if (current.optin, current.optout) != (options.optin, options.optout) {
+            return RespData::Error(
+                "ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode.".to_string(),
+            );
+        }
+    }
+    if let Some(redirect) = options.redirect {
+        if !store.clients.contains(redirect) {
+            return RespData::Error("ERR The client ID you want redirect to does not exist".to_string());
+        }
+    }
+    // A key must not match two prefixes of one client, or it would be
+    // reported twice
+    let mut prefixes = state.tracking.as_ref().map_or(Vec::new(), |current| current.prefixes.clone());
+    for prefix in &options.prefixes {
+        if let Some(other) = prefixes.iter().find(|other| other.starts_with(prefix.as_str()) || prefix.starts_with(other.as_str())) {
+            return RespData::Error(format!(
+                "ERR Prefix '{}' overlaps with an existing prefix '{}'. Prefixes for a single client must not overlap.",
+                prefix, other
+            ));
+        }
+        prefixes.push(prefix.clone());
+    }
+    options.prefixes = prefixes;
+    store.tracking.enable(state.id, options.clone());
+    state.tracking = Some(options);
+    ok()
+}
+
+// HELLO [protover [AUTH username password] [SETNAME name]]. There are no
+// users or passwords, so AUTH is accepted as the default user.
+fn hello(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let mut protocol = state.protocol;
+    if let Some(version) = args.first() {
+        match version.parse::<i64>() {
+            Ok(version @ (2 | 3)) => protocol = version as u8,
+            Ok(_) => return RespData::Error("NOPROTO unsupported protocol version".to_string()),
+            Err(_) => return RespData::Error("ERR Protocol version is not an integer or out of range".to_string()),
+        }
+    }
+    let mut name = None;
+    let mut i = 1;
+    while i < args.len() {
+        let remaining = args.len() - i - 1;
+        match args[i].to_uppercase().as_str() {
+            "AUTH" if remaining >= 2 => i += 2,
+            "SETNAME" if remaining >= 1 => {
+                i += 1;
+                if let Err(e) = check_client_name(&args[i]) {
+                    return RespData::Error(e);
+                }
+                name = Some(args[i].clone());
+            }
+            _ => return RespData::Error(format!("ERR Syntax error in HELLO option '{}'", args[i])),
+        }
+        i += 1;
+    }
+
+    state.protocol = protocol;
+    if let Some(name) = name {
+        state.name = (!name.is_empty()).then_some(name);
+    }
+    // Messages sent to this client from now on must use the new protocol
+    store.clients.update(state);
+
+    let role = if store.replication.is_replica() { "replica" } else { "master" };
+    let fields = [
+        ("server", RespData::BulkString("redis".to_string())),
+        ("version", RespData::BulkString(env!("CARGO_PKG_VERSION").to_string())),
+        ("proto", RespData::Integer(protocol as i64)),
+        ("id", RespData::Integer(state.id as i64)),
+        ("mode", RespData::BulkString("standalone".to_string())),
+        ("role", RespData::BulkString(role.to_string())),
+        ("modules", RespData::Array(Vec::new())),
+    ];
+    let fields = fields.into_iter().map(|(name, value)| (RespData::BulkString(name.to_string()), value));
+    if protocol >= 3 {
+        RespData::Map(fields.collect())
+    } else {
+        RespData::Array(fields.flat_map(|(name, value)| [name, value]).collect())
+    }
+}
+
+fn replconf(args: &[RespData], _store: &Arc<RedisStore>) -> RespData {
+    if args.len().is_multiple_of(2) {
+        return RespData::Error(errors::SYNTAX.to_string());
+    }
+    ok()
+}
+
+fn replicaof(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let (host, port) = match (&args[1], &args[2]) {
+        (RespData::BulkString(host), RespData::BulkString(port)) => (host, port),
+        _ => return RespData::Error(errors::wrong_arity("replicaof")),
+    };
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+        store.replication.stop_replica();
+        return ok();
+    }
+    match port.parse::<u16>() {
+        Ok(port) => start_replication(store, host.clone(), port),
+        Err(_) => RespData::Error("ERR Invalid master port".to_string()),
+    }
+}
+
+// READONLY and READWRITE pick whether a cluster client may read from
+// replicas. Outside a cluster there's nothing to pick, but cluster clients
+// send them anyway.
+fn readwrite(_args: &[RespData], _store: &Arc<RedisStore>) -> RespData {
+    ok()
+}
+
+// Blocks until `numreplicas` replicas have acknowledged this connection's
+// writes or the timeout (in milliseconds, 0 = forever) is up, and replies
+// with how many did
+fn wait<'a>(args: &'a [RespData], store: &'a Arc<RedisStore>, state: &'a mut ConnectionState) -> BoxFuture<'a> {
+    Box::pin(async move {
+        if store.replication.is_replica() {
+            return RespData::Error(
+                "ERR WAIT cannot be used with replica instances. Please also note that since Redis 4.0 if a replica is \
+                 configured to be writable (which is not the default) writes to replicas are just local and are not propagated."
+                    .to_string(),
+            );
+        }
+        let args = bulk_args(&args[1..]);
+        let numreplicas = match args.first().and_then(|n| n.parse::<i64>().ok()) {
+            Some(numreplicas) => numreplicas.max(0) as usize,
+            None => return RespData::Error(errors::NOT_INTEGER.to_string()),
+        };
+        let timeout = match args.get(1).and_then(|t| t.parse::<i64>().ok()) {
+            Some(timeout) if timeout < 0 => return RespData::Error("ERR timeout is negative".to_string()),
+            Some(timeout) => timeout as u64,
+            None => return RespData::Error("ERR timeout is not an integer or out of range".to_string()),
+        };
+        RespData::Integer(wait_for_replicas(store, numreplicas, timeout, state.write_offset).await as i64)
+    })
+}
+
+fn config(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    match &args[1] {
+        RespData::BulkString(sub) if sub.eq_ignore_ascii_case("GET") => {
+            if args.len() < 3 {
+                return RespData::Error(errors::wrong_arity("config|get"));
+            }
+            let config = store.config.read();
+            let mut reply = Vec::new();
+            for name in ServerConfig::PARAMETERS {
+                let matched = args[2..].iter().any(|pattern| match pattern {
+                    RespData::BulkString(pattern) => glob_match(pattern.as_bytes(), name.as_bytes(), true),
+                    _ => false,
+                });
+                if let (true, Some(value)) = (matched, config.get(name)) {
+                    reply.push(RespData::BulkString(name.to_string()));
+                    reply.push(RespData::BulkString(value));
+                }
+            }
+            RespData::Array(reply)
+        }
+        RespData::BulkString(sub) if sub.eq_ignore_ascii_case("SET") => {
+            if args.len() < 4 || !args.len().is_multiple_of(2) {
+                return RespData::Error(errors::wrong_arity("config|set"));
+            }
+            let mut config = store.config.write();
+            // Apply to a scratch copy first so a failure leaves nothing half-set
+            let mut updated = config.clone();
+            for pair in args[2..].chunks(2) {
+                if let (RespData::BulkString(name), RespData::BulkString(value)) = (&pair[0], &pair[1]) {
+                    let name = name.to_lowercase();
+                    if ServerConfig::IMMUTABLE.contains(&name.as_str()) {
+                        return RespData::Error(format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config", name
+                        ));
+                    }
+                    if let Err(e) = updated.set(&name, value) {
+                        if config.get(&name).is_none() {
+                            return RespData::Error(format!("ERR {}", e));
+                        }
+                        return RespData::Error(format!(
+                            "ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, e
+                        ));
+                    }
+                }
+            }
+            *config = updated;
+            logging::set_level(config.loglevel);
+            ok()
+        }
+        RespData::BulkString(sub) if sub.eq_ignore_ascii_case("RESETSTAT") => {
+            store.stats.reset();
+            store.command_stats.clear();
+            ok()
+        }
+        RespData::BulkString(sub) => RespData::Error(errors::unknown_subcommand("config", sub)),
+        _ => RespData::Error(errors::wrong_arity("config")),
+    }
+}
+
+fn object(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let sub = match &args[1] {
+        RespData::BulkString(sub) => sub.to_uppercase(),
+        _ => return RespData::Error(errors::wrong_arity("object")),
+    };
+    if sub == "HELP" {
+        return help(&[
+            "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "ENCODING <key>",
+            "    Return the kind of internal representation used in order to store the value",
+            "    associated with a <key>.",
+            "FREQ <key>",
+            "    Return the access frequency index of the <key>. The returned integer is",
+            "    proportional to the logarithm of the recent access frequency of the key.",
+            "IDLETIME <key>",
+            "    Return the idle time of the <key>, that is the approximated number of",
+            "    seconds elapsed since the last access to the key.",
+            "REFCOUNT <key>",
+            "    Return the number of references of the value associated with the specified",
+            "    <key>.",
+            "HELP",
+            "    Print this help.",
+        ]);
+    }
+    let key = match (args.get(2), args.len()) {
+        (Some(RespData::BulkString(key)), 3) => key,
+        _ => return RespData::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.", sub
+        )),
+    };
+    let reply = store.inspect(key, |value| match sub.as_str() {
+        "ENCODING" => RespData::BulkString(object_encoding(&value.data).to_string()),
+        "IDLETIME" => RespData::Integer((store.now_ms().saturating_sub(value.lru()) / 1000) as i64),
+        "REFCOUNT" => RespData::Integer(1),
+        "FREQ" => RespData::Error("ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".to_string()),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.", sub
+        )),
+    });
+    reply.unwrap_or_else(|| RespData::Error("ERR no such key".to_string()))
+}
+
+fn memory(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let sub = match &args[1] {
+        RespData::BulkString(sub) => sub.to_uppercase(),
+        _ => return RespData::Error(errors::wrong_arity("memory")),
+    };
+    match sub.as_str() {
+        "USAGE" => {
+            let key = match args.get(2) {
+                Some(RespData::BulkString(key)) => key,
+                _ => return RespData::Error(errors::wrong_arity("memory|usage")),
+            };
+            let mut samples = 5;
+            match &args[3..] {
+                [] => {}
+                [RespData::BulkString(opt), RespData::BulkString(n)] if opt.eq_ignore_ascii_case("SAMPLES") => {
+                    match n.parse::<usize>() {
+                        Ok(n) => samples = n,
+                        Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+                    }
+                }
+                _ => return RespData::Error(errors::SYNTAX.to_string()),
+            }
+            let usage = store.inspect(key, |value| {
+                ENTRY_OVERHEAD + key.len() + estimate_value_size_sampled(&value.data, samples)
+            });
+            usage.map_or(RespData::Null, |usage| RespData::Integer(usage as i64))
+        }
+        "STATS" => memory_stats(store),
+        "DOCTOR" => RespData::BulkString(memory_doctor(store)),
+        "HELP" => help(&[
+            "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "DOCTOR",
+            "    Return memory problems reports.",
+            "STATS",
+            "    Return information about the memory usage of the server.",
+            "USAGE <key> [SAMPLES <count>]",
+            "    Return memory in bytes used by <key> and its value. Nested values are",
+            "    sampled up to <count> times (default: 5, 0 means sample all).",
+            "HELP",
+            "    Print this help.",
+        ]),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try MEMORY HELP.", sub
+        )),
+    }
+}
+
+// Cluster introspection for clients that probe it. This node is not part of
+// a cluster, so it owns no slots and knows no other nodes.
+fn cluster(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let sub = match &args[1] {
+        RespData::BulkString(sub) => sub.to_uppercase(),
+        _ => return RespData::Error(errors::wrong_arity("cluster")),
+    };
+    match (sub.as_str(), &args[2..]) {
+        ("INFO", []) => RespData::BulkString(cluster_info()),
+        ("MYID", []) => RespData::BulkString(store.node_id.clone()),
+        ("SLOTS", []) | ("SHARDS", []) => RespData::Array(Vec::new()),
+        ("KEYSLOT", [RespData::BulkString(key)]) => RespData::Integer(key_hash_slot(key.as_bytes()) as i64),
+        ("COUNTKEYSINSLOT", [RespData::BulkString(slot)]) => match slot.parse::<i64>() {
+            Ok(slot) if (0..CLUSTER_SLOTS as i64).contains(&slot) => {
+                RespData::Integer(store.count_keys_in_slot(slot as u16) as i64)
+            }
+            Ok(_) => RespData::Error("ERR Invalid slot".to_string()),
+            Err(_) => RespData::Error(errors::NOT_INTEGER.to_string()),
+        },
+        ("HELP", []) => help(&[
+            "CLUSTER <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "COUNTKEYSINSLOT <slot>",
+            "    Return the number of keys in <slot>.",
+            "INFO",
+            "    Return information about the cluster.",
+            "KEYSLOT <key>",
+            "    Return the hash slot for <key>.",
+            "MYID",
+            "    Return the node id.",
+            "SHARDS",
+            "    Return information about slot range mappings and the nodes associated",
+            "    with them.",
+            "SLOTS",
+            "    Return information about slots range mappings. Each range is made of:",
+            "    start, end, master and replicas IP addresses, ports and ids",
+            "HELP",
+            "    Print this help.",
+        ]),
+        _ => RespData::Error(format!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try CLUSTER HELP.", sub
+        )),
+    }
+}
+
+// The fields of a reply that reads as a map: a map in RESP3, a flat array of
+// names and values before
+fn fields_reply(fields: Vec<(&str, RespData)>, protocol: u8) -> RespData {
+    let fields = fields.into_iter().map(|(name, value)| (RespData::BulkString(name.to_string()), value));
+    if protocol >= 3 {
+        RespData::Map(fields.collect())
+    } else {
+        RespData::Array(fields.flat_map(|(name, value)| [name, value]).collect())
+    }
+}
+
+// An ID given to a stream command, a bare time taking `missing_seq` as its
+// sequence number
+fn parse_stream_id(arg: &str, missing_seq: u64) -> Result<StreamId, String> {
+    StreamId::parse(arg, missing_seq).ok_or_else(|| streams::INVALID_ID.to_string())
+}
+
+fn parse_stream_ids(args: &[String]) -> Result<Vec<StreamId>, String> {
+    args.iter().map(|arg| parse_stream_id(arg, 0)).collect()
+}
+
+// One end of an XRANGE interval: `-` and `+` for the ends of the stream, a
+// leading `(` to leave the ID itself out
+fn parse_range_bound(arg: &str, start: bool) -> Result<StreamId, String> {
+    let missing_seq = if start { 0 } else { u64::MAX };
+    match (arg, arg.strip_prefix('(')) {
+        ("-", _) => Ok(StreamId::MIN),
+        ("+", _) => Ok(StreamId::MAX),
+        (_, Some(id)) => {
+            let id = parse_stream_id(id, missing_seq)?;
+            let next = if start { id.next() } else { id.prev() };
+            next.ok_or_else(|| format!("ERR invalid {} ID for the interval", if start { "start" } else { "end" }))
+        }
+        (_, None) => parse_stream_id(arg, missing_seq),
+    }
+}
+
+fn stream_entry(id: StreamId, fields: Option<&Fields>) -> RespData {
+    let fields = match fields {
+        Some(fields) => RespData::Array(
+            fields
+                .iter()
+                .flat_map(|(name, value)| [RespData::BulkString(name.clone()), RespData::BulkString(value.clone())])
+                .collect(),
+        ),
+        // Deleted while pending
+        None => RespData::NullArray,
+    };
+    RespData::Array(vec![RespData::BulkString(id.to_string()), fields])
+}
+
+fn optional_integer(n: Option<u64>) -> RespData {
+    n.map_or(RespData::Null, |n| RespData::Integer(n as i64))
+}
+
+// Reads a stream
+fn read_stream(store: &RedisStore, key: &str, f: impl FnOnce(Option<&StreamValue>) -> RespData) -> RespData {
+    let reply = store.read_typed(key, ValueKind::Stream, |value| match value {
+        Some(RedisValueType::Stream(stream)) => f(Some(stream)),
+        _ => f(None),
+    });
+    reply.unwrap_or_else(RespData::Error)
+}
+
+// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] id field value [field value ...]
+fn xadd(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let command = args;
+    let args = bulk_args(&args[1..]);
+    let (mut nomkstream, mut trim) = (false, None);
+    let mut i = 1;
+    while let Some(option) = args.get(i).map(|arg| arg.to_uppercase()) {
+        match option.as_str() {
+            "NOMKSTREAM" => nomkstream = true,
+            "MAXLEN" | "MINID" => {
+                let approximate = args.get(i + 1).is_some_and(|arg| arg == "~");
+                if approximate || args.get(i + 1).is_some_and(|arg| arg == "=") {
+                    i += 1;
+                }
+                let threshold = match args.get(i + 1) {
+                    Some(threshold) => threshold,
+                    None => return RespData::Error(errors::SYNTAX.to_string()),
+                };
+                trim = Some(if option == "MAXLEN" {
+                    match threshold.parse::<i64>() {
+                        Ok(max) if max >= 0 => Trim::MaxLen(max as usize),
+                        Ok(_) => return RespData::Error("ERR The MAXLEN argument must be >= 0.".to_string()),
+                        Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+                    }
+                } else {
+                    match parse_stream_id(threshold, 0) {
+                        Ok(min) => Trim::MinId(min),
+                        Err(e) => return RespData::Error(e),
+                    }
+                });
+                i += 1;
+                // Trimming is always exact, which the approximate form allows
+                if args.get(i + 1).is_some_and(|arg| arg.eq_ignore_ascii_case("LIMIT")) {
+                    if !approximate {
+                        return RespData::Error("ERR syntax error, LIMIT cannot be used without the special ~ option".to_string());
+                    }
+                    if args.get(i + 2).and_then(|limit| limit.parse::<u64>().ok()).is_none() {
+                        return RespData::Error(errors::NOT_INTEGER.to_string());
+                    }
+                    i += 2;
+                }
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    let fields = match args.get(i + 1..) {
+        Some(fields) if !fields.is_empty() && fields.len().is_multiple_of(2) => fields,
+        _ => return RespData::Error(errors::wrong_arity("xadd")),
+    };
+    let id = match args[i].split_once('-') {
+        _ if args[i] == "*" => NewId::Auto,
+        Some((ms, "*")) => match ms.parse() {
+            Ok(ms) => NewId::AutoSeq(ms),
+            Err(_) => return RespData::Error(streams::INVALID_ID.to_string()),
+        },
+        _ => match parse_stream_id(&args[i], 0) {
+            Ok(id) => NewId::Explicit(id),
+            Err(e) => return RespData::Error(e),
+        },
+    };
+    let fields = fields.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+    match streams::xadd(store, &args[0], id, fields, nomkstream, trim) {
+        Ok(Some(added)) => {
+            // Replicas must add the entry under the same ID
+            if !matches!(id, NewId::Explicit(_)) {
+                let mut rewritten = command.to_vec();
+                rewritten[i + 1] = RespData::BulkString(added.to_string());
+                state.propagate_as = Some(RespData::Array(rewritten));
+            }
+            RespData::BulkString(added.to_string())
+        }
+        Ok(None) => RespData::Null,
+        Err(e) => RespData::Error(e),
+    }
+}
+
+fn xlen(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    read_stream(store, &args[0], |stream| RespData::Integer(stream.map_or(0, |stream| stream.len()) as i64))
+}
+
+// XRANGE key start end [COUNT count]
+fn xrange(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let bounds = parse_range_bound(&args[1], true).and_then(|start| Ok((start, parse_range_bound(&args[2], false)?)));
+    let (start, end) = match bounds {
+        Ok(bounds) => bounds,
+        Err(e) => return RespData::Error(e),
+    };
+    let count = match &args[3..] {
+        [] => usize::MAX,
+        [option, count] if option.eq_ignore_ascii_case("COUNT") => match count.parse::<i64>() {
+            Ok(count) => count.max(0) as usize,
+            Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+        },
+        _ => return RespData::Error(errors::SYNTAX.to_string()),
+    };
+    read_stream(store, &args[0], |stream| {
+        let entries = stream.into_iter().flat_map(|stream| stream.range(start, end));
+        RespData::Array(entries.take(count).map(|(id, fields)| stream_entry(*id, Some(fields))).collect())
+    })
+}
+
+fn xdel(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let result = parse_stream_ids(&args[1..]).and_then(|ids| streams::xdel(store, &args[0], &ids));
+    result.map_or_else(RespData::Error, |deleted| RespData::Integer(deleted as i64))
+}
+
+// XGROUP CREATE and SETID's position and optional ENTRIESREAD
+fn parse_group_start(args: &[String]) -> Result<(GroupStart, &[String], Option<u64>), String> {
+    let start = match args[0].as_str() {
+        "$" => GroupStart::End,
+        id => GroupStart::Id(parse_stream_id(id, 0)?),
+    };
+    let mut rest = &args[1..];
+    let mut entries_read = None;
+    if let Some(at) = rest.iter().position(|arg| arg.eq_ignore_ascii_case("ENTRIESREAD")) {
+        let value = rest.get(at + 1).ok_or_else(|| errors::SYNTAX.to_string())?;
+        entries_read = match value.parse::<i64>() {
+            Ok(n) if n >= 0 => Some(n as u64),
+            Ok(-1) => None,
+            _ => return Err("ERR value for ENTRIESREAD must be positive or -1".to_string()),
+        };
+        if at + 2 != rest.len() {
+            return Err(errors::SYNTAX.to_string());
+        }
+        rest = &rest[..at];
+    }
+    Ok((start, rest, entries_read))
+}
+
+fn xgroup(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let sub = args[0].to_uppercase();
+    let result = match (sub.as_str(), &args[1..]) {
+        ("CREATE", [key, group, start @ ..]) if !start.is_empty() => parse_group_start(start).and_then(|(start, rest, entries_read)| {
+            let mkstream = match rest {
+                [] => false,
+                [option] if option.eq_ignore_ascii_case("MKSTREAM") => true,
+                _ => return Err(errors::SYNTAX.to_string()),
+            };
+            streams::xgroup_create(store, key, group, start, mkstream, entries_read).map(|()| ok())
+        }),
+        ("SETID", [key, group, start @ ..]) if !start.is_empty() => parse_group_start(start).and_then(|(start, rest, entries_read)| {
+            if !rest.is_empty() {
+                return Err(errors::SYNTAX.to_string());
+            }
+            streams::xgroup_setid(store, key, group, start, entries_read).map(|()| ok())
+        }),
+        ("DESTROY", [key, group]) => streams::xgroup_destroy(store, key, group).map(|destroyed| RespData::Integer(destroyed as i64)),
+        ("CREATECONSUMER", [key, group, consumer]) => {
+            streams::xgroup_createconsumer(store, key, group, consumer).map(|created| RespData::Integer(created as i64))
+        }
+        ("DELCONSUMER", [key, group, consumer]) => {
+            streams::xgroup_delconsumer(store, key, group, consumer).map(|pending| RespData::Integer(pending as i64))
+        }
+        ("HELP", []) => Ok(help(&[
+            "XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CREATE <key> <groupname> <id|$> [option]",
+            "    Create a new consumer group. Options are:",
+            "    * MKSTREAM",
+            "      Create the empty stream if it does not exist.",
+            "    * ENTRIESREAD entries_read",
+            "      Set the group's entries_read counter (internal use).",
+            "CREATECONSUMER <key> <groupname> <consumer>",
+            "    Create a new consumer in the specified group.",
+            "DELCONSUMER <key> <groupname> <consumer>",
+            "    Remove the specified consumer.",
+            "DESTROY <key> <groupname>",
+            "    Remove the specified group.",
+            "SETID <key> <groupname> <id|$> [ENTRIESREAD entries_read]",
+            "    Set the current group ID and entries_read counter.",
+            "HELP",
+            "    Print this help.",
+        ])),
+        _ => Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try XGROUP HELP.", args[0])),
+    };
+    result.unwrap_or_else(RespData::Error)
+}
+
+// XREADGROUP GROUP group consumer [COUNT count] [BLOCK ms] [NOACK] STREAMS key [key ...] id [id ...]
+//
+// BLOCK is checked but never waits: a read that finds nothing new replies
+// nil at once, as if the timeout had passed, and a client reading in a loop
+// just asks again. Waiting would have to happen outside the replication
+// write lock every write holds while it runs, and replicas, which are sent
+// the command as it came, must never wait on it.
+fn xreadgroup(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let args = bulk_args(&args[1..]);
+    if !args[0].eq_ignore_ascii_case("GROUP") {
+        return RespData::Error(errors::SYNTAX.to_string());
+    }
+    let (group, consumer) = (&args[1], &args[2]);
+    let (mut count, mut noack) = (0, false);
+    let mut i = 3;
+    let streams_at = loop {
+        let option = match args.get(i) {
+            Some(option) => option.to_uppercase(),
+            None => return RespData::Error(errors::SYNTAX.to_string()),
+        };
+        match option.as_str() {
+            "COUNT" => match args.get(i + 1).map(|count| count.parse::<i64>()) {
+                Some(Ok(n)) => count = n.max(0) as usize,
+                _ => return RespData::Error(errors::NOT_INTEGER.to_string()),
+            },
+            // Validated like Redis does, then ignored
+            "BLOCK" => match args.get(i + 1).map(|ms| ms.parse::<i64>()) {
+                Some(Ok(ms)) if ms >= 0 => {}
+                Some(Ok(_)) => return RespData::Error("ERR timeout is negative".to_string()),
+                _ => return RespData::Error("ERR timeout is not an integer or out of range".to_string()),
+            },
+            "NOACK" => {
+                noack = true;
+                i += 1;
+                continue;
+            }
+            "STREAMS" => break i + 1,
+            _ => return RespData::Error(errors::SYNTAX.to_string()),
+        }
+        i += 2;
+    };
+    let rest = &args[streams_at..];
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return RespData::Error(
+            "ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.".to_string(),
+        );
+    }
+    let (keys, ids) = rest.split_at(rest.len() / 2);
+    let mut reads = Vec::with_capacity(keys.len());
+    for (key, id) in keys.iter().zip(ids) {
+        let from = match id.as_str() {
+            ">" => ReadFrom::New,
+            "$" => {
+                return RespData::Error(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.".to_string(),
+                )
+            }
+            id => match parse_stream_id(id, 0) {
+                Ok(id) => ReadFrom::Pending(id),
+                Err(e) => return RespData::Error(e),
+            },
+        };
+        // Every group must exist before anything is delivered
+        match streams::has_group(store, key, group) {
+            Ok(true) => reads.push((key, from)),
+            Ok(false) => return RespData::Error(streams::no_group(key, group)),
+            Err(e) => return RespData::Error(e),
+        }
+    }
+
+    let mut replies = Vec::new();
+    for (key, from) in reads {
+        let entries = match streams::xreadgroup(store, key, group, consumer, from, count, noack) {
+            Ok(entries) => entries,
+            Err(e) => return RespData::Error(e),
+        };
+        // New entries are only reported for streams that had some, while a
+        // consumer's history is always reported
+        if entries.is_empty() && matches!(from, ReadFrom::New) {
+            continue;
+        }
+        let entries = entries.iter().map(|(id, fields)| stream_entry(*id, fields.as_ref())).collect();
+        replies.push((RespData::BulkString(key.clone()), RespData::Array(entries)));
+    }
+    match (replies.is_empty(), state.protocol >= 3) {
+        (true, true) => RespData::Null,
+        (true, false) => RespData::NullArray,
+        (false, true) => RespData::Map(replies),
+        (false, false) => RespData::Array(replies.into_iter().map(|(key, entries)| RespData::Array(vec![key, entries])).collect()),
+    }
+}
+
+fn xack(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let result = parse_stream_ids(&args[2..]).and_then(|ids| streams::xack(store, &args[0], &args[1], &ids));
+    result.map_or_else(RespData::Error, |acked| RespData::Integer(acked as i64))
+}
+
+// Entries per node of the radix tree Redis keeps streams in, which XINFO
+// reports the shape of
+const STREAM_NODE_ENTRIES: usize = 100;
+
+// The fields XINFO STREAM starts with, FULL or not
+fn stream_summary(stream: &StreamValue) -> Vec<(&'static str, RespData)> {
+    let nodes = stream.len().div_ceil(STREAM_NODE_ENTRIES);
+    vec![
+        ("length", RespData::Integer(stream.len() as i64)),
+        ("radix-tree-keys", RespData::Integer(nodes as i64)),
+        ("radix-tree-nodes", RespData::Integer(nodes as i64 + 1)),
+        ("last-generated-id", RespData::BulkString(stream.last_id.to_string())),
+        ("max-deleted-entry-id", RespData::BulkString(stream.max_deleted_id.to_string())),
+        ("entries-added", RespData::Integer(stream.entries_added as i64)),
+        ("recorded-first-entry-id", RespData::BulkString(stream.first_id.to_string())),
+    ]
+}
+
+// XINFO STREAM key FULL: every group with its pending entries and
+// consumers, listing at most `count` (0 = all) of each
+fn stream_full(stream: &StreamValue, count: usize, protocol: u8) -> Vec<(&'static str, RespData)> {
+    let limit = if count == 0 { usize::MAX } else { count };
+    let mut fields = stream_summary(stream);
+    let entries = stream.range(StreamId::MIN, StreamId::MAX).take(limit);
+    fields.push(("entries", RespData::Array(entries.map(|(id, fields)| stream_entry(*id, Some(fields))).collect())));
+    let groups = stream.groups.iter().map(|(name, group)| {
+        let pending = group.pending.iter().take(limit).map(|(id, entry)| {
+            RespData::Array(vec![
+                RespData::BulkString(id.to_string()),
+                RespData::BulkString(entry.consumer.clone()),
+                RespData::Integer(entry.delivery_time as i64),
+                RespData::Integer(entry.delivery_count as i64),
+            ])
+        });
+        let consumers = group.consumers.iter().map(|(name, consumer)| {
+            let pending = consumer.pending.iter().take(limit).filter_map(|id| {
+                let entry = group.pending.get(id)?;
+                Some(RespData::Array(vec![
+                    RespData::BulkString(id.to_string()),
+                    RespData::Integer(entry.delivery_time as i64),
+                    RespData::Integer(entry.delivery_count as i64),
+                ]))
+            });
+            fields_reply(
+                vec![
+                    ("name", RespData::BulkString(name.clone())),
+                    ("seen-time", RespData::Integer(consumer.seen_time as i64)),
+                    ("active-time", RespData::Integer(consumer.active_time.map_or(-1, |at| at as i64))),
+                    ("pel-count", RespData::Integer(consumer.pending.len() as i64)),
+                    ("pending", RespData::Array(pending.collect())),
+                ],
+                protocol,
+            )
+        });
+        fields_reply(
+            vec![
+                ("name", RespData::BulkString(name.clone())),
+                ("last-delivered-id", RespData::BulkString(group.last_id.to_string())),
+                ("entries-read", optional_integer(group.entries_read)),
+                ("lag", optional_integer(stream.lag(group))),
+                ("pel-count", RespData::Integer(group.pending.len() as i64)),
+                ("pending", RespData::Array(pending.collect())),
+                ("consumers", RespData::Array(consumers.collect())),
+            ],
+            protocol,
+        )
+    });
+    fields.push(("groups", RespData::Array(groups.collect())));
+    fields
+}
+
+fn xinfo(args: &[RespData], store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let sub = args[0].to_uppercase();
+    let protocol = state.protocol;
+    let now = store.now_ms();
+    // Every subcommand but HELP needs an existing stream
+    let with_stream = |key: &str, f: &dyn Fn(&StreamValue) -> RespData| {
+        read_stream(store, key, |stream| match stream {
+            Some(stream) => f(stream),
+            None => RespData::Error(streams::NO_SUCH_KEY.to_string()),
+        })
+    };
+    match (sub.as_str(), &args[1..]) {
+        ("STREAM", [key, options @ ..]) => {
+            let full = match options {
+                [] => None,
+                [full] if full.eq_ignore_ascii_case("FULL") => Some(10),
+                [full, option, count] if full.eq_ignore_ascii_case("FULL") && option.eq_ignore_ascii_case("COUNT") => {
+                    match count.parse::<i64>() {
+                        Ok(count) => Some(count.max(0) as usize),
+                        Err(_) => return RespData::Error(errors::NOT_INTEGER.to_string()),
+                    }
+                }
+                _ => return RespData::Error(errors::SYNTAX.to_string()),
+            };
+            with_stream(key, &|stream| {
+                let fields = match full {
+                    Some(count) => stream_full(stream, count, protocol),
+                    None => {
+                        let mut fields = stream_summary(stream);
+                        let entry = |entry: Option<(&StreamId, &Fields)>| entry.map_or(RespData::Null, |(id, fields)| stream_entry(*id, Some(fields)));
+                        fields.push(("groups", RespData::Integer(stream.groups.len() as i64)));
+                        fields.push(("first-entry", entry(stream.first_entry())));
+                        fields.push(("last-entry", entry(stream.last_entry())));
+                        fields
+                    }
+                };
+                fields_reply(fields, protocol)
+            })
+        }
+        ("GROUPS", [key]) => with_stream(key, &|stream| {
+            let groups = stream.groups.iter().map(|(name, group)| {
+                fields_reply(
+                    vec![
+                        ("name", RespData::BulkString(name.clone())),
+                        ("consumers", RespData::Integer(group.consumers.len() as i64)),
+                        ("pending", RespData::Integer(group.pending.len() as i64)),
+                        ("last-delivered-id", RespData::BulkString(group.last_id.to_string())),
+                        ("entries-read", optional_integer(group.entries_read)),
+                        ("lag", optional_integer(stream.lag(group))),
+                    ],
+                    protocol,
+                )
+            });
+            RespData::Array(groups.collect())
+        }),
+        ("CONSUMERS", [key, group]) => with_stream(key, &|stream| {
+            let group_value = match stream.groups.get(group) {
+                Some(group) => group,
+                None => return RespData::Error(streams::no_group(key, group)),
+            };
+            let consumers = group_value.consumers.iter().map(|(name, consumer)| {
+                fields_reply(
+                    vec![
+                        ("name", RespData::BulkString(name.clone())),
+                        ("pending", RespData::Integer(consumer.pending.len() as i64)),
+                        ("idle", RespData::Integer(now.saturating_sub(consumer.seen_time) as i64)),
+                        ("inactive", RespData::Integer(consumer.active_time.map_or(-1, |at| now.saturating_sub(at) as i64))),
+                    ],
+                    protocol,
+                )
+            });
+            RespData::Array(consumers.collect())
+        }),
+        ("HELP", []) => help(&[
+            "XINFO <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CONSUMERS <key> <groupname>",
+            "    Show consumers of <groupname>.",
+            "GROUPS <key>",
+            "    Show the stream consumer groups.",
+            "STREAM <key> [FULL [COUNT <count>]",
+            "    Show information about the stream.",
+            "HELP",
+            "    Print this help.",
+        ]),
+        _ => RespData::Error(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try XINFO HELP.", args[0])),
+    }
+}
+
+// A command as COMMAND describes it, under the name it's called by: name,
+// arity, flags, key positions and (empty) ACL categories
+fn command_entry(name: &str, command: &Command) -> RespData {
+    let mut flags = Vec::new();
+    if command.is_write() {
+        flags.push("write");
+    } else if command.first_key > 0 {
+        flags.push("readonly");
+    }
+    if command.is_denyoom() {
+        flags.push("denyoom");
+    }
+    if command.is_no_multi() {
+        flags.push("no_multi");
+    }
+    RespData::Array(vec![
+        RespData::BulkString(name.to_string()),
+        RespData::Integer(command.arity),
+        RespData::Array(flags.into_iter().map(|flag| RespData::SimpleString(flag.to_string())).collect()),
+        RespData::Integer(command.first_key as i64),
+        RespData::Integer(command.last_key),
+        RespData::Integer(command.key_step as i64),
+        RespData::Array(vec![]),
+    ])
+}
+
+// COMMAND lists the commands clients can call, so renamed commands appear
+// under their new names and disabled ones not at all
+fn commands_info(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let args = bulk_args(&args[1..]);
+    let all = || RespData::Array(store.commands.iter().map(|(name, command)| command_entry(name, command)).collect());
+    let sub = match args.first() {
+        Some(sub) => sub.to_uppercase(),
+        None => return all(),
+    };
+    match (sub.as_str(), &args[1..]) {
+        ("INFO", []) => all(),
+        ("COUNT", []) => RespData::Integer(store.commands.iter().count() as i64),
+        ("LIST", []) => RespData::Array(store.commands.iter().map(|(name, _)| RespData::BulkString(name.to_string())).collect()),
+        ("INFO", names) => RespData::Array(
+            names
+                .iter()
+                .map(|name| {
+                    let found = store.commands.iter().find(|(called, _)| called.eq_ignore_ascii_case(name));
+                    found.map_or(RespData::NullArray, |(called, command)| command_entry(called, command))
+                })
+                .collect(),
+        ),
+        ("HELP", []) => help(&[
+            "COMMAND <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "(no subcommand)",
+            "    Return details about all Redis commands.",
+            "COUNT",
+            "    Return the total number of commands in this Redis server.",
+            "LIST",
+            "    Return a list of all commands in this Redis server.",
+            "INFO [<command-name> ...]",
+            "    Return details about multiple Redis commands.",
+            "    If no command names are given, documentation details for all",
+            "    commands are returned.",
+            "HELP",
+            "    Print this help.",
+        ]),
+        _ => RespData::Error(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try COMMAND HELP.", args[0])),
+    }
+}
+
+fn info(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let section = match args.get(1) {
+        Some(RespData::BulkString(section)) => Some(section.to_lowercase()),
+        _ => None,
+    };
+    RespData::BulkString(build_info(store, section.as_deref()))
+}
+
+fn keys(args: &[RespData], store: &Arc<RedisStore>) -> Reply {
+    let pattern = match &args[1] {
+        RespData::BulkString(pattern) => pattern,
+        _ => return RespData::Error(errors::wrong_arity("keys")).into(),
+    };
+    let now = store.now_ms();
+    let keys: Vec<String> = store
+        .data
+        .iter()
+        .filter(|entry| entry.expiry.is_none_or(|expiry| now < expiry) && glob_match(pattern.as_bytes(), entry.key().as_bytes(), false))
+        .map(|entry| entry.key().clone())
+        .collect();
+    if keys.len() >= STREAMED_REPLY_MIN {
+        Reply::Stream(Box::new(KeyNames(keys)))
+    } else {
+        RespData::Array(keys.into_iter().map(RespData::BulkString).collect()).into()
+    }
+}
+
+fn dbsize(_args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    RespData::Integer(store.data.len() as i64)
+}
+
+fn debug(args: &[RespData], store: &Arc<RedisStore>) -> RespData {
+    let sub = match &args[1] {
+        RespData::BulkString(sub) => sub,
+        _ => return RespData::Error(errors::wrong_arity("debug")),
+    };
+    match (sub.to_uppercase().as_str(), args.get(2)) {
+        ("SET-ACTIVE-EXPIRE", Some(RespData::BulkString(flag))) => match flag.parse::<i64>() {
+            Ok(flag) => {
+                store.active_expire_enabled.store(flag != 0, Ordering::Relaxed);
+                ok()
+            }
+            Err(_) => RespData::Error(errors::NOT_INTEGER.to_string()),
+        },
+        _ => RespData::Error(errors::unknown_subcommand("debug", sub)),
+    }
+}
+
+// Serializing and writing a large dataset takes a while, so it runs on the
+// blocking pool while this client waits and the others keep being served
+fn save<'a>(_args: &'a [RespData], store: &'a Arc<RedisStore>) -> BoxFuture<'a> {
+    Box::pin(async move {
+        let store = Arc::clone(store);
+        let result = tokio::task::spawn_blocking(move || store.save())
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        match result {
+            Ok(summary) => {
+                if summary.compressed {
+                    logging::notice!(
+                        "DB saved on disk: {} bytes, compressed from {} (ratio {:.2})",
+                        summary.bytes,
+                        summary.uncompressed,
+                        summary.uncompressed as f64 / summary.bytes.max(1) as f64
+                    );
+                } else {
+                    logging::notice!("DB saved on disk: {} bytes", summary.bytes);
+                }
+                ok()
+            }
+            Err(e) => {
+                logging::warning!("Failed saving the DB: {}", e);
+                RespData::Error(format!("ERR {}", e))
+            }
+        }
+    })
+}
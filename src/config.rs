@@ -0,0 +1,463 @@
+//! Server configuration, settable at startup and through CONFIG SET.
+
+use std::collections::HashMap;
+
+use crate::commands::COMMANDS;
+use crate::listpack::DEFAULT_LIST_MAX_LISTPACK_SIZE;
+use crate::logging::Level;
+use crate::notify;
+use crate::resp::split_inline_args;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "noeviction" => Some(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Some(EvictionPolicy::AllKeysLru),
+            "volatile-lru" => Some(EvictionPolicy::VolatileLru),
+            "allkeys-random" => Some(EvictionPolicy::AllKeysRandom),
+            "volatile-random" => Some(EvictionPolicy::VolatileRandom),
+            "volatile-ttl" => Some(EvictionPolicy::VolatileTtl),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileRandom => "volatile-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    pub(crate) fn volatile_only(&self) -> bool {
+        matches!(self, EvictionPolicy::VolatileLru | EvictionPolicy::VolatileRandom | EvictionPolicy::VolatileTtl)
+    }
+}
+
+// Parses memory amounts such as "100mb" or "1gb" the way redis.conf does
+pub(crate) fn parse_memory(value: &str) -> Option<u64> {
+    let lower = value.to_lowercase();
+    let units: [(&str, u64); 7] = [
+        ("kb", 1024),
+        ("mb", 1024 * 1024),
+        ("gb", 1024 * 1024 * 1024),
+        ("k", 1000),
+        ("m", 1000 * 1000),
+        ("g", 1000 * 1000 * 1000),
+        ("b", 1),
+    ];
+    for (suffix, multiplier) in units {
+        if let Some(number) = lower.strip_suffix(suffix) {
+            return number.parse::<u64>().ok()?.checked_mul(multiplier);
+        }
+    }
+    lower.parse().ok()
+}
+
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+// Listen addresses used when there is no bind directive
+pub(crate) const DEFAULT_BIND: &[&str] = &["*", "-::*"];
+
+// Parses a bind address into its IP and whether binding it may fail
+// quietly. "*" and "::*" stand for every IPv4 and IPv6 interface.
+pub(crate) fn parse_bind_address(address: &str) -> Option<(std::net::IpAddr, bool)> {
+    let (address, optional) = match address.strip_prefix('-') {
+        Some(address) => (address, true),
+        None => (address, false),
+    };
+    let ip = match address {
+        "*" => std::net::Ipv4Addr::UNSPECIFIED.into(),
+        "::*" => std::net::Ipv6Addr::UNSPECIFIED.into(),
+        _ => address.parse().ok()?,
+    };
+    Some((ip, optional))
+}
+
+/// Server configuration. Parameters are read and written by their redis.conf
+/// names, the same way CONFIG GET and CONFIG SET do.
+///
+/// ```
+/// use redis::config::ServerConfig;
+///
+/// let mut config = ServerConfig::default();
+/// config.set("maxmemory", "100mb").unwrap();
+/// assert_eq!(config.get("maxmemory").as_deref(), Some("104857600"));
+/// assert!(config.set("hz", "0").is_err());
+/// ```
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub(crate) maxmemory: u64,
+    pub(crate) maxmemory_policy: EvictionPolicy,
+    pub(crate) maxmemory_samples: usize,
+    // Positive: max entries in a listpack-encoded list; negative: size class
+    // from -1 (4kb) to -5 (64kb)
+    pub(crate) list_max_listpack_size: i64,
+    // Hashes with more fields, or a longer name or value, stop being
+    // listpack-encoded
+    pub(crate) hash_max_listpack_entries: usize,
+    pub(crate) hash_max_listpack_value: usize,
+    // Frequency of background tasks such as active expiration
+    pub(crate) hz: u64,
+    // Largest bulk string a client may send
+    pub(crate) proto_max_bulk_len: u64,
+    // Largest amount of unprocessed input buffered for a single client
+    pub(crate) client_query_buffer_limit: u64,
+    // Seconds a client may stay idle before it is disconnected, 0 = never
+    pub(crate) timeout: u64,
+    pub(crate) maxclients: u64,
+    // Seconds between TCP keepalive probes on client sockets, 0 = off
+    pub(crate) tcp_keepalive: u64,
+    // Listen queue length, only applied at startup
+    pub(crate) tcp_backlog: u32,
+    pub(crate) tcp_nodelay: bool,
+    // TCP port to listen on, 0 = unix socket only
+    pub(crate) port: u16,
+    // Addresses to listen on; a leading '-' marks one that may be unavailable.
+    // Empty means no bind directive was given.
+    pub(crate) bind: Vec<String>,
+    // Without a bind directive, only loopback clients are served
+    pub(crate) protected_mode: bool,
+    // Path of the unix socket to listen on, empty = none
+    pub(crate) unixsocket: String,
+    // Mode bits for the unix socket file, 0 = leave to the umask
+    pub(crate) unixsocketperm: u32,
+    // Where SAVE writes the dataset and startup loads it from
+    pub(crate) dir: String,
+    pub(crate) dbfilename: String,
+    // Gzip the snapshot file. Either kind is read back regardless.
+    pub(crate) rdbcompression: bool,
+    // Start with an empty dataset instead of refusing to when the snapshot
+    // can't be read
+    pub(crate) ignore_corrupt_dump: bool,
+    // While replicating, refuse writes from clients other than the master
+    pub(crate) replica_read_only: bool,
+    pub(crate) loglevel: Level,
+    // Empty logs to stdout
+    pub(crate) logfile: String,
+    // Commands slower than this many microseconds are logged, negative = never
+    pub(crate) slowlog_log_slower_than: i64,
+    // rename-command directives: the new name by original name, empty for a
+    // disabled command
+    pub(crate) renamed_commands: HashMap<String, String>,
+    // Event classes published as keyspace notifications, see notify.rs
+    pub(crate) notify_keyspace_events: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            maxmemory: 0,
+            maxmemory_policy: EvictionPolicy::NoEviction,
+            maxmemory_samples: 5,
+            list_max_listpack_size: DEFAULT_LIST_MAX_LISTPACK_SIZE,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            hz: 10,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            timeout: 0,
+            maxclients: 10000,
+            tcp_keepalive: 300,
+            tcp_backlog: 511,
+            tcp_nodelay: true,
+            port: 6379,
+            bind: Vec::new(),
+            protected_mode: true,
+            unixsocket: String::new(),
+            unixsocketperm: 0,
+            dir: ".".to_string(),
+            dbfilename: "redis-data.json".to_string(),
+            rdbcompression: false,
+            ignore_corrupt_dump: false,
+            replica_read_only: true,
+            loglevel: Level::Notice,
+            logfile: String::new(),
+            slowlog_log_slower_than: 10000,
+            renamed_commands: HashMap::new(),
+            notify_keyspace_events: 0,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub(crate) const PARAMETERS: &'static [&'static str] = &[
+        "maxmemory",
+        "maxmemory-policy",
+        "maxmemory-samples",
+        "list-max-listpack-size",
+        "hash-max-listpack-entries",
+        "hash-max-listpack-value",
+        "hz",
+        "proto-max-bulk-len",
+        "client-query-buffer-limit",
+        "timeout",
+        "maxclients",
+        "tcp-keepalive",
+        "tcp-backlog",
+        "tcp-nodelay",
+        "port",
+        "bind",
+        "protected-mode",
+        "unixsocket",
+        "unixsocketperm",
+        "dir",
+        "dbfilename",
+        "rdbcompression",
+        "ignore-corrupt-dump",
+        "replica-read-only",
+        "loglevel",
+        "logfile",
+        "slowlog-log-slower-than",
+        "notify-keyspace-events",
+    ];
+
+    // Parameters that only take effect at startup
+    pub(crate) const IMMUTABLE: &'static [&'static str] = &[
+        "tcp-backlog",
+        "port",
+        "bind",
+        "unixsocket",
+        "unixsocketperm",
+        "ignore-corrupt-dump",
+        "logfile",
+        "rename-command",
+    ];
+
+    /// The current value of parameter `name`, or `None` if it is unknown.
+    pub fn get(&self, name: &str) -> Option<String> {
+        match name {
+            "maxmemory" => Some(self.maxmemory.to_string()),
+            "maxmemory-policy" => Some(self.maxmemory_policy.name().to_string()),
+            "maxmemory-samples" => Some(self.maxmemory_samples.to_string()),
+            "list-max-listpack-size" | "list-max-ziplist-size" => Some(self.list_max_listpack_size.to_string()),
+            "hash-max-listpack-entries" | "hash-max-ziplist-entries" => Some(self.hash_max_listpack_entries.to_string()),
+            "hash-max-listpack-value" | "hash-max-ziplist-value" => Some(self.hash_max_listpack_value.to_string()),
+            "hz" => Some(self.hz.to_string()),
+            "proto-max-bulk-len" => Some(self.proto_max_bulk_len.to_string()),
+            "client-query-buffer-limit" => Some(self.client_query_buffer_limit.to_string()),
+            "timeout" => Some(self.timeout.to_string()),
+            "maxclients" => Some(self.maxclients.to_string()),
+            "tcp-keepalive" => Some(self.tcp_keepalive.to_string()),
+            "tcp-backlog" => Some(self.tcp_backlog.to_string()),
+            "tcp-nodelay" => Some(if self.tcp_nodelay { "yes" } else { "no" }.to_string()),
+            "port" => Some(self.port.to_string()),
+            "bind" => Some(if self.bind.is_empty() { DEFAULT_BIND.join(" ") } else { self.bind.join(" ") }),
+            "protected-mode" => Some(if self.protected_mode { "yes" } else { "no" }.to_string()),
+            "unixsocket" => Some(self.unixsocket.clone()),
+            "unixsocketperm" => Some(format!("{:o}", self.unixsocketperm)),
+            "dir" => Some(self.dir.clone()),
+            "dbfilename" => Some(self.dbfilename.clone()),
+            "rdbcompression" => Some(if self.rdbcompression { "yes" } else { "no" }.to_string()),
+            "ignore-corrupt-dump" => Some(if self.ignore_corrupt_dump { "yes" } else { "no" }.to_string()),
+            "replica-read-only" | "slave-read-only" => Some(if self.replica_read_only { "yes" } else { "no" }.to_string()),
+            "loglevel" => Some(self.loglevel.name().to_string()),
+            "logfile" => Some(self.logfile.clone()),
+            "slowlog-log-slower-than" => Some(self.slowlog_log_slower_than.to_string()),
+            "notify-keyspace-events" => Some(notify::flags_to_string(self.notify_keyspace_events)),
+            _ => None,
+        }
+    }
+
+    // The command called as `name` given the renames so far, by its own name
+    fn command_called(&self, name: &str) -> Option<&'static str> {
+        COMMANDS
+            .iter()
+            .map(|command| command.name)
+            .find(|command| self.renamed_commands.get(*command).map_or(*command == name, |renamed| !renamed.is_empty() && renamed == name))
+    }
+
+    // Location of the dataset file
+    pub(crate) fn snapshot_path(&self) -> std::path::PathBuf {
+        std::path::Path::new(&self.dir).join(&self.dbfilename)
+    }
+
+    /// Applies a directive as given on the command line or in a config file:
+    /// a name and its arguments, of which bind and rename-command take
+    /// several.
+    pub fn apply(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        let value = match args {
+            [] => return Err("wrong number of arguments".to_string()),
+            [value] => value.clone(),
+            // Quoted so that empty arguments survive being joined
+            values => values.iter().map(|value| if value.is_empty() { "\"\"".to_string() } else { value.clone() }).collect::<Vec<_>>().join(" "),
+        };
+        self.set(&name.to_lowercase(), &value)
+    }
+
+    /// Applies the directives of a config file, one per line with its
+    /// arguments split and quoted like an inline command. Blank lines and
+    /// lines starting with `#` are skipped. Errors name the offending line,
+    /// as Redis reports them.
+    pub fn load_file(&mut self, contents: &str) -> Result<(), String> {
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match split_inline_args(line.as_bytes()) {
+                Some(args) => {
+                    let mut args = args.into_iter().map(|arg| String::from_utf8_lossy(&arg).into_owned());
+                    let name = args.next().unwrap_or_default().to_lowercase();
+                    let args: Vec<String> = args.collect();
+                    if (self.get(&name).is_none() && name != "rename-command") || args.is_empty() {
+                        Err("Bad directive or wrong number of arguments".to_string())
+                    } else {
+                        self.apply(&name, &args)
+                    }
+                }
+                None => Err("Unbalanced quotes in configuration line".to_string()),
+            };
+            if let Err(e) = result {
+                return Err(format!("Reading the configuration file, at line {}\n>>> '{}'\n{}", number + 1, line, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets parameter `name`, failing with a description of the accepted
+    /// values if `value` is invalid.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "maxmemory" => {
+                self.maxmemory = parse_memory(value).ok_or("argument must be a memory value")?;
+            }
+            "maxmemory-policy" => {
+                self.maxmemory_policy = EvictionPolicy::parse(value).ok_or("argument(s) must be one of the following: noeviction, allkeys-lru, volatile-lru, allkeys-random, volatile-random, volatile-ttl")?;
+            }
+            "maxmemory-samples" => {
+                self.maxmemory_samples = value.parse().ok().filter(|&n| n > 0).ok_or("argument must be between 1 and 64 inclusive")?;
+            }
+            "list-max-listpack-size" | "list-max-ziplist-size" => {
+                self.list_max_listpack_size = value.parse().ok().filter(|&n| n >= -5 && n != 0).ok_or("argument must be between -5 and -1 or a positive number")?;
+            }
+            "hash-max-listpack-entries" | "hash-max-ziplist-entries" => {
+                self.hash_max_listpack_entries = value.parse().ok().ok_or("argument couldn't be parsed into an integer")?;
+            }
+            "hash-max-listpack-value" | "hash-max-ziplist-value" => {
+                self.hash_max_listpack_value = value.parse().ok().ok_or("argument couldn't be parsed into an integer")?;
+            }
+            "hz" => {
+                self.hz = value.parse().ok().filter(|&n| (1..=500).contains(&n)).ok_or("argument must be between 1 and 500 inclusive")?;
+            }
+            "proto-max-bulk-len" => {
+                self.proto_max_bulk_len = parse_memory(value).filter(|&n| n >= 1024 * 1024).ok_or("argument must be a memory value of at least 1mb")?;
+            }
+            "client-query-buffer-limit" => {
+                self.client_query_buffer_limit = parse_memory(value).filter(|&n| n >= 1024 * 1024).ok_or("argument must be a memory value of at least 1mb")?;
+            }
+            "timeout" => {
+                self.timeout = value.parse().ok().filter(|&n| n <= i32::MAX as u64).ok_or("argument couldn't be parsed into an integer")?;
+            }
+            "maxclients" => {
+                self.maxclients = value.parse().ok().filter(|&n| n >= 1).ok_or("argument must be between 1 and 4294967295 inclusive")?;
+            }
+            "tcp-keepalive" => {
+                self.tcp_keepalive = value.parse().ok().filter(|&n| n <= i32::MAX as u64).ok_or("argument couldn't be parsed into an integer")?;
+            }
+            "tcp-backlog" => {
+                self.tcp_backlog = value.parse().ok().filter(|&n| n <= i32::MAX as u32).ok_or("argument couldn't be parsed into an integer")?;
+            }
+            "tcp-nodelay" => {
+                self.tcp_nodelay = parse_bool(value).ok_or("argument must be 'yes' or 'no'")?;
+            }
+            "port" => {
+                self.port = value.parse().ok().ok_or("argument must be between 0 and 65535 inclusive")?;
+            }
+            "bind" => {
+                let addresses: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+                if addresses.is_empty() {
+                    return Err("argument must be a list of addresses".to_string());
+                }
+                for address in &addresses {
+                    if parse_bind_address(address).is_none() {
+                        return Err(format!("invalid bind address '{}'", address));
+                    }
+                }
+                self.bind = addresses;
+            }
+            "protected-mode" => {
+                self.protected_mode = parse_bool(value).ok_or("argument must be 'yes' or 'no'")?;
+            }
+            "unixsocket" => {
+                self.unixsocket = value.to_string();
+            }
+            "unixsocketperm" => {
+                // Given in octal, like chmod
+                self.unixsocketperm = u32::from_str_radix(value, 8).ok().filter(|&n| n <= 0o777).ok_or("argument must be an octal number between 0 and 777")?;
+            }
+            "dir" => {
+                if !std::path::Path::new(value).is_dir() {
+                    return Err("No such file or directory".to_string());
+                }
+                self.dir = value.to_string();
+            }
+            "dbfilename" => {
+                // Like Redis, the file always lives in `dir`
+                if value.is_empty() || value.contains('/') {
+                    return Err("dbfilename can't be a path, just a filename".to_string());
+                }
+                self.dbfilename = value.to_string();
+            }
+            "rdbcompression" => {
+                self.rdbcompression = parse_bool(value).ok_or("argument must be 'yes' or 'no'")?;
+            }
+            "ignore-corrupt-dump" => {
+                self.ignore_corrupt_dump = parse_bool(value).ok_or("argument must be 'yes' or 'no'")?;
+            }
+            "replica-read-only" | "slave-read-only" => {
+                self.replica_read_only = parse_bool(value).ok_or("argument must be 'yes' or 'no'")?;
+            }
+            "loglevel" => {
+                self.loglevel = Level::parse(value).ok_or("argument(s) must be one of the following: debug, verbose, notice, warning")?;
+            }
+            "logfile" => {
+                self.logfile = value.to_string();
+            }
+            "slowlog-log-slower-than" => {
+                self.slowlog_log_slower_than = value.parse().ok().ok_or("argument couldn't be parsed into an integer")?;
+            }
+            "notify-keyspace-events" => {
+                self.notify_keyspace_events = notify::parse_flags(value).ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmdn'.")?;
+            }
+            // "<command> <new name>", applied in order like Redis: a name is
+            // only free once the command holding it has been renamed away
+            "rename-command" => {
+                let args = split_inline_args(value.as_bytes()).unwrap_or_default();
+                let (original, name) = match args.as_slice() {
+                    [original, name] => (
+                        String::from_utf8_lossy(original).to_lowercase(),
+                        String::from_utf8_lossy(name).to_lowercase(),
+                    ),
+                    _ => return Err("Bad directive or wrong number of arguments".to_string()),
+                };
+                let command = self.command_called(&original).ok_or("No such command in rename-command")?;
+                if !name.is_empty() && self.command_called(&name).is_some() {
+                    return Err("Target command name already exists".to_string());
+                }
+                self.renamed_commands.insert(command.to_string(), name);
+            }
+            _ => return Err(format!("Unknown option or number of arguments for CONFIG SET - '{}'", name)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+// State that lives with a client connection, and the registry through which
+// server-wide features (CLIENT LIST, PUBLISH) reach other connections
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+
+use crate::resp::RespData;
+use crate::store::RedisStore;
+use crate::tracking::TrackingOptions;
+use crate::util::glob_match;
+
+// Commands a RESP2 client may still send once it has subscribed to something
+const SUBSCRIBED_COMMANDS: &[&str] = &["subscribe", "unsubscribe", "psubscribe", "punsubscribe", "ping", "quit", "reset"];
+
+// Commands queued by MULTI, and whether one of them was refused while
+// queueing, which makes EXEC discard the lot
+#[derive(Default)]
+pub(crate) struct Transaction {
+    pub(crate) commands: Vec<RespData>,
+    pub(crate) aborted: bool,
+}
+
+pub(crate) struct ConnectionState {
+    pub(crate) id: u64,
+    // "ip:port", or "path:0" for unix socket clients, as in CLIENT LIST
+    pub(crate) addr: String,
+    pub(crate) name: Option<String>,
+    pub(crate) db: usize,
+    pub(crate) protocol: u8,
+    pub(crate) channels: HashSet<String>,
+    pub(crate) patterns: HashSet<String>,
+    pub(crate) transaction: Option<Transaction>,
+    pub(crate) monitor: bool,
+    pub(crate) tracking: Option<TrackingOptions>,
+    // CLIENT CACHING's answer, which applies to the next command only
+    pub(crate) caching: Option<bool>,
+    // Messages for this client that don't answer one of its commands, such
+    // as published messages. The connection loop writes them out.
+    pub(crate) sender: mpsc::UnboundedSender<RespData>,
+    // Announced by a replica through REPLCONF listening-port
+    pub(crate) listening_port: u16,
+    // Replication offset after this connection's latest write, for WAIT
+    pub(crate) write_offset: u64,
+    pub(crate) created: Instant,
+    pub(crate) last_interaction: Instant,
+    pub(crate) last_command: &'static str,
+    // Most output this connection has had waiting to be sent, in bytes
+    pub(crate) output_peak: usize,
+    // The link a replica applies its master's commands through
+    pub(crate) is_master: bool,
+    // What the write being run should reach replicas as instead of itself,
+    // set by commands that choose something when run, like XADD's new ID
+    pub(crate) propagate_as: Option<RespData>,
+    // Set while EXEC runs its queued commands under the write lock
+    pub(crate) in_exec: bool,
+}
+
+impl ConnectionState {
+    pub(crate) fn new(id: u64, addr: String) -> (Self, mpsc::UnboundedReceiver<RespData>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let now = Instant::now();
+        let state = ConnectionState {
+            id,
+            addr,
+            name: None,
+            db: 0,
+            protocol: 2,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            transaction: None,
+            monitor: false,
+            tracking: None,
+            caching: None,
+            sender,
+            listening_port: 0,
+            write_offset: 0,
+            created: now,
+            last_interaction: now,
+            last_command: "NULL",
+            output_peak: 0,
+            is_master: false,
+            propagate_as: None,
+            in_exec: false,
+        };
+        (state, receiver)
+    }
+
+    pub(crate) fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    // A RESP2 client with subscriptions can only manage them
+    pub(crate) fn in_subscribe_mode(&self) -> bool {
+        self.protocol == 2 && self.subscriptions() > 0
+    }
+
+    pub(crate) fn allows_in_subscribe_mode(command: &str) -> bool {
+        SUBSCRIBED_COMMANDS.contains(&command)
+    }
+
+    // Sends a reply ahead of the one the current command returns, for
+    // commands such as SUBSCRIBE that answer once per argument
+    pub(crate) fn push(&self, reply: RespData) {
+        let _ = self.sender.send(reply);
+    }
+
+    // An out-of-band message: a push under RESP3, an array under RESP2
+    pub(crate) fn out_of_band(&self, items: Vec<RespData>) -> RespData {
+        out_of_band(self.protocol, items)
+    }
+
+    // One line of CLIENT LIST
+    pub(crate) fn info(&self) -> String {
+        let mut flags = String::new();
+        if self.is_master {
+            flags.push('M');
+        }
+        if self.transaction.is_some() {
+            flags.push('x');
+        }
+        if self.subscriptions() > 0 {
+            flags.push('P');
+        }
+        if self.monitor {
+            flags.push('O');
+        }
+        if self.tracking.is_some() {
+            flags.push('t');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        let multi = self.transaction.as_ref().map_or(-1, |transaction| transaction.commands.len() as i64);
+        format!(
+            "id={} addr={} name={} age={} idle={} flags={} db={} sub={} psub={} multi={} cmd={} resp={}",
+            self.id,
+            self.addr,
+            self.name.as_deref().unwrap_or(""),
+            self.created.elapsed().as_secs(),
+            self.last_interaction.elapsed().as_secs(),
+            flags,
+            self.db,
+            self.channels.len(),
+            self.patterns.len(),
+            multi,
+            self.last_command,
+            self.protocol
+        )
+    }
+}
+
+struct RegisteredClient {
+    // The client's CLIENT LIST line as of its latest command
+    info: String,
+    protocol: u8,
+    output_peak: usize,
+    sender: mpsc::UnboundedSender<RespData>,
+}
+
+#[derive(Default)]
+struct Registry {
+    clients: HashMap<u64, RegisteredClient>,
+    // Subscribers by channel and by pattern
+    channels: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<String, HashSet<u64>>,
+}
+
+// Every connected client, so one connection can list or message the others
+#[derive(Default)]
+pub(crate) struct ClientRegistry {
+    inner: Mutex<Registry>,
+}
+
+impl ClientRegistry {
+    pub(crate) fn register(&self, state: &ConnectionState) {
+        let client = RegisteredClient {
+            info: state.info(),
+            protocol: state.protocol,
+            output_peak: state.output_peak,
+            sender: state.sender.clone(),
+        };
+        self.inner.lock().clients.insert(state.id, client);
+    }
+
+    // Refreshes what other clients see of this one
+    pub(crate) fn update(&self, state: &ConnectionState) {
+        if let Some(client) = self.inner.lock().clients.get_mut(&state.id) {
+            client.info = state.info();
+            client.protocol = state.protocol;
+            client.output_peak = state.output_peak;
+        }
+    }
+
+    // The largest output buffer any connected client has had
+    pub(crate) fn max_output_peak(&self) -> usize {
+        self.inner.lock().clients.values().map(|client| client.output_peak).max().unwrap_or(0)
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        let registry = &mut *self.inner.lock();
+        registry.clients.remove(&id);
+        for subscribers in [&mut registry.channels, &mut registry.patterns] {
+            subscribers.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+    }
+
+    // CLIENT LIST lines of every client but `except`, by id
+    pub(crate) fn list(&self, except: u64) -> Vec<(u64, String)> {
+        let registry = self.inner.lock();
+        let mut clients: Vec<(u64, String)> = registry
+            .clients
+            .iter()
+            .filter(|(id, _)| **id != except)
+            .map(|(id, client)| (*id, client.info.clone()))
+            .collect();
+        clients.sort_unstable_by_key(|(id, _)| *id);
+        clients
+    }
+
+    pub(crate) fn subscribe(&self, id: u64, channel: &str, pattern: bool) {
+        let mut registry = self.inner.lock();
+        let subscribers = if pattern { &mut registry.patterns } else { &mut registry.channels };
+        subscribers.entry(channel.to_string()).or_default().insert(id);
+    }
+
+    pub(crate) fn unsubscribe(&self, id: u64, channel: &str, pattern: bool) {
+        let mut registry = self.inner.lock();
+        let subscribers = if pattern { &mut registry.patterns } else { &mut registry.channels };
+        remove_subscriber(subscribers, channel, id);
+    }
+
+    pub(crate) fn contains(&self, id: u64) -> bool {
+        self.inner.lock().clients.contains_key(&id)
+    }
+
+    pub(crate) fn is_subscribed(&self, id: u64, channel: &str) -> bool {
+        self.inner.lock().channels.get(channel).is_some_and(|ids| ids.contains(&id))
+    }
+
+    // Sends client `id` the message `build` makes for its protocol, if any.
+    // Returns whether the client is connected.
+    pub(crate) fn send(&self, id: u64, build: impl FnOnce(u8) -> Option<RespData>) -> bool {
+        let registry = self.inner.lock();
+        match registry.clients.get(&id) {
+            Some(client) => {
+                if let Some(message) = build(client.protocol) {
+                    let _ = client.sender.send(message);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Delivers `message` to the subscribers of `channel`, returning how many
+    // clients received it
+    pub(crate) fn publish(&self, channel: &str, message: &RespData) -> usize {
+        let registry = self.inner.lock();
+        let mut receivers = 0;
+        let mut deliver = |id: &u64, items: Vec<RespData>| {
+            if let Some(client) = registry.clients.get(id) {
+                if client.sender.send(out_of_band(client.protocol, items)).is_ok() {
+                    receivers += 1;
+                }
+            }
+        };
+        for id in registry.channels.get(channel).into_iter().flatten() {
+            deliver(id, vec![bulk("message"), bulk(channel), message.clone()]);
+        }
+        for (pattern, ids) in &registry.patterns {
+            if glob_match(pattern.as_bytes(), channel.as_bytes(), false) {
+                for id in ids {
+                    deliver(id, vec![bulk("pmessage"), bulk(pattern), bulk(channel), message.clone()]);
+                }
+            }
+        }
+        receivers
+    }
+}
+
+fn remove_subscriber(subscribers: &mut HashMap<String, HashSet<u64>>, channel: &str, id: u64) {
+    if let Some(ids) = subscribers.get_mut(channel) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            subscribers.remove(channel);
+        }
+    }
+}
+
+fn out_of_band(protocol: u8, items: Vec<RespData>) -> RespData {
+    if protocol >= 3 { RespData::Push(items) } else { RespData::Array(items) }
+}
+
+fn bulk(s: &str) -> RespData {
+    RespData::BulkString(s.to_string())
+}
+
+// Keeps a connection in the registry for as long as it is alive
+pub(crate) struct Registration<'a> {
+    store: &'a RedisStore,
+    id: u64,
+}
+
+impl<'a> Registration<'a> {
+    pub(crate) fn new(store: &'a RedisStore, state: &ConnectionState) -> Self {
+        store.clients.register(state);
+        Registration { store, id: state.id }
+    }
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.store.clients.unregister(self.id);
+        self.store.tracking.disable(self.id);
+    }
+}
@@ -0,0 +1,62 @@
+// Error replies, worded exactly like Redis since client libraries match on them
+
+use crate::resp::RespData;
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+pub const NOT_INTEGER: &str = "ERR value is not an integer or out of range";
+pub const OVERFLOW: &str = "ERR increment or decrement would overflow";
+pub const SYNTAX: &str = "ERR syntax error";
+pub const READONLY: &str = "READONLY You can't write against a read only replica.";
+
+// Redis caps how much of the offending input it echoes back
+const MAX_ECHOED_LEN: usize = 128;
+
+pub fn wrong_arity(command: &str) -> String {
+    format!("ERR wrong number of arguments for '{}' command", command.to_lowercase())
+}
+
+pub fn invalid_expire_time(command: &str) -> String {
+    format!("ERR invalid expire time in '{}' command", command.to_lowercase())
+}
+
+pub fn unknown_command(command: &str, args: &[RespData]) -> String {
+    let mut echoed = String::new();
+    for arg in args {
+        if echoed.len() >= MAX_ECHOED_LEN {
+            break;
+        }
+        let arg = match arg {
+            RespData::BulkString(s) | RespData::SimpleString(s) => s.clone(),
+            RespData::BulkBytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            RespData::Integer(n) => n.to_string(),
+            _ => String::new(),
+        };
+        echoed.push_str(&format!("'{}' ", truncate(&arg, MAX_ECHOED_LEN - echoed.len())));
+    }
+    sanitize(format!(
+        "ERR unknown command '{}', with args beginning with: {}",
+        truncate(command, MAX_ECHOED_LEN),
+        echoed
+    ))
+}
+
+pub fn unknown_subcommand(command: &str, subcommand: &str) -> String {
+    sanitize(format!(
+        "ERR unknown subcommand '{}'. Try {} HELP.",
+        truncate(subcommand, MAX_ECHOED_LEN),
+        command.to_uppercase()
+    ))
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// Error replies are single-line, so user input must not smuggle in line breaks
+fn sanitize(message: String) -> String {
+    message.replace(['\r', '\n'], " ")
+}
@@ -0,0 +1,676 @@
+// Gzip (RFC 1952) streams for compressed snapshots. The encoder finds
+// repeats with LZ77 hash chains and writes them with DEFLATE's fixed Huffman
+// codes; the decoder reads any DEFLATE stream, so files recompressed with
+// the gzip tool still load.
+
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+
+// First bytes of every gzip file
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// Compression method 8 (DEFLATE), no flags, no modification time, unknown OS
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+// How far back a match may start
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+// Candidates tried per position; more compresses better but slower
+const MAX_CHAIN: usize = 32;
+// Input is compressed, and output written, this much at a time
+const BLOCK_SIZE: usize = 128 * 1024;
+
+const END_OF_BLOCK: u16 = 256;
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// Order in which a dynamic block lists the code length code lengths
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+const CRC32_TABLE: [u32; 256] = {
+    // Reflected form of the IEEE polynomial 0x04c11db7
+    const POLY: u32 = 0xedb8_8320;
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// Continues a CRC-32 over `data`, starting from 0 for a new stream
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("gzip: {}", message))
+}
+
+// Huffman codes are sent most significant bit first into a stream that is
+// otherwise least significant bit first
+fn reverse_bits(code: u32, len: u32) -> u32 {
+    code.reverse_bits() >> (32 - len)
+}
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, len: u32) {
+        self.acc |= (value as u64) << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    // Pads the last byte with zero bits
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+    }
+
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        let (code, len) = match symbol {
+            0..=143 => (0x30 + symbol, 8),
+            144..=255 => (0x190 + symbol - 144, 9),
+            256..=279 => (symbol - 256, 7),
+            _ => (0xc0 + symbol - 280, 8),
+        };
+        self.write(reverse_bits(code, len), len);
+    }
+
+    fn write_match(&mut self, len: usize, distance: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap_or(0);
+        self.write_literal(257 + code as u16);
+        self.write((len - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+        let code = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+        self.write(reverse_bits(code as u32, 5), 5);
+        self.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+    }
+}
+
+/// Compresses what is written to it into a gzip stream on `inner`. Call
+/// [`GzipWriter::finish`] to end the stream; dropping it leaves the stream
+/// truncated.
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    // The last WINDOW bytes already compressed, followed by the input that
+    // isn't yet
+    buffer: Vec<u8>,
+    pending: usize,
+    // Stream position of buffer[0]
+    offset: usize,
+    // Latest stream position + 1 with each hash of 3 bytes, 0 for none, and
+    // for each position in the window the one before it with the same hash
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    bits: BitWriter,
+    crc: u32,
+    total_in: u64,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(mut inner: W) -> std::io::Result<Self> {
+        inner.write_all(&HEADER)?;
+        Ok(GzipWriter {
+            inner,
+            buffer: Vec::with_capacity(WINDOW + BLOCK_SIZE),
+            pending: 0,
+            offset: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW],
+            bits: BitWriter::default(),
+            crc: 0,
+            total_in: 0,
+        })
+    }
+
+    /// Bytes written so far, before compression.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Compresses what is left and writes the gzip trailer, returning the
+    /// inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.compress(true)?;
+        self.inner.write_all(&self.crc.to_le_bytes())?;
+        // The trailer holds the size modulo 2^32
+        self.inner.write_all(&(self.total_in as u32).to_le_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let bytes = &self.buffer[i..i + MIN_MATCH];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize, hash: usize) {
+        let position = self.offset + i;
+        self.prev[position % WINDOW] = self.head[hash];
+        self.head[hash] = position + 1;
+    }
+
+    // The longest earlier occurrence of the bytes at `i`, as (length, distance)
+    fn longest_match(&self, i: usize, hash: usize) -> (usize, usize) {
+        let position = self.offset + i;
+        let max_len = MAX_MATCH.min(self.buffer.len() - i);
+        let (mut best_len, mut best_distance) = (0, 0);
+        let mut candidate = self.head[hash];
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 {
+                break;
+            }
+            let start = candidate - 1;
+            if start < self.offset || position - start > WINDOW {
+                break;
+            }
+            let j = start - self.offset;
+            if self.buffer[j + best_len] == self.buffer[i + best_len] {
+                let len = self.buffer[j..j + max_len].iter().zip(&self.buffer[i..i + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    (best_len, best_distance) = (len, position - start);
+                    if len == max_len {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[start % WINDOW];
+            // Older entries of the ring may have been overwritten
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        (best_len, best_distance)
+    }
+
+    // Writes the pending input as one fixed Huffman block
+    fn compress(&mut self, last: bool) -> std::io::Result<()> {
+        // BFINAL, then block type 01
+        self.bits.write(last as u32 | 0b10, 3);
+        let mut i = self.pending;
+        while i < self.buffer.len() {
+            if self.buffer.len() - i < MIN_MATCH {
+                self.bits.write_literal(self.buffer[i] as u16);
+                i += 1;
+                continue;
+            }
+            let hash = self.hash(i);
+            let (len, distance) = self.longest_match(i, hash);
+            self.insert(i, hash);
+            if len < MIN_MATCH {
+                self.bits.write_literal(self.buffer[i] as u16);
+                i += 1;
+                continue;
+            }
+            self.bits.write_match(len, distance);
+            for j in i + 1..(i + len).min(self.buffer.len() - MIN_MATCH + 1) {
+                let hash = self.hash(j);
+                self.insert(j, hash);
+            }
+            i += len;
+        }
+        self.bits.write_literal(END_OF_BLOCK);
+        if last {
+            self.bits.align();
+        }
+        self.inner.write_all(&self.bits.out)?;
+        self.bits.out.clear();
+
+        let consumed = self.buffer.len().saturating_sub(WINDOW);
+        self.buffer.drain(..consumed);
+        self.offset += consumed;
+        self.pending = self.buffer.len();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        self.crc = crc32(self.crc, data);
+        self.total_in += data.len() as u64;
+        if self.buffer.len() - self.pending >= BLOCK_SIZE {
+            self.compress(false)?;
+        }
+        Ok(data.len())
+    }
+
+    // Compressed output only goes out a block at a time
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct BitReader<R: BufRead> {
+    inner: R,
+    acc: u64,
+    len: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    // Buffers up to `len` bits, fewer if the input ends first
+    fn fill(&mut self, len: u32) -> std::io::Result<()> {
+        while self.len < len {
+            let byte = match self.inner.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => return Ok(()),
+            };
+            self.inner.consume(1);
+            self.acc |= (byte as u64) << self.len;
+            self.len += 8;
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, len: u32) -> std::io::Result<u32> {
+        self.fill(len)?;
+        if self.len < len {
+            return Err(corrupt("unexpected end of data"));
+        }
+        let value = (self.acc & ((1 << len) - 1)) as u32;
+        self.consume(len);
+        Ok(value)
+    }
+
+    fn consume(&mut self, len: u32) {
+        self.acc >>= len;
+        self.len -= len;
+    }
+
+    // Skips to the next byte boundary
+    fn align(&mut self) {
+        self.consume(self.len % 8);
+    }
+
+    fn u16_le(&mut self) -> std::io::Result<u16> {
+        Ok(self.bits(16)? as u16)
+    }
+
+    fn u32_le(&mut self) -> std::io::Result<u32> {
+        Ok(self.bits(16)? | self.bits(16)? << 16)
+    }
+}
+
+// A canonical Huffman code, decoded by looking up the next `max_len` bits
+struct Huffman {
+    // (symbol, code length) by reversed code, padded with every suffix;
+    // length 0 marks bit patterns no code starts with
+    table: Vec<(u16, u8)>,
+    max_len: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> std::io::Result<Self> {
+        let max_len = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let mut counts = [0u32; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        // Codes left unused at each length; running out means the lengths
+        // describe more codes than fit
+        let mut left: i64 = 1;
+        let mut next_code = [0u32; 16];
+        for len in 1..16 {
+            left = (left << 1) - counts[len] as i64;
+            if left < 0 {
+                return Err(corrupt("invalid Huffman code"));
+            }
+            next_code[len] = (next_code[len - 1] + counts[len - 1]) << 1;
+        }
+        let mut table = vec![(0, 0); 1 << max_len];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let len = len as u32;
+            let code = reverse_bits(next_code[len as usize], len) as usize;
+            next_code[len as usize] += 1;
+            for suffix in 0..1 << (max_len - len) {
+                table[code | suffix << len] = (symbol as u16, len as u8);
+            }
+        }
+        Ok(Huffman { table, max_len })
+    }
+
+    fn fixed() -> (Self, Self) {
+        let mut lengths = [8u8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        let literals = Huffman::new(&lengths).expect("valid fixed code");
+        let distances = Huffman::new(&[5; 32]).expect("valid fixed code");
+        (literals, distances)
+    }
+
+    fn decode<R: BufRead>(&self, input: &mut BitReader<R>) -> std::io::Result<u16> {
+        input.fill(self.max_len)?;
+        let (symbol, len) = self.table[(input.acc & ((1 << self.max_len) - 1)) as usize];
+        if len == 0 || len as u32 > input.len {
+            return Err(corrupt("invalid Huffman code"));
+        }
+        input.consume(len as u32);
+        Ok(symbol)
+    }
+}
+
+enum Block {
+    // Between blocks, or before the first
+    Header,
+    Stored(usize),
+    Huffman(Huffman, Huffman),
+    Done,
+}
+
+/// Decompresses the gzip stream read from `inner`. A stream that doesn't
+/// decode, or whose checksum or size doesn't match, is an `InvalidData`
+/// error.
+pub struct GzipReader<R: BufRead> {
+    input: BitReader<R>,
+    // Decompressed data: what was read from `served` on, and before it the
+    // last WINDOW bytes that later data may refer back to
+    output: Vec<u8>,
+    served: usize,
+    // How much of `output` went into `crc`
+    checked: usize,
+    block: Block,
+    last_block: bool,
+    crc: u32,
+    total_out: u64,
+}
+
+impl<R: BufRead> GzipReader<R> {
+    pub fn new(inner: R) -> std::io::Result<Self> {
+        let mut input = BitReader { inner, acc: 0, len: 0 };
+        let mut header = [0u8; 10];
+        for byte in &mut header {
+            *byte = input.bits(8)? as u8;
+        }
+        if header[..2] != MAGIC || header[2] != 8 {
+            return Err(corrupt("not a gzip stream"));
+        }
+        let flags = header[3];
+        if flags & FLAG_EXTRA != 0 {
+            for _ in 0..input.u16_le()? {
+                input.bits(8)?;
+            }
+        }
+        for flag in [FLAG_NAME, FLAG_COMMENT] {
+            if flags & flag != 0 {
+                while input.bits(8)? != 0 {}
+            }
+        }
+        if flags & FLAG_HCRC != 0 {
+            input.u16_le()?;
+        }
+        Ok(GzipReader {
+            input,
+            output: Vec::with_capacity(2 * WINDOW),
+            served: 0,
+            checked: 0,
+            block: Block::Header,
+            last_block: false,
+            crc: 0,
+            total_out: 0,
+        })
+    }
+
+    fn update_crc(&mut self) {
+        self.crc = crc32(self.crc, &self.output[self.checked..]);
+        self.total_out += (self.output.len() - self.checked) as u64;
+        self.checked = self.output.len();
+    }
+
+    // Decodes some more output, or moves on to the next block
+    fn inflate(&mut self) -> std::io::Result<()> {
+        self.block = match std::mem::replace(&mut self.block, Block::Done) {
+            Block::Header if self.last_block => {
+                self.input.align();
+                self.update_crc();
+                let (crc, size) = (self.input.u32_le()?, self.input.u32_le()?);
+                if crc != self.crc || size != self.total_out as u32 {
+                    return Err(corrupt("checksum mismatch"));
+                }
+                Block::Done
+            }
+            Block::Header => self.block_header()?,
+            Block::Stored(remaining) => {
+                let chunk = remaining.min(WINDOW);
+                for _ in 0..chunk {
+                    self.output.push(self.input.bits(8)? as u8);
+                }
+                if chunk == remaining { Block::Header } else { Block::Stored(remaining - chunk) }
+            }
+            Block::Huffman(literals, distances) => {
+                let start = self.output.len();
+                let mut ended = false;
+                while self.output.len() - start < WINDOW {
+                    let symbol = literals.decode(&mut self.input)?;
+                    if symbol < END_OF_BLOCK {
+                        self.output.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == END_OF_BLOCK {
+                        ended = true;
+                        break;
+                    }
+                    let code = (symbol - 257) as usize;
+                    if code >= LENGTH_BASE.len() {
+                        return Err(corrupt("invalid length code"));
+                    }
+                    let len = LENGTH_BASE[code] as usize + self.input.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = distances.decode(&mut self.input)? as usize;
+                    if code >= DISTANCE_BASE.len() {
+                        return Err(corrupt("invalid distance code"));
+                    }
+                    let distance = DISTANCE_BASE[code] as usize + self.input.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+                    if distance > self.output.len() {
+                        return Err(corrupt("distance too far back"));
+                    }
+                    // The copy may overlap the bytes it produces
+                    let from = self.output.len() - distance;
+                    for k in 0..len {
+                        self.output.push(self.output[from + k]);
+                    }
+                }
+                if ended { Block::Header } else { Block::Huffman(literals, distances) }
+            }
+            Block::Done => Block::Done,
+        };
+        Ok(())
+    }
+
+    fn block_header(&mut self) -> std::io::Result<Block> {
+        self.last_block = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => {
+                self.input.align();
+                let len = self.input.u16_le()?;
+                if self.input.u16_le()? != !len {
+                    return Err(corrupt("invalid stored block length"));
+                }
+                Ok(Block::Stored(len as usize))
+            }
+            1 => {
+                let (literals, distances) = Huffman::fixed();
+                Ok(Block::Huffman(literals, distances))
+            }
+            2 => {
+                let literal_count = self.input.bits(5)? as usize + 257;
+                let distance_count = self.input.bits(5)? as usize + 1;
+                let code_length_count = self.input.bits(4)? as usize + 4;
+                let mut code_lengths = [0u8; 19];
+                for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_lengths[i] = self.input.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths)?;
+                let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (length, repeat) = match code_lengths.decode(&mut self.input)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => match lengths.last() {
+                            Some(&previous) => (previous, 3 + self.input.bits(2)?),
+                            None => return Err(corrupt("repeat with no previous length")),
+                        },
+                        17 => (0, 3 + self.input.bits(3)?),
+                        _ => (0, 11 + self.input.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(length, repeat as usize));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    return Err(corrupt("too many code lengths"));
+                }
+                let literals = Huffman::new(&lengths[..literal_count])?;
+                let distances = Huffman::new(&lengths[literal_count..])?;
+                Ok(Block::Huffman(literals, distances))
+            }
+            _ => Err(corrupt("invalid block type")),
+        }
+    }
+}
+
+impl<R: BufRead> Read for GzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.output.len() - self.served < buf.len() && !matches!(self.block, Block::Done) {
+            self.inflate()?;
+        }
+        let n = buf.len().min(self.output.len() - self.served);
+        buf[..n].copy_from_slice(&self.output[self.served..self.served + n]);
+        self.served += n;
+
+        // Keep only what back-references may still need
+        if self.served > 4 * WINDOW {
+            self.update_crc();
+            let consumed = self.served - WINDOW;
+            self.output.drain(..consumed);
+            self.served -= consumed;
+            self.checked -= consumed;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The inputs of tests/fixtures: text-9.gz is from `gzip -9 -n`, which
+    // writes dynamic Huffman blocks, and noise-1.gz from `gzip -1 -n`, which
+    // stores incompressible data as is
+    fn text() -> Vec<u8> {
+        (0..2000).flat_map(|i| format!("user:{}:profile plan={} visits={}\n", i, ["free", "pro"][i % 2], i * 7).into_bytes()).collect()
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+
+    const TEXT_GZ: &[u8] = include_bytes!("../tests/fixtures/text-9.gz");
+    const NOISE_GZ: &[u8] = include_bytes!("../tests/fixtures/noise-1.gz");
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut writer = GzipWriter::new(Vec::new()).unwrap();
+        // In uneven pieces, so blocks don't line up with writes
+        for piece in data.chunks(7919) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decompress(compressed: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        GzipReader::new(compressed)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    // The type of a stream's first block, from the bits after the header
+    fn first_block_type(compressed: &[u8]) -> u8 {
+        (compressed[HEADER.len()] >> 1) & 0b11
+    }
+
+    #[test]
+    fn what_is_written_reads_back() {
+        let repeated = b"abcabcabd".repeat(100_000);
+        for data in [&b""[..], b"a", b"abc", &text(), &noise(3 * BLOCK_SIZE + 17), &repeated] {
+            let compressed = compress(data);
+            assert_eq!(first_block_type(&compressed), 1);
+            assert_eq!(decompress(&compressed).unwrap(), data, "{} bytes", data.len());
+        }
+    }
+
+    #[test]
+    fn output_of_the_gzip_tool_is_read() {
+        assert_eq!(first_block_type(TEXT_GZ), 2);
+        assert_eq!(decompress(TEXT_GZ).unwrap(), text());
+        assert_eq!(first_block_type(NOISE_GZ), 0);
+        assert_eq!(decompress(NOISE_GZ).unwrap(), noise(40_000));
+    }
+
+    #[test]
+    fn truncated_streams_are_rejected() {
+        for fixture in [TEXT_GZ, NOISE_GZ] {
+            for len in (0..fixture.len()).step_by(101).chain(fixture.len() - 8..fixture.len()) {
+                let error = decompress(&fixture[..len]).unwrap_err();
+                assert_eq!(error.kind(), ErrorKind::InvalidData, "cut at {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_bits_are_caught() {
+        for (fixture, original) in [(TEXT_GZ, text()), (NOISE_GZ, noise(40_000))] {
+            // Past the modification time and OS bytes, which nothing checks
+            for position in (HEADER.len()..fixture.len()).step_by(fixture.len() / 200) {
+                for bit in [0, 3, 7] {
+                    let mut damaged = fixture.to_vec();
+                    damaged[position] ^= 1 << bit;
+                    // Padding after the last block is never read, so a flip
+                    // there still yields the original
+                    match decompress(&damaged) {
+                        Ok(out) => assert!(out == original, "flip at {}:{}", position, bit),
+                        Err(error) => assert_eq!(error.kind(), ErrorKind::InvalidData, "flip at {}:{}", position, bit),
+                    }
+                }
+            }
+        }
+    }
+}
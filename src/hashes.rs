@@ -0,0 +1,208 @@
+// Hash writes, including per-field expiry (HEXPIRE and friends)
+
+use crate::store::{HashValue, RedisStore, RedisValueType, ValueKind, HASH_OVERHEAD};
+
+// Latest field expiry accepted, in Unix milliseconds, as in Redis
+pub const MAX_FIELD_EXPIRY_MS: u64 = ((1 << 48) - 1) >> 2;
+
+// Per-field replies of HEXPIRE and HPERSIST
+pub const NO_SUCH_FIELD: i64 = -2;
+pub const NO_EXPIRY: i64 = -1;
+pub const CONDITION_NOT_MET: i64 = 0;
+pub const UPDATED: i64 = 1;
+pub const DELETED: i64 = 2;
+
+// When HEXPIRE may replace a field's current expiry
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExpiryCondition {
+    Always,
+    // Only fields without an expiry
+    Nx,
+    // Only fields with an expiry
+    Xx,
+    // Only if the new expiry is later; no expiry counts as never
+    Gt,
+    // Only if the new expiry is earlier
+    Lt,
+}
+
+impl ExpiryCondition {
+    fn allows(self, current: Option<u64>, new: u64) -> bool {
+        match self {
+            ExpiryCondition::Always => true,
+            ExpiryCondition::Nx => current.is_none(),
+            ExpiryCondition::Xx => current.is_some(),
+            ExpiryCondition::Gt => current.is_some_and(|current| new > current),
+            ExpiryCondition::Lt => current.is_none_or(|current| new < current),
+        }
+    }
+}
+
+// What HGETEX does to the fields it returns
+pub enum FieldExpiry {
+    Keep,
+    Persist,
+    At(u64),
+}
+
+fn hash_mut(slot: &mut Option<RedisValueType>) -> &mut HashValue {
+    match slot.get_or_insert_with(|| RedisValueType::Hash(HashValue::default())) {
+        RedisValueType::Hash(hash) => hash,
+        _ => unreachable!("with_typed checked the kind"),
+    }
+}
+
+// Drops an emptied hash so its key is deleted, returning the size change
+fn drop_if_empty(slot: &mut Option<RedisValueType>) -> isize {
+    if matches!(slot, Some(RedisValueType::Hash(hash)) if hash.is_empty()) {
+        *slot = None;
+        return -(HASH_OVERHEAD as isize);
+    }
+    0
+}
+
+// Sets the given fields, returning how many of them are new
+pub fn hset(store: &RedisStore, key: &str, pairs: Vec<(String, String)>) -> Result<usize, String> {
+    let limits = store.hash_limits();
+    store.with_typed(key, ValueKind::Hash, |slot| {
+        let mut delta = if slot.is_none() { HASH_OVERHEAD as isize } else { 0 };
+        let hash = hash_mut(slot);
+        let mut added = 0;
+        for (name, value) in pairs {
+            let (new, size) = hash.insert(name, value, limits);
+            added += new as usize;
+            delta += size;
+        }
+        Ok((added, delta))
+    })
+}
+
+// Deletes the given fields, and the key once none are left. Returns how
+// many fields existed.
+pub fn hdel(store: &RedisStore, key: &str, fields: &[String]) -> Result<usize, String> {
+    Ok(hgetdel(store, key, fields)?.iter().filter(|value| value.is_some()).count())
+}
+
+// Deletes the given fields, returning their values
+pub fn hgetdel(store: &RedisStore, key: &str, fields: &[String]) -> Result<Vec<Option<String>>, String> {
+    store.with_typed(key, ValueKind::Hash, |slot| {
+        if slot.is_none() {
+            return Ok((vec![None; fields.len()], 0));
+        }
+        let hash = hash_mut(slot);
+        let mut delta = 0;
+        let mut values = Vec::with_capacity(fields.len());
+        for field in fields {
+            values.push(hash.remove(field).map(|(value, size)| {
+                delta += size;
+                value
+            }));
+        }
+        delta += drop_if_empty(slot);
+        Ok((values, delta))
+    })
+}
+
+// Gives the given fields the expiry `at` (Unix milliseconds) if `condition`
+// allows it, deleting them if it already passed. Returns a status per field.
+pub fn hexpire(
+    store: &RedisStore,
+    key: &str,
+    at: u64,
+    condition: ExpiryCondition,
+    fields: &[String],
+) -> Result<Vec<i64>, String> {
+    let now = store.now_ms();
+    let statuses = store.with_typed(key, ValueKind::Hash, |slot| {
+        if slot.is_none() {
+            return Ok((vec![NO_SUCH_FIELD; fields.len()], 0));
+        }
+        let hash = hash_mut(slot);
+        let mut delta = 0;
+        let mut statuses = Vec::with_capacity(fields.len());
+        for field in fields {
+            let status = match hash.field_expiry(field) {
+                None => NO_SUCH_FIELD,
+                Some(current) if !condition.allows(current, at) => CONDITION_NOT_MET,
+                Some(_) if at <= now => {
+                    delta += hash.remove(field).map_or(0, |(_, size)| size);
+                    DELETED
+                }
+                Some(_) => {
+                    delta += hash.set_expiry(field, Some(at));
+                    UPDATED
+                }
+            };
+            statuses.push(status);
+        }
+        delta += drop_if_empty(slot);
+        Ok((statuses, delta))
+    })?;
+    if statuses.contains(&UPDATED) {
+        store.schedule_field_expiry(key, at);
+    }
+    Ok(statuses)
+}
+
+// Removes the expiry of the given fields. Returns a status per field.
+pub fn hpersist(store: &RedisStore, key: &str, fields: &[String]) -> Result<Vec<i64>, String> {
+    store.with_typed(key, ValueKind::Hash, |slot| {
+        let mut delta = 0;
+        let statuses = match slot {
+            Some(RedisValueType::Hash(hash)) => fields
+                .iter()
+                .map(|field| match hash.field_expiry(field) {
+                    None => NO_SUCH_FIELD,
+                    Some(None) => NO_EXPIRY,
+                    Some(Some(_)) => {
+                        delta += hash.set_expiry(field, None);
+                        UPDATED
+                    }
+                })
+                .collect(),
+            _ => vec![NO_SUCH_FIELD; fields.len()],
+        };
+        Ok((statuses, delta))
+    })
+}
+
+// Returns the values of the given fields, changing the expiry of those that
+// exist as asked
+pub fn hgetex(store: &RedisStore, key: &str, expiry: FieldExpiry, fields: &[String]) -> Result<Vec<Option<String>>, String> {
+    let now = store.now_ms();
+    let values = store.with_typed(key, ValueKind::Hash, |slot| {
+        if slot.is_none() {
+            return Ok((vec![None; fields.len()], 0));
+        }
+        let hash = hash_mut(slot);
+        let mut delta = 0;
+        let mut values = Vec::with_capacity(fields.len());
+        for field in fields {
+            let value = match expiry {
+                FieldExpiry::At(at) if at <= now => hash.remove(field).map(|(value, size)| {
+                    delta += size;
+                    value
+                }),
+                _ => hash.get(field).map(str::to_string),
+            };
+            match expiry {
+                FieldExpiry::At(at) if at > now => {
+                    delta += hash.set_expiry(field, Some(at));
+                }
+                FieldExpiry::Persist => {
+                    delta += hash.set_expiry(field, None);
+                }
+                _ => {}
+            }
+            values.push(value);
+        }
+        delta += drop_if_empty(slot);
+        Ok((values, delta))
+    })?;
+    if let FieldExpiry::At(at) = expiry {
+        if at > now && values.iter().any(Option::is_some) {
+            store.schedule_field_expiry(key, at);
+        }
+    }
+    Ok(values)
+}
@@ -0,0 +1,147 @@
+// INFO sections and MEMORY reports
+
+use std::sync::atomic::Ordering;
+
+use crate::resp::RespData;
+use crate::stats::{commandstats_info, latencystats_info};
+use crate::store::{RedisStore, ENTRY_OVERHEAD};
+
+pub fn bytes_to_human(bytes: u64) -> String {
+    let units = [("G", 1u64 << 30), ("M", 1 << 20), ("K", 1 << 10)];
+    for (unit, size) in units {
+        if bytes >= size {
+            return format!("{:.2}{}", bytes as f64 / size as f64, unit);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+// Overhead is the per-key bookkeeping; the dataset is everything else
+pub struct MemoryBreakdown {
+    pub total: u64,
+    pub peak: u64,
+    pub keys: u64,
+    pub overhead: u64,
+    pub dataset: u64,
+}
+
+pub fn memory_breakdown(store: &RedisStore) -> MemoryBreakdown {
+    let total = store.used_memory();
+    let keys = store.data.len() as u64;
+    let overhead = (keys * ENTRY_OVERHEAD as u64).min(total);
+    MemoryBreakdown {
+        total,
+        peak: store.peak_memory(),
+        keys,
+        overhead,
+        dataset: total - overhead,
+    }
+}
+
+pub fn percentage(part: u64, whole: u64) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 * 100.0 / whole as f64 }
+}
+
+pub fn memory_stats(store: &RedisStore) -> RespData {
+    let memory = memory_breakdown(store);
+    let field = |name: &str, value: RespData| [RespData::BulkString(name.to_string()), value];
+    let reply = [
+        field("peak.allocated", RespData::Integer(memory.peak as i64)),
+        field("total.allocated", RespData::Integer(memory.total as i64)),
+        field("startup.allocated", RespData::Integer(0)),
+        field("clients.slaves", RespData::Integer(0)),
+        field("overhead.total", RespData::Integer(memory.overhead as i64)),
+        field("keys.count", RespData::Integer(memory.keys as i64)),
+        field("keys.bytes-per-key", RespData::Integer(memory.total.checked_div(memory.keys).unwrap_or(0) as i64)),
+        field("dataset.bytes", RespData::Integer(memory.dataset as i64)),
+        field("dataset.percentage", RespData::BulkString(format!("{}", percentage(memory.dataset, memory.total)))),
+        field("peak.percentage", RespData::BulkString(format!("{}", percentage(memory.total, memory.peak)))),
+        field("db.0", RespData::Array(vec![
+            RespData::BulkString("overhead.hashtable.main".to_string()),
+            RespData::Integer(memory.overhead as i64),
+        ])),
+    ];
+    RespData::Array(reply.into_iter().flatten().collect())
+}
+
+pub fn memory_doctor(store: &RedisStore) -> String {
+    let memory = memory_breakdown(store);
+    if memory.keys == 0 {
+        return "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions. Please, leave for your mission on Earth and fill it with some data. The new Sam and I will be back to our programming as soon as I finished rebooting.".to_string();
+    }
+
+    let mut issues = Vec::new();
+    if memory.peak as f64 > memory.total as f64 * 1.5 {
+        issues.push(" * Peak memory: In the past this instance used more than 150% the memory that is currently using. The allocator is normally not able to release memory after a peak, so you can expect to see a big fragmentation ratio, however this is actually harmless and is only due to the memory peak, and if the Redis instance Resident Set Size (RSS) is currently bigger than expected, the memory will be used as soon as you fill the Redis instance with more data. If the memory peak was only occasional and you want to reclaim memory, the only option is to restart the instance.");
+    }
+    if memory.overhead > memory.dataset {
+        issues.push(" * High per-key overhead: more memory is spent on bookkeeping for each key than on the values themselves. Many small keys cost much more than fewer aggregated values; consider grouping related small values into lists.");
+    }
+    if store.config.read().maxmemory == 0 {
+        issues.push(" * Maxmemory is not set: this instance will keep growing until the operating system runs out of memory. Consider setting 'maxmemory' together with a 'maxmemory-policy' that fits your workload.");
+    }
+
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base.".to_string();
+    }
+    format!(
+        "Sam, I detected a few issues in this Redis instance memory implants:\n\n{}\n\nI'm here to keep you safe, Sam. I want to help you.\n",
+        issues.join("\n\n")
+    )
+}
+
+pub fn memory_info(store: &RedisStore) -> String {
+    let memory = memory_breakdown(store);
+    let used_memory = memory.total;
+    let config = store.config.read();
+    let mut info = String::from("# Memory\r\n");
+    info.push_str(&format!("used_memory:{}\r\n", used_memory));
+    info.push_str(&format!("used_memory_human:{}\r\n", bytes_to_human(used_memory)));
+    info.push_str(&format!("used_memory_peak:{}\r\n", memory.peak));
+    info.push_str(&format!("used_memory_peak_human:{}\r\n", bytes_to_human(memory.peak)));
+    info.push_str(&format!("used_memory_peak_perc:{:.2}%\r\n", percentage(used_memory, memory.peak)));
+    info.push_str(&format!("used_memory_overhead:{}\r\n", memory.overhead));
+    info.push_str(&format!("used_memory_dataset:{}\r\n", memory.dataset));
+    info.push_str(&format!("used_memory_dataset_perc:{:.2}%\r\n", percentage(memory.dataset, used_memory)));
+    info.push_str(&format!("maxmemory:{}\r\n", config.maxmemory));
+    info.push_str(&format!("maxmemory_human:{}\r\n", bytes_to_human(config.maxmemory)));
+    info.push_str(&format!("maxmemory_policy:{}\r\n", config.maxmemory_policy.name()));
+    info
+}
+
+pub fn clients_info(store: &RedisStore) -> String {
+    format!(
+        "# Clients\r\nconnected_clients:{}\r\nclient_recent_max_output_buffer:{}\r\nmaxclients:{}\r\n",
+        store.connected_clients.load(Ordering::Relaxed),
+        store.clients.max_output_peak(),
+        store.config.read().maxclients
+    )
+}
+
+pub fn build_info(store: &RedisStore, section: Option<&str>) -> String {
+    let all = matches!(section, Some("all") | Some("everything"));
+    let default = all || matches!(section, None | Some("default"));
+    let mut sections = Vec::new();
+    if default || section == Some("clients") {
+        sections.push(clients_info(store));
+    }
+    if default || section == Some("memory") {
+        sections.push(memory_info(store));
+    }
+    if default || section == Some("stats") {
+        sections.push(store.stats.info());
+    }
+    if default || section == Some("replication") {
+        sections.push(store.replication.info());
+    }
+    if default || section == Some("cluster") {
+        sections.push("# Cluster\r\ncluster_enabled:0\r\n".to_string());
+    }
+    if all || section == Some("commandstats") {
+        sections.push(commandstats_info(store));
+    }
+    if all || section == Some("latencystats") {
+        sections.push(latencystats_info(store));
+    }
+    sections.join("\r\n")
+}
@@ -0,0 +1,81 @@
+// LCS: the longest common subsequence of two strings, and where its
+// contiguous runs sit in each of them
+
+// One contiguous run of the subsequence, as inclusive byte ranges into
+// each string
+pub struct LcsMatch {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+    pub len: usize,
+}
+
+pub struct Lcs {
+    pub subsequence: Vec<u8>,
+    // From the end of the strings backwards, as Redis reports them
+    pub matches: Vec<LcsMatch>,
+}
+
+// Bytes of the table comparing strings of these lengths, None on overflow
+pub fn table_size(a_len: usize, b_len: usize) -> Option<usize> {
+    (a_len + 1).checked_mul(b_len + 1)?.checked_mul(std::mem::size_of::<u32>())
+}
+
+// Fills the classic dynamic programming table, then walks it back from the
+// end of both strings the way Redis does, so ties pick the same subsequence
+// and matches come out in the same order
+pub fn longest_common_subsequence(a: &[u8], b: &[u8]) -> Lcs {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut subsequence = vec![0u8; table[a.len() * width + b.len()] as usize];
+    let mut matches = Vec::new();
+    let (mut i, mut j) = (a.len(), b.len());
+    let mut idx = subsequence.len();
+    // The run being extended backwards, as (a start, a end, b start, b end)
+    let mut run: Option<(usize, usize, usize, usize)> = None;
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            subsequence[idx - 1] = a[i - 1];
+            run = match run {
+                None => Some((i - 1, i - 1, j - 1, j - 1)),
+                Some((a_start, a_end, b_start, b_end)) if a_start == i && b_start == j => {
+                    Some((a_start - 1, a_end, b_start - 1, b_end))
+                }
+                Some(run) => {
+                    emit = true;
+                    Some(run)
+                }
+            };
+            // Nothing comes before the first byte of either string
+            if run.is_some_and(|(a_start, _, b_start, _)| a_start == 0 || b_start == 0) {
+                emit = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = run.is_some();
+        }
+        if emit {
+            if let Some((a_start, a_end, b_start, b_end)) = run.take() {
+                matches.push(LcsMatch { a: (a_start, a_end), b: (b_start, b_end), len: a_end - a_start + 1 });
+            }
+        }
+    }
+    Lcs { subsequence, matches }
+}
@@ -0,0 +1,40 @@
+//! A Redis-compatible server: an in-memory keyspace speaking RESP over TCP
+//! and unix sockets, with replication, eviction and persistence.
+//!
+//! The store can be used on its own, without any networking:
+//!
+//! ```
+//! use redis::store::{RedisStore, RedisValueType, SetOptions};
+//!
+//! let store = RedisStore::new();
+//! store.set_with_options("greeting".to_string(), RedisValueType::String("hello".to_string()), SetOptions::None);
+//! assert!(store.exists("greeting"));
+//! assert_eq!(store.incr("counter"), Ok(1));
+//! ```
+
+pub mod clock;
+pub mod cluster;
+pub mod config;
+pub mod resp;
+pub mod server;
+pub mod store;
+
+mod commands;
+mod connection;
+mod errors;
+mod gzip;
+mod hashes;
+mod info;
+mod lcs;
+mod listpack;
+mod logging;
+mod migrate;
+mod notify;
+mod rdb;
+mod replication;
+mod reply;
+mod sort;
+mod stats;
+mod streams;
+mod tracking;
+mod util;
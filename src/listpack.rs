@@ -0,0 +1,309 @@
+// Compact encoding for small lists and hashes: entries stored one after the
+// other in a single buffer, each prefixed with its length, instead of as
+// separately allocated strings. Like Redis' listpacks this trades linear
+// lookups and inserts for memory, so values switch to the general
+// representation once they outgrow the configured limits.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::store::STRING_OVERHEAD;
+
+// Redis' default list-max-listpack-size: up to 8kb per listpack
+pub(crate) const DEFAULT_LIST_MAX_LISTPACK_SIZE: i64 = -2;
+// Bytes of a Redis listpack's header and terminator, counted against the
+// size limits
+const LISTPACK_HEADER: usize = 7;
+// With a count limit, a listpack is still converted past this size
+const LISTPACK_SIZE_SAFETY_LIMIT: usize = 8192;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Listpack {
+    buffer: Vec<u8>,
+    len: usize,
+}
+
+fn varint_len(mut n: usize) -> usize {
+    let mut len = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        len += 1;
+    }
+    len
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+// Bytes an entry holding `len` bytes takes
+pub(crate) fn entry_size(len: usize) -> usize {
+    varint_len(len) + len
+}
+
+// Entries are only ever made from strings
+pub(crate) fn entry_str(entry: &[u8]) -> &str {
+    std::str::from_utf8(entry).expect("listpack entries are UTF-8")
+}
+
+impl Listpack {
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // Bytes taken by the entries
+    pub(crate) fn bytes(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub(crate) fn iter(&self) -> Entries<'_> {
+        Entries { buffer: &self.buffer, offset: 0 }
+    }
+
+    pub(crate) fn push_back(&mut self, entry: &[u8]) {
+        write_varint(&mut self.buffer, entry.len());
+        self.buffer.extend_from_slice(entry);
+        self.len += 1;
+    }
+
+    pub(crate) fn push_front(&mut self, entry: &[u8]) {
+        self.splice(0, 0, &[entry]);
+    }
+
+    // Replaces the `count` entries starting at byte `offset` with `entries`
+    pub(crate) fn splice(&mut self, offset: usize, count: usize, entries: &[&[u8]]) {
+        let mut removed = Entries { buffer: &self.buffer, offset };
+        for _ in 0..count {
+            removed.next();
+        }
+        let end = removed.offset;
+        let mut encoded = Vec::with_capacity(entries.iter().map(|entry| entry_size(entry.len())).sum());
+        for entry in entries {
+            write_varint(&mut encoded, entry.len());
+            encoded.extend_from_slice(entry);
+        }
+        self.buffer.splice(offset..end, encoded);
+        self.len = self.len + entries.len() - count;
+    }
+}
+
+pub(crate) struct Entries<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl Entries<'_> {
+    // Where the next entry starts in the buffer
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.offset >= self.buffer.len() {
+            return None;
+        }
+        let mut len = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.buffer[self.offset];
+            self.offset += 1;
+            len |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        let entry = &self.buffer[self.offset..self.offset + len];
+        self.offset += len;
+        Some(entry)
+    }
+}
+
+// Whether a list of `len` elements taking `bytes` as a listpack stays one
+// under list-max-listpack-size: positive caps the number of elements,
+// negative picks a size from -1 (4kb) to -5 (64kb)
+fn list_fits(limit: i64, len: usize, bytes: usize) -> bool {
+    let bytes = bytes + LISTPACK_HEADER;
+    if limit > 0 {
+        len <= limit as usize && bytes <= LISTPACK_SIZE_SAFETY_LIMIT
+    } else {
+        bytes <= 4096usize << (-limit - 1).clamp(0, 4)
+    }
+}
+
+/// The elements of a list, stored compactly while the list is small.
+// Shared copy-on-write with the replies that stream it, so a streamed reply
+// sees the list as it was when the command ran
+#[derive(Debug)]
+pub struct ListValue(Arc<ListEncoding>);
+
+#[derive(Debug, Clone)]
+enum ListEncoding {
+    Listpack(Listpack),
+    Deque(VecDeque<String>),
+}
+
+// A copy of its own, unlike `share`
+impl Clone for ListValue {
+    fn clone(&self) -> Self {
+        ListValue(Arc::new((*self.0).clone()))
+    }
+}
+
+impl ListValue {
+    pub fn new() -> Self {
+        ListValue(Arc::new(ListEncoding::Listpack(Listpack::default())))
+    }
+
+    // The list as it is now, without copying it. The next change to either
+    // side copies the list first.
+    pub(crate) fn share(&self) -> Self {
+        ListValue(Arc::clone(&self.0))
+    }
+
+    pub fn len(&self) -> usize {
+        match &*self.0 {
+            ListEncoding::Listpack(listpack) => listpack.len(),
+            ListEncoding::Deque(list) => list.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The elements from head to tail.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &str> + Send + '_> {
+        match &*self.0 {
+            ListEncoding::Listpack(listpack) => Box::new(listpack.iter().map(entry_str)),
+            ListEncoding::Deque(list) => Box::new(list.iter().map(String::as_str)),
+        }
+    }
+
+    /// The elements from index `start` up to but excluding `end`, both
+    /// within the list.
+    pub fn range(&self, start: usize, end: usize) -> Box<dyn Iterator<Item = &str> + Send + '_> {
+        match &*self.0 {
+            ListEncoding::Listpack(listpack) => Box::new(listpack.iter().skip(start).take(end - start).map(entry_str)),
+            ListEncoding::Deque(list) => Box::new(list.range(start..end).map(String::as_str)),
+        }
+    }
+
+    // Name of the encoding, as reported by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match &*self.0 {
+            ListEncoding::Listpack(_) => "listpack",
+            ListEncoding::Deque(_) => "quicklist",
+        }
+    }
+
+    // Approximate heap size of the elements, measuring at most `samples` of
+    // them (0 = all) and extrapolating
+    pub(crate) fn heap_size(&self, samples: usize) -> usize {
+        match &*self.0 {
+            ListEncoding::Listpack(listpack) => listpack.bytes(),
+            ListEncoding::Deque(list) => {
+                let element_size = |item: &String| STRING_OVERHEAD + item.capacity();
+                if samples == 0 || samples >= list.len() {
+                    list.iter().map(element_size).sum()
+                } else {
+                    list.iter().take(samples).map(element_size).sum::<usize>() * list.len() / samples
+                }
+            }
+        }
+    }
+
+    // Adds `value` at the head or the tail, converting the list if it
+    // outgrows `limit` (list-max-listpack-size). Returns the change in
+    // heap size.
+    pub(crate) fn push(&mut self, value: String, front: bool, limit: i64) -> isize {
+        let mut delta = 0;
+        if let ListEncoding::Listpack(listpack) = Arc::make_mut(&mut self.0) {
+            if list_fits(limit, listpack.len() + 1, listpack.bytes() + entry_size(value.len())) {
+                if front {
+                    listpack.push_front(value.as_bytes());
+                } else {
+                    listpack.push_back(value.as_bytes());
+                }
+                return entry_size(value.len()) as isize;
+            }
+            delta = self.convert(false);
+        }
+        if let ListEncoding::Deque(list) = Arc::make_mut(&mut self.0) {
+            delta += (STRING_OVERHEAD + value.capacity()) as isize;
+            if front {
+                list.push_front(value);
+            } else {
+                list.push_back(value);
+            }
+        }
+        delta
+    }
+
+    // Picks the encoding Redis would for the list as it is now, for lists
+    // created whole rather than grown
+    pub(crate) fn fit(&mut self, limit: i64) {
+        let compact = match &*self.0 {
+            ListEncoding::Listpack(listpack) => list_fits(limit, listpack.len(), listpack.bytes()),
+            ListEncoding::Deque(list) => list_fits(limit, list.len(), list.iter().map(|item| entry_size(item.len())).sum()),
+        };
+        self.convert(compact);
+    }
+
+    // Switches to the compact encoding or away from it, returning the change
+    // in heap size
+    fn convert(&mut self, compact: bool) -> isize {
+        let before = self.heap_size(0) as isize;
+        self.0 = Arc::new(match (&*self.0, compact) {
+            (ListEncoding::Deque(list), true) => {
+                let mut listpack = Listpack::default();
+                for item in list {
+                    listpack.push_back(item.as_bytes());
+                }
+                ListEncoding::Listpack(listpack)
+            }
+            (ListEncoding::Listpack(listpack), false) => {
+                ListEncoding::Deque(listpack.iter().map(|entry| entry_str(entry).to_string()).collect())
+            }
+            _ => return 0,
+        });
+        self.heap_size(0) as isize - before
+    }
+}
+
+impl Default for ListValue {
+    fn default() -> Self {
+        ListValue::new()
+    }
+}
+
+impl FromIterator<String> for ListValue {
+    fn from_iter<I: IntoIterator<Item = String>>(items: I) -> Self {
+        let mut list = ListValue(Arc::new(ListEncoding::Deque(items.into_iter().collect())));
+        list.fit(DEFAULT_LIST_MAX_LISTPACK_SIZE);
+        list
+    }
+}
+
+// Snapshots store lists as plain arrays, whatever their encoding
+impl Serialize for ListValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for ListValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<String>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
@@ -0,0 +1,145 @@
+// Redis-style logging: one timestamped line per message, filtered by the
+// loglevel setting and written to stdout or the logfile, e.g.
+//
+//   4242:M 15 Oct 2026 09:30:12.345 * Ready to accept connections
+//
+// This is not built on the tracing crate, which can't be added to this
+// build, so there are no spans or structured fields: lines about a client
+// spell out its id and address in the message instead.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+}
+
+impl Level {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "debug" => Some(Level::Debug),
+            "verbose" => Some(Level::Verbose),
+            "notice" => Some(Level::Notice),
+            "warning" => Some(Level::Warning),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Verbose => "verbose",
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+        }
+    }
+
+    // The marker Redis prints after the timestamp
+    fn symbol(&self) -> char {
+        match self {
+            Level::Debug => '.',
+            Level::Verbose => '-',
+            Level::Notice => '*',
+            Level::Warning => '#',
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Notice as u8);
+// None logs to stdout
+static LOGFILE: Mutex<Option<File>> = parking_lot::const_mutex(None);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+// Sends output to `path`, appending, or to stdout when it is empty
+pub fn set_logfile(path: &str) -> std::io::Result<()> {
+    let file = if path.is_empty() {
+        None
+    } else {
+        Some(OpenOptions::new().create(true).append(true).open(path)?)
+    };
+    *LOGFILE.lock() = file;
+    Ok(())
+}
+
+pub fn log(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let line = format!("{}:M {} {} {}\n", std::process::id(), timestamp(), level.symbol(), args);
+    // Logging must never take the server down, so write errors are dropped
+    match &mut *LOGFILE.lock() {
+        Some(file) => {
+            let _ = file.write_all(line.as_bytes());
+        }
+        None => {
+            let _ = std::io::stdout().lock().write_all(line.as_bytes());
+        }
+    }
+}
+
+// UTC time as "15 Oct 2026 09:30:12.345"
+fn timestamp() -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{:02} {} {} {:02}:{:02}:{:02}.{:03}",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+// Converts days since the Unix epoch to a (year, month, day) date, using
+// Howard Hinnant's days_from_civil inverse
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Debug, format_args!($($arg)*)) };
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Verbose, format_args!($($arg)*)) };
+}
+
+macro_rules! notice {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Notice, format_args!($($arg)*)) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::logging::log($crate::logging::Level::Warning, format_args!($($arg)*)) };
+}
+
+pub(crate) use {debug, notice, verbose, warning};
@@ -0,0 +1,122 @@
+// MIGRATE: moving keys to another server over a RESP connection
+
+use crate::rdb::create_dump_payload;
+use crate::replication::RespClient;
+use crate::resp::RespData;
+use crate::store::{RedisStore, RedisValue};
+use crate::tracking::invalidate_keys;
+
+pub struct MigrateOptions {
+    pub db: u64,
+    pub timeout: std::time::Duration,
+    pub copy: bool,
+    pub replace: bool,
+}
+
+// A key the target acknowledged, with the payload and expiry it was sent with
+pub struct SentKey {
+    key: String,
+    payload: Vec<u8>,
+    expiry: Option<u64>,
+}
+
+// Sends the keys to the target and returns its verdict with the keys it
+// acknowledged. Nothing is locked meanwhile, so writes go on while the
+// target answers; the keys are removed afterwards by remove_migrated.
+pub async fn migrate_keys(store: &RedisStore, addr: &str, keys: &[String], options: &MigrateOptions) -> (RespData, Vec<SentKey>) {
+    let now = store.now_ms();
+    let mut commands = Vec::new();
+    let mut migrating = Vec::new();
+
+    if options.db != 0 {
+        commands.push(vec![
+            RespData::BulkString("SELECT".to_string()),
+            RespData::BulkString(options.db.to_string()),
+        ]);
+    }
+    // Read without touching the LRU clock or keyspace stats, like DUMP
+    for key in keys {
+        if let Some((payload, expiry)) = store.inspect(key, |value| (create_dump_payload(&value.data), value.expiry)) {
+            let ttl = expiry.map(|expiry| expiry.saturating_sub(now).max(1)).unwrap_or(0);
+            let mut restore = vec![
+                RespData::BulkString("RESTORE".to_string()),
+                RespData::BulkString(key.clone()),
+                RespData::BulkString(ttl.to_string()),
+                RespData::BulkBytes(payload.clone()),
+            ];
+            if options.replace {
+                restore.push(RespData::BulkString("REPLACE".to_string()));
+            }
+            commands.push(restore);
+            migrating.push((key.clone(), payload, expiry));
+        }
+    }
+
+    if migrating.is_empty() {
+        return (RespData::SimpleString("NOKEY".to_string()), Vec::new());
+    }
+
+    let mut client = match tokio::time::timeout(options.timeout, RespClient::connect(addr)).await {
+        Ok(Ok(client)) => client,
+        _ => return (RespData::Error("IOERR error or timeout connecting to the client".to_string()), Vec::new()),
+    };
+
+    if !matches!(tokio::time::timeout(options.timeout, client.send(&commands)).await, Ok(Ok(()))) {
+        return (RespData::Error("IOERR error or timeout writing to target instance".to_string()), Vec::new());
+    }
+
+    let mut error = None;
+    if options.db != 0 {
+        match tokio::time::timeout(options.timeout, client.read_reply()).await {
+            Ok(Ok(RespData::Error(e))) => {
+                return (RespData::Error(format!("ERR Target instance replied with error: {}", e)), Vec::new());
+            }
+            Ok(Ok(_)) => {}
+            _ => return (RespData::Error("IOERR error or timeout reading to target instance".to_string()), Vec::new()),
+        }
+    }
+
+    let mut acknowledged = Vec::new();
+    for (key, payload, expiry) in migrating {
+        match tokio::time::timeout(options.timeout, client.read_reply()).await {
+            Ok(Ok(RespData::Error(e))) => {
+                error.get_or_insert_with(|| format!("ERR Target instance replied with error: {}", e));
+            }
+            Ok(Ok(_)) => {
+                if !options.copy {
+                    acknowledged.push(SentKey { key, payload, expiry });
+                }
+            }
+            _ => {
+                error = Some("IOERR error or timeout reading to target instance".to_string());
+                break;
+            }
+        }
+    }
+
+    let reply = match error {
+        Some(e) => RespData::Error(e),
+        None => RespData::SimpleString("OK".to_string()),
+    };
+    (reply, acknowledged)
+}
+
+// Removes the keys the target acknowledged and propagates their deletion.
+// Only keys that still hold what was sent go: a write that landed while
+// the target was answering must not be lost. Callers hold the write lock.
+pub fn remove_migrated(store: &RedisStore, acknowledged: Vec<SentKey>) {
+    let migrated: Vec<String> = acknowledged
+        .into_iter()
+        .filter(|sent| {
+            let unchanged = |value: &RedisValue| value.expiry == sent.expiry && create_dump_payload(&value.data) == sent.payload;
+            store.remove_entry_if(&sent.key, unchanged)
+        })
+        .map(|sent| sent.key)
+        .collect();
+    if !migrated.is_empty() {
+        invalidate_keys(store, &migrated.iter().map(String::as_str).collect::<Vec<_>>(), None);
+        let mut del = vec![RespData::BulkString("DEL".to_string())];
+        del.extend(migrated.into_iter().map(RespData::BulkString));
+        store.replication.propagate(&RespData::Array(del));
+    }
+}
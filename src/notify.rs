@@ -0,0 +1,80 @@
+// Keyspace notifications: events about keys published over pub/sub, on
+// __keyspace@0__:<key> with the event as message and on
+// __keyevent@0__:<event> with the key as message. Which events are sent is
+// chosen by notify-keyspace-events, in the letters redis.conf uses.
+
+use crate::resp::RespData;
+use crate::store::RedisStore;
+
+pub(crate) const KEYSPACE: u32 = 1 << 0;
+pub(crate) const KEYEVENT: u32 = 1 << 1;
+pub(crate) const GENERIC: u32 = 1 << 2;
+pub(crate) const STRING: u32 = 1 << 3;
+pub(crate) const LIST: u32 = 1 << 4;
+pub(crate) const SET: u32 = 1 << 5;
+pub(crate) const HASH: u32 = 1 << 6;
+pub(crate) const ZSET: u32 = 1 << 7;
+pub(crate) const EXPIRED: u32 = 1 << 8;
+pub(crate) const EVICTED: u32 = 1 << 9;
+pub(crate) const STREAM: u32 = 1 << 10;
+pub(crate) const KEY_MISS: u32 = 1 << 11;
+pub(crate) const MODULE: u32 = 1 << 12;
+pub(crate) const NEW: u32 = 1 << 13;
+// What "A" stands for: every class but key misses, new keys and modules
+pub(crate) const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+// The letter of each class, in the order CONFIG GET lists them
+const CLASSES: [(char, u32); 13] = [
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('d', MODULE),
+    ('K', KEYSPACE),
+    ('E', KEYEVENT),
+    ('m', KEY_MISS),
+];
+
+pub(crate) fn parse_flags(value: &str) -> Option<u32> {
+    value.chars().try_fold(0, |flags, c| match c {
+        'A' => Some(flags | ALL),
+        'n' => Some(flags | NEW),
+        _ => CLASSES.iter().find(|(letter, _)| *letter == c).map(|(_, class)| flags | class),
+    })
+}
+
+pub(crate) fn flags_to_string(flags: u32) -> String {
+    let mut letters = String::new();
+    if flags & ALL == ALL {
+        letters.push('A');
+    }
+    for (letter, class) in CLASSES {
+        if flags & class != 0 && (flags & ALL != ALL || class & ALL == 0) {
+            letters.push(letter);
+        }
+    }
+    if flags & NEW != 0 {
+        letters.push('n');
+    }
+    letters
+}
+
+// Publishes `event` for `key` if its class is enabled, on the channels
+// notify-keyspace-events asks for
+pub(crate) fn notify(store: &RedisStore, class: u32, event: &str, key: &str) {
+    let flags = store.config.read().notify_keyspace_events;
+    if flags & class == 0 {
+        return;
+    }
+    if flags & KEYSPACE != 0 {
+        store.clients.publish(&format!("__keyspace@0__:{}", key), &RespData::BulkString(event.to_string()));
+    }
+    if flags & KEYEVENT != 0 {
+        store.clients.publish(&format!("__keyevent@0__:{}", event), &RespData::BulkString(key.to_string()));
+    }
+}
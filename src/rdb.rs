@@ -0,0 +1,435 @@
+// RDB encoding, used by DUMP/RESTORE and full resynchronization
+
+use crate::store::{HashLimits, HashValue, RedisValue, RedisValueType};
+use crate::streams::{Consumer, ConsumerGroup, PendingEntry, StreamId, StreamValue};
+
+// DUMP payloads use the RDB object encoding followed by a 2-byte RDB version
+// and an 8-byte CRC64 (Jones polynomial), both little-endian.
+pub const RDB_TYPE_STRING: u8 = 0;
+pub const RDB_TYPE_LIST: u8 = 1;
+pub const RDB_TYPE_HASH: u8 = 4;
+// Hash with per-field TTLs, as written by Redis 7.4: the earliest field
+// expiry, then each field's expiry relative to it (0 for none) before its
+// name and value
+pub const RDB_TYPE_HASH_METADATA: u8 = 24;
+// Redis writes streams as listpacks in a radix tree. This server writes them
+// entry by entry, then the counters and groups, under a type byte of its own,
+// so stream DUMP payloads and snapshots only load here.
+pub const RDB_TYPE_STREAM: u8 = 0xf0;
+pub const RDB_ENC_INT8: u8 = 0;
+pub const RDB_ENC_INT16: u8 = 1;
+pub const RDB_ENC_INT32: u8 = 2;
+pub const RDB_VERSION: u16 = 12;
+pub const RDB_OPCODE_AUX: u8 = 0xfa;
+pub const RDB_OPCODE_RESIZEDB: u8 = 0xfb;
+pub const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+pub const RDB_OPCODE_EXPIRETIME: u8 = 0xfd;
+pub const RDB_OPCODE_SELECTDB: u8 = 0xfe;
+pub const RDB_OPCODE_EOF: u8 = 0xff;
+
+pub const CRC64_TABLE: [u64; 256] = {
+    // Reflected form of the Jones polynomial 0xad93d23594c935a9
+    pub const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc64(data: &[u8]) -> u64 {
+    data.iter().fold(0u64, |crc, &byte| {
+        CRC64_TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+pub fn rdb_write_len(out: &mut Vec<u8>, len: usize) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(0x40 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as usize {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+}
+
+pub fn rdb_write_string(out: &mut Vec<u8>, s: &[u8]) {
+    rdb_write_len(out, s.len());
+    out.extend_from_slice(s);
+}
+
+pub fn rdb_write_integer(out: &mut Vec<u8>, n: i64) {
+    if let Ok(n) = i8::try_from(n) {
+        out.push(0xc0 | RDB_ENC_INT8);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else if let Ok(n) = i16::try_from(n) {
+        out.push(0xc0 | RDB_ENC_INT16);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(0xc0 | RDB_ENC_INT32);
+        out.extend_from_slice(&n.to_le_bytes());
+    } else {
+        rdb_write_string(out, n.to_string().as_bytes());
+    }
+}
+
+pub fn rdb_value_type(value: &RedisValueType) -> u8 {
+    match value {
+        RedisValueType::String(_) | RedisValueType::Integer(_) => RDB_TYPE_STRING,
+        RedisValueType::List(_) => RDB_TYPE_LIST,
+        RedisValueType::Hash(hash) if hash.iter().any(|(_, _, expiry)| expiry.is_some()) => RDB_TYPE_HASH_METADATA,
+        RedisValueType::Hash(_) => RDB_TYPE_HASH,
+        RedisValueType::Stream(_) => RDB_TYPE_STREAM,
+    }
+}
+
+pub fn rdb_write_object(out: &mut Vec<u8>, value: &RedisValueType) {
+    match value {
+        RedisValueType::String(s) => rdb_write_string(out, s.as_bytes()),
+        RedisValueType::Integer(n) => rdb_write_integer(out, *n),
+        RedisValueType::List(list) => {
+            rdb_write_len(out, list.len());
+            for item in list.iter() {
+                rdb_write_string(out, item.as_bytes());
+            }
+        }
+        RedisValueType::Hash(hash) => {
+            let min_expiry = hash.iter().filter_map(|(_, _, expiry)| expiry).min();
+            if let Some(min_expiry) = min_expiry {
+                out.extend_from_slice(&min_expiry.to_le_bytes());
+            }
+            rdb_write_len(out, hash.len());
+            for (name, value, expiry) in hash.iter() {
+                if let Some(min_expiry) = min_expiry {
+                    rdb_write_len(out, expiry.map_or(0, |expiry| (expiry - min_expiry + 1) as usize));
+                }
+                rdb_write_string(out, name.as_bytes());
+                rdb_write_string(out, value.as_bytes());
+            }
+        }
+        RedisValueType::Stream(stream) => rdb_write_stream(out, stream),
+    }
+}
+
+fn rdb_write_id(out: &mut Vec<u8>, id: StreamId) {
+    rdb_write_len(out, id.ms as usize);
+    rdb_write_len(out, id.seq as usize);
+}
+
+// Counters that may be missing are written plus one, with 0 for none
+fn rdb_write_optional(out: &mut Vec<u8>, n: Option<u64>) {
+    rdb_write_len(out, n.map_or(0, |n| n as usize + 1));
+}
+
+fn rdb_write_stream(out: &mut Vec<u8>, stream: &StreamValue) {
+    rdb_write_len(out, stream.len());
+    for (id, fields) in stream.range(StreamId::MIN, StreamId::MAX) {
+        rdb_write_id(out, *id);
+        rdb_write_len(out, fields.len());
+        for (name, value) in fields {
+            rdb_write_string(out, name.as_bytes());
+            rdb_write_string(out, value.as_bytes());
+        }
+    }
+    rdb_write_id(out, stream.last_id);
+    rdb_write_id(out, stream.first_id);
+    rdb_write_id(out, stream.max_deleted_id);
+    rdb_write_len(out, stream.entries_added as usize);
+    rdb_write_len(out, stream.groups.len());
+    for (name, group) in &stream.groups {
+        rdb_write_string(out, name.as_bytes());
+        rdb_write_id(out, group.last_id);
+        rdb_write_optional(out, group.entries_read);
+        rdb_write_len(out, group.consumers.len());
+        for (name, consumer) in &group.consumers {
+            rdb_write_string(out, name.as_bytes());
+            rdb_write_len(out, consumer.seen_time as usize);
+            rdb_write_optional(out, consumer.active_time);
+        }
+        rdb_write_len(out, group.pending.len());
+        for (id, entry) in &group.pending {
+            rdb_write_id(out, *id);
+            rdb_write_string(out, entry.consumer.as_bytes());
+            rdb_write_len(out, entry.delivery_time as usize);
+            rdb_write_len(out, entry.delivery_count as usize);
+        }
+    }
+}
+
+pub fn create_dump_payload(value: &RedisValueType) -> Vec<u8> {
+    let mut payload = vec![rdb_value_type(value)];
+    rdb_write_object(&mut payload, value);
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(&payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    payload
+}
+
+// Either a plain length or one of the special integer encodings
+pub enum RdbLength {
+    Len(usize),
+    Encoded(u8),
+}
+
+pub struct RdbReader<'a> {
+    pub buf: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.buf.len());
+        match end {
+            Some(end) => {
+                let bytes = &self.buf[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            }
+            None => Err("ERR Bad data format".to_string()),
+        }
+    }
+
+    pub fn read_byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_len(&mut self) -> Result<RdbLength, String> {
+        let first = self.read_byte()?;
+        match first >> 6 {
+            0 => Ok(RdbLength::Len((first & 0x3f) as usize)),
+            1 => {
+                let second = self.read_byte()?;
+                Ok(RdbLength::Len((((first & 0x3f) as usize) << 8) | second as usize))
+            }
+            2 if first == 0x80 => {
+                let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+                Ok(RdbLength::Len(u32::from_be_bytes(bytes) as usize))
+            }
+            2 if first == 0x81 => {
+                let bytes: [u8; 8] = self.take(8)?.try_into().unwrap();
+                usize::try_from(u64::from_be_bytes(bytes))
+                    .map(RdbLength::Len)
+                    .map_err(|_| "ERR Bad data format".to_string())
+            }
+            3 => Ok(RdbLength::Encoded(first & 0x3f)),
+            _ => Err("ERR Bad data format".to_string()),
+        }
+    }
+
+    pub fn read_plain_len(&mut self) -> Result<usize, String> {
+        match self.read_len()? {
+            RdbLength::Len(len) => Ok(len),
+            RdbLength::Encoded(_) => Err("ERR Bad data format".to_string()),
+        }
+    }
+
+    pub fn read_string_object(&mut self) -> Result<RedisValueType, String> {
+        match self.read_len()? {
+            RdbLength::Len(len) => {
+                let bytes = self.take(len)?;
+                Ok(RedisValueType::String(String::from_utf8_lossy(bytes).to_string()))
+            }
+            RdbLength::Encoded(RDB_ENC_INT8) => {
+                Ok(RedisValueType::Integer(i8::from_le_bytes([self.read_byte()?]) as i64))
+            }
+            RdbLength::Encoded(RDB_ENC_INT16) => {
+                let bytes: [u8; 2] = self.take(2)?.try_into().unwrap();
+                Ok(RedisValueType::Integer(i16::from_le_bytes(bytes) as i64))
+            }
+            RdbLength::Encoded(RDB_ENC_INT32) => {
+                let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+                Ok(RedisValueType::Integer(i32::from_le_bytes(bytes) as i64))
+            }
+            // LZF-compressed strings are not supported
+            RdbLength::Encoded(_) => Err("ERR Bad data format".to_string()),
+        }
+    }
+
+    pub fn read_object(&mut self, value_type: u8) -> Result<RedisValueType, String> {
+        match value_type {
+            RDB_TYPE_STRING => self.read_string_object(),
+            RDB_TYPE_LIST => {
+                let len = self.read_plain_len()?;
+                let mut list = Vec::new();
+                for _ in 0..len {
+                    list.push(self.read_string()?);
+                }
+                Ok(RedisValueType::List(list.into_iter().collect()))
+            }
+            RDB_TYPE_HASH | RDB_TYPE_HASH_METADATA => {
+                let min_expiry = if value_type == RDB_TYPE_HASH_METADATA {
+                    Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+                } else {
+                    None
+                };
+                let len = self.read_plain_len()?;
+                let mut hash = HashValue::default();
+                for _ in 0..len {
+                    let expiry = match min_expiry {
+                        Some(min_expiry) => match self.read_plain_len()? {
+                            0 => None,
+                            ttl => Some(min_expiry.checked_add(ttl as u64 - 1).ok_or("ERR Bad data format")?),
+                        },
+                        None => None,
+                    };
+                    let name = self.read_string()?;
+                    let value = self.read_string()?;
+                    hash.insert(name.clone(), value, HashLimits::default());
+                    hash.set_expiry(&name, expiry);
+                }
+                Ok(RedisValueType::Hash(hash))
+            }
+            RDB_TYPE_STREAM => Ok(RedisValueType::Stream(Box::new(self.read_stream()?))),
+            _ => Err("ERR Bad data format".to_string()),
+        }
+    }
+
+    fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(self.read_plain_len()? as u64)
+    }
+
+    fn read_id(&mut self) -> Result<StreamId, String> {
+        Ok(StreamId::new(self.read_u64()?, self.read_u64()?))
+    }
+
+    fn read_optional(&mut self) -> Result<Option<u64>, String> {
+        Ok(self.read_u64()?.checked_sub(1))
+    }
+
+    fn read_stream(&mut self) -> Result<StreamValue, String> {
+        let mut stream = StreamValue::default();
+        for _ in 0..self.read_plain_len()? {
+            let id = self.read_id()?;
+            let mut fields = Vec::new();
+            for _ in 0..self.read_plain_len()? {
+                fields.push((self.read_string()?, self.read_string()?));
+            }
+            if id <= stream.last_id && !stream.is_empty() {
+                return Err("ERR Bad data format".to_string());
+            }
+            stream.add(id, fields);
+        }
+        stream.last_id = self.read_id()?;
+        stream.first_id = self.read_id()?;
+        stream.max_deleted_id = self.read_id()?;
+        stream.entries_added = self.read_u64()?;
+        for _ in 0..self.read_plain_len()? {
+            let name = self.read_string()?;
+            let mut group = ConsumerGroup { last_id: self.read_id()?, entries_read: self.read_optional()?, ..ConsumerGroup::default() };
+            for _ in 0..self.read_plain_len()? {
+                let name = self.read_string()?;
+                let consumer = Consumer { seen_time: self.read_u64()?, active_time: self.read_optional()?, ..Consumer::default() };
+                group.consumers.insert(name, consumer);
+            }
+            for _ in 0..self.read_plain_len()? {
+                let id = self.read_id()?;
+                let entry = PendingEntry { consumer: self.read_string()?, delivery_time: self.read_u64()?, delivery_count: self.read_u64()? };
+                let owner = group.consumers.get_mut(&entry.consumer).ok_or("ERR Bad data format")?;
+                owner.pending.insert(id);
+                group.pending.insert(id, entry);
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+
+    pub fn read_string(&mut self) -> Result<String, String> {
+        match self.read_string_object()? {
+            RedisValueType::String(s) => Ok(s),
+            RedisValueType::Integer(n) => Ok(n.to_string()),
+            _ => Err("ERR Bad data format".to_string()),
+        }
+    }
+}
+
+pub fn parse_dump_payload(payload: &[u8]) -> Result<RedisValueType, String> {
+    if payload.len() < 10 {
+        return Err("ERR DUMP payload version or checksum are wrong".to_string());
+    }
+
+    let (body, footer) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    let crc = u64::from_le_bytes(footer.try_into().unwrap());
+    if version > RDB_VERSION || crc64(body) != crc {
+        return Err("ERR DUMP payload version or checksum are wrong".to_string());
+    }
+
+    let mut reader = RdbReader { buf: &body[..body.len() - 2], pos: 0 };
+    let value_type = reader.read_byte()?;
+    let value = reader.read_object(value_type)?;
+
+    if reader.pos != reader.buf.len() {
+        return Err("ERR Bad data format".to_string());
+    }
+    Ok(value)
+}
+
+// Parses a complete RDB file as sent by a master during full resync
+pub fn parse_rdb(rdb: &[u8]) -> Result<Vec<(String, RedisValue)>, String> {
+    if rdb.len() < 9 || &rdb[..5] != b"REDIS" {
+        return Err("wrong signature trying to load DB from file".to_string());
+    }
+    let version = std::str::from_utf8(&rdb[5..9])
+        .ok()
+        .and_then(|v| v.parse::<u16>().ok())
+        .ok_or_else(|| "invalid RDB version".to_string())?;
+    if version > RDB_VERSION {
+        return Err(format!("can't handle RDB format version {}", version));
+    }
+
+    let mut reader = RdbReader { buf: rdb, pos: 9 };
+    let mut entries = Vec::new();
+    let mut expiry = None;
+    loop {
+        match reader.read_byte()? {
+            RDB_OPCODE_EOF => break,
+            RDB_OPCODE_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            RDB_OPCODE_RESIZEDB => {
+                reader.read_len()?;
+                reader.read_len()?;
+            }
+            RDB_OPCODE_SELECTDB => {
+                reader.read_len()?;
+            }
+            RDB_OPCODE_EXPIRETIME_MS => {
+                let bytes: [u8; 8] = reader.take(8)?.try_into().unwrap();
+                expiry = Some(u64::from_le_bytes(bytes));
+            }
+            RDB_OPCODE_EXPIRETIME => {
+                let bytes: [u8; 4] = reader.take(4)?.try_into().unwrap();
+                expiry = Some(u32::from_le_bytes(bytes) as u64 * 1000);
+            }
+            value_type => {
+                let key = reader.read_string()?;
+                let data = reader.read_object(value_type)?;
+                entries.push((key, RedisValue::new(data, expiry.take())));
+            }
+        }
+    }
+
+    // Version 5+ files end with a CRC64 of everything before it; zero means disabled
+    if version >= 5 {
+        let bytes: [u8; 8] = reader.take(8)?.try_into().unwrap();
+        let crc = u64::from_le_bytes(bytes);
+        if crc != 0 && crc != crc64(&rdb[..reader.pos - 8]) {
+            return Err("wrong RDB checksum".to_string());
+        }
+    }
+    Ok(entries)
+}
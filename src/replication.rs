@@ -0,0 +1,547 @@
+// Master and replica sides of replication
+
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bytes::{Buf, BytesMut};
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::connection::ConnectionState;
+use crate::logging;
+use crate::rdb::parse_rdb;
+use crate::resp::{find_crlf, parse_resp, serialize_resp, write_resp, RespData};
+use crate::server::{command_name, execute_command};
+use crate::store::RedisStore;
+use crate::util::{current_time_ms, random_hex};
+
+pub struct ReplicaHandle {
+    pub id: u64,
+    pub ip: String,
+    pub listening_port: u16,
+    pub sender: mpsc::UnboundedSender<Arc<[u8]>>,
+    // Offset the replica last acknowledged with REPLCONF ACK
+    pub ack_offset: u64,
+    pub last_ack_ms: u64,
+}
+
+pub struct ReplicaRegistry {
+    pub master_repl_offset: u64,
+    pub replicas: Vec<ReplicaHandle>,
+    // Snapshots being built for replicas that haven't registered yet. Each
+    // is sent the writes propagated meanwhile, to re-copy what they touched.
+    pub snapshots: Vec<mpsc::UnboundedSender<RespData>>,
+}
+
+impl ReplicaRegistry {
+    fn propagate(&mut self, command: &RespData) {
+        self.snapshots.retain(|snapshot| snapshot.send(command.clone()).is_ok());
+        self.send(command);
+    }
+
+    fn send(&mut self, command: &RespData) {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        let payload: Arc<[u8]> = serialize_resp(command).into();
+        self.master_repl_offset += payload.len() as u64;
+        self.replicas.retain(|replica| replica.sender.send(Arc::clone(&payload)).is_ok());
+    }
+}
+
+// What a write command holds while it runs: exclusive while replicas are
+// attached, so they receive writes in the order the master applied them,
+// and shared otherwise, so writes only wait for transactions
+pub enum WriteGuard<'a> {
+    Shared { _guard: tokio::sync::RwLockReadGuard<'a, ()> },
+    Exclusive { _guard: tokio::sync::RwLockWriteGuard<'a, ()> },
+}
+
+// Connection to our master while running as a replica
+pub struct MasterLink {
+    pub id: u64,
+    pub host: String,
+    pub port: u16,
+    pub link_up: bool,
+    pub master_replid: String,
+    // Offset in the master's replication stream processed so far
+    pub offset: u64,
+    pub task: tokio::task::JoinHandle<()>,
+}
+
+pub struct ReplicationState {
+    pub replid: String,
+    pub next_replica_id: AtomicU64,
+    pub registry: Mutex<ReplicaRegistry>,
+    pub master: Mutex<Option<MasterLink>>,
+    pub next_link_id: AtomicU64,
+    // Signalled whenever a replica acknowledges an offset
+    pub ack_notify: tokio::sync::Notify,
+    // Held while a write command executes and is propagated; see
+    // WriteGuard. Transactions and replica registration hold it exclusively.
+    pub write_lock: tokio::sync::RwLock<()>,
+}
+
+impl ReplicationState {
+    pub fn new() -> Self {
+        ReplicationState {
+            replid: random_hex(40),
+            next_replica_id: AtomicU64::new(1),
+            registry: Mutex::new(ReplicaRegistry { master_repl_offset: 0, replicas: Vec::new(), snapshots: Vec::new() }),
+            master: Mutex::new(None),
+            next_link_id: AtomicU64::new(1),
+            ack_notify: tokio::sync::Notify::new(),
+            write_lock: tokio::sync::RwLock::new(()),
+        }
+    }
+
+    pub async fn lock_write(&self) -> WriteGuard<'_> {
+        if self.connected_replicas() == 0 {
+            let guard = self.write_lock.read().await;
+            // Replicas only register while holding the lock exclusively
+            if self.connected_replicas() == 0 {
+                return WriteGuard::Shared { _guard: guard };
+            }
+        }
+        WriteGuard::Exclusive { _guard: self.write_lock.write().await }
+    }
+
+    pub fn propagate(&self, command: &RespData) {
+        self.registry.lock().propagate(command);
+    }
+
+    // Makes a change outside the write lock, such as a lazy expiration, and
+    // propagates the command `f` returns for it before any other command
+    // can be, so a write that follows the change can't overtake it
+    pub fn apply_and_propagate<R>(&self, f: impl FnOnce() -> (R, Option<RespData>)) -> R {
+        let mut registry = self.registry.lock();
+        let (result, command) = f();
+        if let Some(command) = &command {
+            registry.propagate(command);
+        }
+        result
+    }
+
+    // Asks every replica to acknowledge its offset. Not a write, so
+    // snapshots in progress don't see it.
+    pub fn request_acks(&self) {
+        self.registry.lock().send(&RespData::Array(bulk_strings(&["REPLCONF", "GETACK", "*"])));
+    }
+
+    pub fn master_repl_offset(&self) -> u64 {
+        self.registry.lock().master_repl_offset
+    }
+
+    pub fn connected_replicas(&self) -> usize {
+        self.registry.lock().replicas.len()
+    }
+
+    pub fn acknowledged_replicas(&self, offset: u64) -> usize {
+        self.registry.lock().replicas.iter().filter(|replica| replica.ack_offset >= offset).count()
+    }
+
+    pub fn record_ack(&self, id: u64, offset: u64) {
+        let mut registry = self.registry.lock();
+        if let Some(replica) = registry.replicas.iter_mut().find(|replica| replica.id == id) {
+            replica.ack_offset = replica.ack_offset.max(offset);
+            replica.last_ack_ms = current_time_ms();
+        }
+        drop(registry);
+        self.ack_notify.notify_waiters();
+    }
+
+    // Starts recording the writes propagated from now on, for a snapshot
+    // about to be copied
+    pub fn start_snapshot(&self) -> mpsc::UnboundedReceiver<RespData> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.registry.lock().snapshots.push(sender);
+        receiver
+    }
+
+    // Registers a replica once `refresh` brought its snapshot up to date
+    // with the writes recorded since `start_snapshot`, returning its id, the
+    // offset its stream starts at, and the receiving end of its command
+    // stream. Callers must hold the write lock exclusively.
+    pub fn register_replica(
+        &self,
+        mut snapshot: mpsc::UnboundedReceiver<RespData>,
+        refresh: impl FnOnce(Vec<RespData>),
+        ip: String,
+        listening_port: u16,
+    ) -> (u64, u64, mpsc::UnboundedReceiver<Arc<[u8]>>) {
+        let id = self.next_replica_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut registry = self.registry.lock();
+        let mut writes = Vec::new();
+        while let Ok(command) = snapshot.try_recv() {
+            writes.push(command);
+        }
+        drop(snapshot);
+        refresh(writes);
+        let offset = registry.master_repl_offset;
+        registry.replicas.push(ReplicaHandle {
+            id,
+            ip,
+            listening_port,
+            sender,
+            ack_offset: 0,
+            last_ack_ms: current_time_ms(),
+        });
+        (id, offset, receiver)
+    }
+
+    pub fn unregister_replica(&self, id: u64) {
+        self.registry.lock().replicas.retain(|replica| replica.id != id);
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.master.lock().is_some()
+    }
+
+    // Applies `f` to the master link if `id` is still the current one
+    pub fn update_link<R>(&self, id: u64, f: impl FnOnce(&mut MasterLink) -> R) -> Option<R> {
+        self.master.lock().as_mut().filter(|link| link.id == id).map(f)
+    }
+
+    pub fn stop_replica(&self) {
+        if let Some(link) = self.master.lock().take() {
+            link.task.abort();
+        }
+    }
+
+    pub fn info(&self) -> String {
+        let registry = self.registry.lock();
+        let mut info = String::from("# Replication\r\n");
+        match self.master.lock().as_ref() {
+            Some(link) => {
+                info.push_str("role:slave\r\n");
+                info.push_str(&format!("master_host:{}\r\n", link.host));
+                info.push_str(&format!("master_port:{}\r\n", link.port));
+                info.push_str(&format!("master_link_status:{}\r\n", if link.link_up { "up" } else { "down" }));
+                info.push_str(&format!("slave_repl_offset:{}\r\n", link.offset));
+            }
+            None => info.push_str("role:master\r\n"),
+        }
+        info.push_str(&format!("connected_slaves:{}\r\n", registry.replicas.len()));
+        let now = current_time_ms();
+        for (i, replica) in registry.replicas.iter().enumerate() {
+            info.push_str(&format!(
+                "slave{}:ip={},port={},state=online,offset={},lag={}\r\n",
+                i,
+                replica.ip,
+                replica.listening_port,
+                replica.ack_offset,
+                now.saturating_sub(replica.last_ack_ms) / 1000
+            ));
+        }
+        match self.master.lock().as_ref() {
+            Some(link) if link.link_up => {
+                info.push_str(&format!("master_replid:{}\r\n", link.master_replid));
+                info.push_str(&format!("master_repl_offset:{}\r\n", link.offset));
+            }
+            _ => {
+                info.push_str(&format!("master_replid:{}\r\n", self.replid));
+                info.push_str(&format!("master_repl_offset:{}\r\n", registry.master_repl_offset));
+            }
+        }
+        info
+    }
+}
+
+// Minimal outbound RESP client used when this server talks to another one
+pub struct RespClient {
+    pub stream: TcpStream,
+    pub buffer: BytesMut,
+}
+
+impl RespClient {
+    pub async fn connect(addr: &str) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(RespClient { stream, buffer: BytesMut::with_capacity(4096) })
+    }
+
+    pub async fn send(&mut self, commands: &[Vec<RespData>]) -> std::io::Result<()> {
+        let mut out = BytesMut::new();
+        for args in commands {
+            write_resp(&RespData::Array(args.clone()), 2, &mut out);
+        }
+        self.stream.write_all(&out).await
+    }
+
+    pub async fn fill_buffer(&mut self) -> std::io::Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed by peer"));
+        }
+        Ok(())
+    }
+
+    // Reads one frame, also returning how many bytes it occupied on the wire
+    pub async fn read_frame(&mut self) -> std::io::Result<(usize, RespData)> {
+        loop {
+            if let Some((consumed, reply)) = parse_resp(&self.buffer)? {
+                self.buffer.advance(consumed);
+                return Ok((consumed, reply));
+            }
+            self.fill_buffer().await?;
+        }
+    }
+
+    pub async fn read_reply(&mut self) -> std::io::Result<RespData> {
+        Ok(self.read_frame().await?.1)
+    }
+
+    // Reads the full-resync payload: "$<len>\r\n" followed by exactly len
+    // bytes with no trailing CRLF. Masters may send newlines as keepalives
+    // while the snapshot is being prepared.
+    pub async fn read_rdb_payload(&mut self) -> std::io::Result<Vec<u8>> {
+        let len = loop {
+            while self.buffer.first() == Some(&b'\n') {
+                self.buffer.advance(1);
+            }
+            if !self.buffer.is_empty() {
+                if self.buffer[0] != b'$' {
+                    return Err(Error::new(ErrorKind::InvalidData, "bad protocol from master"));
+                }
+                if let Some(pos) = find_crlf(&self.buffer, 1) {
+                    let len = String::from_utf8_lossy(&self.buffer[1..pos]).parse::<usize>().map_err(|_| {
+                        Error::new(ErrorKind::InvalidData, "bad bulk length from master")
+                    })?;
+                    self.buffer.advance(pos + 2);
+                    break len;
+                }
+            }
+            self.fill_buffer().await?;
+        };
+
+        while self.buffer.len() < len {
+            self.fill_buffer().await?;
+        }
+        Ok(self.buffer.split_to(len).to_vec())
+    }
+}
+
+pub fn bulk_strings(args: &[&str]) -> Vec<RespData> {
+    args.iter().map(|arg| RespData::BulkString(arg.to_string())).collect()
+}
+
+pub fn start_replication(store: &Arc<RedisStore>, host: String, port: u16) -> RespData {
+    let mut master = store.replication.master.lock();
+    if let Some(link) = master.as_ref() {
+        if link.host == host && link.port == port {
+            return RespData::SimpleString("OK Already connected to specified master".to_string());
+        }
+    }
+    if let Some(link) = master.take() {
+        link.task.abort();
+    }
+
+    let id = store.replication.next_link_id.fetch_add(1, Ordering::Relaxed);
+    let task = tokio::spawn(run_master_link(Arc::clone(store), id, host.clone(), port));
+    *master = Some(MasterLink {
+        id,
+        host,
+        port,
+        link_up: false,
+        master_replid: String::new(),
+        offset: 0,
+        task,
+    });
+    RespData::SimpleString("OK".to_string())
+}
+
+// Keeps a replica connected to its master, resyncing with backoff whenever the link drops
+pub async fn run_master_link(store: Arc<RedisStore>, id: u64, host: String, port: u16) {
+    let mut backoff = std::time::Duration::from_millis(100);
+    loop {
+        match sync_with_master(&store, id, &host, port).await {
+            Ok(()) => logging::warning!("Connection with master {}:{} lost", host, port),
+            Err(e) => logging::warning!("Error replicating from master {}:{}: {}", host, port, e),
+        }
+
+        let was_up = store.replication.update_link(id, |link| std::mem::replace(&mut link.link_up, false));
+        match was_up {
+            None => return,
+            Some(true) => backoff = std::time::Duration::from_millis(100),
+            Some(false) => {}
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(std::time::Duration::from_secs(5));
+    }
+}
+
+pub async fn sync_with_master(store: &Arc<RedisStore>, id: u64, host: &str, port: u16) -> std::io::Result<()> {
+    let mut client = RespClient::connect(&format!("{}:{}", host, port)).await?;
+
+    let listening_port = store.config.read().port.to_string();
+    let handshake = [
+        bulk_strings(&["PING"]),
+        bulk_strings(&["REPLCONF", "listening-port", &listening_port]),
+        bulk_strings(&["REPLCONF", "capa", "psync2"]),
+    ];
+    for command in handshake {
+        client.send(&[command]).await?;
+        if let RespData::Error(e) = client.read_reply().await? {
+            return Err(Error::other(format!("master rejected handshake: {}", e)));
+        }
+    }
+
+    client.send(&[bulk_strings(&["PSYNC", "?", "-1"])]).await?;
+    let (master_replid, offset) = match client.read_reply().await? {
+        RespData::SimpleString(reply) if reply.starts_with("FULLRESYNC ") => {
+            let mut parts = reply.split_whitespace().skip(1);
+            match (parts.next(), parts.next().and_then(|o| o.parse::<u64>().ok())) {
+                (Some(replid), Some(offset)) => (replid.to_string(), offset),
+                _ => return Err(Error::new(ErrorKind::InvalidData, "malformed FULLRESYNC reply")),
+            }
+        }
+        reply => return Err(Error::other(format!("unexpected reply to PSYNC: {:?}", reply))),
+    };
+
+    let rdb = client.read_rdb_payload().await?;
+    let entries = parse_rdb(&rdb).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    {
+        let _guard = store.replication.write_lock.write().await;
+        store.clear_entries();
+        for (key, value) in entries {
+            store.insert_entry(key, value);
+        }
+    }
+
+    let linked = store.replication.update_link(id, |link| {
+        link.link_up = true;
+        link.master_replid = master_replid;
+        link.offset = offset;
+    });
+    if linked.is_none() {
+        return Ok(());
+    }
+
+    // The master's commands run as a client of their own, as in Redis
+    let client_id = store.next_client_id.fetch_add(1, Ordering::Relaxed);
+    let (mut master, _) = ConnectionState::new(client_id, format!("{}:{}", host, port));
+    master.is_master = true;
+    loop {
+        let (consumed, command) = client.read_frame().await?;
+        match command_name(&command).as_deref() {
+            Some("PING") => {}
+            Some("REPLCONF") => {
+                let is_getack = matches!(&command, RespData::Array(args)
+                    if matches!(args.get(1), Some(RespData::BulkString(opt)) if opt.eq_ignore_ascii_case("GETACK")));
+                if is_getack {
+                    // The ack covers everything before the GETACK itself
+                    let offset = store.replication.update_link(id, |link| link.offset).unwrap_or(0);
+                    client.send(&[bulk_strings(&["REPLCONF", "ACK", &offset.to_string()])]).await?;
+                }
+            }
+            _ => {
+                execute_command(&command, store, &mut master).await?;
+            }
+        }
+
+        if store.replication.update_link(id, |link| link.offset += consumed as u64).is_none() {
+            return Ok(());
+        }
+    }
+}
+
+// Blocks until `numreplicas` replicas acknowledged `offset` or the timeout
+// (in milliseconds, 0 meaning forever) elapses, returning how many did
+pub async fn wait_for_replicas(store: &RedisStore, numreplicas: usize, timeout: u64, offset: u64) -> usize {
+    let replication = &store.replication;
+    if offset == 0 {
+        return replication.connected_replicas();
+    }
+
+    let acked = replication.acknowledged_replicas(offset);
+    if acked >= numreplicas {
+        return acked;
+    }
+    replication.request_acks();
+
+    let deadline = (timeout > 0).then(|| tokio::time::Instant::now() + std::time::Duration::from_millis(timeout));
+    loop {
+        let notified = replication.ack_notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let acked = replication.acknowledged_replicas(offset);
+        if acked >= numreplicas {
+            return acked;
+        }
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                    return replication.acknowledged_replicas(offset);
+                }
+            }
+            None => notified.await,
+        }
+    }
+}
+
+// Takes over a connection that sent PSYNC: sends a full snapshot, then
+// streams every propagated write until the replica disconnects
+pub async fn serve_replica<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut reader: R,
+    mut writer: W,
+    store: &RedisStore,
+    ip: String,
+    listening_port: u16,
+) -> std::io::Result<()> {
+    // Entries are copied while writers carry on. The ones they touched in
+    // the meantime are copied again once they are locked out.
+    let snapshot = store.replication.start_snapshot();
+    let mut records = store.snapshot_records();
+    let (id, offset, mut receiver) = {
+        let _guard = store.replication.write_lock.write().await;
+        let refresh = |writes: Vec<RespData>| store.refresh_snapshot_records(&mut records, &writes);
+        store.replication.register_replica(snapshot, refresh, ip, listening_port)
+    };
+    let rdb = store.rdb_snapshot(records);
+
+    let result = async {
+        writer.write_all(format!("+FULLRESYNC {} {}\r\n", store.replication.replid, offset).as_bytes()).await?;
+        writer.write_all(format!("${}\r\n", rdb.len()).as_bytes()).await?;
+        writer.write_all(&rdb).await?;
+        writer.flush().await?;
+
+        let mut buffer = BytesMut::with_capacity(4096);
+        loop {
+            tokio::select! {
+                payload = receiver.recv() => match payload {
+                    Some(payload) => {
+                        writer.write_all(&payload).await?;
+                        writer.flush().await?;
+                    }
+                    None => return Ok(()),
+                },
+                n = reader.read_buf(&mut buffer) => {
+                    if n? == 0 {
+                        return Ok(());
+                    }
+                    // Replicas only ever send REPLCONF ACK <offset> on this link
+                    while let Some((consumed, command)) = parse_resp(&buffer)? {
+                        buffer.advance(consumed);
+                        if let RespData::Array(args) = &command {
+                            if let (Some(RespData::BulkString(opt)), Some(RespData::BulkString(offset))) = (args.get(1), args.get(2)) {
+                                if opt.eq_ignore_ascii_case("ACK") {
+                                    if let Ok(offset) = offset.parse::<u64>() {
+                                        store.replication.record_ack(id, offset);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }.await;
+
+    store.replication.unregister_replica(id);
+    result
+}
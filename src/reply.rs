@@ -0,0 +1,106 @@
+// Replies too large to build whole. A handler returns a stream instead, and
+// the connection loop writes the array header followed by the elements as
+// it reads them, flushing the output as it goes, so a reply of hundreds of
+// megabytes never sits in memory at once.
+//
+// A stream reads from a snapshot taken when the command runs, so it is
+// consistent as a whole, as if built under one lock. Lists and hashes are
+// shared with their snapshots copy-on-write: a write to a list while a reply
+// streams it copies the list first, and the write and the reply go on
+// without waiting for each other. KEYS has no value to share, so it copies
+// the names of the matching keys when it runs, reading the keyspace one
+// shard at a time, and streams the copy. Replies below STREAMED_REPLY_MIN
+// elements are always built whole.
+
+use crate::resp::RespData;
+use crate::store::{HashValue, ListValue};
+
+// Replies with fewer elements than this are built whole
+pub(crate) const STREAMED_REPLY_MIN: usize = 1024;
+
+pub(crate) trait ReplyStream: Send + Sync {
+    // Number of elements
+    fn len(&self) -> usize;
+
+    fn items(&self) -> Box<dyn Iterator<Item = RespData> + Send + '_>;
+}
+
+pub(crate) enum Reply {
+    Value(RespData),
+    Stream(Box<dyn ReplyStream>),
+}
+
+impl Reply {
+    // The whole reply, for callers such as EXEC that need it as one value
+    pub(crate) fn into_value(self) -> RespData {
+        match self {
+            Reply::Value(value) => value,
+            Reply::Stream(stream) => RespData::Array(stream.items().collect()),
+        }
+    }
+}
+
+impl From<RespData> for Reply {
+    fn from(value: RespData) -> Self {
+        Reply::Value(value)
+    }
+}
+
+// The elements of a list from index `start` up to `end`
+pub(crate) struct ListRange {
+    list: ListValue,
+    start: usize,
+    end: usize,
+}
+
+impl ListRange {
+    pub(crate) fn new(list: &ListValue, start: usize, end: usize) -> Self {
+        ListRange { list: list.share(), start, end }
+    }
+}
+
+impl ReplyStream for ListRange {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    fn items(&self) -> Box<dyn Iterator<Item = RespData> + Send + '_> {
+        Box::new(self.list.range(self.start, self.end).map(|item| RespData::BulkString(item.to_string())))
+    }
+}
+
+// Each field's name followed by its value
+pub(crate) struct HashEntries(HashValue);
+
+impl HashEntries {
+    pub(crate) fn new(hash: &HashValue) -> Self {
+        HashEntries(hash.share())
+    }
+}
+
+impl ReplyStream for HashEntries {
+    fn len(&self) -> usize {
+        2 * self.0.len()
+    }
+
+    fn items(&self) -> Box<dyn Iterator<Item = RespData> + Send + '_> {
+        Box::new(
+            self.0
+                .iter()
+                .flat_map(|(name, value, _)| [RespData::BulkString(name.to_string()), RespData::BulkString(value.to_string())]),
+        )
+    }
+}
+
+// Key names, copied when the command ran
+pub(crate) struct KeyNames(pub(crate) Vec<String>);
+
+impl ReplyStream for KeyNames {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn items(&self) -> Box<dyn Iterator<Item = RespData> + Send + '_> {
+        Box::new(self.0.iter().map(|key| RespData::BulkString(key.clone())))
+    }
+}
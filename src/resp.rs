@@ -0,0 +1,457 @@
+//! RESP codec: the value type shared by the server, the replication link and
+//! MIGRATE, the streaming parsers for replies and client requests, and the
+//! serializer.
+
+use std::io::{Error, ErrorKind};
+
+use bytes::{BufMut, BytesMut};
+
+/// A RESP value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespData {
+    SimpleString(String),
+    Error(String),
+    Integer(i64),
+    BulkString(String),
+    // Bulk string whose payload is not valid UTF-8 (e.g. DUMP payloads)
+    BulkBytes(Vec<u8>),
+    Array(Vec<RespData>),
+    // Null bulk string ($-1), what most commands reply when there's nothing
+    Null,
+    // Null array (*-1), which clients tell apart from a null bulk string.
+    // Under RESP3 both are sent as the one null type, `_`.
+    NullArray,
+    // RESP3 only, so sent only to connections that switched with HELLO 3
+    Map(Vec<(RespData, RespData)>),
+    // Out-of-band RESP3 data such as invalidation messages
+    Push(Vec<RespData>),
+}
+
+/// Parses one RESP value from the front of `buffer`, returning how many bytes
+/// it took, or `None` if the frame hasn't fully arrived yet.
+///
+/// ```
+/// use redis::resp::{parse_resp, RespData};
+///
+/// let (consumed, value) = parse_resp(b"+OK\r\n:1").unwrap().unwrap();
+/// assert_eq!(consumed, 5);
+/// assert!(matches!(value, RespData::SimpleString(s) if s == "OK"));
+/// assert!(parse_resp(b"$5\r\nhel").unwrap().is_none());
+///
+/// // Null bulk strings and null arrays stay distinct; RESP3's null is a
+/// // null bulk string
+/// assert_eq!(parse_resp(b"$-1\r\n").unwrap(), Some((5, RespData::Null)));
+/// assert_eq!(parse_resp(b"*-1\r\n").unwrap(), Some((5, RespData::NullArray)));
+/// assert_eq!(parse_resp(b"_\r\n").unwrap(), Some((3, RespData::Null)));
+/// ```
+pub fn parse_resp(buffer: &[u8]) -> std::io::Result<Option<(usize, RespData)>> {
+    parse_value(buffer, 0)
+}
+
+// Parses the value starting at `pos`, returning the position just past it.
+// Nested values are parsed in place by advancing the cursor, so only the
+// payloads themselves are ever copied.
+fn parse_value(buffer: &[u8], pos: usize) -> std::io::Result<Option<(usize, RespData)>> {
+    let type_byte = match buffer.get(pos) {
+        Some(b'+' | b'-' | b':' | b'$' | b'*' | b'%' | b'>' | b'_') => buffer[pos],
+        Some(_) => return Err(Error::new(ErrorKind::InvalidData, "Invalid RESP data type")),
+        None => return Ok(None),
+    };
+    let line_end = match find_crlf(buffer, pos + 1) {
+        Some(line_end) => line_end,
+        None => return Ok(None),
+    };
+    let line = &buffer[pos + 1..line_end];
+    let next = line_end + 2;
+
+    match type_byte {
+        b'+' => Ok(Some((next, RespData::SimpleString(String::from_utf8_lossy(line).to_string())))),
+        b'-' => Ok(Some((next, RespData::Error(String::from_utf8_lossy(line).to_string())))),
+        b':' => match parse_length(line) {
+            Some(num) => Ok(Some((next, RespData::Integer(num)))),
+            None => Err(Error::new(ErrorKind::InvalidData, "Invalid integer")),
+        },
+        b'$' => parse_bulk_string(buffer, line, next),
+        b'_' if line.is_empty() => Ok(Some((next, RespData::Null))),
+        b'_' => Err(Error::new(ErrorKind::InvalidData, "Invalid null")),
+        b'%' => match parse_aggregate(buffer, line, next, 2)? {
+            Some((pos, Some(elements))) => {
+                let mut pairs = Vec::with_capacity(elements.len() / 2);
+                let mut elements = elements.into_iter();
+                while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+                    pairs.push((key, value));
+                }
+                Ok(Some((pos, RespData::Map(pairs))))
+            }
+            Some((pos, None)) => Ok(Some((pos, RespData::NullArray))),
+            None => Ok(None),
+        },
+        b'>' => Ok(parse_aggregate(buffer, line, next, 1)?.map(|(pos, elements)| {
+            (pos, elements.map_or(RespData::NullArray, RespData::Push))
+        })),
+        _ => Ok(parse_aggregate(buffer, line, next, 1)?.map(|(pos, elements)| {
+            (pos, elements.map_or(RespData::NullArray, RespData::Array))
+        })),
+    }
+}
+// Redis' fixed limits for client requests; the bulk length limit is the
+// proto-max-bulk-len config
+const MAX_MULTIBULK_LEN: i64 = i32::MAX as i64;
+const MAX_INLINE_LEN: usize = 64 * 1024;
+
+fn protocol_error(detail: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, detail.into())
+}
+
+fn parse_length(digits: &[u8]) -> Option<i64> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+fn bulk_from_bytes(bytes: Vec<u8>) -> RespData {
+    match String::from_utf8(bytes) {
+        Ok(string) => RespData::BulkString(string),
+        Err(e) => RespData::BulkBytes(e.into_bytes()),
+    }
+}
+
+// C isspace(), which is what Redis splits inline commands on
+fn is_inline_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c)
+}
+
+/// Splits an inline command line into arguments following the rules of
+/// Redis' sdssplitargs: double quotes support escapes such as `\n` and
+/// `\x41`, single quotes only `\'`, and a closing quote must end the
+/// argument. Returns `None` on unbalanced quotes.
+///
+/// ```
+/// use redis::resp::split_inline_args;
+///
+/// let args = split_inline_args(br#"SET "a key" '\x41'"#).unwrap();
+/// assert_eq!(args, vec![b"SET".to_vec(), b"a key".to_vec(), b"\\x41".to_vec()]);
+/// assert!(split_inline_args(b"GET \"open").is_none());
+/// ```
+pub fn split_inline_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut p = 0;
+    loop {
+        while p < line.len() && is_inline_space(line[p]) {
+            p += 1;
+        }
+        if p == line.len() {
+            return Some(args);
+        }
+
+        let mut current = Vec::new();
+        let mut in_double = false;
+        let mut in_single = false;
+        loop {
+            let byte = line.get(p).copied();
+            if in_double {
+                let hex = |i: usize| line.get(i).and_then(|&b| (b as char).to_digit(16));
+                match byte? {
+                    b'\\' if line.get(p + 1) == Some(&b'x') && hex(p + 2).is_some() && hex(p + 3).is_some() => {
+                        current.push((hex(p + 2)? * 16 + hex(p + 3)?) as u8);
+                        p += 3;
+                    }
+                    b'\\' if p + 1 < line.len() => {
+                        p += 1;
+                        current.push(match line[p] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                    }
+                    b'"' => {
+                        // The closing quote must be followed by a space or nothing
+                        if line.get(p + 1).is_some_and(|&b| !is_inline_space(b)) {
+                            return None;
+                        }
+                        in_double = false;
+                    }
+                    other => current.push(other),
+                }
+            } else if in_single {
+                match byte? {
+                    b'\\' if line.get(p + 1) == Some(&b'\'') => {
+                        p += 1;
+                        current.push(b'\'');
+                    }
+                    b'\'' => {
+                        if line.get(p + 1).is_some_and(|&b| !is_inline_space(b)) {
+                            return None;
+                        }
+                        in_single = false;
+                    }
+                    other => current.push(other),
+                }
+            } else {
+                match byte {
+                    None => break,
+                    Some(b) if is_inline_space(b) => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(other) => current.push(other),
+                }
+            }
+            p += 1;
+        }
+        args.push(current);
+    }
+}
+
+// Parses an inline command: a plain line of space-separated arguments, as
+// sent by telnet users and health checkers. Unbalanced quotes only reject
+// that line, so they come back as an error "request" for the caller to reply
+// with rather than as a protocol error.
+fn parse_inline_request(buffer: &[u8]) -> std::io::Result<Option<(usize, RespData)>> {
+    let newline = match buffer.iter().position(|&b| b == b'\n') {
+        Some(newline) => newline,
+        None if buffer.len() > MAX_INLINE_LEN => return Err(protocol_error("too big inline request")),
+        None => return Ok(None),
+    };
+    let line = buffer[..newline].strip_suffix(b"\r").unwrap_or(&buffer[..newline]);
+    let request = match split_inline_args(line) {
+        Some(args) => RespData::Array(args.into_iter().map(bulk_from_bytes).collect()),
+        None => RespData::Error("ERR Protocol error: unbalanced quotes in request".to_string()),
+    };
+    Ok(Some((newline + 1, request)))
+}
+
+/// Parses client requests: either multibulks of bulk strings or inline
+/// commands. Malformed input, including lengths over the limits, fails with
+/// an `InvalidData` error whose message is the detail for "-ERR Protocol
+/// error: ...". Empty requests parse as an empty array, which callers skip.
+///
+/// A multibulk that arrives over several reads is parsed as it comes in:
+/// the elements already complete are kept between calls, so each byte is
+/// looked at once however many reads the request takes.
+#[derive(Default)]
+pub struct RequestParser {
+    partial: Option<PartialRequest>,
+}
+
+struct PartialRequest {
+    elements: Vec<RespData>,
+    // Elements still to come, and where the next one starts
+    remaining: usize,
+    pos: usize,
+}
+
+impl RequestParser {
+    /// Parses the request at the front of `buffer`, returning how many
+    /// bytes it took. Until a request is returned, `buffer` must keep its
+    /// front and only grow; once one is, the caller drops those bytes.
+    pub fn parse(&mut self, buffer: &[u8], max_bulk_len: u64) -> std::io::Result<Option<(usize, RespData)>> {
+        let result = self.parse_next(buffer, max_bulk_len);
+        if !matches!(result, Ok(None)) {
+            self.partial = None;
+        }
+        result
+    }
+
+    fn parse_next(&mut self, buffer: &[u8], max_bulk_len: u64) -> std::io::Result<Option<(usize, RespData)>> {
+        let partial = match &mut self.partial {
+            Some(partial) => partial,
+            None => {
+                if buffer.is_empty() {
+                    return Ok(None);
+                }
+                if buffer[0] != b'*' {
+                    return parse_inline_request(buffer);
+                }
+                let pos = match find_crlf(buffer, 1) {
+                    Some(pos) => pos,
+                    None => return Ok(None),
+                };
+                let count = parse_length(&buffer[1..pos])
+                    .filter(|&count| (-1..=MAX_MULTIBULK_LEN).contains(&count))
+                    .ok_or_else(|| protocol_error("invalid multibulk length"))?;
+                let remaining = count.max(0) as usize;
+                self.partial.insert(PartialRequest {
+                    elements: Vec::with_capacity(remaining.min(1024)),
+                    remaining,
+                    pos: pos + 2,
+                })
+            }
+        };
+
+        while partial.remaining > 0 {
+            let current_pos = partial.pos;
+            match buffer.get(current_pos) {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(&other) => return Err(protocol_error(format!("expected '$', got '{}'", other as char))),
+            }
+            let pos = match find_crlf(buffer, current_pos + 1) {
+                Some(pos) => pos,
+                None => return Ok(None),
+            };
+            let len = parse_length(&buffer[current_pos + 1..pos])
+                .filter(|&len| len >= 0 && len as u64 <= max_bulk_len)
+                .ok_or_else(|| protocol_error("invalid bulk length"))? as usize;
+
+            let start = pos + 2;
+            if buffer.len() < start + len + 2 {
+                return Ok(None);
+            }
+            if &buffer[start + len..start + len + 2] != b"\r\n" {
+                return Err(protocol_error("expected '\\r\\n'"));
+            }
+            partial.elements.push(bulk_from_bytes(buffer[start..start + len].to_vec()));
+            partial.pos = start + len + 2;
+            partial.remaining -= 1;
+        }
+        Ok(Some((partial.pos, RespData::Array(std::mem::take(&mut partial.elements)))))
+    }
+}
+
+fn parse_bulk_string(buffer: &[u8], header: &[u8], start: usize) -> std::io::Result<Option<(usize, RespData)>> {
+    let len = match parse_length(header) {
+        Some(-1) => return Ok(Some((start, RespData::Null))),
+        Some(len) if len >= 0 => len as usize,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid bulk string length")),
+    };
+
+    let end = start + len;
+    if buffer.len() < end + 2 {
+        return Ok(None);
+    }
+    if &buffer[end..end + 2] != b"\r\n" {
+        return Err(Error::new(ErrorKind::InvalidData, "Bulk string not terminated by CRLF"));
+    }
+    Ok(Some((end + 2, bulk_from_bytes(buffer[start..end].to_vec()))))
+}
+
+// Parses the elements of an array, push or map (`per_entry` elements per
+// counted entry), or None for a null aggregate
+fn parse_aggregate(
+    buffer: &[u8],
+    header: &[u8],
+    start: usize,
+    per_entry: usize,
+) -> std::io::Result<Option<(usize, Option<Vec<RespData>>)>> {
+    let len = match parse_length(header) {
+        Some(-1) => return Ok(Some((start, None))),
+        Some(len) if len >= 0 => (len as usize).saturating_mul(per_entry),
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid array length")),
+    };
+
+    // The length is untrusted, so don't let it drive the allocation
+    let mut elements = Vec::with_capacity(len.min(1024));
+    let mut pos = start;
+    for _ in 0..len {
+        match parse_value(buffer, pos)? {
+            Some((next, element)) => {
+                elements.push(element);
+                pos = next;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((pos, Some(elements))))
+}
+
+// Position of the first CRLF at or after `start`, if it has arrived yet
+pub(crate) fn find_crlf(buffer: &[u8], start: usize) -> Option<usize> {
+    buffer.get(start..)?
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|offset| start + offset)
+}
+
+/// Encodes `data` as RESP2, the protocol every connection starts with.
+///
+/// ```
+/// use redis::resp::{serialize_resp, RespData};
+///
+/// let reply = RespData::Array(vec![RespData::Integer(1), RespData::Null, RespData::NullArray]);
+/// assert_eq!(serialize_resp(&reply), b"*3\r\n:1\r\n$-1\r\n*-1\r\n");
+/// ```
+pub fn serialize_resp(data: &RespData) -> Vec<u8> {
+    let mut buffer = BytesMut::new();
+    write_resp(data, 2, &mut buffer);
+    buffer.to_vec()
+}
+
+// Appends the encoding of `data` for a connection speaking `protocol` to
+// `out`, so one buffer can be reused across replies
+pub(crate) fn write_resp(data: &RespData, protocol: u8, out: &mut BytesMut) {
+    match data {
+        RespData::SimpleString(s) => {
+            out.put_u8(b'+');
+            out.put_slice(s.as_bytes());
+            out.put_slice(b"\r\n");
+        }
+        RespData::Error(s) => {
+            out.put_u8(b'-');
+            out.put_slice(s.as_bytes());
+            out.put_slice(b"\r\n");
+        }
+        RespData::Integer(n) => write_resp_header(out, b':', *n),
+        RespData::BulkString(s) => {
+            write_resp_header(out, b'$', s.len() as i64);
+            out.put_slice(s.as_bytes());
+            out.put_slice(b"\r\n");
+        }
+        RespData::BulkBytes(b) => {
+            write_resp_header(out, b'$', b.len() as i64);
+            out.put_slice(b);
+            out.put_slice(b"\r\n");
+        }
+        RespData::Array(arr) => {
+            write_resp_header(out, b'*', arr.len() as i64);
+            for item in arr {
+                write_resp(item, protocol, out);
+            }
+        }
+        RespData::Null | RespData::NullArray if protocol >= 3 => out.put_slice(b"_\r\n"),
+        RespData::Null => out.put_slice(b"$-1\r\n"),
+        RespData::NullArray => out.put_slice(b"*-1\r\n"),
+        RespData::Map(pairs) => {
+            write_resp_header(out, b'%', pairs.len() as i64);
+            for (key, value) in pairs {
+                write_resp(key, protocol, out);
+                write_resp(value, protocol, out);
+            }
+        }
+        RespData::Push(items) => {
+            write_resp_header(out, b'>', items.len() as i64);
+            for item in items {
+                write_resp(item, protocol, out);
+            }
+        }
+    }
+}
+
+// Appends the header of an array of `len` elements, for replies whose
+// elements are written separately
+pub(crate) fn write_array_header(len: usize, out: &mut BytesMut) {
+    write_resp_header(out, b'*', len as i64);
+}
+
+// Writes a type byte followed by a decimal number and CRLF, formatting the
+// number on the stack
+fn write_resp_header(out: &mut BytesMut, prefix: u8, n: i64) {
+    let mut digits = [0u8; 20];
+    let mut pos = digits.len();
+    let mut value = n.unsigned_abs();
+    loop {
+        pos -= 1;
+        digits[pos] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    out.reserve(digits.len() - pos + 4);
+    out.put_u8(prefix);
+    if n < 0 {
+        out.put_u8(b'-');
+    }
+    out.put_slice(&digits[pos..]);
+    out.put_slice(b"\r\n");
+}
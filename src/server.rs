@@ -0,0 +1,888 @@
+//! Listeners, connection handling and command dispatch.
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BytesMut};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+use crate::cluster::{load_node_id, NODE_ID_FILE};
+use crate::commands;
+use crate::config::{parse_bind_address, ServerConfig, DEFAULT_BIND};
+use crate::connection::{ConnectionState, Registration};
+use crate::errors;
+use crate::logging;
+use crate::replication::serve_replica;
+use crate::reply::{Reply, ReplyStream};
+use crate::resp::{write_array_header, write_resp, RequestParser, RespData};
+use crate::stats::{record_command_stats, ServerStats};
+use crate::store::{RedisStore, ACTIVE_EXPIRE_CPU_PERCENT};
+use crate::tracking::invalidate_keys;
+
+pub(crate) fn command_name(command: &RespData) -> Option<String> {
+    match command {
+        RespData::Array(array) => match array.first() {
+            Some(RespData::BulkString(name)) => Some(name.to_uppercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Counts a command refused before running, returning its error reply
+fn reject(store: &RedisStore, spec: &commands::Command, error: String) -> RespData {
+    let stats = store.command_stats.entry(spec.name.to_string()).or_default();
+    stats.rejected_calls.fetch_add(1, Ordering::Relaxed);
+    RespData::Error(error)
+}
+
+// Checks a command against the connection's state, then runs it, or queues
+// it between MULTI and EXEC. A command refused while queueing makes EXEC
+// discard the transaction.
+pub(crate) async fn execute_command(
+    command: &RespData,
+    store: &Arc<RedisStore>,
+    state: &mut ConnectionState,
+) -> std::io::Result<Reply> {
+    let args = match command {
+        RespData::Array(args) => args,
+        _ => return Ok(RespData::Error("ERR invalid command format".to_string()).into()),
+    };
+    let name = match args.first() {
+        Some(RespData::BulkString(name)) => name,
+        _ => return Ok(RespData::Error("ERR invalid command format".to_string()).into()),
+    };
+    let abort_transaction = |state: &mut ConnectionState| {
+        if let Some(transaction) = &mut state.transaction {
+            transaction.aborted = true;
+        }
+    };
+    let spec = match store.commands.lookup(name) {
+        Some(spec) => spec,
+        None => {
+            abort_transaction(state);
+            return Ok(RespData::Error(errors::unknown_command(name, &args[1..])).into());
+        }
+    };
+    let refusal = if !spec.accepts(args.len()) {
+        Some(errors::wrong_arity(spec.name))
+    } else if state.in_subscribe_mode() && !ConnectionState::allows_in_subscribe_mode(spec.name) {
+        Some(format!(
+            "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+            spec.name
+        ))
+    } else if state.transaction.is_some() && spec.is_no_multi() {
+        Some("ERR Command not allowed inside a transaction".to_string())
+    } else if spec.writes(args)
+        && !state.is_master
+        && store.replication.is_replica()
+        && store.config.read().replica_read_only
+    {
+        Some(errors::READONLY.to_string())
+    } else {
+        None
+    };
+    if let Some(error) = refusal {
+        abort_transaction(state);
+        return Ok(reject(store, spec, error).into());
+    }
+    // Keys and values are stored as text, so arguments that aren't valid
+    // UTF-8 are converted here, once, before any handler sees them
+    let converted = if spec.is_binary() { None } else { text_command(args) };
+    let command = converted.as_ref().unwrap_or(command);
+    let args = match command {
+        RespData::Array(args) => args,
+        _ => unreachable!("converted commands are arrays"),
+    };
+
+    if let Some(transaction) = &mut state.transaction {
+        if !matches!(spec.name, "multi" | "exec" | "discard") {
+            transaction.commands.push(command.clone());
+            return Ok(RespData::SimpleString("QUEUED".to_string()).into());
+        }
+    }
+    state.last_command = spec.name;
+    let response = if spec.name == "exec" {
+        exec_transaction(store, state).await.into()
+    } else {
+        let _write_guard = if spec.writes(args) && !spec.propagates_itself() {
+            Some(store.replication.lock_write().await)
+        } else {
+            None
+        };
+        run_command(spec, args, command, store, state).await
+    };
+    // CLIENT CACHING only covers the command after it
+    if !is_client_caching(spec, args) {
+        state.caching = None;
+    }
+    Ok(response)
+}
+
+// The command with its binary arguments replaced by their lossy UTF-8
+// text, or None if every argument already was text
+fn text_command(args: &[RespData]) -> Option<RespData> {
+    if !args.iter().any(|arg| matches!(arg, RespData::BulkBytes(_))) {
+        return None;
+    }
+    let args = args
+        .iter()
+        .map(|arg| match arg {
+            RespData::BulkBytes(bytes) => RespData::BulkString(String::from_utf8_lossy(bytes).into_owned()),
+            arg => arg.clone(),
+        })
+        .collect();
+    Some(RespData::Array(args))
+}
+
+fn is_client_caching(spec: &commands::Command, args: &[RespData]) -> bool {
+    spec.name == "client" && matches!(args.get(1), Some(RespData::BulkString(sub)) if sub.eq_ignore_ascii_case("caching"))
+}
+
+// Runs the commands queued since MULTI. Other writers are kept out until
+// the last one is done.
+async fn exec_transaction(store: &Arc<RedisStore>, state: &mut ConnectionState) -> RespData {
+    let transaction = match state.transaction.take() {
+        Some(transaction) => transaction,
+        None => return RespData::Error("ERR EXEC without MULTI".to_string()),
+    };
+    if transaction.aborted {
+        return RespData::Error("EXECABORT Transaction discarded because of previous errors.".to_string());
+    }
+    // Every queued command was looked up and arity-checked when queued
+    let queued: Vec<(&commands::Command, &[RespData], &RespData)> = transaction
+        .commands
+        .iter()
+        .filter_map(|command| match command {
+            RespData::Array(args) => match args.first() {
+                Some(RespData::BulkString(name)) => store.commands.lookup(name).map(|spec| (spec, args.as_slice(), command)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let _write_guard = if queued.iter().any(|(spec, args, _)| spec.writes(args)) {
+        Some(store.replication.write_lock.write().await)
+    } else {
+        None
+    };
+    let mut replies = Vec::with_capacity(queued.len());
+    state.in_exec = true;
+    for (spec, args, command) in queued {
+        replies.push(run_command(spec, args, command, store, state).await.into_value());
+    }
+    state.in_exec = false;
+    RespData::Array(replies)
+}
+
+// Runs an arity-checked command, propagating it to replicas if it is a
+// successful write. Writes must hold the replication write lock, but for
+// those that propagate themselves, which take it as they need it.
+async fn run_command(
+    spec: &commands::Command,
+    args: &[RespData],
+    command: &RespData,
+    store: &Arc<RedisStore>,
+    state: &mut ConnectionState,
+) -> Reply {
+    // Replicas apply whatever their master sends and never evict on their own
+    if spec.is_denyoom() && spec.writes(args) && !store.replication.is_replica() {
+        if let Err(e) = store.evict_if_needed() {
+            return reject(store, spec, e).into();
+        }
+    }
+
+    let start = std::time::Instant::now();
+    let response = match spec.handler {
+        commands::Handler::Sync(handler) => handler(args, store).into(),
+        commands::Handler::Async(handler) => handler(args, store).await.into(),
+        commands::Handler::Connection(handler) => handler(args, store, state).into(),
+        commands::Handler::AsyncConnection(handler) => handler(args, store, state).await.into(),
+        commands::Handler::Streamed(handler) => handler(args, store),
+    };
+    let usec = start.elapsed().as_micros() as u64;
+    let slower_than = store.config.read().slowlog_log_slower_than;
+    if slower_than >= 0 && usec >= slower_than as u64 {
+        logging::warning!("Slow command: {} took {} microseconds", spec.name, usec);
+    }
+
+    let failed = matches!(response, Reply::Value(RespData::Error(_)));
+    let propagate_as = state.propagate_as.take();
+    record_command_stats(store, spec.name, usec, failed);
+    if !failed {
+        let keys: Vec<&str> = spec
+            .keys(args)
+            .filter_map(|key| match key {
+                RespData::BulkString(key) => Some(key.as_str()),
+                _ => None,
+            })
+            .collect();
+        if spec.writes(args) {
+            if !spec.propagates_itself() {
+                store.replication.propagate(propagate_as.as_ref().unwrap_or(command));
+            }
+            state.write_offset = store.replication.master_repl_offset();
+            invalidate_keys(store, &keys, Some(state.id));
+        } else if state.tracking.as_ref().is_some_and(|tracking| tracking.tracks_reads(state.caching)) {
+            store.tracking.remember(state.id, keys.into_iter());
+        }
+    }
+    response
+}
+
+// Pipelined replies are flushed early once this much output is pending
+const OUTPUT_FLUSH_THRESHOLD: usize = 64 * 1024;
+// How often an idle connection re-reads the timeout config
+const IDLE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Reads more input, disconnecting the client (Ok(None)) once it has been
+// idle for longer than the timeout config. The config is re-read at least
+// every IDLE_CHECK_INTERVAL so CONFIG SET applies to open connections.
+// Clients with subscriptions are never idle, as in Redis.
+async fn read_with_idle_timeout<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut BytesMut,
+    store: &RedisStore,
+    state: &ConnectionState,
+) -> std::io::Result<Option<usize>> {
+    loop {
+        let timeout = if state.subscriptions() > 0 { 0 } else { store.config.read().timeout };
+        let wait = if timeout == 0 {
+            IDLE_CHECK_INTERVAL
+        } else {
+            match std::time::Duration::from_secs(timeout).checked_sub(state.last_interaction.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining.min(IDLE_CHECK_INTERVAL),
+                _ => return Ok(None),
+            }
+        };
+        // read_buf is cancel safe, so timing out never loses input
+        if let Ok(n) = tokio::time::timeout(wait, reader.read_buf(buffer)).await {
+            return n.map(Some);
+        }
+    }
+}
+
+async fn flush_output<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    output: &mut BytesMut,
+    store: &RedisStore,
+    state: &mut ConnectionState,
+) -> std::io::Result<()> {
+    if output.is_empty() {
+        return Ok(());
+    }
+    state.output_peak = state.output_peak.max(output.len());
+    ServerStats::incr(&store.stats.total_net_output_bytes, output.len() as u64);
+    writer.write_all(output).await?;
+    writer.flush().await?;
+    output.clear();
+    Ok(())
+}
+
+// Writes a streamed reply, flushing whenever the output passes
+// OUTPUT_FLUSH_THRESHOLD so the buffer stays bounded however large the
+// reply is
+async fn write_stream<W: AsyncWrite + Unpin>(
+    stream: Box<dyn ReplyStream>,
+    writer: &mut W,
+    output: &mut BytesMut,
+    store: &RedisStore,
+    state: &mut ConnectionState,
+) -> std::io::Result<()> {
+    write_array_header(stream.len(), output);
+    for item in stream.items() {
+        write_resp(&item, state.protocol, output);
+        if output.len() >= OUTPUT_FLUSH_THRESHOLD {
+            flush_output(writer, output, store, state).await?;
+        }
+    }
+    Ok(())
+}
+
+// A client connection, which can arrive over TCP or the unix socket
+enum ClientStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+// `peer_ip` is the client's address, or the socket path for unix clients
+async fn handle_connection(
+    stream: ClientStream,
+    id: u64,
+    peer_ip: String,
+    addr: String,
+    store: Arc<RedisStore>,
+) -> std::io::Result<()> {
+    let (mut reader, writer) = tokio::io::split(stream);
+    let mut writer = BufWriter::new(writer);
+    let mut buffer = BytesMut::with_capacity(4096);
+    let mut parser = RequestParser::default();
+    let mut output = BytesMut::with_capacity(4096);
+    let (mut state, mut pushed) = ConnectionState::new(id, addr);
+    let _registration = Registration::new(&store, &state);
+
+    loop {
+        // Read data into buffer, writing out messages such as published ones
+        // while waiting
+        let read = tokio::select! {
+            read = read_with_idle_timeout(&mut reader, &mut buffer, &store, &state) => read?,
+            Some(message) = pushed.recv() => {
+                write_resp(&message, state.protocol, &mut output);
+                while let Ok(message) = pushed.try_recv() {
+                    write_resp(&message, state.protocol, &mut output);
+                }
+                flush_output(&mut writer, &mut output, &store, &mut state).await?;
+                continue;
+            }
+        };
+        let n = match read {
+            Some(n) => n,
+            None => {
+                logging::verbose!("Closing idle client id={} addr={}", id, state.addr);
+                return Ok(());
+            }
+        };
+        if n == 0 {
+            logging::verbose!("Client closed connection id={} addr={}", id, state.addr);
+            return Ok(());
+        }
+        state.last_interaction = std::time::Instant::now();
+        ServerStats::incr(&store.stats.total_net_input_bytes, n as u64);
+
+        let (max_bulk_len, query_buffer_limit) = {
+            let config = store.config.read();
+            (config.proto_max_bulk_len, config.client_query_buffer_limit)
+        };
+
+        // Run every complete command in the buffer, collecting the replies so
+        // a pipelined batch is answered with a single write
+        loop {
+            let (consumed, command) = match parser.parse(&buffer, max_bulk_len) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    // Like Redis, report what was wrong and close the connection,
+                    // since there is no way to resynchronize with the stream
+                    logging::verbose!("Protocol error ({}) from client id={} addr={}", e, id, state.addr);
+                    write_resp(&RespData::Error(format!("ERR Protocol error: {}", e)), state.protocol, &mut output);
+                    return flush_output(&mut writer, &mut output, &store, &mut state).await;
+                }
+                Err(e) => return Err(e),
+            };
+            buffer.advance(consumed);
+            match &command {
+                RespData::Array(args) if args.is_empty() => continue,
+                RespData::Error(_) => {
+                    write_resp(&command, state.protocol, &mut output);
+                    continue;
+                }
+                _ => {}
+            }
+            ServerStats::incr(&store.stats.total_commands_processed, 1);
+            // Under the name it has after rename-command
+            let spec = match &command {
+                RespData::Array(args) => match args.first() {
+                    Some(RespData::BulkString(name)) => store.commands.lookup(name),
+                    _ => None,
+                },
+                _ => None,
+            };
+            // Only the name and keys, since values may be sensitive
+            if logging::enabled(logging::Level::Debug) {
+                if let (Some(spec), RespData::Array(args)) = (spec, &command) {
+                    let keys: Vec<String> = spec.keys(args).map(|key| format!("{:?}", key_text(key))).collect();
+                    logging::debug!("Client id={} addr={} command={} keys=[{}]", id, state.addr, spec.name, keys.join(","));
+                }
+            }
+            // In a transaction or subscribed, the dispatcher decides what to
+            // do with these
+            let intercepted = if state.transaction.is_none() && !state.in_subscribe_mode() {
+                spec.map(|spec| spec.name)
+            } else {
+                None
+            };
+            match intercepted {
+                Some("psync") | Some("sync") => {
+                    flush_output(&mut writer, &mut output, &store, &mut state).await?;
+                    return serve_replica(reader, writer, &store, peer_ip, state.listening_port).await;
+                }
+                Some("replconf") => {
+                    if let RespData::Array(args) = &command {
+                        if let (Some(RespData::BulkString(opt)), Some(RespData::BulkString(port))) = (args.get(1), args.get(2)) {
+                            if opt.eq_ignore_ascii_case("listening-port") {
+                                state.listening_port = port.parse().unwrap_or(0);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            // Earlier replies shouldn't be held back while the command waits
+            if spec.is_some_and(|spec| matches!(spec.handler, commands::Handler::AsyncConnection(_))) {
+                flush_output(&mut writer, &mut output, &store, &mut state).await?;
+            }
+            let response = execute_command(&command, &store, &mut state).await?;
+            // Replies the command sent ahead of its own, such as SUBSCRIBE's
+            // per-channel confirmations
+            while let Ok(message) = pushed.try_recv() {
+                write_resp(&message, state.protocol, &mut output);
+            }
+            match response {
+                Reply::Value(response) => write_resp(&response, state.protocol, &mut output),
+                Reply::Stream(stream) => write_stream(stream, &mut writer, &mut output, &store, &mut state).await?,
+            }
+            if output.len() >= OUTPUT_FLUSH_THRESHOLD {
+                flush_output(&mut writer, &mut output, &store, &mut state).await?;
+            }
+        }
+        // Before replying, so a client that saw the reply sees the update
+        store.clients.update(&state);
+        flush_output(&mut writer, &mut output, &store, &mut state).await?;
+
+        // Whatever is left is an incomplete request; don't let it grow without bound
+        if buffer.len() as u64 > query_buffer_limit {
+            logging::warning!(
+                "Closing client id={} addr={} that reached max query buffer length ({} bytes)",
+                id,
+                state.addr,
+                buffer.len()
+            );
+            return Ok(());
+        }
+    }
+}
+
+fn key_text(key: &RespData) -> std::borrow::Cow<'_, str> {
+    match key {
+        RespData::BulkString(key) => key.into(),
+        RespData::BulkBytes(key) => String::from_utf8_lossy(key),
+        _ => "".into(),
+    }
+}
+
+// Builds the listener through socket2 so that tcp-backlog can be applied
+fn bind_listener(addr: std::net::SocketAddr, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    // Keep "::" from also claiming the IPv4 port, which may be bound separately
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog as i32)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+fn configure_client_socket(socket: &TcpStream, config: &ServerConfig) -> std::io::Result<()> {
+    socket.set_nodelay(config.tcp_nodelay)?;
+    if config.tcp_keepalive > 0 {
+        // Like Redis, probe every third of the period once the keepalive time is up
+        let keepalive = TcpKeepalive::new()
+            .with_time(std::time::Duration::from_secs(config.tcp_keepalive))
+            .with_interval(std::time::Duration::from_secs((config.tcp_keepalive / 3).max(1)));
+        SockRef::from(socket).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+// Binds the unix socket, replacing a file left behind by a server that
+// didn't shut down cleanly. A socket that still accepts connections belongs
+// to a live server and is left alone.
+fn bind_unix_listener(path: &str, perm: u32) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(Error::new(ErrorKind::AlreadyExists, "path exists and is not a socket"));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(Error::new(ErrorKind::AddrInUse, "another server is listening on it"));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if perm != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(perm))?;
+    }
+    Ok(listener)
+}
+
+// Accepted connections from every listener are funnelled into one channel,
+// tagged with the peer address (None for the unix socket)
+type AcceptedClient = (ClientStream, Option<std::net::SocketAddr>);
+
+async fn accept_tcp(listener: TcpListener, clients: mpsc::UnboundedSender<AcceptedClient>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, addr)) => {
+                if clients.send((ClientStream::Tcp(socket), Some(addr))).is_err() {
+                    return;
+                }
+            }
+            // Failures such as running out of file descriptors are
+            // per-connection, so the listener keeps going
+            Err(e) => logging::warning!("Accepting client connection: {}", e),
+        }
+    }
+}
+
+async fn accept_unix(listener: UnixListener, clients: mpsc::UnboundedSender<AcceptedClient>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                if clients.send((ClientStream::Unix(socket), None)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => logging::warning!("Accepting client connection: {}", e),
+        }
+    }
+}
+
+fn is_loopback(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(ip) => ip.is_loopback(),
+        std::net::IpAddr::V6(ip) => ip.is_loopback() || ip.to_ipv4_mapped().is_some_and(|ip| ip.is_loopback()),
+    }
+}
+
+const PROTECTED_MODE_ERROR: &[u8] = b"-DENIED Redis is running in protected mode because protected mode is enabled and no bind address was configured. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. 2) Alternatively you can just restart the server with the '--protected-mode no' option. 3) Bind the server to specific addresses with the '--bind' option. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.\r\n";
+
+// Counts a client connection for as long as it is alive
+struct ConnectedClient(Arc<RedisStore>);
+
+impl ConnectedClient {
+    fn new(store: Arc<RedisStore>) -> Self {
+        store.connected_clients.fetch_add(1, Ordering::Relaxed);
+        ConnectedClient(store)
+    }
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.0.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Runs the active expire cycle `hz` times a second until shutdown is signalled
+async fn active_expire_task(store: Arc<RedisStore>, mut shutdown: tokio::sync::watch::Receiver<bool>) {
+    loop {
+        let hz = store.config.read().hz;
+        let period = std::time::Duration::from_micros(1_000_000 / hz);
+        tokio::select! {
+            _ = tokio::time::sleep(period) => {}
+            _ = shutdown.changed() => return,
+        }
+        if !store.active_expire_enabled.load(Ordering::Relaxed) {
+            continue;
+        }
+        let budget = period * ACTIVE_EXPIRE_CPU_PERCENT as u32 / 100;
+        let cycle_store = Arc::clone(&store);
+        // The cycle is CPU-bound and takes shard locks, so keep it off the reactor
+        if let Err(e) = tokio::task::spawn_blocking(move || cycle_store.active_expire_cycle(budget)).await {
+            logging::warning!("Active expire cycle failed: {}", e);
+        }
+    }
+}
+
+/// A server bound to its listening sockets, ready to serve clients.
+///
+/// ```no_run
+/// use redis::config::ServerConfig;
+/// use redis::server::Server;
+///
+/// # async fn start() -> std::io::Result<()> {
+/// let mut config = ServerConfig::default();
+/// config.set("port", "6380").unwrap();
+/// Server::bind(config).await?.run().await
+/// # }
+/// ```
+pub struct Server {
+    store: Arc<RedisStore>,
+    clients: mpsc::UnboundedReceiver<AcceptedClient>,
+    acceptors: Vec<tokio::task::JoinHandle<()>>,
+    unixsocket: String,
+}
+
+impl Server {
+    /// Loads the saved dataset, then binds every configured TCP address and
+    /// the unix socket. Failing to bind an address is an error naming it.
+    pub async fn bind(config: ServerConfig) -> std::io::Result<Server> {
+        if config.port == 0 && config.unixsocket.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Configured to not listen anywhere"));
+        }
+
+        let store = Self::load(config.clone()).await?;
+        let mut listeners = Vec::new();
+        if config.port != 0 {
+            let addresses = if config.bind.is_empty() {
+                DEFAULT_BIND.iter().map(|s| s.to_string()).collect()
+            } else {
+                config.bind.clone()
+            };
+            for address in &addresses {
+                // Validated when the config was set
+                let (ip, optional) = parse_bind_address(address).expect("bind address");
+                let addr = std::net::SocketAddr::new(ip, config.port);
+                match bind_listener(addr, config.tcp_backlog) {
+                    Ok(listener) => listeners.push(listener),
+                    // Optional addresses, such as IPv6 on hosts without it, are
+                    // skipped unless something else already owns the port
+                    Err(e) if optional && e.kind() != ErrorKind::AddrInUse => {
+                        logging::warning!("Skipping listening socket {}: {}", addr, e);
+                    }
+                    Err(e) => {
+                        return Err(Error::new(
+                            e.kind(),
+                            format!("Could not create server TCP listening socket {}: {}", addr, e),
+                        ));
+                    }
+                }
+            }
+        }
+        Self::start(store, listeners)
+    }
+
+    /// Serves TCP clients from an already bound listener instead of the
+    /// configured port and bind addresses, e.g. one bound to port 0. A
+    /// configured unix socket is still opened.
+    pub async fn with_listener(config: ServerConfig, listener: std::net::TcpListener) -> std::io::Result<Server> {
+        listener.set_nonblocking(true)?;
+        let store = Self::load(config).await?;
+        Self::start(store, vec![TcpListener::from_std(listener)?])
+    }
+
+    // Sets up logging and creates the store with the saved dataset. Loading
+    // a large snapshot blocks, so it runs on the blocking pool.
+    async fn load(config: ServerConfig) -> std::io::Result<Arc<RedisStore>> {
+        logging::set_level(config.loglevel);
+        if let Err(e) = logging::set_logfile(&config.logfile) {
+            return Err(Error::new(e.kind(), format!("Can't open the log file {}: {}", config.logfile, e)));
+        }
+        logging::notice!(
+            "Redis version={}, bits={}, pid={}, just started",
+            env!("CARGO_PKG_VERSION"),
+            usize::BITS,
+            std::process::id()
+        );
+        logging::notice!(
+            "Configuration: port={} bind={} unixsocket={} dir={} maxmemory={} maxmemory-policy={} maxclients={} loglevel={}",
+            config.port,
+            config.get("bind").unwrap_or_default(),
+            config.unixsocket,
+            config.dir,
+            config.maxmemory,
+            config.get("maxmemory-policy").unwrap_or_default(),
+            config.maxclients,
+            config.loglevel.name()
+        );
+
+        let mut store = RedisStore::with_config(config);
+        let dir = std::path::PathBuf::from(&store.config.read().dir);
+        match load_node_id(&dir) {
+            Ok(id) => store.node_id = id,
+            Err(e) => logging::warning!(
+                "Can't keep the node id in {}: {}. Using {} until the next restart",
+                dir.join(NODE_ID_FILE).display(),
+                e,
+                store.node_id
+            ),
+        }
+        let store = Arc::new(store);
+        let path = store.config.read().snapshot_path();
+        let loading = Arc::clone(&store);
+        let result = tokio::task::spawn_blocking(move || loading.load())
+            .await
+            .unwrap_or_else(|e| Err(Error::other(e)));
+        match result {
+            Ok(Some(summary)) => logging::notice!(
+                "DB loaded from {}: {} keys loaded, {} expired keys skipped, format version {}",
+                path.display(),
+                summary.loaded,
+                summary.expired,
+                summary.version
+            ),
+            Ok(None) => logging::notice!("No saved dataset at {}, starting empty", path.display()),
+            // Starting empty would overwrite the file at the next SAVE, so
+            // that takes an explicit opt-in
+            Err(e) if store.config.read().ignore_corrupt_dump => {
+                logging::warning!("Error loading data from {}: {}. Starting empty (ignore-corrupt-dump)", path.display(), e);
+            }
+            Err(e) => {
+                return Err(Error::new(
+                    e.kind(),
+                    format!(
+                        "Error loading data from {}: {}. Fix or move the file, or set ignore-corrupt-dump to start empty",
+                        path.display(),
+                        e
+                    ),
+                ));
+            }
+        }
+        Ok(store)
+    }
+
+    fn start(store: Arc<RedisStore>, listeners: Vec<TcpListener>) -> std::io::Result<Server> {
+        let (clients_tx, clients) = mpsc::unbounded_channel();
+        let mut acceptors = Vec::new();
+        for listener in listeners {
+            logging::notice!("Listening on {}", listener.local_addr()?);
+            acceptors.push(tokio::spawn(accept_tcp(listener, clients_tx.clone())));
+        }
+        let (unixsocket, unixsocketperm) = {
+            let config = store.config.read();
+            (config.unixsocket.clone(), config.unixsocketperm)
+        };
+        if !unixsocket.is_empty() {
+            match bind_unix_listener(&unixsocket, unixsocketperm) {
+                Ok(listener) => {
+                    logging::notice!("Listening on unix socket {}", unixsocket);
+                    acceptors.push(tokio::spawn(accept_unix(listener, clients_tx)));
+                }
+                Err(e) => {
+                    for acceptor in &acceptors {
+                        acceptor.abort();
+                    }
+                    return Err(Error::new(e.kind(), format!("Failed opening Unix socket {}: {}", unixsocket, e)));
+                }
+            }
+        }
+
+        Ok(Server { store, clients, acceptors, unixsocket })
+    }
+
+    /// The store behind this server, for programs embedding it.
+    pub fn store(&self) -> &Arc<RedisStore> {
+        &self.store
+    }
+
+    /// Serves clients until Ctrl-C.
+    pub async fn run(self) -> std::io::Result<()> {
+        self.run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+    }
+
+    /// Serves clients until `shutdown` completes, then closes the listeners,
+    /// stops background tasks and removes the unix socket. Connections that
+    /// are already open are left to finish on their own.
+    pub async fn run_until(self, shutdown: impl std::future::Future<Output = ()>) -> std::io::Result<()> {
+        let Server { store, mut clients, acceptors, unixsocket } = self;
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let expire_task = tokio::spawn(active_expire_task(Arc::clone(&store), shutdown_rx));
+        tokio::pin!(shutdown);
+        logging::notice!("Ready to accept connections");
+
+        loop {
+            let (socket, peer_addr) = tokio::select! {
+                accepted = clients.recv() => match accepted {
+                    Some(accepted) => accepted,
+                    None => break,
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if let Some(ip) = peer_addr.map(|addr| addr.ip()) {
+                let config = store.config.read();
+                if config.protected_mode && config.bind.is_empty() && !is_loopback(ip) {
+                    drop(config);
+                    logging::verbose!("Denied connection from {} in protected mode", ip);
+                    tokio::spawn(async move {
+                        let mut socket = socket;
+                        let _ = socket.write_all(PROTECTED_MODE_ERROR).await;
+                    });
+                    continue;
+                }
+            }
+            // As in CLIENT LIST: "ip:port", or "path:0" for the unix socket
+            let (peer_ip, addr) = match peer_addr {
+                Some(addr) => (addr.ip().to_string(), addr.to_string()),
+                None => (unixsocket.clone(), format!("{}:0", unixsocket)),
+            };
+            if let ClientStream::Tcp(tcp) = &socket {
+                if let Err(e) = configure_client_socket(tcp, &store.config.read()) {
+                    logging::warning!("Error configuring client socket {}: {}", peer_ip, e);
+                }
+            }
+
+            // Over the limit the client still gets told why, rather than being
+            // left waiting in the backlog
+            if store.connected_clients.load(Ordering::Relaxed) >= store.config.read().maxclients {
+                logging::verbose!("Rejected connection from {}: max number of clients reached", peer_ip);
+                ServerStats::incr(&store.stats.rejected_connections, 1);
+                tokio::spawn(async move {
+                    let mut socket = socket;
+                    let _ = socket.write_all(b"-ERR max number of clients reached\r\n").await;
+                });
+                continue;
+            }
+            ServerStats::incr(&store.stats.total_connections_received, 1);
+
+            // Registered before spawning so back-to-back accepts see the new count
+            let client = ConnectedClient::new(Arc::clone(&store));
+            let connection_store = Arc::clone(&store);
+            let id = store.next_client_id.fetch_add(1, Ordering::Relaxed);
+            logging::verbose!("Accepted client id={} addr={}", id, addr);
+
+            tokio::spawn(async move {
+                let _client = client;
+                if let Err(err) = handle_connection(socket, id, peer_ip, addr.clone(), connection_store).await {
+                    logging::verbose!("Error on client id={} addr={}: {}", id, addr, err);
+                }
+            });
+        }
+
+        logging::warning!("Received shutdown signal, shutting down");
+        for acceptor in &acceptors {
+            acceptor.abort();
+        }
+        let _ = shutdown_tx.send(true);
+        let _ = expire_task.await;
+        if !unixsocket.is_empty() {
+            let _ = fs::remove_file(&unixsocket);
+        }
+        Ok(())
+    }
+}
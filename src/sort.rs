@@ -0,0 +1,145 @@
+// SORT: ordering the elements of a list, by their own value or through
+// other keys named after them
+
+use std::cmp::Ordering;
+
+use crate::resp::RespData;
+use crate::store::{RedisStore, RedisValueType, ValueKind};
+
+pub const NOT_A_DOUBLE: &str = "ERR One or more scores can't be converted into double";
+
+pub struct SortOptions {
+    pub by: Option<String>,
+    pub limit: Option<(i64, i64)>,
+    pub get: Vec<String>,
+    pub desc: bool,
+    pub alpha: bool,
+}
+
+// Whether a SORT call has a STORE option, the only way it writes. Option
+// values are skipped, so a BY or GET pattern spelled "store" isn't one.
+pub fn has_store_option(args: &[RespData]) -> bool {
+    let mut i = 2;
+    while i < args.len() {
+        match &args[i] {
+            RespData::BulkString(arg) if arg.eq_ignore_ascii_case("STORE") => return true,
+            RespData::BulkString(arg) if arg.eq_ignore_ascii_case("LIMIT") => i += 2,
+            RespData::BulkString(arg) if arg.eq_ignore_ascii_case("BY") || arg.eq_ignore_ascii_case("GET") => i += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+struct Sortable {
+    element: String,
+    score: f64,
+    by_value: Option<String>,
+}
+
+// The value a BY or GET pattern yields for `element`: "#" is the element
+// itself, otherwise the first '*' is replaced by the element to name a
+// string key, or with "->field" appended, a hash field. Patterns without a
+// '*' and keys that are missing or of another type yield None.
+fn lookup_pattern(store: &RedisStore, pattern: &str, element: &str) -> Option<String> {
+    if pattern == "#" {
+        return Some(element.to_string());
+    }
+    let star = pattern.find('*')?;
+    let (key_pattern, field) = match pattern[star + 1..].find("->").map(|arrow| star + 1 + arrow) {
+        Some(arrow) if arrow + 2 < pattern.len() => (&pattern[..arrow], Some(&pattern[arrow + 2..])),
+        _ => (pattern, None),
+    };
+    let key = format!("{}{}{}", &key_pattern[..star], element, &key_pattern[star + 1..]);
+    store
+        .inspect(&key, |value| match (&value.data, field) {
+            (RedisValueType::String(s), None) => Some(s.clone()),
+            (RedisValueType::Integer(n), None) => Some(n.to_string()),
+            (RedisValueType::Hash(hash), Some(field)) => hash.get(field).map(str::to_string),
+            _ => None,
+        })
+        .flatten()
+}
+
+// Redis' LIMIT clamping, as a range into `len` elements
+fn limit_range(limit: Option<(i64, i64)>, len: usize) -> std::ops::Range<usize> {
+    let len = len as i64;
+    let (offset, count) = limit.unwrap_or((0, -1));
+    let mut start = offset.max(0);
+    let mut end = if count < 0 { len - 1 } else { start.saturating_add(count) - 1 };
+    if start >= len {
+        start = len - 1;
+        end = len - 2;
+    }
+    if end >= len {
+        end = len - 1;
+    }
+    if end < start {
+        return 0..0;
+    }
+    start as usize..end as usize + 1
+}
+
+// Sorts the list at `key`, returning the elements or, with GET patterns, the
+// values looked up for each of them
+pub fn sort_elements(store: &RedisStore, key: &str, options: &SortOptions) -> Result<Vec<Option<String>>, String> {
+    let mut elements: Vec<String> = store.read_typed(key, ValueKind::List, |value| match value {
+        Some(RedisValueType::List(list)) => list.iter().map(str::to_string).collect(),
+        _ => Vec::new(),
+    })?;
+    let range = limit_range(options.limit, elements.len());
+
+    // A BY pattern without '*' leaves the list in its own order
+    let selected: Vec<String> = if options.by.as_ref().is_some_and(|by| !by.contains('*')) {
+        if options.desc {
+            elements.reverse();
+        }
+        elements.drain(range).collect()
+    } else {
+        let mut sortables = Vec::with_capacity(elements.len());
+        let mut not_a_double = false;
+        for element in elements {
+            let by_value = match &options.by {
+                Some(by) => lookup_pattern(store, by, &element),
+                None => None,
+            };
+            let mut score = 0.0;
+            if !options.alpha {
+                let value = if options.by.is_some() { by_value.as_deref() } else { Some(element.as_str()) };
+                // Missing external keys sort as 0
+                if let Some(value) = value {
+                    match value.trim_start().parse::<f64>() {
+                        Ok(parsed) if !parsed.is_nan() => score = parsed,
+                        _ => not_a_double = true,
+                    }
+                }
+            }
+            sortables.push(Sortable { element, score, by_value });
+        }
+        if not_a_double {
+            return Err(NOT_A_DOUBLE.to_string());
+        }
+
+        sortables.sort_by(|a, b| {
+            let order = if !options.alpha {
+                // Ties are broken by the elements so the result is deterministic
+                a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal).then_with(|| a.element.cmp(&b.element))
+            } else if options.by.is_some() {
+                a.by_value.cmp(&b.by_value)
+            } else {
+                a.element.cmp(&b.element)
+            };
+            if options.desc { order.reverse() } else { order }
+        });
+        sortables.drain(range).map(|sortable| sortable.element).collect()
+    };
+
+    if options.get.is_empty() {
+        return Ok(selected.into_iter().map(Some).collect());
+    }
+    Ok(selected
+        .iter()
+        .flat_map(|element| options.get.iter().map(move |pattern| lookup_pattern(store, pattern, element)))
+        .collect())
+}
@@ -0,0 +1,169 @@
+// Counters behind INFO stats, commandstats and latencystats
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::store::RedisStore;
+
+// Server-wide counters reported by INFO stats. Only reset by CONFIG RESETSTAT.
+#[derive(Default)]
+pub struct ServerStats {
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    pub total_connections_received: AtomicU64,
+    pub total_commands_processed: AtomicU64,
+    pub total_net_input_bytes: AtomicU64,
+    pub total_net_output_bytes: AtomicU64,
+    pub expired_keys: AtomicU64,
+    // Hash fields removed because their own TTL passed
+    pub expired_subkeys: AtomicU64,
+    pub evicted_keys: AtomicU64,
+    pub rejected_connections: AtomicU64,
+}
+
+impl ServerStats {
+    pub fn incr(counter: &AtomicU64, by: u64) {
+        counter.fetch_add(by, Ordering::Relaxed);
+    }
+
+    pub fn info(&self) -> String {
+        let fields = [
+            ("total_connections_received", &self.total_connections_received),
+            ("total_commands_processed", &self.total_commands_processed),
+            ("total_net_input_bytes", &self.total_net_input_bytes),
+            ("total_net_output_bytes", &self.total_net_output_bytes),
+            ("rejected_connections", &self.rejected_connections),
+            ("expired_keys", &self.expired_keys),
+            ("expired_subkeys", &self.expired_subkeys),
+            ("evicted_keys", &self.evicted_keys),
+            ("keyspace_hits", &self.keyspace_hits),
+            ("keyspace_misses", &self.keyspace_misses),
+        ];
+        let mut info = String::from("# Stats\r\n");
+        for (name, counter) in fields {
+            info.push_str(&format!("{}:{}\r\n", name, counter.load(Ordering::Relaxed)));
+        }
+        info
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.keyspace_hits,
+            &self.keyspace_misses,
+            &self.total_connections_received,
+            &self.total_commands_processed,
+            &self.total_net_input_bytes,
+            &self.total_net_output_bytes,
+            &self.expired_keys,
+            &self.expired_subkeys,
+            &self.evicted_keys,
+            &self.rejected_connections,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// Latency histogram buckets: bucket i counts calls that took < 2^i microseconds
+pub const LATENCY_BUCKETS: usize = 40;
+
+pub struct CommandStats {
+    pub calls: AtomicU64,
+    pub usec: AtomicU64,
+    pub max_usec: AtomicU64,
+    pub rejected_calls: AtomicU64,
+    pub failed_calls: AtomicU64,
+    pub latency_histogram: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl Default for CommandStats {
+    fn default() -> Self {
+        CommandStats {
+            calls: AtomicU64::new(0),
+            usec: AtomicU64::new(0),
+            max_usec: AtomicU64::new(0),
+            rejected_calls: AtomicU64::new(0),
+            failed_calls: AtomicU64::new(0),
+            latency_histogram: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl CommandStats {
+    pub fn record(&self, usec: u64, failed: bool) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.usec.fetch_add(usec, Ordering::Relaxed);
+        self.max_usec.fetch_max(usec, Ordering::Relaxed);
+        if failed {
+            self.failed_calls.fetch_add(1, Ordering::Relaxed);
+        }
+        let bucket = (u64::BITS - usec.leading_zeros()) as usize;
+        self.latency_histogram[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Upper bound of the histogram bucket containing the given percentile
+    pub fn percentile_usec(&self, percentile: f64) -> u64 {
+        let counts: Vec<u64> = self.latency_histogram.iter().map(|c| c.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        let target = ((total as f64) * percentile / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return 1u64 << bucket;
+            }
+        }
+        1u64 << (LATENCY_BUCKETS - 1)
+    }
+}
+
+pub fn record_command_stats(store: &RedisStore, name: &str, usec: u64, failed: bool) {
+    // Fast path only takes a shard read lock; the entry is created once per command
+    if let Some(stats) = store.command_stats.get(name) {
+        stats.record(usec, failed);
+        return;
+    }
+    store.command_stats.entry(name.to_string()).or_default().record(usec, failed);
+}
+
+pub fn commandstats_info(store: &RedisStore) -> String {
+    let mut names: Vec<String> = store.command_stats.iter().map(|entry| entry.key().clone()).collect();
+    names.sort();
+    let mut info = String::from("# Commandstats\r\n");
+    for name in names {
+        if let Some(stats) = store.command_stats.get(&name) {
+            let calls = stats.calls.load(Ordering::Relaxed);
+            let usec = stats.usec.load(Ordering::Relaxed);
+            info.push_str(&format!(
+                "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}\r\n",
+                name,
+                calls,
+                usec,
+                if calls == 0 { 0.0 } else { usec as f64 / calls as f64 },
+                stats.rejected_calls.load(Ordering::Relaxed),
+                stats.failed_calls.load(Ordering::Relaxed)
+            ));
+        }
+    }
+    info
+}
+
+pub fn latencystats_info(store: &RedisStore) -> String {
+    let mut names: Vec<String> = store.command_stats.iter().map(|entry| entry.key().clone()).collect();
+    names.sort();
+    let mut info = String::from("# Latencystats\r\n");
+    for name in names {
+        if let Some(stats) = store.command_stats.get(&name) {
+            if stats.calls.load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            info.push_str(&format!(
+                "latency_percentiles_usec_{}:p50={:.3},p99={:.3},p99.9={:.3}\r\n",
+                name,
+                stats.percentile_usec(50.0) as f64,
+                stats.percentile_usec(99.0) as f64,
+                stats.percentile_usec(99.9) as f64
+            ));
+        }
+    }
+    info
+}
@@ -0,0 +1,1493 @@
+//! The keyspace and its command-level API.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SystemClock};
+use crate::cluster::{key_hash_slot, NODE_ID_LEN};
+use crate::commands::CommandTable;
+use crate::config::{EvictionPolicy, ServerConfig};
+use crate::connection::ClientRegistry;
+use crate::errors;
+use crate::hashes;
+use crate::gzip::{GzipReader, GzipWriter, MAGIC};
+use crate::listpack::{entry_str, Listpack};
+use crate::notify::{self, notify};
+use crate::rdb::{
+    crc64, create_dump_payload, parse_dump_payload, rdb_value_type, rdb_write_len, rdb_write_object, rdb_write_string,
+    RDB_OPCODE_EOF, RDB_OPCODE_EXPIRETIME_MS, RDB_OPCODE_SELECTDB, RDB_VERSION,
+};
+use crate::replication::ReplicationState;
+use crate::resp::RespData;
+use crate::stats::{CommandStats, ServerStats};
+use crate::tracking::{invalidate_all, invalidate_keys, TrackingTable};
+use crate::util::{random_hex, random_u64};
+
+pub use crate::listpack::ListValue;
+pub use crate::streams::StreamValue;
+
+/// A stored value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RedisValueType {
+    String(String),
+    List(ListValue),
+    Integer(i64),
+    Hash(HashValue),
+    Stream(Box<StreamValue>),
+}
+
+/// A hash field's value, which can expire on its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashField {
+    pub value: String,
+    /// Absolute expiry as a Unix timestamp in milliseconds
+    pub expiry: Option<u64>,
+}
+
+// When a hash stays in the compact encoding: hash-max-listpack-entries and
+// hash-max-listpack-value
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HashLimits {
+    pub(crate) entries: usize,
+    pub(crate) value: usize,
+}
+
+impl Default for HashLimits {
+    fn default() -> Self {
+        HashLimits { entries: 128, value: 64 }
+    }
+}
+
+impl HashLimits {
+    fn allow(&self, name: &str, value: &str) -> bool {
+        name.len() <= self.value && value.len() <= self.value
+    }
+}
+
+/// The fields of a hash.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(from = "HashSnapshot", into = "HashSnapshot")]
+pub struct HashValue {
+    // Shared copy-on-write with the replies that stream it, like a list's
+    // elements
+    fields: Arc<HashFields>,
+    // No field expires before this, so most reads don't have to look for
+    // expired fields. It may be earlier than the actual earliest expiry.
+    pub(crate) next_expiry: Option<u64>,
+}
+
+// A compact hash holds three entries per field: its name, its value and its
+// expiry, as 8 little-endian bytes or empty for none
+#[derive(Debug, Clone)]
+enum HashFields {
+    Listpack(Listpack),
+    Table(HashMap<String, HashField>),
+}
+
+impl Default for HashFields {
+    fn default() -> Self {
+        HashFields::Listpack(Listpack::default())
+    }
+}
+
+// Snapshots store hashes as a map whatever their encoding
+#[derive(Serialize, Deserialize)]
+struct HashSnapshot {
+    fields: HashMap<String, HashField>,
+    next_expiry: Option<u64>,
+}
+
+// A copy of its own, unlike `share`
+impl Clone for HashValue {
+    fn clone(&self) -> Self {
+        HashValue { fields: Arc::new((*self.fields).clone()), next_expiry: self.next_expiry }
+    }
+}
+
+impl From<HashSnapshot> for HashValue {
+    fn from(snapshot: HashSnapshot) -> Self {
+        let mut hash = HashValue { fields: Arc::new(HashFields::Table(snapshot.fields)), next_expiry: snapshot.next_expiry };
+        hash.fit(HashLimits::default());
+        hash
+    }
+}
+
+impl From<HashValue> for HashSnapshot {
+    fn from(hash: HashValue) -> Self {
+        let fields = match Arc::unwrap_or_clone(hash.fields) {
+            HashFields::Table(fields) => fields,
+            HashFields::Listpack(listpack) => compact_fields(&listpack)
+                .map(|field| (field.name.to_string(), HashField { value: field.value.to_string(), expiry: field.expiry }))
+                .collect(),
+        };
+        HashSnapshot { fields, next_expiry: hash.next_expiry }
+    }
+}
+
+// Where a field of a compact hash is
+struct CompactField<'a> {
+    // Offsets of its name and expiry entries
+    offset: usize,
+    expiry_offset: usize,
+    name: &'a str,
+    value: &'a str,
+    expiry: Option<u64>,
+}
+
+fn compact_fields(listpack: &Listpack) -> impl Iterator<Item = CompactField<'_>> {
+    let mut entries = listpack.iter();
+    std::iter::from_fn(move || {
+        let offset = entries.offset();
+        let name = entry_str(entries.next()?);
+        let value = entry_str(entries.next()?);
+        let expiry_offset = entries.offset();
+        let expiry = entries.next()?.try_into().ok().map(u64::from_le_bytes);
+        Some(CompactField { offset, expiry_offset, name, value, expiry })
+    })
+}
+
+fn find_compact<'a>(listpack: &'a Listpack, name: &str) -> Option<CompactField<'a>> {
+    compact_fields(listpack).find(|field| field.name == name)
+}
+
+fn expiry_entry(expiry: &Option<u64>) -> Vec<u8> {
+    expiry.map_or(Vec::new(), |expiry| expiry.to_le_bytes().to_vec())
+}
+
+impl HashValue {
+    // The hash as it is now, without copying its fields. The next change to
+    // either side copies them first.
+    pub(crate) fn share(&self) -> Self {
+        HashValue { fields: Arc::clone(&self.fields), next_expiry: self.next_expiry }
+    }
+
+    pub fn len(&self) -> usize {
+        match &*self.fields {
+            HashFields::Listpack(listpack) => listpack.len() / 3,
+            HashFields::Table(fields) => fields.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, field: &str) -> Option<&str> {
+        match &*self.fields {
+            HashFields::Listpack(listpack) => find_compact(listpack, field).map(|field| field.value),
+            HashFields::Table(fields) => fields.get(field).map(|field| field.value.as_str()),
+        }
+    }
+
+    /// Each field's name, value and expiry.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&str, &str, Option<u64>)> + Send + '_> {
+        match &*self.fields {
+            HashFields::Listpack(listpack) => Box::new(compact_fields(listpack).map(|field| (field.name, field.value, field.expiry))),
+            HashFields::Table(fields) => {
+                Box::new(fields.iter().map(|(name, field)| (name.as_str(), field.value.as_str(), field.expiry)))
+            }
+        }
+    }
+
+    // Name of the encoding, as reported by OBJECT ENCODING
+    pub(crate) fn encoding(&self) -> &'static str {
+        match (&*self.fields, self.next_expiry.is_some()) {
+            (HashFields::Listpack(_), false) => "listpack",
+            (HashFields::Listpack(_), true) => "listpackex",
+            (HashFields::Table(_), _) => "hashtable",
+        }
+    }
+
+    // Approximate heap size of the fields, measuring at most `samples` of
+    // them (0 = all) and extrapolating
+    pub(crate) fn heap_size(&self, samples: usize) -> usize {
+        match &*self.fields {
+            HashFields::Listpack(listpack) => listpack.bytes(),
+            HashFields::Table(fields) => {
+                let measured = if samples == 0 { fields.len() } else { samples.min(fields.len()) };
+                let sampled: usize = fields.iter().take(measured).map(|(name, field)| table_field_size(name, &field.value)).sum();
+                (sampled * fields.len()).checked_div(measured).unwrap_or(0)
+            }
+        }
+    }
+
+    // Sets a field's value, dropping any TTL it had like HSET does, and
+    // converts the hash if it outgrows `limits`. Returns whether the field
+    // is new, and the change in heap size.
+    pub(crate) fn insert(&mut self, name: String, value: String, limits: HashLimits) -> (bool, isize) {
+        let mut delta = 0;
+        if let HashFields::Listpack(listpack) = Arc::make_mut(&mut self.fields) {
+            let before = listpack.bytes() as isize;
+            if limits.allow(&name, &value) {
+                match find_compact(listpack, &name).map(|field| field.offset) {
+                    Some(offset) => {
+                        listpack.splice(offset, 3, &[name.as_bytes(), value.as_bytes(), &[]]);
+                        return (false, listpack.bytes() as isize - before);
+                    }
+                    None if listpack.len() / 3 < limits.entries => {
+                        for entry in [name.as_bytes(), value.as_bytes(), &[]] {
+                            listpack.push_back(entry);
+                        }
+                        return (true, listpack.bytes() as isize - before);
+                    }
+                    None => {}
+                }
+            }
+            delta = self.convert(false);
+        }
+        if let HashFields::Table(fields) = Arc::make_mut(&mut self.fields) {
+            delta += table_field_size(&name, &value) as isize;
+            if let Some(old) = fields.get(&name) {
+                delta -= table_field_size(&name, &old.value) as isize;
+            }
+            let new = fields.insert(name, HashField { value, expiry: None }).is_none();
+            return (new, delta);
+        }
+        unreachable!("converted to a table")
+    }
+
+    // Removes a field, returning its value and the change in heap size
+    pub(crate) fn remove(&mut self, name: &str) -> Option<(String, isize)> {
+        match Arc::make_mut(&mut self.fields) {
+            HashFields::Listpack(listpack) => {
+                let before = listpack.bytes() as isize;
+                let (offset, value) = find_compact(listpack, name).map(|field| (field.offset, field.value.to_string()))?;
+                listpack.splice(offset, 3, &[]);
+                Some((value, listpack.bytes() as isize - before))
+            }
+            HashFields::Table(fields) => {
+                let removed = fields.remove(name)?;
+                let size = table_field_size(name, &removed.value) as isize;
+                Some((removed.value, -size))
+            }
+        }
+    }
+
+    // Sets or clears the expiry of an existing field, returning the change
+    // in heap size (0 if there is no such field)
+    pub(crate) fn set_expiry(&mut self, name: &str, expiry: Option<u64>) -> isize {
+        let delta = match Arc::make_mut(&mut self.fields) {
+            HashFields::Listpack(listpack) => {
+                let before = listpack.bytes() as isize;
+                let Some(offset) = find_compact(listpack, name).map(|field| field.expiry_offset) else {
+                    return 0;
+                };
+                listpack.splice(offset, 1, &[&expiry_entry(&expiry)]);
+                listpack.bytes() as isize - before
+            }
+            HashFields::Table(fields) => match fields.get_mut(name) {
+                Some(field) => {
+                    field.expiry = expiry;
+                    0
+                }
+                None => return 0,
+            },
+        };
+        if let Some(expiry) = expiry {
+            self.next_expiry = Some(self.next_expiry.map_or(expiry, |next| next.min(expiry)));
+        }
+        delta
+    }
+
+    pub(crate) fn field_expiry(&self, name: &str) -> Option<Option<u64>> {
+        match &*self.fields {
+            HashFields::Listpack(listpack) => find_compact(listpack, name).map(|field| field.expiry),
+            HashFields::Table(fields) => fields.get(name).map(|field| field.expiry),
+        }
+    }
+
+    // Removes the fields that expired by `now`, returning their names and
+    // the heap size freed
+    pub(crate) fn expire_fields(&mut self, now: u64) -> (Vec<String>, usize) {
+        if self.next_expiry.is_none_or(|next| now < next) {
+            return (Vec::new(), 0);
+        }
+        let expired: Vec<String> = self
+            .iter()
+            .filter(|(_, _, expiry)| expiry.is_some_and(|expiry| now >= expiry))
+            .map(|(name, _, _)| name.to_string())
+            .collect();
+        let freed = expired.iter().filter_map(|name| self.remove(name)).map(|(_, delta)| -delta as usize).sum();
+        self.next_expiry = self.iter().filter_map(|(_, _, expiry)| expiry).min();
+        (expired, freed)
+    }
+
+    // Picks the encoding Redis would for the hash as it is now, for hashes
+    // created whole rather than grown
+    pub(crate) fn fit(&mut self, limits: HashLimits) {
+        let compact = self.len() <= limits.entries && self.iter().all(|(name, value, _)| limits.allow(name, value));
+        self.convert(compact);
+    }
+
+    // Switches to the compact encoding or away from it, returning the change
+    // in heap size
+    fn convert(&mut self, compact: bool) -> isize {
+        let before = self.heap_size(0) as isize;
+        self.fields = Arc::new(match (&*self.fields, compact) {
+            (HashFields::Table(fields), true) => {
+                let mut listpack = Listpack::default();
+                for (name, field) in fields {
+                    for entry in [name.as_bytes(), field.value.as_bytes(), &expiry_entry(&field.expiry)] {
+                        listpack.push_back(entry);
+                    }
+                }
+                HashFields::Listpack(listpack)
+            }
+            (HashFields::Listpack(listpack), false) => HashFields::Table(
+                compact_fields(listpack)
+                    .map(|field| (field.name.to_string(), HashField { value: field.value.to_string(), expiry: field.expiry }))
+                    .collect(),
+            ),
+            _ => return 0,
+        });
+        self.heap_size(0) as isize - before
+    }
+}
+
+/// A value together with its expiry time.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedisValue {
+    pub data: RedisValueType,
+    /// Absolute expiry as a Unix timestamp in milliseconds
+    pub expiry: Option<u64>,
+    // Last access time in milliseconds, used for LRU eviction. Read from
+    // the store's clock, which stamps it when the value is inserted. Atomic
+    // so that reads can stamp it under a shared shard lock.
+    #[serde(skip)]
+    lru: AtomicU64,
+}
+
+impl Clone for RedisValue {
+    fn clone(&self) -> Self {
+        RedisValue { data: self.data.clone(), expiry: self.expiry, lru: AtomicU64::new(self.lru()) }
+    }
+}
+
+// The kind of value a command operates on. Integers are strings as far as
+// commands are concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ValueKind {
+    String,
+    List,
+    Hash,
+    Stream,
+}
+
+impl RedisValueType {
+    pub(crate) fn kind(&self) -> ValueKind {
+        match self {
+            RedisValueType::String(_) | RedisValueType::Integer(_) => ValueKind::String,
+            RedisValueType::List(_) => ValueKind::List,
+            RedisValueType::Hash(_) => ValueKind::Hash,
+            RedisValueType::Stream(_) => ValueKind::Stream,
+        }
+    }
+}
+
+impl RedisValue {
+    pub fn new(data: RedisValueType, expiry: Option<u64>) -> Self {
+        RedisValue { data, expiry, lru: AtomicU64::new(0) }
+    }
+
+    pub(crate) fn lru(&self) -> u64 {
+        self.lru.load(Ordering::Relaxed)
+    }
+
+    // Records an access at `now`
+    pub(crate) fn touch(&self, now: u64) {
+        self.lru.store(now, Ordering::Relaxed);
+    }
+}
+
+// Keys that were given a TTL, which the active expire cycle and the
+// volatile eviction policies sample at random. A key is dropped when it
+// expires, or when a sample finds it gone or without a TTL, so a few of
+// the keys may be stale.
+#[derive(Default)]
+pub(crate) struct VolatileKeys {
+    keys: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl VolatileKeys {
+    fn insert(&mut self, key: &str) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_string(), self.keys.len());
+            self.keys.push(key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(position) = self.positions.remove(key) {
+            self.keys.swap_remove(position);
+            if let Some(moved) = self.keys.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+        }
+    }
+
+    // Up to `count` keys picked at random, possibly the same one twice
+    fn sample(&self, count: usize) -> Vec<String> {
+        if self.keys.is_empty() {
+            return Vec::new();
+        }
+        (0..count.min(self.keys.len())).map(|_| self.keys[random_u64() as usize % self.keys.len()].clone()).collect()
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.positions.clear();
+    }
+}
+
+// Keys sampled per active expire iteration, and the share of expired keys
+// among them above which the cycle keeps going
+pub(crate) const ACTIVE_EXPIRE_KEYS_PER_LOOP: usize = 20;
+pub(crate) const ACTIVE_EXPIRE_STALE_PERCENT: usize = 25;
+// Share of each tick the active expire cycle may spend
+pub(crate) const ACTIVE_EXPIRE_CPU_PERCENT: u64 = 25;
+
+// Approximate heap overheads used for memory accounting
+pub(crate) const ENTRY_OVERHEAD: usize = 72;
+pub(crate) const STRING_OVERHEAD: usize = 24;
+pub(crate) const LIST_OVERHEAD: usize = 56;
+pub(crate) const HASH_OVERHEAD: usize = 72;
+pub(crate) const STREAM_OVERHEAD: usize = 96;
+// Per field, on top of its name and value strings
+pub(crate) const HASH_FIELD_OVERHEAD: usize = 24;
+
+fn table_field_size(name: &str, value: &String) -> usize {
+    HASH_FIELD_OVERHEAD + 2 * STRING_OVERHEAD + name.len() + value.capacity()
+}
+
+pub(crate) fn estimate_value_size(value: &RedisValueType) -> usize {
+    estimate_value_size_sampled(value, 0)
+}
+
+// For aggregates, measures at most `samples` elements (0 = all) and
+// extrapolates to the full size
+pub(crate) fn estimate_value_size_sampled(value: &RedisValueType, samples: usize) -> usize {
+    match value {
+        RedisValueType::String(s) => STRING_OVERHEAD + s.capacity(),
+        RedisValueType::Integer(_) => 0,
+        RedisValueType::List(list) => LIST_OVERHEAD + list.heap_size(samples),
+        RedisValueType::Hash(hash) => HASH_OVERHEAD + hash.heap_size(samples),
+        RedisValueType::Stream(stream) => STREAM_OVERHEAD + stream.heap_size(samples),
+    }
+}
+
+pub(crate) fn estimate_entry_size(key: &str, value: &RedisValue) -> usize {
+    ENTRY_OVERHEAD + key.len() + estimate_value_size(&value.data)
+}
+
+// LRANGE's `start` and `stop` for a list of `len` elements, negative
+// indexes counting from the tail, as a range of indexes; None if empty
+pub(crate) fn list_range_bounds(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize + 1))
+}
+
+// Strings up to this length are reported as "embstr", like Redis
+pub(crate) const EMBSTR_SIZE_LIMIT: usize = 44;
+// Name of the representation used for this value, as reported by OBJECT
+// ENCODING
+pub(crate) fn object_encoding(value: &RedisValueType) -> &'static str {
+    match value {
+        RedisValueType::Integer(_) => "int",
+        RedisValueType::String(s) if s.len() <= 20 && s.parse::<i64>().is_ok_and(|n| n.to_string() == *s) => "int",
+        RedisValueType::String(s) if s.len() <= EMBSTR_SIZE_LIMIT => "embstr",
+        RedisValueType::String(_) => "raw",
+        RedisValueType::List(list) => list.encoding(),
+        RedisValueType::Hash(hash) => hash.encoding(),
+        RedisValueType::Stream(_) => "stream",
+    }
+}
+
+/// Expiry to give a key when setting it: relative seconds (`EX`) or
+/// milliseconds (`PX`), an absolute Unix time in seconds (`EXAT`) or
+/// milliseconds (`PXAT`), or whatever expiry the key already has (`KEEPTTL`).
+#[allow(clippy::upper_case_acronyms)]
+pub enum SetOptions {
+    None,
+    EX(u64),
+    PX(u64),
+    EXAT(u64),
+    PXAT(u64),
+    KEEPTTL,
+}
+
+// Format of the snapshot file. Version 0 is the original bare array of
+// entries, which is still read.
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    entries: &'a [(String, RedisValue)],
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    entries: Vec<(String, RedisValue)>,
+}
+
+// Just enough to tell a newer format apart from a damaged file
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+/// What [`RedisStore::save`] wrote.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SaveSummary {
+    /// Size of the snapshot file
+    pub bytes: u64,
+    /// Size of the serialized dataset before compression
+    pub uncompressed: u64,
+    /// Whether the file is gzipped (`rdbcompression`)
+    pub compressed: bool,
+}
+
+// The snapshot file's JSON, decompressed if it starts like a gzip file, or
+// None if there is no file
+fn open_snapshot(path: &Path) -> std::io::Result<Option<Box<dyn BufRead>>> {
+    let mut reader = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if reader.fill_buf()?.starts_with(&MAGIC) {
+        let decoder = GzipReader::new(reader).map_err(|e| Error::new(e.kind(), format!("corrupt snapshot: {}", e)))?;
+        return Ok(Some(Box::new(BufReader::new(decoder))));
+    }
+    Ok(Some(Box::new(reader)))
+}
+
+// Whether the first non-blank byte is `byte`, consuming the blanks before it
+fn next_byte_is(reader: &mut dyn BufRead, byte: u8) -> std::io::Result<bool> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(false);
+        }
+        match buffer.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let found = buffer[i] == byte;
+                reader.consume(i);
+                return Ok(found);
+            }
+            None => {
+                let blanks = buffer.len();
+                reader.consume(blanks);
+            }
+        }
+    }
+}
+
+/// What [`RedisStore::load`] found in the snapshot file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadSummary {
+    /// Format version of the file, 0 for the original unversioned format
+    pub version: u32,
+    /// Keys added to the store
+    pub loaded: usize,
+    /// Keys left out because they had already expired
+    pub expired: usize,
+}
+
+/// The keyspace plus the server state that travels with it: configuration,
+/// statistics, memory accounting and replication.
+///
+/// Every method takes `&self`, so a store is typically shared as an
+/// `Arc<RedisStore>`.
+///
+/// ```
+/// use redis::store::RedisStore;
+///
+/// let store = RedisStore::new();
+/// assert_eq!(store.rpush("queue", vec!["a".to_string(), "b".to_string()]), Ok(2));
+/// assert_eq!(store.del(&["queue".to_string()]), 1);
+/// assert!(!store.exists("queue"));
+/// ```
+pub struct RedisStore {
+    pub(crate) data: DashMap<String, RedisValue>,
+    // Toggled by DEBUG SET-ACTIVE-EXPIRE
+    pub(crate) active_expire_enabled: AtomicBool,
+    pub(crate) replication: ReplicationState,
+    pub(crate) stats: ServerStats,
+    pub(crate) command_stats: DashMap<String, CommandStats>,
+    pub(crate) config: RwLock<ServerConfig>,
+    // Approximate bytes used by the dataset, maintained on every insert/remove
+    pub(crate) used_memory: AtomicI64,
+    pub(crate) peak_memory: AtomicU64,
+    pub(crate) connected_clients: AtomicU64,
+    pub(crate) next_client_id: AtomicU64,
+    pub(crate) clients: ClientRegistry,
+    pub(crate) tracking: TrackingTable,
+    pub(crate) clock: Arc<dyn Clock>,
+    // CLUSTER MYID. Servers keep it across restarts; see cluster::load_node_id.
+    pub(crate) node_id: String,
+    // Hashes with field TTLs, keyed by when their next field may expire, so
+    // the active expire cycle finds them without scanning
+    pub(crate) hash_field_expiries: Mutex<BinaryHeap<Reverse<(u64, String)>>>,
+    pub(crate) volatile_keys: Mutex<VolatileKeys>,
+    // Built once at startup, since rename-command can't change afterwards
+    pub(crate) commands: CommandTable,
+}
+
+impl Default for RedisStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RedisStore {
+    /// An empty store with the default configuration.
+    pub fn new() -> Self {
+        Self::with_config(ServerConfig::default())
+    }
+
+    /// An empty store using `config`.
+    pub fn with_config(config: ServerConfig) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// An empty store using `config` that takes the time from `clock`.
+    pub fn with_clock(config: ServerConfig, clock: Arc<dyn Clock>) -> Self {
+        let commands = CommandTable::new(&config.renamed_commands);
+        RedisStore {
+            data: DashMap::new(),
+            active_expire_enabled: AtomicBool::new(true),
+            replication: ReplicationState::new(),
+            stats: ServerStats::default(),
+            command_stats: DashMap::new(),
+            config: RwLock::new(config),
+            used_memory: AtomicI64::new(0),
+            peak_memory: AtomicU64::new(0),
+            connected_clients: AtomicU64::new(0),
+            next_client_id: AtomicU64::new(1),
+            clients: ClientRegistry::default(),
+            tracking: TrackingTable::default(),
+            clock,
+            node_id: random_hex(NODE_ID_LEN),
+            hash_field_expiries: Mutex::new(BinaryHeap::new()),
+            volatile_keys: Mutex::new(VolatileKeys::default()),
+            commands,
+        }
+    }
+
+    // The current time in Unix milliseconds, for everything expiry related
+    pub(crate) fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    pub(crate) fn used_memory(&self) -> u64 {
+        self.used_memory.load(Ordering::Relaxed).max(0) as u64
+    }
+
+    pub(crate) fn adjust_used_memory(&self, added: usize, removed: usize) {
+        let delta = added as i64 - removed as i64;
+        let used = self.used_memory.fetch_add(delta, Ordering::Relaxed) + delta;
+        if delta > 0 {
+            self.peak_memory.fetch_max(used.max(0) as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn peak_memory(&self) -> u64 {
+        self.peak_memory.load(Ordering::Relaxed).max(self.used_memory())
+    }
+
+    pub(crate) fn insert_entry(&self, key: String, mut value: RedisValue) -> Option<RedisValue> {
+        value.touch(self.now_ms());
+        match &mut value.data {
+            RedisValueType::List(list) => list.fit(self.config.read().list_max_listpack_size),
+            RedisValueType::Hash(hash) => hash.fit(self.hash_limits()),
+            _ => {}
+        }
+        if let RedisValueType::Hash(HashValue { next_expiry: Some(at), .. }) = &value.data {
+            self.schedule_field_expiry(&key, *at);
+        }
+        let added = estimate_entry_size(&key, &value);
+        let removed_key_len = key.len();
+        // Indexed after the insert, so a sample can't drop it in between
+        let volatile = value.expiry.is_some().then(|| key.clone());
+        let old = self.data.insert(key, value);
+        if let Some(key) = volatile {
+            self.volatile_keys.lock().insert(&key);
+        }
+        let removed = old.as_ref().map_or(0, |old| ENTRY_OVERHEAD + removed_key_len + estimate_value_size(&old.data));
+        self.adjust_used_memory(added, removed);
+        old
+    }
+
+    // The expiry of `key`, dropping the key from the volatile index if it is
+    // gone or has none. Checked under the index lock, so that a concurrent
+    // insert with a TTL indexes the key again afterwards.
+    fn volatile_expiry(&self, key: &str) -> Option<u64> {
+        let mut volatile = self.volatile_keys.lock();
+        let expiry = self.data.get(key).and_then(|value| value.expiry);
+        if expiry.is_none() {
+            volatile.remove(key);
+        }
+        expiry
+    }
+
+    pub(crate) fn remove_entry(&self, key: &str) -> Option<RedisValue> {
+        let (key, value) = self.data.remove(key)?;
+        self.adjust_used_memory(0, estimate_entry_size(&key, &value));
+        Some(value)
+    }
+
+    // Removes `key` only if `f` accepts its value, checked under the same
+    // shard lock so the value can't change in between
+    pub(crate) fn remove_entry_if(&self, key: &str, f: impl FnOnce(&RedisValue) -> bool) -> bool {
+        match self.data.remove_if(key, |_, value| f(value)) {
+            Some((key, value)) => {
+                self.adjust_used_memory(0, estimate_entry_size(&key, &value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn clear_entries(&self) {
+        self.data.clear();
+        self.volatile_keys.lock().clear();
+        self.used_memory.store(0, Ordering::Relaxed);
+        invalidate_all(self);
+    }
+
+    // Samples up to `count` entries starting at a random position, returning
+    // each key with its LRU clock and expiry. Finding the position walks the
+    // shard, so keys with a TTL are better sampled by sample_volatile_keys.
+    pub(crate) fn sample_keys(&self, count: usize) -> Vec<(String, u64, Option<u64>)> {
+        let shards = self.data.shards();
+        let start = random_u64() as usize % shards.len();
+        let mut samples = Vec::with_capacity(count);
+        for i in 0..shards.len() {
+            let shard = shards[(start + i) % shards.len()].read();
+            if shard.is_empty() {
+                continue;
+            }
+            let offset = random_u64() as usize % shard.len();
+            let remaining = count - samples.len();
+            for (key, value) in shard.iter().skip(offset).chain(shard.iter().take(offset)).take(remaining) {
+                let value = value.get();
+                samples.push((key.clone(), value.lru(), value.expiry));
+            }
+            if samples.len() >= count {
+                break;
+            }
+        }
+        samples
+    }
+
+    // Samples up to `count` keys with a TTL at random, like sample_keys
+    pub(crate) fn sample_volatile_keys(&self, count: usize) -> Vec<(String, u64, Option<u64>)> {
+        let keys = self.volatile_keys.lock().sample(count);
+        keys.into_iter()
+            .filter_map(|key| {
+                let expiry = self.volatile_expiry(&key)?;
+                let lru = self.data.get(&key)?.lru();
+                Some((key, lru, Some(expiry)))
+            })
+            .collect()
+    }
+
+    // Evicts keys according to maxmemory-policy until memory is back under
+    // maxmemory, or returns the OOM error if that isn't possible
+    pub(crate) fn evict_if_needed(&self) -> Result<(), String> {
+        let (maxmemory, policy, samples) = {
+            let config = self.config.read();
+            (config.maxmemory, config.maxmemory_policy, config.maxmemory_samples)
+        };
+        let oom = || "OOM command not allowed when used memory > 'maxmemory'.".to_string();
+
+        let mut failed_attempts = 0;
+        while maxmemory > 0 && self.used_memory() > maxmemory {
+            if policy == EvictionPolicy::NoEviction {
+                return Err(oom());
+            }
+
+            let candidates = if policy.volatile_only() { self.sample_volatile_keys(samples) } else { self.sample_keys(samples) }.into_iter();
+            let victim = match policy {
+                EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => {
+                    candidates.min_by_key(|(_, lru, _)| *lru).map(|(key, _, _)| key)
+                }
+                EvictionPolicy::VolatileTtl => candidates.min_by_key(|(_, _, expiry)| *expiry).map(|(key, _, _)| key),
+                _ => candidates.map(|(key, _, _)| key).next(),
+            };
+
+            match victim {
+                Some(key) => {
+                    failed_attempts = 0;
+                    if self.remove_entry(&key).is_some() {
+                        ServerStats::incr(&self.stats.evicted_keys, 1);
+                        invalidate_keys(self, &[&key], None);
+                        notify(self, notify::EVICTED, "evicted", &key);
+                        self.replication.propagate(&RespData::Array(vec![
+                            RespData::BulkString("DEL".to_string()),
+                            RespData::BulkString(key),
+                        ]));
+                    }
+                }
+                None => {
+                    // Volatile policies may simply have sampled no keys with a TTL
+                    failed_attempts += 1;
+                    if failed_attempts >= 16 || self.data.is_empty() {
+                        return Err(oom());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Makes the active expire cycle look at the hash at `key` once `at` passes
+    pub(crate) fn schedule_field_expiry(&self, key: &str, at: u64) {
+        self.hash_field_expiries.lock().push(Reverse((at, key.to_string())));
+    }
+
+    pub(crate) fn hash_limits(&self) -> HashLimits {
+        let config = self.config.read();
+        HashLimits { entries: config.hash_max_listpack_entries, value: config.hash_max_listpack_value }
+    }
+
+    // Removes the fields of the hash at `key` that expired by `now`,
+    // propagating them as an HDEL and deleting the key if none are left.
+    // Returns when the next remaining field expires.
+    fn expire_hash_fields(&self, key: &str, now: u64) -> Option<u64> {
+        let (expired, freed, next_expiry) = self.replication.apply_and_propagate(|| {
+            let (expired, freed, next_expiry) = match self.data.get_mut(key) {
+                Some(mut entry) => match &mut entry.data {
+                    RedisValueType::Hash(hash) => {
+                        let (expired, freed) = hash.expire_fields(now);
+                        (expired, freed, hash.next_expiry)
+                    }
+                    _ => return ((0, 0, None), None),
+                },
+                None => return ((0, 0, None), None),
+            };
+            if expired.is_empty() {
+                return ((0, 0, next_expiry), None);
+            }
+            let count = expired.len();
+            let mut hdel = vec![RespData::BulkString("HDEL".to_string()), RespData::BulkString(key.to_string())];
+            hdel.extend(expired.into_iter().map(RespData::BulkString));
+            ((count, freed, next_expiry), Some(RespData::Array(hdel)))
+        });
+        if expired == 0 {
+            return next_expiry;
+        }
+
+        self.adjust_used_memory(0, freed);
+        ServerStats::incr(&self.stats.expired_subkeys, expired as u64);
+        let emptied = self.data.remove_if(key, |_, value| matches!(&value.data, RedisValueType::Hash(hash) if hash.is_empty()));
+        if let Some((key, value)) = &emptied {
+            self.adjust_used_memory(0, estimate_entry_size(key, value));
+        }
+        invalidate_keys(self, &[key], None);
+        next_expiry
+    }
+
+    // Deletes `key` if it expired, or else the hash fields of it that did.
+    // Returns whether the key itself expired.
+    pub(crate) fn expire_if_needed(&self, key: &str, now: u64) -> bool {
+        let fields_due = match self.data.get(key) {
+            Some(entry) => {
+                if entry.expiry.is_some_and(|expiry| now >= expiry) {
+                    drop(entry);
+                    return self.remove_expired_key(key, now);
+                }
+                matches!(&entry.data, RedisValueType::Hash(hash) if hash.next_expiry.is_some_and(|next| now >= next))
+            }
+            None => return false,
+        };
+        if fields_due {
+            self.expire_hash_fields(key, now);
+        }
+        false
+    }
+
+    // Removes the key if its TTL has passed, propagating the expiration to
+    // replicas as an explicit DEL. Must not be called while holding a guard
+    // on the key's shard.
+    fn remove_expired_key(&self, key: &str, now: u64) -> bool {
+        let removed = self.replication.apply_and_propagate(|| {
+            let removed = self.data.remove_if(key, |_, value| value.expiry.is_some_and(|expiry| now >= expiry));
+            let del = removed.is_some().then(|| {
+                RespData::Array(vec![RespData::BulkString("DEL".to_string()), RespData::BulkString(key.to_string())])
+            });
+            (removed, del)
+        });
+        let (key, value) = match &removed {
+            Some(removed) => removed,
+            None => return false,
+        };
+        self.volatile_expiry(key);
+        self.adjust_used_memory(0, estimate_entry_size(key, value));
+        ServerStats::incr(&self.stats.expired_keys, 1);
+        invalidate_keys(self, &[key], None);
+        notify(self, notify::EXPIRED, "expired", key);
+        true
+    }
+
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        if hit {
+            ServerStats::incr(&self.stats.keyspace_hits, 1);
+        } else {
+            ServerStats::incr(&self.stats.keyspace_misses, 1);
+        }
+    }
+
+    /// A copy of the value at `key`, if it exists and hasn't expired. Counts
+    /// as a keyspace hit or miss. Copying a large value is expensive, so
+    /// unless the copy is needed, use [`read`](Self::read) instead.
+    pub fn get(&self, key: &str) -> Option<RedisValue> {
+        let now = self.now_ms();
+        let value = if self.expire_if_needed(key, now) {
+            None
+        } else {
+            self.data.get(key).map(|entry| {
+                entry.touch(now);
+                entry.clone()
+            })
+        };
+        self.record_lookup(value.is_some());
+        value
+    }
+
+    /// Runs `f` on the value at `key` in place, if it exists and hasn't
+    /// expired, and returns its result. Counts as a keyspace hit or miss.
+    /// The key's shard is locked while `f` runs, so `f` must not use the
+    /// store.
+    ///
+    /// ```
+    /// use redis::store::{RedisStore, RedisValueType};
+    ///
+    /// let store = RedisStore::new();
+    /// store.rpush("list", vec!["a".to_string(), "b".to_string()]).unwrap();
+    /// let len = store.read("list", |value| match value {
+    ///     RedisValueType::List(list) => list.len(),
+    ///     _ => 0,
+    /// });
+    /// assert_eq!(len, Some(2));
+    /// assert_eq!(store.read("missing", |_| ()), None);
+    /// ```
+    pub fn read<R>(&self, key: &str, f: impl FnOnce(&RedisValueType) -> R) -> Option<R> {
+        let now = self.now_ms();
+        self.expire_if_needed(key, now);
+        let result = self.data.get(key).map(|entry| {
+            entry.touch(now);
+            f(&entry.data)
+        });
+        self.record_lookup(result.is_some());
+        result
+    }
+
+    // Runs `f` on the value at `key`, or on None if the key is missing, failing
+    // with WRONGTYPE if it holds a different kind of value
+    pub(crate) fn read_typed<T>(&self, key: &str, expected: ValueKind, f: impl FnOnce(Option<&RedisValueType>) -> T) -> Result<T, String> {
+        let now = self.now_ms();
+        self.expire_if_needed(key, now);
+        let result = match self.data.get(key) {
+            Some(entry) => {
+                if entry.data.kind() != expected {
+                    return Err(errors::WRONGTYPE.to_string());
+                }
+                entry.touch(now);
+                self.record_lookup(true);
+                f(Some(&entry.data))
+            }
+            None => {
+                self.record_lookup(false);
+                f(None)
+            }
+        };
+        Ok(result)
+    }
+
+    // Write counterpart of `read_typed`, run under the key's shard lock. `f`
+    // may fill an empty slot to create the key or empty it to delete the key,
+    // and returns its result along with the change in the value's size in
+    // bytes. The key keeps its TTL.
+    pub(crate) fn with_typed<T>(
+        &self,
+        key: &str,
+        expected: ValueKind,
+        f: impl FnOnce(&mut Option<RedisValueType>) -> Result<(T, isize), String>,
+    ) -> Result<T, String> {
+        let now = self.now_ms();
+        self.expire_if_needed(key, now);
+
+        let entry_size = (ENTRY_OVERHEAD + key.len()) as isize;
+        let (result, delta) = match self.data.entry(key.to_string()) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().data.kind() != expected {
+                    return Err(errors::WRONGTYPE.to_string());
+                }
+                let mut slot = Some(std::mem::replace(&mut entry.get_mut().data, RedisValueType::Integer(0)));
+                let outcome = f(&mut slot);
+                match (outcome, slot) {
+                    (Ok((result, delta)), None) => {
+                        entry.remove();
+                        (result, delta - entry_size)
+                    }
+                    (outcome, Some(data)) => {
+                        let value = entry.get_mut();
+                        value.data = data;
+                        value.touch(now);
+                        outcome?
+                    }
+                    (Err(e), None) => {
+                        entry.remove();
+                        return Err(e);
+                    }
+                }
+            }
+            Entry::Vacant(entry) => {
+                let mut slot = None;
+                let (result, delta) = f(&mut slot)?;
+                match slot {
+                    Some(data) => {
+                        entry.insert(RedisValue { data, expiry: None, lru: AtomicU64::new(now) });
+                        (result, delta + entry_size)
+                    }
+                    None => (result, delta),
+                }
+            }
+        };
+        self.adjust_used_memory(delta.max(0) as usize, (-delta).max(0) as usize);
+        Ok(result)
+    }
+
+    // Runs `f` on the live value without touching its LRU clock or the keyspace stats
+    pub(crate) fn inspect<R>(&self, key: &str, f: impl FnOnce(&RedisValue) -> R) -> Option<R> {
+        if self.expire_if_needed(key, self.now_ms()) {
+            return None;
+        }
+        self.data.get(key).map(|entry| f(&entry))
+    }
+
+    /// Sets `key`, replacing any previous value and, unless `options` is
+    /// `KEEPTTL`, its TTL. An absolute expiry
+    /// that already passed leaves the key expired right away, like Redis.
+    ///
+    /// ```
+    /// use redis::store::{RedisStore, RedisValueType, SetOptions};
+    ///
+    /// let store = RedisStore::new();
+    /// store.set_with_options("session".to_string(), RedisValueType::String("abc".to_string()), SetOptions::EX(60));
+    /// assert!(store.ttl("session").unwrap().is_some());
+    /// ```
+    pub fn set_with_options(&self, key: String, value: RedisValueType, options: SetOptions) {
+        let expiry = match options {
+            SetOptions::None => None,
+            SetOptions::EX(seconds) => Some(self.now_ms() + seconds * 1000),
+            SetOptions::PX(millis) => Some(self.now_ms() + millis),
+            SetOptions::EXAT(timestamp) => Some(timestamp * 1000),
+            SetOptions::PXAT(timestamp) => Some(timestamp),
+            SetOptions::KEEPTTL => self.inspect(&key, |value| value.expiry).flatten(),
+        };
+
+        let now = self.now_ms();
+        if expiry.is_some_and(|expiry| now >= expiry) {
+            self.insert_entry(key.clone(), RedisValue::new(value, expiry));
+            self.expire_if_needed(&key, now);
+        } else {
+            self.insert_entry(key, RedisValue::new(value, expiry));
+        }
+    }
+
+    /// Whether `key` exists and hasn't expired.
+    pub fn exists(&self, key: &str) -> bool {
+        let exists = !self.expire_if_needed(key, self.now_ms()) && self.data.contains_key(key);
+        self.record_lookup(exists);
+        exists
+    }
+
+    /// Removes `keys`, returning how many existed.
+    pub fn del(&self, keys: &[String]) -> usize {
+        keys.iter().filter(|k| self.remove_entry(k).is_some()).count()
+    }
+
+    /// Time left before `key` expires: `None` if the key doesn't exist,
+    /// `Some(None)` if it never expires.
+    pub fn ttl(&self, key: &str) -> Option<Option<std::time::Duration>> {
+        let now = self.now_ms();
+        self.inspect(key, |value| {
+            value.expiry.map(|expiry| std::time::Duration::from_millis(expiry.saturating_sub(now)))
+        })
+    }
+
+    /// Increments the integer at `key`, starting from 0 if it doesn't exist.
+    /// Errors are the Redis error replies, such as WRONGTYPE.
+    pub fn incr(&self, key: &str) -> Result<i64, String> {
+        self.incr_by(key, 1)
+    }
+
+    /// Decrements the integer at `key`, starting from 0 if it doesn't exist.
+    pub fn decr(&self, key: &str) -> Result<i64, String> {
+        self.incr_by(key, -1)
+    }
+
+    /// Adds `delta` to the integer at `key`. The read-modify-write happens
+    /// under the key's shard lock, so concurrent increments can't lose updates.
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64, String> {
+        self.with_typed(key, ValueKind::String, |slot| {
+            let current = match slot {
+                Some(RedisValueType::Integer(n)) => *n,
+                Some(RedisValueType::String(s)) => s.parse::<i64>().map_err(|_| errors::NOT_INTEGER.to_string())?,
+                _ => 0,
+            };
+            let updated = current.checked_add(delta).ok_or(errors::OVERFLOW)?;
+            let old_size = slot.as_ref().map_or(0, estimate_value_size);
+            *slot = Some(RedisValueType::Integer(updated));
+            Ok((updated, -(old_size as isize)))
+        })
+    }
+
+    /// Prepends `values` to the list at `key`, creating it if needed, and
+    /// returns the new length.
+    pub fn lpush(&self, key: &str, values: Vec<String>) -> Result<usize, String> {
+        self.push(key, values, true)
+    }
+
+    /// Appends `values` to the list at `key`, creating it if needed, and
+    /// returns the new length.
+    pub fn rpush(&self, key: &str, values: Vec<String>) -> Result<usize, String> {
+        self.push(key, values, false)
+    }
+
+    /// Length of the list at `key`, 0 if there is none.
+    pub fn llen(&self, key: &str) -> Result<usize, String> {
+        self.read_typed(key, ValueKind::List, |value| match value {
+            Some(RedisValueType::List(list)) => list.len(),
+            _ => 0,
+        })
+    }
+
+    /// The elements of the list at `key` from `start` to `stop` inclusive,
+    /// negative indexes counting from the tail as in LRANGE. Only those
+    /// elements are copied.
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<String>, String> {
+        self.read_typed(key, ValueKind::List, |value| {
+            let list = match value {
+                Some(RedisValueType::List(list)) => list,
+                _ => return Vec::new(),
+            };
+            match list_range_bounds(list.len(), start, stop) {
+                Some((start, end)) => list.range(start, end).map(str::to_string).collect(),
+                None => Vec::new(),
+            }
+        })
+    }
+
+    /// Sets the given fields of the hash at `key`, creating it if needed,
+    /// and returns how many of them are new.
+    pub fn hset(&self, key: &str, pairs: Vec<(String, String)>) -> Result<usize, String> {
+        hashes::hset(self, key, pairs)
+    }
+
+    // Pushes onto the list in place, creating it if needed
+    pub(crate) fn push(&self, key: &str, values: Vec<String>, front: bool) -> Result<usize, String> {
+        let limit = self.config.read().list_max_listpack_size;
+        self.with_typed(key, ValueKind::List, |slot| {
+            let mut delta = if slot.is_none() { LIST_OVERHEAD as isize } else { 0 };
+            let list = match slot.get_or_insert_with(|| RedisValueType::List(ListValue::new())) {
+                RedisValueType::List(list) => list,
+                _ => unreachable!("with_typed checked the kind"),
+            };
+            for value in values {
+                delta += list.push(value, front, limit);
+            }
+            Ok((list.len(), delta))
+        })
+    }
+
+    /// Serializes the value at `key` in the DUMP format.
+    pub fn dump(&self, key: &str) -> Option<Vec<u8>> {
+        self.get(key).map(|value| create_dump_payload(&value.data))
+    }
+
+    /// Creates `key` from a DUMP payload. `ttl` is in milliseconds, relative
+    /// unless `absttl` is set, with 0 meaning no expiry.
+    pub fn restore(&self, key: &str, ttl: u64, payload: &[u8], replace: bool, absttl: bool) -> Result<(), String> {
+        if !replace && self.exists(key) {
+            return Err("BUSYKEY Target key name already exists.".to_string());
+        }
+
+        let value = parse_dump_payload(payload)?;
+
+        let options = match (ttl, absttl) {
+            (0, _) => SetOptions::None,
+            (timestamp, true) => {
+                // An absolute TTL that already passed leaves nothing to restore
+                if timestamp <= self.now_ms() {
+                    self.remove_entry(key);
+                    return Ok(());
+                }
+                SetOptions::PXAT(timestamp)
+            }
+            (millis, false) => SetOptions::PX(millis),
+        };
+
+        self.set_with_options(key.to_string(), value, options);
+        Ok(())
+    }
+
+    /// Writes the dataset to the `dbfilename` file in `dir`, gzipped if
+    /// `rdbcompression` is on, leaving out keys that have expired but
+    /// weren't removed yet. Expiry times are stored as absolute Unix
+    /// timestamps. The file is written under a temporary name first, so a
+    /// failed save leaves the previous one in place.
+    pub fn save(&self) -> std::io::Result<SaveSummary> {
+        let now = self.now_ms();
+        let data: Vec<(String, RedisValue)> = self.data
+            .iter()
+            .filter(|entry| entry.expiry.is_none_or(|expiry| now < expiry))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+
+        let snapshot = SnapshotRef { version: SNAPSHOT_VERSION, entries: &data };
+        let (path, compressed) = {
+            let config = self.config.read();
+            (config.snapshot_path(), config.rdbcompression)
+        };
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+
+        let result = (|| {
+            let mut writer = BufWriter::new(File::create(&temp)?);
+            // Serialized straight into the file, never whole in memory
+            let uncompressed = if compressed {
+                let mut encoder = GzipWriter::new(writer)?;
+                serde_json::to_writer(&mut encoder, &snapshot)?;
+                let uncompressed = encoder.total_in();
+                writer = encoder.finish()?;
+                uncompressed
+            } else {
+                serde_json::to_writer(&mut writer, &snapshot)?;
+                0
+            };
+            writer.flush()?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            let bytes = file.metadata()?.len();
+            fs::rename(&temp, &path)?;
+            Ok(SaveSummary { bytes, uncompressed: if compressed { uncompressed } else { bytes }, compressed })
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    /// Loads the `dbfilename` file in `dir`, returning `None` if there is
+    /// none. Keys whose expiry has passed are skipped. A file that can't be
+    /// parsed, or is in a newer format, is an `InvalidData` error and leaves
+    /// the store untouched.
+    pub fn load(&self) -> std::io::Result<Option<LoadSummary>> {
+        let path = self.config.read().snapshot_path();
+        let mut reader = match open_snapshot(&path)? {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+
+        let unknown_version = |version: u32| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown snapshot format version {} (this server reads up to {})", version, SNAPSHOT_VERSION),
+            )
+        };
+        let corrupt = |e: serde_json::Error| Error::new(ErrorKind::InvalidData, format!("corrupt snapshot: {}", e));
+        let (version, entries) = if next_byte_is(&mut reader, b'[')? {
+            (0, serde_json::from_reader(reader).map_err(corrupt)?)
+        } else {
+            match serde_json::from_reader::<_, Snapshot>(reader) {
+                Ok(snapshot) if snapshot.version <= SNAPSHOT_VERSION => (snapshot.version, snapshot.entries),
+                Ok(snapshot) => return Err(unknown_version(snapshot.version)),
+                Err(e) => {
+                    // A newer format fails to parse as this one, so read
+                    // the file again for just its version
+                    let header = open_snapshot(&path).ok().flatten().map(serde_json::from_reader::<_, SnapshotHeader>);
+                    return Err(match header {
+                        Some(Ok(header)) if header.version > SNAPSHOT_VERSION => unknown_version(header.version),
+                        _ => corrupt(e),
+                    });
+                }
+            }
+        };
+
+        let now = self.now_ms();
+        let mut summary = LoadSummary { version, loaded: 0, expired: 0 };
+        for (key, value) in entries {
+            if value.expiry.is_some_and(|expiry| now >= expiry) {
+                summary.expired += 1;
+                continue;
+            }
+            self.insert_entry(key, value);
+            summary.loaded += 1;
+        }
+        Ok(Some(summary))
+    }
+
+    // Redis-style adaptive expire cycle: sample the keys with a TTL and keep
+    // going while more than a quarter of a sample turns out to be expired
+    // (or stale in the index), until the time budget runs out. Returns the
+    // number of keys expired.
+    pub(crate) fn active_expire_cycle(&self, budget: std::time::Duration) -> usize {
+        let start = std::time::Instant::now();
+        self.active_expire_hash_fields(start, budget);
+        let mut expired = 0;
+        loop {
+            let now = self.now_ms();
+            let sampled = self.volatile_keys.lock().sample(ACTIVE_EXPIRE_KEYS_PER_LOOP);
+            if sampled.is_empty() {
+                break;
+            }
+
+            let mut reclaimed = 0;
+            for key in &sampled {
+                match self.volatile_expiry(key) {
+                    Some(expiry) if now >= expiry => {
+                        if self.expire_if_needed(key, now) {
+                            expired += 1;
+                            reclaimed += 1;
+                        }
+                    }
+                    Some(_) => {}
+                    None => reclaimed += 1,
+                }
+            }
+
+            if reclaimed * 100 <= sampled.len() * ACTIVE_EXPIRE_STALE_PERCENT || start.elapsed() >= budget {
+                break;
+            }
+        }
+        expired
+    }
+
+    // Visits the hashes whose next field expiry has passed, in due order
+    fn active_expire_hash_fields(&self, start: std::time::Instant, budget: std::time::Duration) {
+        while start.elapsed() < budget {
+            let now = self.now_ms();
+            let key = {
+                let mut queue = self.hash_field_expiries.lock();
+                match queue.peek() {
+                    Some(Reverse((at, _))) if *at <= now => queue.pop().map(|Reverse((_, key))| key),
+                    _ => None,
+                }
+            };
+            let key = match key {
+                Some(key) => key,
+                None => break,
+            };
+            let next_expiry = self.expire_hash_fields(&key, now);
+            if let Some(at) = next_expiry.filter(|at| *at > now) {
+                self.schedule_field_expiry(&key, at);
+            }
+        }
+    }
+
+    // Live keys hashing to cluster slot `slot`. Nothing indexes keys by
+    // slot outside cluster mode, so this scans the keyspace.
+    pub(crate) fn count_keys_in_slot(&self, slot: u16) -> usize {
+        let now = self.now_ms();
+        self.data
+            .iter()
+            .filter(|entry| entry.expiry.is_none_or(|expiry| now < expiry) && key_hash_slot(entry.key().as_bytes()) == slot)
+            .count()
+    }
+
+    // The RDB record of every live entry, by key. Entries are read one
+    // shard at a time, so writers only wait for the shard being copied.
+    pub(crate) fn snapshot_records(&self) -> HashMap<String, Vec<u8>> {
+        let now = self.now_ms();
+        self.data
+            .iter()
+            .filter_map(|entry| Some((entry.key().clone(), rdb_record(entry.key(), entry.value(), now)?)))
+            .collect()
+    }
+
+    // Re-reads the records of the keys `writes` may have touched since
+    // `records` were copied. Keys that no longer exist are dropped. Writes
+    // whose keys aren't among their arguments mean reading everything again.
+    pub(crate) fn refresh_snapshot_records(&self, records: &mut HashMap<String, Vec<u8>>, writes: &[RespData]) {
+        let mut keys = Vec::new();
+        for write in writes {
+            let args = match write {
+                RespData::Array(args) => args,
+                _ => continue,
+            };
+            let spec = match args.first() {
+                Some(RespData::BulkString(name)) => self.commands.lookup(name),
+                _ => None,
+            };
+            if spec.is_none_or(|spec| spec.first_key == 0) {
+                *records = self.snapshot_records();
+                return;
+            }
+            // Every argument is taken for a key: re-reading one that isn't
+            // costs a lookup, while missing a key would lose a write
+            keys.extend(args[1..].iter().filter_map(|arg| match arg {
+                RespData::BulkString(key) => Some(key.as_str()),
+                _ => None,
+            }));
+        }
+
+        let now = self.now_ms();
+        for key in keys {
+            match self.data.get(key).and_then(|entry| rdb_record(key, entry.value(), now)) {
+                Some(record) => records.insert(key.to_string(), record),
+                None => records.remove(key),
+            };
+        }
+    }
+
+    // Full RDB file made of `records`, used for replica full syncs
+    pub(crate) fn rdb_snapshot(&self, records: HashMap<String, Vec<u8>>) -> Vec<u8> {
+        let mut rdb = Vec::new();
+        rdb.extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+        rdb.push(RDB_OPCODE_SELECTDB);
+        rdb_write_len(&mut rdb, 0);
+        for record in records.into_values() {
+            rdb.extend_from_slice(&record);
+        }
+        rdb.push(RDB_OPCODE_EOF);
+        let crc = crc64(&rdb);
+        rdb.extend_from_slice(&crc.to_le_bytes());
+        rdb
+    }
+}
+
+// The RDB encoding of one entry with its expiry, or None if it expired
+fn rdb_record(key: &str, value: &RedisValue, now: u64) -> Option<Vec<u8>> {
+    let mut record = Vec::new();
+    if let Some(expiry) = value.expiry {
+        if now >= expiry {
+            return None;
+        }
+        record.push(RDB_OPCODE_EXPIRETIME_MS);
+        record.extend_from_slice(&expiry.to_le_bytes());
+    }
+    record.push(rdb_value_type(&value.data));
+    rdb_write_string(&mut record, key.as_bytes());
+    rdb_write_object(&mut record, &value.data);
+    Some(record)
+}
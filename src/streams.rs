@@ -0,0 +1,728 @@
+// Streams: an append-only log of entries, each a list of field-value pairs
+// under an ID that only grows, and the consumer groups reading it. Besides
+// the entries, a stream keeps the counters XINFO reports, which is also how
+// a group's lag is worked out without walking the log.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::store::{RedisStore, RedisValueType, ValueKind, STREAM_OVERHEAD, STRING_OVERHEAD};
+
+pub const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+pub const BUSYGROUP: &str = "BUSYGROUP Consumer Group name already exists";
+pub const NO_SUCH_KEY: &str = "ERR no such key";
+const XGROUP_NO_KEY: &str = "ERR The XGROUP subcommand requires the key to exist. \
+Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+// Approximate heap sizes for memory accounting, on top of the strings
+const ENTRY_SIZE: usize = 48;
+const GROUP_SIZE: usize = 96;
+const CONSUMER_SIZE: usize = 64;
+// Per pending entry, counting its place in both the group and the consumer
+const PENDING_SIZE: usize = 64;
+
+pub fn no_group(key: &str, group: &str) -> String {
+    format!("NOGROUP No such consumer group '{}' for key name '{}'", group, key)
+}
+
+/// An entry ID: a Unix time in milliseconds and a sequence number within it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+
+    // "ms-seq", or a bare "ms" taking `missing_seq` as its sequence number
+    pub fn parse(s: &str, missing_seq: u64) -> Option<StreamId> {
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().ok()?),
+            None => (s, missing_seq),
+        };
+        Some(StreamId { ms: ms.parse().ok()?, seq })
+    }
+
+    // The ID right after this one, None after the last possible ID
+    pub fn next(self) -> Option<StreamId> {
+        if self.seq < u64::MAX {
+            Some(StreamId::new(self.ms, self.seq + 1))
+        } else if self.ms < u64::MAX {
+            Some(StreamId::new(self.ms + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    // The ID right before this one, None before 0-0
+    pub fn prev(self) -> Option<StreamId> {
+        if self.seq > 0 {
+            Some(StreamId::new(self.ms, self.seq - 1))
+        } else if self.ms > 0 {
+            Some(StreamId::new(self.ms - 1, u64::MAX))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl From<StreamId> for String {
+    fn from(id: StreamId) -> String {
+        id.to_string()
+    }
+}
+
+impl TryFrom<String> for StreamId {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        StreamId::parse(&s, 0).ok_or_else(|| format!("invalid stream ID '{}'", s))
+    }
+}
+
+pub type Fields = Vec<(String, String)>;
+
+/// A stream's entries and consumer groups.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamValue {
+    entries: BTreeMap<StreamId, Fields>,
+    /// ID of the last entry ever added; new IDs must be above it
+    pub last_id: StreamId,
+    /// ID of the first entry, 0-0 while the stream is empty
+    pub first_id: StreamId,
+    /// Highest ID removed by XDEL
+    pub max_deleted_id: StreamId,
+    /// Entries ever added, including those deleted or trimmed since
+    pub entries_added: u64,
+    pub groups: BTreeMap<String, ConsumerGroup>,
+}
+
+/// A consumer group: how far it has read, and what it delivered but
+/// hasn't had acknowledged yet.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConsumerGroup {
+    pub last_id: StreamId,
+    /// Entries read up to `last_id` since the stream began, None when that
+    /// can't be told
+    pub entries_read: Option<u64>,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingEntry {
+    pub consumer: String,
+    /// When the entry was last delivered, in Unix milliseconds
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Consumer {
+    /// Last time the consumer tried to read, in Unix milliseconds
+    pub seen_time: u64,
+    /// Last time the consumer was delivered something, if ever
+    pub active_time: Option<u64>,
+    pub pending: BTreeSet<StreamId>,
+}
+
+// How XADD picks the new entry's ID
+#[derive(Clone, Copy)]
+pub enum NewId {
+    // `*`: the current time, or right after the last ID
+    Auto,
+    // `ms-*`: the next sequence number for this time
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+// XADD's and XTRIM's MAXLEN and MINID
+#[derive(Clone, Copy)]
+pub enum Trim {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+fn fields_size(fields: &Fields) -> usize {
+    ENTRY_SIZE + fields.iter().map(|(name, value)| 2 * STRING_OVERHEAD + name.len() + value.len()).sum::<usize>()
+}
+
+fn pending_size(consumer: &str) -> usize {
+    PENDING_SIZE + consumer.len()
+}
+
+fn consumer_size(name: &str) -> usize {
+    CONSUMER_SIZE + name.len()
+}
+
+impl ConsumerGroup {
+    fn heap_size(&self, name: &str) -> usize {
+        GROUP_SIZE
+            + name.len()
+            + self.pending.values().map(|entry| pending_size(&entry.consumer)).sum::<usize>()
+            + self.consumers.keys().map(|name| consumer_size(name)).sum::<usize>()
+    }
+
+    // The consumer, created if it doesn't exist yet, and the size change
+    fn consumer(&mut self, name: &str, now: u64) -> (&mut Consumer, isize) {
+        let mut delta = 0;
+        if !self.consumers.contains_key(name) {
+            let consumer = Consumer { seen_time: now, ..Consumer::default() };
+            self.consumers.insert(name.to_string(), consumer);
+            delta = consumer_size(name) as isize;
+        }
+        (self.consumers.get_mut(name).expect("just inserted"), delta)
+    }
+
+    // Makes `id` pending for `consumer`, taking it from whoever had it
+    fn deliver(&mut self, id: StreamId, consumer: &str, now: u64) -> isize {
+        let mut delta = pending_size(consumer) as isize;
+        let entry = PendingEntry { consumer: consumer.to_string(), delivery_time: now, delivery_count: 1 };
+        if let Some(previous) = self.pending.insert(id, entry) {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+            delta -= pending_size(&previous.consumer) as isize;
+        }
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+        }
+        delta
+    }
+
+    // Drops `id` from the pending entries, returning the size change if it
+    // was there
+    fn acknowledge(&mut self, id: StreamId) -> Option<isize> {
+        let entry = self.pending.remove(&id)?;
+        if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+            owner.pending.remove(&id);
+        }
+        Some(-(pending_size(&entry.consumer) as isize))
+    }
+}
+
+// The counters a group's lag is worked out from, copied out of the stream
+// so its groups can be changed while they're consulted
+#[derive(Clone, Copy)]
+struct Counters {
+    len: usize,
+    first_id: StreamId,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    entries_added: u64,
+}
+
+impl Counters {
+    // Whether XDEL removed anything from `start` on, which makes counting
+    // entries from IDs unreliable
+    fn has_tombstones_from(&self, start: StreamId) -> bool {
+        self.len > 0 && self.max_deleted_id != StreamId::MIN && start <= self.max_deleted_id
+    }
+
+    // How many entries up to and including `id` were ever added, when the
+    // counters alone tell
+    fn entries_read_at(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.len == 0 && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        if id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+        // Only without deletions past the first entry are the entries
+        // before it exactly the ones trimmed away
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < self.first_id {
+            if id < self.first_id {
+                return Some(self.entries_added - self.len as u64);
+            }
+            if id == self.first_id {
+                return Some(self.entries_added - self.len as u64 + 1);
+            }
+        }
+        None
+    }
+
+    fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if let Some(read) = group.entries_read {
+            if !self.has_tombstones_from(group.last_id) {
+                return Some(self.entries_added.saturating_sub(read));
+            }
+        }
+        self.entries_read_at(group.last_id).map(|read| self.entries_added - read)
+    }
+}
+
+impl StreamValue {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn first_entry(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.first_key_value()
+    }
+
+    pub fn last_entry(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.last_key_value()
+    }
+
+    // Entries with IDs from `start` to `end`, inclusive
+    pub fn range(&self, start: StreamId, end: StreamId) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        let range = if start <= end { Some(self.entries.range(start..=end)) } else { None };
+        range.into_iter().flatten()
+    }
+
+    fn counters(&self) -> Counters {
+        Counters {
+            len: self.entries.len(),
+            first_id: self.first_id,
+            last_id: self.last_id,
+            max_deleted_id: self.max_deleted_id,
+            entries_added: self.entries_added,
+        }
+    }
+
+    // The group's lag: entries added that it hasn't read, None when that
+    // can't be told
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        self.counters().lag(group)
+    }
+
+    // For aggregates, measures at most `samples` entries (0 = all) and
+    // extrapolates to the full size. Groups are always measured whole.
+    pub(crate) fn heap_size(&self, samples: usize) -> usize {
+        let len = self.entries.len();
+        let entries = if samples == 0 || len <= samples {
+            self.entries.values().map(fields_size).sum()
+        } else {
+            self.entries.values().take(samples).map(fields_size).sum::<usize>() * len / samples
+        };
+        entries + self.groups.iter().map(|(name, group)| group.heap_size(name)).sum::<usize>()
+    }
+
+    // The ID XADD would give its next entry
+    pub fn next_id(&self, requested: NewId, now: u64) -> Result<StreamId, String> {
+        let too_small = "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+        match requested {
+            NewId::Auto if now > self.last_id.ms => Ok(StreamId::new(now, 0)),
+            NewId::Auto => self
+                .last_id
+                .next()
+                .ok_or_else(|| "ERR The stream has exhausted the last possible ID, unable to add more items".to_string()),
+            NewId::AutoSeq(ms) if ms > self.last_id.ms => Ok(StreamId::new(ms, 0)),
+            NewId::AutoSeq(ms) if ms == self.last_id.ms && self.last_id.seq < u64::MAX => {
+                Ok(StreamId::new(ms, self.last_id.seq + 1))
+            }
+            NewId::AutoSeq(_) => Err(too_small.to_string()),
+            NewId::Explicit(id) if id == StreamId::MIN => Err("ERR The ID specified in XADD must be greater than 0-0".to_string()),
+            NewId::Explicit(id) if id <= self.last_id => Err(too_small.to_string()),
+            NewId::Explicit(id) => Ok(id),
+        }
+    }
+
+    // Appends an entry under `id`, which must be above `last_id`. Returns
+    // the size change.
+    pub fn add(&mut self, id: StreamId, fields: Fields) -> isize {
+        let size = fields_size(&fields) as isize;
+        if self.entries.is_empty() {
+            self.first_id = id;
+        }
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+        size
+    }
+
+    // Drops the oldest entries until the stream satisfies `trim`, returning
+    // how many went and the size change
+    pub fn trim(&mut self, trim: Trim) -> (usize, isize) {
+        let (mut removed, mut delta) = (0, 0);
+        while let Some(first) = self.entries.first_key_value().map(|(id, _)| *id) {
+            let keep = match trim {
+                Trim::MaxLen(max) => self.entries.len() <= max,
+                Trim::MinId(min) => first >= min,
+            };
+            if keep {
+                break;
+            }
+            let fields = self.entries.remove(&first).expect("first entry exists");
+            delta -= fields_size(&fields) as isize;
+            removed += 1;
+        }
+        if removed > 0 {
+            self.first_id = self.entries.keys().next().copied().unwrap_or_default();
+        }
+        (removed, delta)
+    }
+
+    // Deletes the entry `id`, returning the size change if it existed
+    pub fn delete(&mut self, id: StreamId) -> Option<isize> {
+        let fields = self.entries.remove(&id)?;
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        if id == self.first_id || self.entries.is_empty() {
+            self.first_id = self.entries.keys().next().copied().unwrap_or_default();
+        }
+        Some(-(fields_size(&fields) as isize))
+    }
+
+    // Adds a group that has read up to `last_id`, returning the size change,
+    // or None if the name is taken
+    pub fn create_group(&mut self, name: &str, last_id: StreamId, entries_read: Option<u64>) -> Option<isize> {
+        if self.groups.contains_key(name) {
+            return None;
+        }
+        let group = ConsumerGroup { last_id, entries_read, ..ConsumerGroup::default() };
+        let size = group.heap_size(name) as isize;
+        self.groups.insert(name.to_string(), group);
+        Some(size)
+    }
+
+    // Moves a group's position, as XGROUP SETID
+    pub fn set_group_id(&mut self, name: &str, last_id: StreamId, entries_read: Option<u64>) -> bool {
+        match self.groups.get_mut(name) {
+            Some(group) => {
+                group.last_id = last_id;
+                group.entries_read = entries_read;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Removes a group, returning the size change if it existed
+    pub fn destroy_group(&mut self, name: &str) -> Option<isize> {
+        let group = self.groups.remove(name)?;
+        Some(-(group.heap_size(name) as isize))
+    }
+
+    // Delivers up to `count` entries (0 = all) the group hasn't read yet to
+    // `consumer`, making them pending for it unless `noack`. None if there's
+    // no such group.
+    pub fn read_new(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        noack: bool,
+        now: u64,
+    ) -> Option<(Vec<(StreamId, Fields)>, isize)> {
+        let counters = self.counters();
+        let group = self.groups.get_mut(group)?;
+        let (reader, mut delta) = group.consumer(consumer, now);
+        reader.seen_time = now;
+        let mut delivered = Vec::new();
+        let start = match group.last_id.next() {
+            Some(start) => start,
+            None => return Some((delivered, delta)),
+        };
+        for (id, fields) in self.entries.range(start..) {
+            if count > 0 && delivered.len() == count {
+                break;
+            }
+            group.entries_read = match group.entries_read {
+                Some(read) if !counters.has_tombstones_from(*id) => Some(read + 1),
+                _ => counters.entries_read_at(*id),
+            };
+            group.last_id = *id;
+            if !noack {
+                delta += group.deliver(*id, consumer, now);
+            }
+            delivered.push((*id, fields.clone()));
+        }
+        if !delivered.is_empty() {
+            if let Some(reader) = group.consumers.get_mut(consumer) {
+                reader.active_time = Some(now);
+            }
+        }
+        Some((delivered, delta))
+    }
+
+    // Redelivers up to `count` (0 = all) of the consumer's pending entries
+    // with IDs above `after`. Entries deleted since come back without
+    // fields. None if there's no such group.
+    #[allow(clippy::type_complexity)]
+    pub fn read_pending(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: StreamId,
+        count: usize,
+        now: u64,
+    ) -> Option<(Vec<(StreamId, Option<Fields>)>, isize)> {
+        let group = self.groups.get_mut(group)?;
+        let (reader, delta) = group.consumer(consumer, now);
+        reader.seen_time = now;
+        let mut delivered = Vec::new();
+        if let Some(start) = after.next() {
+            let ids = reader.pending.range(start..).take(if count == 0 { usize::MAX } else { count });
+            for id in ids.copied().collect::<Vec<_>>() {
+                if let Some(entry) = group.pending.get_mut(&id) {
+                    entry.delivery_time = now;
+                    entry.delivery_count += 1;
+                }
+                delivered.push((id, self.entries.get(&id).cloned()));
+            }
+        }
+        Some((delivered, delta))
+    }
+
+    // Acknowledges `ids`, returning how many were pending and the size
+    // change. None if there's no such group.
+    pub fn ack(&mut self, group: &str, ids: &[StreamId]) -> Option<(usize, isize)> {
+        let group = self.groups.get_mut(group)?;
+        let (mut acked, mut delta) = (0, 0);
+        for id in ids {
+            if let Some(size) = group.acknowledge(*id) {
+                acked += 1;
+                delta += size;
+            }
+        }
+        Some((acked, delta))
+    }
+}
+
+fn stream_mut(slot: &mut Option<RedisValueType>) -> &mut StreamValue {
+    match slot.get_or_insert_with(|| RedisValueType::Stream(Box::default())) {
+        RedisValueType::Stream(stream) => stream,
+        _ => unreachable!("with_typed checked the kind"),
+    }
+}
+
+// Adds an entry, creating the stream unless `nomkstream`, then trims it.
+// Returns the new entry's ID, or None if there was no stream to add to.
+pub fn xadd(
+    store: &RedisStore,
+    key: &str,
+    id: NewId,
+    fields: Fields,
+    nomkstream: bool,
+    trim: Option<Trim>,
+) -> Result<Option<StreamId>, String> {
+    let now = store.now_ms();
+    store.with_typed(key, ValueKind::Stream, |slot| {
+        if slot.is_none() && nomkstream {
+            return Ok((None, 0));
+        }
+        let mut delta = if slot.is_none() { STREAM_OVERHEAD as isize } else { 0 };
+        let created = slot.is_none();
+        let stream = stream_mut(slot);
+        let id = match stream.next_id(id, now) {
+            Ok(id) => id,
+            Err(e) => {
+                if created {
+                    *slot = None;
+                }
+                return Err(e);
+            }
+        };
+        delta += stream.add(id, fields);
+        if let Some(trim) = trim {
+            delta += stream.trim(trim).1;
+        }
+        Ok((Some(id), delta))
+    })
+}
+
+// Deletes entries, returning how many existed. The stream stays, empty or
+// not, as in Redis.
+pub fn xdel(store: &RedisStore, key: &str, ids: &[StreamId]) -> Result<usize, String> {
+    store.with_typed(key, ValueKind::Stream, |slot| {
+        let stream = match slot {
+            Some(RedisValueType::Stream(stream)) => stream,
+            _ => return Ok((0, 0)),
+        };
+        let (mut deleted, mut delta) = (0, 0);
+        for id in ids {
+            if let Some(size) = stream.delete(*id) {
+                deleted += 1;
+                delta += size;
+            }
+        }
+        Ok((deleted, delta))
+    })
+}
+
+// Where XGROUP CREATE and SETID put a group: at an ID or after the last entry
+#[derive(Clone, Copy)]
+pub enum GroupStart {
+    Id(StreamId),
+    End,
+}
+
+impl GroupStart {
+    fn resolve(self, stream: &StreamValue) -> StreamId {
+        match self {
+            GroupStart::Id(id) => id,
+            GroupStart::End => stream.last_id,
+        }
+    }
+}
+
+pub fn xgroup_create(
+    store: &RedisStore,
+    key: &str,
+    group: &str,
+    start: GroupStart,
+    mkstream: bool,
+    entries_read: Option<u64>,
+) -> Result<(), String> {
+    store.with_typed(key, ValueKind::Stream, |slot| {
+        if slot.is_none() && !mkstream {
+            return Err(XGROUP_NO_KEY.to_string());
+        }
+        let mut delta = if slot.is_none() { STREAM_OVERHEAD as isize } else { 0 };
+        let created = slot.is_none();
+        let stream = stream_mut(slot);
+        let last_id = start.resolve(stream);
+        match stream.create_group(group, last_id, entries_read) {
+            Some(size) => delta += size,
+            None => {
+                if created {
+                    *slot = None;
+                }
+                return Err(BUSYGROUP.to_string());
+            }
+        }
+        Ok(((), delta))
+    })
+}
+
+// Runs `f` on an existing stream's value, failing like Redis's XGROUP
+// subcommands when there's no stream
+fn with_stream<T>(
+    store: &RedisStore,
+    key: &str,
+    f: impl FnOnce(&mut StreamValue) -> Result<(T, isize), String>,
+) -> Result<T, String> {
+    store.with_typed(key, ValueKind::Stream, |slot| match slot {
+        Some(RedisValueType::Stream(stream)) => f(stream),
+        _ => Err(XGROUP_NO_KEY.to_string()),
+    })
+}
+
+pub fn xgroup_setid(
+    store: &RedisStore,
+    key: &str,
+    group: &str,
+    start: GroupStart,
+    entries_read: Option<u64>,
+) -> Result<(), String> {
+    with_stream(store, key, |stream| {
+        let last_id = start.resolve(stream);
+        match stream.set_group_id(group, last_id, entries_read) {
+            true => Ok(((), 0)),
+            false => Err(no_group(key, group)),
+        }
+    })
+}
+
+// Removes a group, returning whether it existed
+pub fn xgroup_destroy(store: &RedisStore, key: &str, group: &str) -> Result<bool, String> {
+    with_stream(store, key, |stream| Ok(stream.destroy_group(group).map_or((false, 0), |size| (true, size))))
+}
+
+// Adds a consumer to a group, returning whether it's new
+pub fn xgroup_createconsumer(store: &RedisStore, key: &str, group: &str, consumer: &str) -> Result<bool, String> {
+    let now = store.now_ms();
+    with_stream(store, key, |stream| {
+        let group_value = stream.groups.get_mut(group).ok_or_else(|| no_group(key, group))?;
+        let (_, delta) = group_value.consumer(consumer, now);
+        Ok((delta > 0, delta))
+    })
+}
+
+// Removes a consumer and its pending entries, returning how many it had
+pub fn xgroup_delconsumer(store: &RedisStore, key: &str, group: &str, consumer: &str) -> Result<usize, String> {
+    with_stream(store, key, |stream| {
+        let group_value = stream.groups.get_mut(group).ok_or_else(|| no_group(key, group))?;
+        let removed = match group_value.consumers.remove(consumer) {
+            Some(removed) => removed,
+            None => return Ok((0, 0)),
+        };
+        let mut delta = -(consumer_size(consumer) as isize);
+        for id in &removed.pending {
+            if let Some(size) = group_value.acknowledge(*id) {
+                delta += size;
+            }
+        }
+        Ok((removed.pending.len(), delta))
+    })
+}
+
+// Where XREADGROUP reads a stream from: entries never delivered to the group
+// (`>`), or the consumer's own pending entries after an ID
+#[derive(Clone, Copy)]
+pub enum ReadFrom {
+    New,
+    Pending(StreamId),
+}
+
+// Whether the group exists on the stream at `key`, which fails if the key
+// holds another kind of value
+pub fn has_group(store: &RedisStore, key: &str, group: &str) -> Result<bool, String> {
+    store.read_typed(key, ValueKind::Stream, |value| match value {
+        Some(RedisValueType::Stream(stream)) => stream.groups.contains_key(group),
+        _ => false,
+    })
+}
+
+// One stream's part of XREADGROUP
+#[allow(clippy::type_complexity)]
+pub fn xreadgroup(
+    store: &RedisStore,
+    key: &str,
+    group: &str,
+    consumer: &str,
+    from: ReadFrom,
+    count: usize,
+    noack: bool,
+) -> Result<Vec<(StreamId, Option<Fields>)>, String> {
+    let now = store.now_ms();
+    store.with_typed(key, ValueKind::Stream, |slot| {
+        let stream = match slot {
+            Some(RedisValueType::Stream(stream)) => stream,
+            _ => return Err(no_group(key, group)),
+        };
+        let read = match from {
+            ReadFrom::New => stream.read_new(group, consumer, count, noack, now).map(|(entries, delta)| {
+                (entries.into_iter().map(|(id, fields)| (id, Some(fields))).collect(), delta)
+            }),
+            ReadFrom::Pending(after) => stream.read_pending(group, consumer, after, count, now),
+        };
+        read.ok_or_else(|| no_group(key, group))
+    })
+}
+
+// Acknowledges entries, returning how many were pending
+pub fn xack(store: &RedisStore, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, String> {
+    store.with_typed(key, ValueKind::Stream, |slot| match slot {
+        Some(RedisValueType::Stream(stream)) => Ok(stream.ack(group, ids).unwrap_or((0, 0))),
+        _ => Ok((0, 0)),
+    })
+}
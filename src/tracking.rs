@@ -0,0 +1,191 @@
+// Client-side caching (CLIENT TRACKING): which clients may have cached
+// which keys, and the invalidation messages sent when those keys change
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::resp::RespData;
+use crate::store::RedisStore;
+
+// Where RESP2 clients receive invalidations, through a redirect client
+// subscribed to it
+pub(crate) const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+// What CLIENT TRACKING ON was asked for
+#[derive(Clone, Default, PartialEq)]
+pub(crate) struct TrackingOptions {
+    pub(crate) redirect: Option<u64>,
+    // Invalidate every key under the prefixes, whether read or not
+    pub(crate) bcast: bool,
+    pub(crate) prefixes: Vec<String>,
+    // Only track reads after CLIENT CACHING YES
+    pub(crate) optin: bool,
+    // Track reads except after CLIENT CACHING NO
+    pub(crate) optout: bool,
+    // Not told about the client's own writes
+    pub(crate) noloop: bool,
+}
+
+impl TrackingOptions {
+    // Whether keys the client reads now should be remembered, given its
+    // CLIENT CACHING answer for the current command
+    pub(crate) fn tracks_reads(&self, caching: Option<bool>) -> bool {
+        !self.bcast && (!self.optin || caching == Some(true)) && (!self.optout || caching != Some(false))
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    clients: HashMap<u64, TrackingOptions>,
+    // Readers of each key; a key is forgotten once invalidated, until read again
+    keys: HashMap<String, HashSet<u64>>,
+    // BCAST clients by prefix, "" matching every key
+    prefixes: HashMap<String, HashSet<u64>>,
+}
+
+#[derive(Default)]
+pub(crate) struct TrackingTable {
+    table: Mutex<Table>,
+    // Lets writes skip the lock while no client tracks
+    enabled: AtomicBool,
+}
+
+impl TrackingTable {
+    pub(crate) fn enable(&self, id: u64, options: TrackingOptions) {
+        let mut table = self.table.lock();
+        if options.bcast {
+            let prefixes = if options.prefixes.is_empty() { vec![String::new()] } else { options.prefixes.clone() };
+            for prefix in prefixes {
+                table.prefixes.entry(prefix).or_default().insert(id);
+            }
+        }
+        table.clients.insert(id, options);
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    // Also called when a client disconnects
+    pub(crate) fn disable(&self, id: u64) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let table = &mut *self.table.lock();
+        if table.clients.remove(&id).is_none() {
+            return;
+        }
+        for readers in [&mut table.keys, &mut table.prefixes] {
+            readers.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+        self.enabled.store(!table.clients.is_empty(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn remember<'a>(&self, id: u64, keys: impl Iterator<Item = &'a str>) {
+        let mut table = self.table.lock();
+        for key in keys {
+            match table.keys.get_mut(key) {
+                Some(readers) => {
+                    readers.insert(id);
+                }
+                None => {
+                    table.keys.insert(key.to_string(), HashSet::from([id]));
+                }
+            }
+        }
+    }
+
+    // The clients to tell that `keys` changed, each with its options and the
+    // keys that concern it. `by` is the client that changed them, if any.
+    fn take_invalidations(&self, keys: &[&str], by: Option<u64>) -> Vec<(u64, TrackingOptions, Vec<String>)> {
+        let mut table = self.table.lock();
+        let mut concerned: HashMap<u64, Vec<String>> = HashMap::new();
+        for key in keys {
+            for id in table.keys.remove(*key).into_iter().flatten() {
+                concerned.entry(id).or_default().push(key.to_string());
+            }
+            for (prefix, ids) in &table.prefixes {
+                if key.starts_with(prefix.as_str()) {
+                    for id in ids {
+                        concerned.entry(*id).or_default().push(key.to_string());
+                    }
+                }
+            }
+        }
+        concerned
+            .into_iter()
+            .filter_map(|(id, mut keys)| {
+                let options = table.clients.get(&id)?;
+                if options.noloop && by == Some(id) {
+                    return None;
+                }
+                keys.dedup();
+                Some((id, options.clone(), keys))
+            })
+            .collect()
+    }
+
+    // Every tracking client, forgetting what they read, for a flush
+    fn take_all(&self) -> Vec<(u64, TrackingOptions)> {
+        let mut table = self.table.lock();
+        table.keys.clear();
+        table.clients.iter().map(|(id, options)| (*id, options.clone())).collect()
+    }
+}
+
+// The invalidation message for `keys` (None meaning every key) as the
+// receiving client expects it, or None for a RESP2 client that isn't a
+// subscribed redirect target and so has no way to receive it
+fn invalidation(keys: Option<Vec<String>>, protocol: u8, subscribed: bool) -> Option<RespData> {
+    let keys = keys.map_or(RespData::Null, |keys| RespData::Array(keys.into_iter().map(RespData::BulkString).collect()));
+    if protocol >= 3 {
+        Some(RespData::Push(vec![RespData::BulkString("invalidate".to_string()), keys]))
+    } else if subscribed {
+        Some(RespData::Array(vec![
+            RespData::BulkString("message".to_string()),
+            RespData::BulkString(INVALIDATE_CHANNEL.to_string()),
+            keys,
+        ]))
+    } else {
+        None
+    }
+}
+
+// Sends an invalidation to the tracking client `id` or its redirect target,
+// telling a RESP3 client when its redirect target has gone away
+fn deliver(store: &RedisStore, id: u64, options: &TrackingOptions, keys: Option<Vec<String>>) {
+    let target = options.redirect.unwrap_or(id);
+    let redirected = options.redirect.is_some();
+    let subscribed = redirected && store.clients.is_subscribed(target, INVALIDATE_CHANNEL);
+    if store.clients.send(target, |protocol| invalidation(keys, protocol, subscribed)) || !redirected {
+        return;
+    }
+    store.clients.send(id, |protocol| {
+        (protocol >= 3).then(|| {
+            RespData::Push(vec![RespData::BulkString("tracking-redir-broken".to_string()), RespData::Integer(target as i64)])
+        })
+    });
+}
+
+// Tells the clients tracking `keys` that they changed. `by` is the client
+// whose command changed them, None for expiry and eviction.
+pub(crate) fn invalidate_keys(store: &RedisStore, keys: &[&str], by: Option<u64>) {
+    if !store.tracking.enabled.load(Ordering::Relaxed) || keys.is_empty() {
+        return;
+    }
+    for (id, options, keys) in store.tracking.take_invalidations(keys, by) {
+        deliver(store, id, &options, Some(keys));
+    }
+}
+
+// Tells every tracking client to drop its whole cache
+pub(crate) fn invalidate_all(store: &RedisStore) {
+    if !store.tracking.enabled.load(Ordering::Relaxed) {
+        return;
+    }
+    for (id, options) in store.tracking.take_all() {
+        deliver(store, id, &options, None);
+    }
+}
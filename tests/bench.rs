@@ -0,0 +1,43 @@
+mod common;
+
+use common::{run_binary, spawn_server};
+use redis::resp::RespData;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn a_small_benchmark_completes_with_throughput() {
+    let server = spawn_server().await;
+    let port = server.addr.port().to_string();
+
+    let args = ["-p", &port, "-c", "4", "-n", "200", "-P", "8", "-r", "50", "-t", "ping,set,get,incr,lpush,lrange", "--csv"];
+    let (code, out, err) = run_binary(env!("CARGO_BIN_EXE_bench"), &args, b"").await;
+    assert_eq!(code, 0, "{}", err);
+    assert!(err.is_empty(), "{}", err);
+    let mut lines = out.lines();
+    assert_eq!(lines.next().unwrap().split(',').count(), 8);
+    let tests: Vec<&str> = lines
+        .map(|line| {
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim_matches('"')).collect();
+            let rps: f64 = fields[1].parse().unwrap();
+            assert!(rps > 0.0, "{}", line);
+            fields[0]
+        })
+        .collect();
+    assert_eq!(tests, ["PING", "SET", "GET", "INCR", "LPUSH", "LRANGE_100 (first 100 elements)"]);
+
+    // Keys were spread over the keyspace, and every request was sent
+    let mut client = server.client().await;
+    match client.cmd(&["DBSIZE"]).await {
+        RespData::Integer(n) => assert!(n > 2 && n <= 101, "{}", n),
+        reply => panic!("DBSIZE: {:?}", reply),
+    }
+    let list = client.cmd(&["SORT", "mylist", "BY", "nosort"]).await;
+    assert!(matches!(&list, RespData::Array(items) if items.len() == 200), "{:?}", list);
+
+    // Error replies are counted and reported without stopping the run
+    let args = ["-p", &port, "-c", "2", "-n", "10", "-t", "incr", "-q"];
+    client.cmd(&["SET", "counter:000000000000", "x"]).await;
+    let (code, out, err) = run_binary(env!("CARGO_BIN_EXE_bench"), &args, b"").await;
+    assert_eq!(code, 0);
+    assert!(out.starts_with("INCR: "), "{}", out);
+    assert!(err.starts_with("INCR: 10 requests failed, the first with: ERR value is not an integer"), "{}", err);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{bulk, run_binary, spawn_server, spawn_server_with, TempDir, TestServer};
+use redis::resp::{serialize_resp, RespData};
+
+// Runs the CLI against `server` with `args`, feeding it `stdin`, and
+// returns its exit code and output
+async fn run_cli(server: &TestServer, args: &[&str], stdin: &[u8]) -> (i32, String, String) {
+    let port = server.addr.port().to_string();
+    let mut cli_args = vec!["-p", port.as_str()];
+    cli_args.extend_from_slice(args);
+    run(&cli_args, stdin).await
+}
+
+async fn run(args: &[&str], stdin: &[u8]) -> (i32, String, String) {
+    run_binary(env!("CARGO_BIN_EXE_cli"), args, stdin).await
+}
+
+#[tokio::test]
+async fn commands_from_arguments_print_every_reply_type() {
+    let server = spawn_server().await;
+
+    assert_eq!(run_cli(&server, &["SET", "greeting", "hello \"world\"\n"], b"").await.1, "OK\n");
+    assert_eq!(run_cli(&server, &["GET", "greeting"], b"").await.1, "\"hello \\\"world\\\"\\n\"\n");
+    assert_eq!(run_cli(&server, &["GET", "missing"], b"").await.1, "(nil)\n");
+    assert_eq!(run_cli(&server, &["INCR", "counter"], b"").await.1, "(integer) 1\n");
+    assert_eq!(run_cli(&server, &["INCR", "greeting"], b"").await.1, "(error) ERR value is not an integer or out of range\n");
+    assert_eq!(run_cli(&server, &["SORT", "missing"], b"").await.1, "(empty array)\n");
+
+    run_cli(&server, &["RPUSH", "list", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10"], b"").await;
+    let (_, out, _) = run_cli(&server, &["SORT", "list", "LIMIT", "7", "3", "GET", "#", "GET", "nope"], b"").await;
+    assert_eq!(out, "1) \"8\"\n2) (nil)\n3) \"9\"\n4) (nil)\n5) \"10\"\n6) (nil)\n");
+    let (_, out, _) = run_cli(&server, &["SORT", "list"], b"").await;
+    assert!(out.starts_with(" 1) \"1\"\n 2) \"2\"\n") && out.ends_with("10) \"10\"\n"), "{}", out);
+
+    // Nested aggregates are numbered under their parent
+    run_cli(&server, &["HSET", "h", "f", "v"], b"").await;
+    let (_, out, _) = run_cli(&server, &["-3", "HELLO", "3"], b"").await;
+    assert!(out.starts_with("1# \"server\" => \"redis\"\n2# \"version\" => "), "{}", out);
+    assert!(out.contains("\n7# \"modules\" => (empty array)\n"), "{}", out);
+    let (_, out, _) = run_cli(&server, &["HELLO", "2"], b"").await;
+    assert!(out.starts_with(" 1) \"server\"\n 2) \"redis\"\n"), "{}", out);
+}
+
+#[tokio::test]
+async fn commands_are_read_from_stdin_with_quoting() {
+    let server = spawn_server().await;
+
+    let input = b"SET \"two words\" 'it\\'s'\nSET k \"a\\x41\"\n\nGET \"two words\"\nGET k\nSET 'a'b c\nGET \"open\nPING\nQUIT\nPING\n";
+    let (code, out, _) = run_cli(&server, &[], input).await;
+    assert_eq!(code, 0);
+    assert_eq!(out, "OK\nOK\n\"it's\"\n\"aA\"\nInvalid argument(s)\nInvalid argument(s)\nPONG\n");
+}
+
+#[tokio::test]
+async fn repeat_runs_a_command_several_times() {
+    let server = spawn_server().await;
+    let (_, out, _) = run_cli(&server, &["-r", "3", "-i", "0.01", "INCR", "n"], b"").await;
+    assert_eq!(out, "(integer) 1\n(integer) 2\n(integer) 3\n");
+}
+
+#[tokio::test]
+async fn pipe_mode_loads_raw_protocol() {
+    let server = spawn_server().await;
+
+    let mut protocol = Vec::new();
+    for i in 0..1000 {
+        let set = RespData::Array(vec![bulk("SET"), bulk(&format!("key:{}", i)), bulk(&i.to_string())]);
+        protocol.extend_from_slice(&serialize_resp(&set));
+    }
+    protocol.extend_from_slice(b"INCR key:5\r\n");
+    let (code, out, _) = run_cli(&server, &["--pipe"], &protocol).await;
+    assert_eq!(code, 0);
+    assert_eq!(out, "All data transferred. Waiting for the last reply...\nLast reply received from server.\nerrors: 0, replies: 1001\n");
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["GET", "key:999"]).await, bulk("999"));
+    assert_eq!(client.cmd(&["GET", "key:5"]).await, bulk("6"));
+
+    let (code, out, err) = run_cli(&server, &["--pipe"], b"INCR key:5\r\nNOSUCHCOMMAND\r\n").await;
+    assert_eq!(code, 1);
+    assert!(out.ends_with("errors: 1, replies: 2\n"), "{}", out);
+    assert!(err.starts_with("ERR unknown command 'NOSUCHCOMMAND'"), "{}", err);
+}
+
+#[tokio::test]
+async fn connects_over_unix_sockets_and_reports_failures() {
+    let socket_dir = TempDir::new();
+    let path = socket_dir.path().join("redis.sock");
+    let _server = spawn_server_with(|config| {
+        config.set("unixsocket", path.to_str().unwrap()).unwrap();
+    })
+    .await;
+    assert_eq!(run(&["-s", path.to_str().unwrap(), "PING"], b"").await.1, "PONG\n");
+
+    let missing = socket_dir.path().join("missing.sock");
+    let (code, _, err) = run(&["-s", missing.to_str().unwrap(), "PING"], b"").await;
+    assert_eq!(code, 1);
+    assert!(err.starts_with(&format!("Could not connect to Redis at {}: ", missing.display())), "{}", err);
+    let (code, _, err) = run(&["-x"], b"").await;
+    assert_eq!(code, 1);
+    assert!(err.starts_with("Unrecognized option '-x'"), "{}", err);
+}
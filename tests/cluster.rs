@@ -0,0 +1,91 @@
+mod common;
+
+use common::{bulk, error, int, spawn_server};
+use redis::cluster::{crc16, key_hash_slot, CLUSTER_SLOTS};
+use redis::resp::RespData;
+
+#[test]
+fn crc16_matches_the_xmodem_check_value() {
+    assert_eq!(crc16(b""), 0);
+    assert_eq!(crc16(b"123456789"), 0x31c3);
+    assert_eq!(crc16(b"foo"), 0xaf96);
+}
+
+#[test]
+fn keys_hash_to_the_slots_redis_cluster_uses() {
+    for (key, slot) in [("foo", 12182), ("bar", 5061), ("hello", 866), ("", 0), ("user1000", 3443)] {
+        assert_eq!(key_hash_slot(key.as_bytes()), slot, "{}", key);
+    }
+    assert!((0..=u8::MAX).all(|b| key_hash_slot(&[b]) < CLUSTER_SLOTS));
+}
+
+#[test]
+fn hash_tags_pick_the_hashed_part() {
+    // Only the first non-empty {...} counts
+    assert_eq!(key_hash_slot(b"{user1000}.following"), 3443);
+    assert_eq!(key_hash_slot(b"{user1000}.followers"), 3443);
+    assert_eq!(key_hash_slot(b"foo{bar}{zap}"), key_hash_slot(b"bar"));
+    assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+    // An empty or unclosed tag means the whole key is hashed
+    assert_eq!(key_hash_slot(b"foo{}{bar}"), 8363);
+    assert_eq!(key_hash_slot(b"foo{bar"), crc16(b"foo{bar") % CLUSTER_SLOTS);
+}
+
+#[tokio::test]
+async fn cluster_introspection_describes_a_single_node() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    match client.cmd(&["CLUSTER", "INFO"]).await {
+        RespData::BulkString(info) => {
+            assert!(info.contains("cluster_enabled:0\r\n"), "{}", info);
+            assert!(info.contains("cluster_known_nodes:1\r\n"), "{}", info);
+        }
+        reply => panic!("CLUSTER INFO: {:?}", reply),
+    }
+    match client.cmd(&["INFO"]).await {
+        RespData::BulkString(info) => assert!(info.contains("# Cluster\r\ncluster_enabled:0\r\n"), "{}", info),
+        reply => panic!("INFO: {:?}", reply),
+    }
+    assert_eq!(client.cmd(&["CLUSTER", "SLOTS"]).await, RespData::Array(Vec::new()));
+    assert_eq!(client.cmd(&["CLUSTER", "SHARDS"]).await, RespData::Array(Vec::new()));
+    assert_eq!(client.cmd(&["CLUSTER", "KEYSLOT", "{user1000}.following"]).await, int(3443));
+
+    client.cmd(&["SET", "{user1000}.following", "1"]).await;
+    client.cmd(&["SET", "{user1000}.followers", "2"]).await;
+    client.cmd(&["SET", "foo", "3"]).await;
+    client.cmd(&["SET", "{user1000}.gone", "4", "PX", "1"]).await;
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert_eq!(client.cmd(&["CLUSTER", "COUNTKEYSINSLOT", "3443"]).await, int(2));
+    assert_eq!(client.cmd(&["CLUSTER", "COUNTKEYSINSLOT", "12182"]).await, int(1));
+    assert_eq!(client.cmd(&["CLUSTER", "COUNTKEYSINSLOT", "0"]).await, int(0));
+    assert_eq!(client.cmd(&["CLUSTER", "COUNTKEYSINSLOT", "16384"]).await, error("ERR Invalid slot"));
+    assert_eq!(
+        client.cmd(&["CLUSTER", "COUNTKEYSINSLOT", "x"]).await,
+        error("ERR value is not an integer or out of range")
+    );
+    assert_eq!(
+        client.cmd(&["CLUSTER", "ADDSLOTS", "1"]).await,
+        error("ERR unknown subcommand or wrong number of arguments for 'ADDSLOTS'. Try CLUSTER HELP.")
+    );
+}
+
+#[tokio::test]
+async fn the_node_id_survives_restarts() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    let id = match client.cmd(&["CLUSTER", "MYID"]).await {
+        RespData::BulkString(id) => id,
+        reply => panic!("CLUSTER MYID: {:?}", reply),
+    };
+    assert_eq!(id.len(), 40);
+    assert!(id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()), "{}", id);
+
+    let server = server.restart().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["CLUSTER", "MYID"]).await, bulk(&id));
+
+    let other = spawn_server().await;
+    assert_ne!(other.client().await.cmd(&["CLUSTER", "MYID"]).await, bulk(&id));
+}
@@ -0,0 +1,818 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{bulk, bulks, error, int, integers, ok, spawn_server, TestClient};
+use redis::resp::{serialize_resp, RespData};
+
+#[tokio::test]
+async fn set_rejects_malformed_options() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    let syntax = error("ERR syntax error");
+    let not_integer = error("ERR value is not an integer or out of range");
+    let invalid_expire = error("ERR invalid expire time in 'set' command");
+    let cases: &[(&[&str], &RespData)] = &[
+        (&["BOGUS"], &syntax),
+        (&["EX"], &syntax),
+        (&["PX"], &syntax),
+        (&["EX", "10", "PX", "10000"], &syntax),
+        (&["PX", "10000", "EX", "10"], &syntax),
+        (&["EX", "10", "BOGUS"], &syntax),
+        (&["EX", "notanumber", "BOGUS"], &syntax),
+        (&["EX", "notanumber"], &not_integer),
+        (&["PX", "1.5"], &not_integer),
+        (&["EX", "99999999999999999999"], &not_integer),
+        (&["EX", "0"], &invalid_expire),
+        (&["PX", "0"], &invalid_expire),
+        (&["EX", "-1"], &invalid_expire),
+        (&["PX", "-100"], &invalid_expire),
+        (&["EX", "9223372036854775"], &invalid_expire),
+        (&["PX", "9223372036854775807"], &invalid_expire),
+        (&["EXAT"], &syntax),
+        (&["EX", "10", "EXAT", "1900000000"], &syntax),
+        (&["PXAT", "1900000000000", "PX", "10"], &syntax),
+        (&["EXAT", "tomorrow"], &not_integer),
+        (&["EXAT", "0"], &invalid_expire),
+        (&["PXAT", "-1"], &invalid_expire),
+        (&["EXAT", "9223372036854776"], &invalid_expire),
+        (&["KEEPTTL", "EX", "10"], &syntax),
+        (&["PX", "10", "KEEPTTL"], &syntax),
+    ];
+    for (options, expected) in cases {
+        let mut args = vec!["SET", "key", "value"];
+        args.extend_from_slice(options);
+        assert_eq!(client.cmd(&args).await, **expected, "SET key value {}", options.join(" "));
+        assert_eq!(client.cmd(&["EXISTS", "key"]).await, int(0), "{}", options.join(" "));
+    }
+}
+
+#[tokio::test]
+async fn set_accepts_well_formed_expire_times() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["SET", "seconds", "v", "ex", "100"]).await, ok());
+    assert_eq!(client.cmd(&["SET", "millis", "v", "PX", "50"]).await, ok());
+    // Repeating the same option is allowed, the last one wins
+    assert_eq!(client.cmd(&["SET", "twice", "v", "PX", "100000", "PX", "50"]).await, ok());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(client.cmd(&["GET", "seconds"]).await, bulk("v"));
+    assert_eq!(client.cmd(&["GET", "millis"]).await, RespData::Null);
+    assert_eq!(client.cmd(&["GET", "twice"]).await, RespData::Null);
+}
+
+#[tokio::test]
+async fn set_accepts_absolute_expire_times() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let now_ms = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+
+    let in_100s = (now_ms / 1000 + 100).to_string();
+    assert_eq!(client.cmd(&["SET", "seconds", "v", "EXAT", &in_100s]).await, ok());
+    let ttl = client.cmd(&["TTL", "seconds"]).await;
+    assert!(matches!(ttl, RespData::Integer(99..=100)), "{:?}", ttl);
+
+    let in_50s = (now_ms + 50_000).to_string();
+    assert_eq!(client.cmd(&["SET", "millis", "v", "pxat", &in_50s]).await, ok());
+    let pttl = client.cmd(&["PTTL", "millis"]).await;
+    assert!(matches!(pttl, RespData::Integer(45_000..=50_000)), "{:?}", pttl);
+
+    // A time in the past still succeeds, leaving the key already expired
+    assert_eq!(client.cmd(&["SET", "seconds", "v", "EXAT", "1"]).await, ok());
+    assert_eq!(client.cmd(&["SET", "gone", "v", "PXAT", &(now_ms - 1000).to_string()]).await, ok());
+    assert_eq!(client.cmd(&["EXISTS", "seconds"]).await, int(0));
+    assert_eq!(client.cmd(&["TTL", "gone"]).await, int(-2));
+
+    assert_eq!(client.cmd(&["SET", "persistent", "v"]).await, ok());
+    assert_eq!(client.cmd(&["TTL", "persistent"]).await, int(-1));
+    assert_eq!(client.cmd(&["PTTL", "persistent"]).await, int(-1));
+}
+
+#[tokio::test]
+async fn errors_are_worded_like_redis() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(
+        client.cmd(&["FOO", "a", "b"]).await,
+        error("ERR unknown command 'FOO', with args beginning with: 'a' 'b' ")
+    );
+    // Echoed input is capped and kept on one line
+    let long = "x".repeat(200);
+    assert_eq!(
+        client.cmd(&["FOO", &long]).await,
+        error(&format!("ERR unknown command 'FOO', with args beginning with: '{}' ", "x".repeat(128)))
+    );
+    assert_eq!(
+        client.cmd(&["FOO", "line\r\nbreak"]).await,
+        error("ERR unknown command 'FOO', with args beginning with: 'line  break' ")
+    );
+    assert_eq!(client.cmd(&["CONFIG", "BOGUS"]).await, error("ERR unknown subcommand 'BOGUS'. Try CONFIG HELP."));
+
+    for args in [&["GET"][..], &["get", "a", "b"], &["SET", "k"], &["LRANGE", "k", "0"], &["HSET", "k", "f"]] {
+        let expected = format!("ERR wrong number of arguments for '{}' command", args[0].to_lowercase());
+        assert_eq!(client.cmd(args).await, error(&expected), "{:?}", args);
+    }
+}
+
+#[tokio::test]
+async fn exists_counts_every_key_given() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["SET", "a", "1"]).await, ok());
+    assert_eq!(client.cmd(&["RPUSH", "b", "x"]).await, int(1));
+    assert_eq!(client.cmd(&["EXISTS", "a"]).await, int(1));
+    assert_eq!(client.cmd(&["EXISTS", "a", "b", "missing"]).await, int(2));
+    assert_eq!(client.cmd(&["EXISTS", "a", "a", "missing"]).await, int(2));
+    assert_eq!(client.cmd(&["EXISTS", "missing", "missing"]).await, int(0));
+    assert_eq!(client.cmd(&["EXISTS"]).await, error("ERR wrong number of arguments for 'exists' command"));
+}
+
+async fn sorted_keys(client: &mut TestClient, pattern: &str) -> Vec<String> {
+    let mut keys: Vec<String> = match client.cmd(&["KEYS", pattern]).await {
+        RespData::Array(keys) => keys
+            .into_iter()
+            .map(|key| match key {
+                RespData::BulkString(key) => key,
+                other => panic!("KEYS {}: {:?}", pattern, other),
+            })
+            .collect(),
+        other => panic!("KEYS {}: {:?}", pattern, other),
+    };
+    keys.sort();
+    keys
+}
+
+#[tokio::test]
+async fn keys_lists_the_live_keys_matching_a_pattern() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    for key in ["user:1", "user:2", "user:10", "session"] {
+        assert_eq!(client.cmd(&["SET", key, "v"]).await, ok());
+    }
+    assert_eq!(client.cmd(&["SET", "user:3", "v", "PX", "1"]).await, ok());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    assert_eq!(sorted_keys(&mut client, "user:?").await, ["user:1", "user:2"]);
+    assert_eq!(sorted_keys(&mut client, "user:*").await, ["user:1", "user:10", "user:2"]);
+    assert_eq!(sorted_keys(&mut client, "*").await, ["session", "user:1", "user:10", "user:2"]);
+    assert_eq!(sorted_keys(&mut client, "[su]ession").await, ["session"]);
+    assert!(sorted_keys(&mut client, "USER:*").await.is_empty());
+    assert_eq!(client.cmd(&["KEYS"]).await, error("ERR wrong number of arguments for 'keys' command"));
+}
+
+#[tokio::test]
+async fn incr_and_decr_count_from_zero_and_refuse_non_integers() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["INCR", "up"]).await, int(1));
+    assert_eq!(client.cmd(&["DECR", "down"]).await, int(-1));
+    assert_eq!(client.cmd(&["SET", "n", "41"]).await, ok());
+    assert_eq!(client.cmd(&["INCR", "n"]).await, int(42));
+    assert_eq!(client.cmd(&["GET", "n"]).await, bulk("42"));
+
+    assert_eq!(client.cmd(&["SET", "text", "forty"]).await, ok());
+    assert_eq!(client.cmd(&["INCR", "text"]).await, error("ERR value is not an integer or out of range"));
+    assert_eq!(client.cmd(&["GET", "text"]).await, bulk("forty"));
+    assert_eq!(client.cmd(&["SET", "max", &i64::MAX.to_string()]).await, ok());
+    assert_eq!(client.cmd(&["INCR", "max"]).await, error("ERR increment or decrement would overflow"));
+    assert_eq!(client.cmd(&["SET", "min", &i64::MIN.to_string()]).await, ok());
+    assert_eq!(client.cmd(&["DECR", "min"]).await, error("ERR increment or decrement would overflow"));
+    assert_eq!(client.cmd(&["GET", "min"]).await, bulk(&i64::MIN.to_string()));
+    assert_eq!(client.cmd(&["RPUSH", "list", "x"]).await, int(1));
+    assert_eq!(
+        client.cmd(&["DECR", "list"]).await,
+        error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_incr_and_decr_cancel_out() {
+    let server = spawn_server().await;
+    let mut tasks = Vec::new();
+    for op in ["INCR", "DECR", "INCR", "DECR"] {
+        let mut client = server.client().await;
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..250 {
+                client.cmd(&[op, "counter"]).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["GET", "counter"]).await, bulk("0"));
+}
+
+// Sends a command whose arguments may not be valid UTF-8
+async fn binary_cmd(client: &mut TestClient, args: &[&[u8]]) -> RespData {
+    let command = RespData::Array(args.iter().map(|arg| RespData::BulkBytes(arg.to_vec())).collect());
+    client.send_raw(&serialize_resp(&command)).await;
+    client.read_reply().await
+}
+
+#[tokio::test]
+async fn binary_arguments_are_stored_as_text() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    // What the bytes turn into once stored
+    let lossy = |bytes: &[u8]| bulk(&String::from_utf8_lossy(bytes));
+
+    assert_eq!(binary_cmd(&mut client, &[b"SET", b"k", b"\xff\xfe"]).await, ok());
+    assert_eq!(binary_cmd(&mut client, &[b"GET", b"k"]).await, lossy(b"\xff\xfe"));
+    assert_eq!(binary_cmd(&mut client, &[b"SET", b"\xff", b"v"]).await, ok());
+    assert_eq!(binary_cmd(&mut client, &[b"GET", b"\xff"]).await, bulk("v"));
+
+    assert_eq!(binary_cmd(&mut client, &[b"HSET", b"h", b"\xff", b"v"]).await, int(1));
+    assert_eq!(binary_cmd(&mut client, &[b"HGET", b"h", b"\xff"]).await, bulk("v"));
+    assert_eq!(binary_cmd(&mut client, &[b"HSET", b"h", b"f", b"\xfe"]).await, int(1));
+    assert_eq!(client.cmd(&["HGET", "h", "f"]).await, lossy(b"\xfe"));
+
+    // No argument is dropped, wherever it is
+    assert_eq!(binary_cmd(&mut client, &[b"RPUSH", b"l", b"\xff"]).await, int(1));
+    assert_eq!(binary_cmd(&mut client, &[b"RPUSH", b"l", b"a", b"\xfe", b"b"]).await, int(4));
+    assert_eq!(
+        client.cmd(&["LRANGE", "l", "0", "-1"]).await,
+        RespData::Array(vec![lossy(b"\xff"), bulk("a"), lossy(b"\xfe"), bulk("b")])
+    );
+    assert_eq!(binary_cmd(&mut client, &[b"DEL", b"\xff", b"k"]).await, int(2));
+}
+
+// Asserts `key` still has a TTL, below the one it was created with
+async fn assert_counting_down(client: &mut TestClient, key: &str, created_with_ms: i64) {
+    match client.cmd(&["PTTL", key]).await {
+        RespData::Integer(ttl) => assert!(ttl > 0 && ttl < created_with_ms, "PTTL {} of {}", ttl, key),
+        other => panic!("PTTL of {}: {:?}", key, other),
+    }
+}
+
+#[tokio::test]
+async fn writes_other_than_set_keep_the_ttl() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["SET", "counter", "0", "PX", "60000"]).await, ok());
+    assert_eq!(client.cmd(&["RPUSH", "list", "a"]).await, int(1));
+    let payload = match client.cmd(&["DUMP", "list"]).await {
+        RespData::BulkBytes(payload) => payload,
+        RespData::BulkString(payload) => payload.into_bytes(),
+        other => panic!("DUMP: {:?}", other),
+    };
+    let restore = RespData::Array(vec![
+        bulk("RESTORE"),
+        bulk("list"),
+        bulk("60000"),
+        RespData::BulkBytes(payload),
+        bulk("REPLACE"),
+    ]);
+    client.send_raw(&serialize_resp(&restore)).await;
+    assert_eq!(client.read_reply().await, ok());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    assert_eq!(client.cmd(&["INCR", "counter"]).await, int(1));
+    assert_counting_down(&mut client, "counter", 60000).await;
+    assert_eq!(client.cmd(&["DECR", "counter"]).await, int(0));
+    assert_counting_down(&mut client, "counter", 60000).await;
+    assert_eq!(client.cmd(&["LPUSH", "list", "b"]).await, int(2));
+    assert_counting_down(&mut client, "list", 60000).await;
+    assert_eq!(client.cmd(&["RPUSH", "list", "c"]).await, int(3));
+    assert_counting_down(&mut client, "list", 60000).await;
+
+    // SET clears the TTL unless told to keep it
+    assert_eq!(client.cmd(&["SET", "counter", "5", "KEEPTTL"]).await, ok());
+    assert_counting_down(&mut client, "counter", 60000).await;
+    assert_eq!(client.cmd(&["GET", "counter"]).await, bulk("5"));
+    assert_eq!(client.cmd(&["SET", "counter", "6"]).await, ok());
+    assert_eq!(client.cmd(&["PTTL", "counter"]).await, int(-1));
+    assert_eq!(client.cmd(&["SET", "fresh", "v", "KEEPTTL"]).await, ok());
+    assert_eq!(client.cmd(&["PTTL", "fresh"]).await, int(-1));
+}
+
+#[tokio::test]
+async fn sort_orders_numbers_and_strings() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["RPUSH", "numbers", "3", "10", "-1.5", "2", "10"]).await;
+    assert_eq!(client.cmd(&["SORT", "numbers"]).await, bulks(&["-1.5", "2", "3", "10", "10"]));
+    assert_eq!(client.cmd(&["SORT", "numbers", "DESC"]).await, bulks(&["10", "10", "3", "2", "-1.5"]));
+    assert_eq!(client.cmd(&["SORT", "numbers", "ALPHA"]).await, bulks(&["-1.5", "10", "10", "2", "3"]));
+    assert_eq!(client.cmd(&["SORT", "numbers", "LIMIT", "1", "2"]).await, bulks(&["2", "3"]));
+    assert_eq!(client.cmd(&["SORT", "numbers", "LIMIT", "3", "-1"]).await, bulks(&["10", "10"]));
+    assert_eq!(client.cmd(&["SORT", "numbers", "LIMIT", "9", "2"]).await, bulks(&[]));
+    assert_eq!(client.cmd(&["SORT", "numbers", "LIMIT", "0", "2", "ALPHA", "DESC"]).await, bulks(&["3", "2"]));
+
+    client.cmd(&["RPUSH", "words", "banana", "apple", "cherry"]).await;
+    assert_eq!(client.cmd(&["SORT", "words", "ALPHA"]).await, bulks(&["apple", "banana", "cherry"]));
+    assert_eq!(client.cmd(&["SORT", "words"]).await, error("ERR One or more scores can't be converted into double"));
+
+    assert_eq!(client.cmd(&["SORT", "missing"]).await, bulks(&[]));
+    client.cmd(&["SET", "string", "x"]).await;
+    assert_eq!(client.cmd(&["SORT", "string"]).await, error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+    assert_eq!(client.cmd(&["SORT", "numbers", "LIMIT", "0"]).await, error("ERR syntax error"));
+    assert_eq!(client.cmd(&["SORT", "numbers", "LIMIT", "a", "b"]).await, error("ERR value is not an integer or out of range"));
+    assert_eq!(client.cmd(&["SORT", "numbers", "SIDEWAYS"]).await, error("ERR syntax error"));
+}
+
+#[tokio::test]
+async fn sort_by_and_get_dereference_other_keys() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    // The example from the SORT documentation
+    client.cmd(&["RPUSH", "mylist", "1", "2", "3"]).await;
+    for (id, weight, object) in [("1", "30", "one"), ("2", "10", "two"), ("3", "20", "three")] {
+        client.cmd(&["SET", &format!("weight_{}", id), weight]).await;
+        client.cmd(&["SET", &format!("object_{}", id), object]).await;
+    }
+    assert_eq!(client.cmd(&["SORT", "mylist", "BY", "weight_*"]).await, bulks(&["2", "3", "1"]));
+    assert_eq!(client.cmd(&["SORT", "mylist", "BY", "weight_*", "DESC"]).await, bulks(&["1", "3", "2"]));
+    assert_eq!(client.cmd(&["SORT", "mylist", "BY", "weight_*", "GET", "object_*"]).await, bulks(&["two", "three", "one"]));
+    assert_eq!(
+        client.cmd(&["SORT", "mylist", "BY", "weight_*", "GET", "object_*", "GET", "#"]).await,
+        bulks(&["two", "2", "three", "3", "one", "1"])
+    );
+    assert_eq!(client.cmd(&["SORT", "mylist", "BY", "object_*", "ALPHA"]).await, bulks(&["1", "3", "2"]));
+
+    // Without a '*' the pattern can't name a key, so nothing is sorted
+    client.cmd(&["RPUSH", "unsorted", "b", "c", "a"]).await;
+    assert_eq!(client.cmd(&["SORT", "unsorted", "BY", "nosort"]).await, bulks(&["b", "c", "a"]));
+    assert_eq!(client.cmd(&["SORT", "unsorted", "BY", "nosort", "DESC"]).await, bulks(&["a", "c", "b"]));
+    assert_eq!(client.cmd(&["SORT", "unsorted", "BY", "nosort", "LIMIT", "1", "1"]).await, bulks(&["c"]));
+    assert_eq!(
+        client.cmd(&["SORT", "unsorted", "BY", "nosort", "GET", "object_*", "GET", "fixed"]).await,
+        RespData::Array(vec![RespData::Null; 6])
+    );
+
+    // Missing weights sort as 0, missing GET keys are nil
+    client.cmd(&["RPUSH", "mylist", "4"]).await;
+    assert_eq!(
+        client.cmd(&["SORT", "mylist", "BY", "weight_*", "GET", "object_*"]).await,
+        RespData::Array(vec![RespData::Null, bulk("two"), bulk("three"), bulk("one")])
+    );
+    // Hash fields through "->" only come from hashes
+    assert_eq!(
+        client.cmd(&["SORT", "mylist", "BY", "weight_*", "GET", "object_*->name"]).await,
+        RespData::Array(vec![RespData::Null; 4])
+    );
+}
+
+#[tokio::test]
+async fn sort_store_writes_a_list() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["RPUSH", "mylist", "3", "1", "2"]).await;
+    client.cmd(&["SET", "object_1", "one"]).await;
+    client.cmd(&["SET", "result", "old", "EX", "100"]).await;
+    assert_eq!(client.cmd(&["SORT", "mylist", "STORE", "result"]).await, int(3));
+    assert_eq!(client.cmd(&["SORT", "result", "BY", "nosort"]).await, bulks(&["1", "2", "3"]));
+    assert_eq!(client.cmd(&["TTL", "result"]).await, int(-1));
+
+    assert_eq!(client.cmd(&["SORT", "mylist", "GET", "object_*", "STORE", "result"]).await, int(3));
+    assert_eq!(client.cmd(&["SORT", "result", "BY", "nosort"]).await, bulks(&["one", "", ""]));
+
+    assert_eq!(client.cmd(&["SORT", "missing", "STORE", "result"]).await, int(0));
+    assert_eq!(client.cmd(&["EXISTS", "result"]).await, int(0));
+}
+
+#[tokio::test]
+async fn hashes_set_get_and_delete_fields() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["HSET", "h", "a", "1", "b", "2"]).await, int(2));
+    assert_eq!(client.cmd(&["HSET", "h", "a", "10", "c", "3"]).await, int(1));
+    assert_eq!(client.cmd(&["HSET", "h", "a"]).await, error("ERR wrong number of arguments for 'hset' command"));
+    assert_eq!(client.cmd(&["HGET", "h", "a"]).await, bulk("10"));
+    assert_eq!(client.cmd(&["HGET", "h", "missing"]).await, RespData::Null);
+    assert_eq!(client.cmd(&["HLEN", "h"]).await, int(3));
+    assert_eq!(client.cmd(&["HEXISTS", "h", "b"]).await, int(1));
+    assert_eq!(client.cmd(&["HDEL", "h", "b", "missing"]).await, int(1));
+    assert_eq!(client.cmd(&["HDEL", "h", "c"]).await, int(1));
+    assert_eq!(client.cmd(&["HGETALL", "h"]).await, bulks(&["a", "10"]));
+    assert_eq!(client.cmd(&["HGETALL", "missing"]).await, RespData::Array(vec![]));
+
+    client.cmd(&["SET", "s", "x"]).await;
+    let wrongtype = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+    assert_eq!(client.cmd(&["HSET", "s", "a", "1"]).await, wrongtype);
+    assert_eq!(client.cmd(&["HGET", "s", "a"]).await, wrongtype);
+
+    assert_eq!(client.cmd(&["HDEL", "h", "a"]).await, int(1));
+    assert_eq!(client.cmd(&["EXISTS", "h"]).await, int(0));
+}
+
+#[tokio::test]
+async fn hexpire_reports_a_status_per_field() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["HSET", "h", "a", "1", "b", "2", "c", "3"]).await;
+    assert_eq!(client.cmd(&["HEXPIRE", "missing", "100", "FIELDS", "1", "a"]).await, integers(&[-2]));
+    assert_eq!(client.cmd(&["HEXPIRE", "h", "100", "FIELDS", "2", "a", "nope"]).await, integers(&[1, -2]));
+    assert_eq!(client.cmd(&["HEXPIRE", "h", "200", "NX", "FIELDS", "2", "a", "b"]).await, integers(&[0, 1]));
+    assert_eq!(client.cmd(&["HEXPIRE", "h", "300", "XX", "FIELDS", "2", "a", "c"]).await, integers(&[1, 0]));
+    assert_eq!(client.cmd(&["HEXPIRE", "h", "250", "GT", "FIELDS", "3", "a", "b", "c"]).await, integers(&[0, 1, 0]));
+    assert_eq!(client.cmd(&["HEXPIRE", "h", "50", "LT", "FIELDS", "2", "a", "c"]).await, integers(&[1, 1]));
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "4", "a", "b", "c", "nope"]).await, integers(&[50, 250, 50, -2]));
+
+    let pttl = client.cmd(&["HPTTL", "h", "FIELDS", "1", "a"]).await;
+    assert!(matches!(pttl, RespData::Array(ref items) if matches!(items[0], RespData::Integer(ms) if ms > 49_000 && ms <= 50_000)));
+
+    assert_eq!(client.cmd(&["HPERSIST", "h", "FIELDS", "2", "a", "nope"]).await, integers(&[1, -2]));
+    assert_eq!(client.cmd(&["HPERSIST", "h", "FIELDS", "1", "a"]).await, integers(&[-1]));
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "1", "a"]).await, integers(&[-1]));
+
+    // An expiry in the past deletes the field, and the key with its last field
+    assert_eq!(client.cmd(&["HPEXPIRE", "h", "0", "FIELDS", "2", "b", "c"]).await, integers(&[2, 2]));
+    assert_eq!(client.cmd(&["HLEN", "h"]).await, int(1));
+    assert_eq!(client.cmd(&["HEXPIRE", "h", "0", "FIELDS", "1", "a"]).await, integers(&[2]));
+    assert_eq!(client.cmd(&["EXISTS", "h"]).await, int(0));
+}
+
+#[tokio::test]
+async fn hexpire_rejects_malformed_arguments() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["HSET", "h", "a", "1"]).await;
+
+    let missing = error("ERR Mandatory argument FIELDS is missing or not at the right position");
+    let zero = error("ERR Parameter `numFields` should be greater than 0");
+    let mismatch = error("ERR The `numfields` parameter must match the number of arguments");
+    let cases: &[(&[&str], RespData)] = &[
+        (&["HEXPIRE", "h", "10", "a", "1", "a"], missing.clone()),
+        (&["HEXPIRE", "h", "10", "BOGUS", "FIELDS", "1", "a"], missing),
+        (&["HEXPIRE", "h", "10", "FIELDS", "0", "a"], zero),
+        (&["HEXPIRE", "h", "10", "FIELDS", "2", "a"], mismatch.clone()),
+        (&["HEXPIRE", "h", "10", "FIELDS", "1", "a", "b"], mismatch),
+        (&["HEXPIRE", "h", "ten", "FIELDS", "1", "a"], error("ERR value is not an integer or out of range")),
+        (&["HEXPIRE", "h", "-1", "FIELDS", "1", "a"], error("ERR invalid expire time, must be >= 0")),
+        (&["HPEXPIRE", "h", "9223372036854775807", "FIELDS", "1", "a"], error("ERR invalid expire time in 'hpexpire' command")),
+    ];
+    for (args, expected) in cases {
+        assert_eq!(client.cmd(args).await, *expected, "{}", args.join(" "));
+    }
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "1", "a"]).await, integers(&[-1]));
+}
+
+#[tokio::test]
+async fn expired_fields_disappear_from_reads() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["HSET", "h", "short", "1", "long", "2"]).await;
+    client.cmd(&["HPEXPIRE", "h", "50", "FIELDS", "1", "short"]).await;
+    client.cmd(&["HSET", "gone", "only", "1"]).await;
+    client.cmd(&["HPEXPIRE", "gone", "50", "FIELDS", "1", "only"]).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(client.cmd(&["HGET", "h", "short"]).await, RespData::Null);
+    assert_eq!(client.cmd(&["HLEN", "h"]).await, int(1));
+    assert_eq!(client.cmd(&["HGETALL", "h"]).await, bulks(&["long", "2"]));
+    assert_eq!(client.cmd(&["EXISTS", "gone"]).await, int(0));
+
+    // Setting a field again clears its expiry
+    client.cmd(&["HEXPIRE", "h", "100", "FIELDS", "1", "long"]).await;
+    client.cmd(&["HSET", "h", "long", "3"]).await;
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "1", "long"]).await, integers(&[-1]));
+}
+
+#[tokio::test]
+async fn hgetex_and_hgetdel_return_fields() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["HSET", "h", "a", "1", "b", "2", "c", "3"]).await;
+    let values = RespData::Array(vec![bulk("1"), RespData::Null]);
+    assert_eq!(client.cmd(&["HGETEX", "h", "EX", "100", "FIELDS", "2", "a", "nope"]).await, values);
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "2", "a", "nope"]).await, integers(&[100, -2]));
+    assert_eq!(client.cmd(&["HGETEX", "h", "PERSIST", "FIELDS", "1", "a"]).await, bulks(&["1"]));
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "1", "a"]).await, integers(&[-1]));
+    assert_eq!(client.cmd(&["HGETEX", "h", "FIELDS", "1", "b"]).await, bulks(&["2"]));
+
+    assert_eq!(client.cmd(&["HGETDEL", "h", "FIELDS", "2", "b", "nope"]).await, RespData::Array(vec![bulk("2"), RespData::Null]));
+    assert_eq!(client.cmd(&["HGETDEL", "h", "FIELDS", "2", "a", "c"]).await, bulks(&["1", "3"]));
+    assert_eq!(client.cmd(&["EXISTS", "h"]).await, int(0));
+}
+
+#[tokio::test]
+async fn field_expiries_survive_dump_and_restart() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["HSET", "h", "a", "1", "b", "2"]).await;
+    client.cmd(&["HEXPIRE", "h", "100", "FIELDS", "1", "a"]).await;
+    let payload = match client.cmd(&["DUMP", "h"]).await {
+        RespData::BulkBytes(payload) => payload,
+        RespData::BulkString(payload) => payload.into_bytes(),
+        other => panic!("DUMP: {:?}", other),
+    };
+    let restore = RespData::Array(vec![bulk("RESTORE"), bulk("copy"), bulk("0"), RespData::BulkBytes(payload)]);
+    client.send_raw(&serialize_resp(&restore)).await;
+    assert_eq!(client.read_reply().await, ok());
+    assert_eq!(client.cmd(&["HTTL", "copy", "FIELDS", "2", "a", "b"]).await, integers(&[100, -1]));
+
+    client.cmd(&["SAVE"]).await;
+    let server = server.restart().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "2", "a", "b"]).await, integers(&[100, -1]));
+    assert_eq!(client.cmd(&["HGET", "copy", "a"]).await, bulk("1"));
+}
+
+// Exercises every list command, whatever encoding the lists end up in
+async fn run_list_suite(client: &mut TestClient) {
+    assert_eq!(client.cmd(&["RPUSH", "list", "b", "c"]).await, int(2));
+    assert_eq!(client.cmd(&["LPUSH", "list", "a", "z"]).await, int(4));
+    assert_eq!(client.cmd(&["RPUSH", "list", "", "é"]).await, int(6));
+    assert_eq!(client.cmd(&["SORT", "list", "BY", "nosort"]).await, bulks(&["z", "a", "b", "c", "", "é"]));
+    assert_eq!(client.cmd(&["SORT", "list", "ALPHA", "LIMIT", "1", "3"]).await, bulks(&["a", "b", "c"]));
+    assert_eq!(client.cmd(&["LLEN", "list"]).await, int(6));
+    assert_eq!(client.cmd(&["LRANGE", "list", "0", "-1"]).await, bulks(&["z", "a", "b", "c", "", "é"]));
+    assert_eq!(client.cmd(&["LRANGE", "list", "1", "2"]).await, bulks(&["a", "b"]));
+    assert_eq!(client.cmd(&["LRANGE", "list", "-2", "100"]).await, bulks(&["", "é"]));
+
+    let long = "x".repeat(300);
+    client.cmd(&["RPUSH", "list", &long]).await;
+    assert_eq!(client.cmd(&["SORT", "list", "BY", "nosort", "LIMIT", "6", "1"]).await, bulks(&[&long]));
+
+    let payload = match client.cmd(&["DUMP", "list"]).await {
+        RespData::BulkBytes(payload) => payload,
+        RespData::BulkString(payload) => payload.into_bytes(),
+        other => panic!("DUMP: {:?}", other),
+    };
+    let restore = RespData::Array(vec![bulk("RESTORE"), bulk("copy"), bulk("0"), RespData::BulkBytes(payload)]);
+    client.send_raw(&serialize_resp(&restore)).await;
+    assert_eq!(client.read_reply().await, ok());
+    assert_eq!(
+        client.cmd(&["SORT", "copy", "BY", "nosort"]).await,
+        client.cmd(&["SORT", "list", "BY", "nosort"]).await
+    );
+
+    client.cmd(&["RPUSH", "numbers", "3", "1", "2"]).await;
+    assert_eq!(client.cmd(&["SORT", "numbers", "STORE", "sorted"]).await, int(3));
+    assert_eq!(client.cmd(&["SORT", "sorted", "BY", "nosort"]).await, bulks(&["1", "2", "3"]));
+    client.cmd(&["SET", "string", "x"]).await;
+    assert_eq!(client.cmd(&["LPUSH", "string", "a"]).await, error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+    assert_eq!(client.cmd(&["LLEN", "string"]).await, error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+    assert_eq!(client.cmd(&["LRANGE", "string", "0", "1"]).await, error("WRONGTYPE Operation against a key holding the wrong kind of value"));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_pushes_all_land_on_the_list() {
+    let server = spawn_server().await;
+    let mut tasks = Vec::new();
+    for push in ["LPUSH", "RPUSH", "LPUSH", "RPUSH"] {
+        let mut client = server.client().await;
+        tasks.push(tokio::spawn(async move {
+            for i in 0..250 {
+                client.cmd(&[push, "list", &i.to_string()]).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["LLEN", "list"]).await, int(1000));
+}
+
+#[tokio::test]
+async fn pushes_leave_other_types_alone() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let wrongtype = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+    assert_eq!(client.cmd(&["SET", "string", "x"]).await, ok());
+    assert_eq!(client.cmd(&["RPUSH", "string", "a", "b"]).await, wrongtype);
+    assert_eq!(client.cmd(&["GET", "string"]).await, bulk("x"));
+    assert_eq!(client.cmd(&["HSET", "hash", "f", "v"]).await, int(1));
+    assert_eq!(client.cmd(&["LPUSH", "hash", "a"]).await, wrongtype);
+    assert_eq!(client.cmd(&["HGET", "hash", "f"]).await, bulk("v"));
+
+    // An expired key of another type is gone, so the push starts a new list
+    assert_eq!(client.cmd(&["SET", "short", "x", "PX", "1"]).await, ok());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(client.cmd(&["LPUSH", "short", "a", "b"]).await, int(2));
+    assert_eq!(client.cmd(&["LRANGE", "short", "0", "-1"]).await, bulks(&["b", "a"]));
+    assert_eq!(client.cmd(&["PTTL", "short"]).await, int(-1));
+}
+
+#[tokio::test]
+async fn lrange_clamps_indexes_like_redis() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["RPUSH", "list", "a", "b", "c", "d"]).await;
+    for (start, stop, expected) in [
+        ("0", "0", &["a"][..]),
+        ("-100", "1", &["a", "b"]),
+        ("2", "-1", &["c", "d"]),
+        ("-1", "-1", &["d"]),
+        ("3", "1", &[]),
+        ("4", "10", &[]),
+        ("0", "-5", &[]),
+    ] {
+        assert_eq!(client.cmd(&["LRANGE", "list", start, stop]).await, bulks(expected), "{} {}", start, stop);
+    }
+    assert_eq!(client.cmd(&["LRANGE", "missing", "0", "-1"]).await, bulks(&[]));
+    assert_eq!(client.cmd(&["LLEN", "missing"]).await, int(0));
+    assert_eq!(client.cmd(&["LRANGE", "list", "a", "1"]).await, error("ERR value is not an integer or out of range"));
+    assert_eq!(client.cmd(&["LRANGE", "list", "0"]).await, error("ERR wrong number of arguments for 'lrange' command"));
+}
+
+#[tokio::test]
+async fn list_commands_behave_the_same_in_both_encodings() {
+    for size in ["128", "1"] {
+        let server = spawn_server().await;
+        let mut client = server.client().await;
+        assert_eq!(client.cmd(&["CONFIG", "SET", "list-max-listpack-size", size]).await, ok());
+        run_list_suite(&mut client).await;
+        let encoding = if size == "1" { "quicklist" } else { "listpack" };
+        assert_eq!(client.cmd(&["OBJECT", "ENCODING", "list"]).await, bulk(encoding));
+        assert_eq!(client.cmd(&["OBJECT", "ENCODING", "copy"]).await, bulk(encoding));
+    }
+}
+
+#[tokio::test]
+async fn lists_convert_once_they_outgrow_the_limits() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["CONFIG", "SET", "list-max-listpack-size", "3"]).await;
+    client.cmd(&["RPUSH", "list", "a", "b", "c"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "list"]).await, bulk("listpack"));
+    client.cmd(&["LPUSH", "list", "z"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "list"]).await, bulk("quicklist"));
+    assert_eq!(client.cmd(&["SORT", "list", "BY", "nosort"]).await, bulks(&["z", "a", "b", "c"]));
+
+    // Negative sizes limit the bytes instead: -1 is 4kb
+    client.cmd(&["CONFIG", "SET", "list-max-listpack-size", "-1"]).await;
+    client.cmd(&["RPUSH", "bytes", &"x".repeat(2000), &"y".repeat(2000)]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "bytes"]).await, bulk("listpack"));
+    client.cmd(&["RPUSH", "bytes", &"z".repeat(100)]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "bytes"]).await, bulk("quicklist"));
+}
+
+#[tokio::test]
+async fn hashes_convert_once_they_outgrow_the_limits() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.cmd(&["HSET", "h", "a", "1", "b", "2"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "h"]).await, bulk("listpack"));
+    client.cmd(&["HEXPIRE", "h", "100", "FIELDS", "1", "a"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "h"]).await, bulk("listpackex"));
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "2", "a", "b"]).await, integers(&[100, -1]));
+    client.cmd(&["HSET", "h", "long", &"v".repeat(65)]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "h"]).await, bulk("hashtable"));
+    assert_eq!(client.cmd(&["HGET", "h", "b"]).await, bulk("2"));
+    assert_eq!(client.cmd(&["HTTL", "h", "FIELDS", "2", "a", "b"]).await, integers(&[100, -1]));
+
+    assert_eq!(client.cmd(&["CONFIG", "SET", "hash-max-listpack-entries", "2"]).await, ok());
+    client.cmd(&["HSET", "small", "a", "1", "b", "2"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "small"]).await, bulk("listpack"));
+    client.cmd(&["HSET", "small", "c", "3"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "small"]).await, bulk("hashtable"));
+    assert_eq!(client.cmd(&["HDEL", "small", "a"]).await, int(1));
+    assert_eq!(client.cmd(&["HGET", "small", "c"]).await, bulk("3"));
+    assert_eq!(client.cmd(&["CONFIG", "GET", "hash-max-listpack-entries"]).await, bulks(&["hash-max-listpack-entries", "2"]));
+}
+
+fn lcs_match(a: (i64, i64), b: (i64, i64), len: Option<i64>) -> RespData {
+    let mut entry = vec![RespData::Array(vec![int(a.0), int(a.1)]), RespData::Array(vec![int(b.0), int(b.1)])];
+    entry.extend(len.map(int));
+    RespData::Array(entry)
+}
+
+// The examples from the LCS documentation
+#[tokio::test]
+async fn lcs_matches_the_documented_examples() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["SET", "key1", "ohmytext"]).await;
+    client.cmd(&["SET", "key2", "mynewtext"]).await;
+
+    assert_eq!(client.cmd(&["LCS", "key1", "key2"]).await, bulk("mytext"));
+    assert_eq!(client.cmd(&["LCS", "key1", "key2", "LEN"]).await, int(6));
+    assert_eq!(
+        client.cmd(&["LCS", "key1", "key2", "IDX"]).await,
+        RespData::Array(vec![
+            bulk("matches"),
+            RespData::Array(vec![lcs_match((4, 7), (5, 8), None), lcs_match((2, 3), (0, 1), None)]),
+            bulk("len"),
+            int(6),
+        ])
+    );
+    assert_eq!(
+        client.cmd(&["LCS", "key1", "key2", "IDX", "MINMATCHLEN", "4"]).await,
+        RespData::Array(vec![bulk("matches"), RespData::Array(vec![lcs_match((4, 7), (5, 8), None)]), bulk("len"), int(6)])
+    );
+    assert_eq!(
+        client.cmd(&["LCS", "key1", "key2", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"]).await,
+        RespData::Array(vec![bulk("matches"), RespData::Array(vec![lcs_match((4, 7), (5, 8), Some(4))]), bulk("len"), int(6)])
+    );
+
+    client.cmd(&["HELLO", "3"]).await;
+    assert_eq!(
+        client.cmd(&["LCS", "key1", "key2", "IDX", "WITHMATCHLEN"]).await,
+        RespData::Map(vec![
+            (bulk("matches"), RespData::Array(vec![lcs_match((4, 7), (5, 8), Some(4)), lcs_match((2, 3), (0, 1), Some(2))])),
+            (bulk("len"), int(6)),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn lcs_handles_missing_keys_and_bad_arguments() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["SET", "a", "hello"]).await;
+    client.cmd(&["SET", "n", "12345"]).await;
+    client.cmd(&["INCR", "n"]).await;
+    client.cmd(&["RPUSH", "list", "x"]).await;
+
+    assert_eq!(client.cmd(&["LCS", "a", "missing"]).await, bulk(""));
+    assert_eq!(client.cmd(&["LCS", "missing", "other", "LEN"]).await, int(0));
+    assert_eq!(
+        client.cmd(&["LCS", "a", "missing", "IDX"]).await,
+        RespData::Array(vec![bulk("matches"), RespData::Array(vec![]), bulk("len"), int(0)])
+    );
+    assert_eq!(client.cmd(&["LCS", "n", "a"]).await, bulk(""));
+    assert_eq!(client.cmd(&["LCS", "n", "n"]).await, bulk("12346"));
+
+    let wrongtype = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+    assert_eq!(client.cmd(&["LCS", "a", "list"]).await, wrongtype);
+    assert_eq!(client.cmd(&["LCS", "list", "a"]).await, wrongtype);
+    assert_eq!(
+        client.cmd(&["LCS", "a", "a", "LEN", "IDX"]).await,
+        error("ERR If you want both the length and indexes, please just use IDX.")
+    );
+    assert_eq!(client.cmd(&["LCS", "a", "a", "BOGUS"]).await, error("ERR syntax error"));
+    assert_eq!(client.cmd(&["LCS", "a", "a", "IDX", "MINMATCHLEN"]).await, error("ERR syntax error"));
+    assert_eq!(
+        client.cmd(&["LCS", "a", "a", "IDX", "MINMATCHLEN", "x"]).await,
+        error("ERR value is not an integer or out of range")
+    );
+    assert_eq!(client.cmd(&["LCS", "a"]).await, error("ERR wrong number of arguments for 'lcs' command"));
+
+    // Keys that aren't valid UTF-8 are keys like any other
+    assert_eq!(binary_cmd(&mut client, &[b"LCS", b"\xff", b"a"]).await, bulk(""));
+    assert_eq!(binary_cmd(&mut client, &[b"SET", b"\xff", b"help"]).await, ok());
+    assert_eq!(binary_cmd(&mut client, &[b"LCS", b"a", b"\xff", b"LEN"]).await, int(3));
+
+    // The comparison table would take 601 * 601 * 4 bytes, over 1mb
+    assert_eq!(client.cmd(&["CONFIG", "SET", "proto-max-bulk-len", "1mb"]).await, ok());
+    let long = "x".repeat(600);
+    client.cmd(&["SET", "long", &long]).await;
+    assert_eq!(
+        client.cmd(&["LCS", "long", "long"]).await,
+        error("ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len")
+    );
+    assert_eq!(client.cmd(&["LCS", "long", "a", "LEN"]).await, int(0));
+}
+
+#[tokio::test]
+async fn migrate_moves_keys_and_keeps_those_the_target_refuses() {
+    let source = spawn_server().await;
+    let target = spawn_server().await;
+    let mut from = source.client().await;
+    let mut to = target.client().await;
+    let port = target.addr.port().to_string();
+    from.cmd(&["SET", "a", "1"]).await;
+    from.cmd(&["SET", "b", "2"]).await;
+    from.cmd(&["RPUSH", "c", "x"]).await;
+    to.cmd(&["SET", "b", "taken"]).await;
+
+    assert_eq!(from.cmd(&["MIGRATE", "127.0.0.1", &port, "a", "0", "1000"]).await, ok());
+    assert_eq!(from.cmd(&["EXISTS", "a"]).await, int(0));
+    assert_eq!(to.cmd(&["GET", "a"]).await, bulk("1"));
+
+    // Without REPLACE the target refuses an existing key, which stays here
+    assert_eq!(
+        from.cmd(&["MIGRATE", "127.0.0.1", &port, "b", "0", "1000"]).await,
+        error("ERR Target instance replied with error: BUSYKEY Target key name already exists.")
+    );
+    assert_eq!(from.cmd(&["GET", "b"]).await, bulk("2"));
+    assert_eq!(to.cmd(&["GET", "b"]).await, bulk("taken"));
+
+    assert_eq!(from.cmd(&["MIGRATE", "127.0.0.1", &port, "", "0", "1000", "REPLACE", "KEYS", "b", "c", "missing"]).await, ok());
+    assert_eq!(from.cmd(&["EXISTS", "b", "c"]).await, int(0));
+    assert_eq!(to.cmd(&["GET", "b"]).await, bulk("2"));
+    assert_eq!(to.cmd(&["LRANGE", "c", "0", "-1"]).await, RespData::Array(vec![bulk("x")]));
+
+    assert_eq!(
+        from.cmd(&["MIGRATE", "127.0.0.1", &port, "", "0", "1000", "KEYS", "missing"]).await,
+        RespData::SimpleString("NOKEY".to_string())
+    );
+    from.cmd(&["SET", "kept", "v"]).await;
+    assert_eq!(from.cmd(&["MIGRATE", "127.0.0.1", &port, "kept", "0", "1000", "COPY"]).await, ok());
+    assert_eq!(from.cmd(&["GET", "kept"]).await, bulk("v"));
+    assert_eq!(to.cmd(&["GET", "kept"]).await, bulk("v"));
+}
@@ -0,0 +1,257 @@
+// Shared harness for the integration tests: an in-process server on an
+// ephemeral port with its own data directory, and a small RESP client built
+// on the crate's codec.
+
+// Each test binary uses a different subset of the helpers
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use redis::config::ServerConfig;
+use redis::resp::{parse_resp, serialize_resp, RespData};
+use redis::server::Server;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+// How long a reply may take before the test is failed rather than left hanging
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A directory under the system temp dir, removed on drop
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "redis-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).expect("create temp dir");
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub dir: TempDir,
+    config: ServerConfig,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl TestServer {
+    pub async fn client(&self) -> TestClient {
+        TestClient::connect(self.addr).await
+    }
+
+    // Stops accepting clients and waits for the server to wind down,
+    // handing back its data directory
+    pub async fn shutdown(self) -> (TempDir, ServerConfig) {
+        let _ = self.shutdown.send(());
+        self.task.await.expect("server task").expect("server run");
+        (self.dir, self.config)
+    }
+
+    // Shuts down and starts a fresh server on the same data directory and
+    // config, which is how persistence is tested
+    pub async fn restart(self) -> TestServer {
+        let (dir, config) = self.shutdown().await;
+        start(dir, config).await
+    }
+}
+
+pub async fn spawn_server() -> TestServer {
+    spawn_server_with(|_| {}).await
+}
+
+// Starts a server after letting the test adjust its config
+pub async fn spawn_server_with(configure: impl FnOnce(&mut ServerConfig)) -> TestServer {
+    let dir = TempDir::new();
+    let mut config = ServerConfig::default();
+    config.set("dir", dir.path().to_str().unwrap()).unwrap();
+    configure(&mut config);
+    start(dir, config).await
+}
+
+async fn start(dir: TempDir, config: ServerConfig) -> TestServer {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind ephemeral port");
+    let addr = listener.local_addr().unwrap();
+    let server = Server::with_listener(config.clone(), listener).await.expect("start server");
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let task = tokio::spawn(server.run_until(async {
+        let _ = stopped.await;
+    }));
+    TestServer { addr, dir, config, shutdown, task }
+}
+
+// Runs one of the crate's binaries to completion, feeding it `stdin`, and
+// returns its exit code, stdout and stderr
+pub async fn run_binary(path: &str, args: &[&str], stdin: &[u8]) -> (i32, String, String) {
+    let mut child = Command::new(path)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("start binary");
+    let mut input = child.stdin.take().unwrap();
+    input.write_all(stdin).await.unwrap();
+    drop(input);
+    let output = child.wait_with_output().await.expect("run binary");
+    (
+        output.status.code().unwrap_or(-1),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+pub fn bulk(s: &str) -> RespData {
+    RespData::BulkString(s.to_string())
+}
+
+pub fn ok() -> RespData {
+    RespData::SimpleString("OK".to_string())
+}
+
+pub fn error(message: &str) -> RespData {
+    RespData::Error(message.to_string())
+}
+
+pub fn int(n: i64) -> RespData {
+    RespData::Integer(n)
+}
+
+pub fn array(items: Vec<RespData>) -> RespData {
+    RespData::Array(items)
+}
+
+pub fn bulks(items: &[&str]) -> RespData {
+    RespData::Array(items.iter().map(|item| bulk(item)).collect())
+}
+
+pub fn integers(items: &[i64]) -> RespData {
+    RespData::Array(items.iter().map(|&n| int(n)).collect())
+}
+
+pub fn command(args: &[&str]) -> RespData {
+    RespData::Array(args.iter().map(|arg| bulk(arg)).collect())
+}
+
+pub struct TestClient {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl TestClient {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).await.expect("connect to test server");
+        TestClient { stream, buffer: BytesMut::new() }
+    }
+
+    pub async fn cmd(&mut self, args: &[&str]) -> RespData {
+        self.send(args).await;
+        self.read_reply().await
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.stream.local_addr().expect("local address of test client")
+    }
+
+    pub async fn send(&mut self, args: &[&str]) {
+        self.send_raw(&serialize_resp(&command(args))).await;
+    }
+
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).await.expect("write to test server");
+    }
+
+    // The value of `field` in INFO `section`
+    pub async fn info_field(&mut self, section: &str, field: &str) -> String {
+        match self.cmd(&["INFO", section]).await {
+            RespData::BulkString(info) => info
+                .lines()
+                .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+                .unwrap_or_else(|| panic!("no {} in INFO {}", field, section))
+                .to_string(),
+            reply => panic!("INFO: {:?}", reply),
+        }
+    }
+
+    // Sends a command and returns its reply exactly as it came over the
+    // wire, for tests that pin the encoding
+    pub async fn cmd_raw(&mut self, args: &[&str]) -> Vec<u8> {
+        self.send(args).await;
+        tokio::time::timeout(REPLY_TIMEOUT, async {
+            loop {
+                if let Some((consumed, _)) = parse_resp(&self.buffer).expect("valid RESP reply") {
+                    return self.buffer.split_to(consumed).to_vec();
+                }
+                let n = self.stream.read_buf(&mut self.buffer).await.expect("read from test server");
+                assert!(n > 0, "server closed the connection");
+            }
+        })
+        .await
+        .expect("timed out waiting for a reply")
+    }
+
+    pub async fn read_reply(&mut self) -> RespData {
+        tokio::time::timeout(REPLY_TIMEOUT, async {
+            loop {
+                if let Some((consumed, reply)) = parse_resp(&self.buffer).expect("valid RESP reply") {
+                    let _ = self.buffer.split_to(consumed);
+                    return reply;
+                }
+                let n = self.stream.read_buf(&mut self.buffer).await.expect("read from test server");
+                assert!(n > 0, "server closed the connection");
+            }
+        })
+        .await
+        .expect("timed out waiting for a reply")
+    }
+
+    // Reads exactly `len` bytes of replies without parsing them, for replies
+    // too large to parse as they arrive
+    pub async fn read_exact(&mut self, len: usize) -> Vec<u8> {
+        while self.buffer.len() < len {
+            let n = tokio::time::timeout(REPLY_TIMEOUT, self.stream.read_buf(&mut self.buffer))
+                .await
+                .expect("timed out waiting for a reply")
+                .expect("read from test server");
+            assert!(n > 0, "server closed the connection");
+        }
+        self.buffer.split_to(len).to_vec()
+    }
+
+    // Waits for the server to close the connection, returning what it sent
+    // before doing so. Closing with our input still unread resets the
+    // connection, which counts as closing too.
+    pub async fn read_to_close(&mut self) -> Vec<u8> {
+        let mut rest = self.buffer.split().to_vec();
+        let read = tokio::time::timeout(REPLY_TIMEOUT, self.stream.read_to_end(&mut rest))
+            .await
+            .expect("timed out waiting for the connection to close");
+        if let Err(e) = read {
+            assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset, "read from test server: {}", e);
+        }
+        rest
+    }
+}
@@ -0,0 +1,574 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{array, bulk, error, int, ok, spawn_server, TestClient};
+use redis::resp::RespData;
+
+fn queued() -> RespData {
+    RespData::SimpleString("QUEUED".to_string())
+}
+
+#[tokio::test]
+async fn exec_runs_the_commands_queued_by_multi() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    assert_eq!(client.cmd(&["EXEC"]).await, error("ERR EXEC without MULTI"));
+    assert_eq!(client.cmd(&["DISCARD"]).await, error("ERR DISCARD without MULTI"));
+
+    assert_eq!(client.cmd(&["MULTI"]).await, ok());
+    assert_eq!(client.cmd(&["MULTI"]).await, error("ERR MULTI calls can not be nested"));
+    assert_eq!(client.cmd(&["SET", "counter", "1"]).await, queued());
+    assert_eq!(client.cmd(&["INCR", "counter"]).await, queued());
+    assert_eq!(client.cmd(&["GET", "counter"]).await, queued());
+    // Nothing runs before EXEC
+    assert_eq!(other.cmd(&["GET", "counter"]).await, RespData::Null);
+    assert_eq!(
+        client.cmd(&["EXEC"]).await,
+        array(vec![ok(), int(2), bulk("2")])
+    );
+
+    // Errors raised while running don't stop the rest
+    client.cmd(&["MULTI"]).await;
+    client.cmd(&["RPUSH", "counter", "x"]).await;
+    client.cmd(&["INCR", "counter"]).await;
+    assert_eq!(
+        client.cmd(&["EXEC"]).await,
+        array(vec![
+            error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            int(3)
+        ])
+    );
+
+    client.cmd(&["MULTI"]).await;
+    client.cmd(&["SET", "counter", "100"]).await;
+    assert_eq!(client.cmd(&["DISCARD"]).await, ok());
+    assert_eq!(client.cmd(&["GET", "counter"]).await, bulk("3"));
+}
+
+#[tokio::test]
+async fn commands_refused_while_queueing_abort_the_transaction() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    let execabort = error("EXECABORT Transaction discarded because of previous errors.");
+    let refused: [(&[&str], RespData); 3] = [
+        (&["BOGUS"], error("ERR unknown command 'BOGUS', with args beginning with: ")),
+        (&["GET"], error("ERR wrong number of arguments for 'get' command")),
+        (&["WAIT", "0", "0"], error("ERR Command not allowed inside a transaction")),
+    ];
+    for (command, expected) in refused {
+        assert_eq!(client.cmd(&["MULTI"]).await, ok());
+        assert_eq!(client.cmd(&["SET", "key", "value"]).await, queued());
+        assert_eq!(client.cmd(command).await, expected);
+        assert_eq!(client.cmd(&["EXEC"]).await, execabort, "{}", command.join(" "));
+        assert_eq!(client.cmd(&["GET", "key"]).await, RespData::Null);
+    }
+}
+
+#[tokio::test]
+async fn subscribed_clients_can_only_manage_subscriptions() {
+    let server = spawn_server().await;
+    let mut subscriber = server.client().await;
+    let mut publisher = server.client().await;
+
+    subscriber.send(&["SUBSCRIBE", "news", "weather"]).await;
+    assert_eq!(subscriber.read_reply().await, array(vec![bulk("subscribe"), bulk("news"), int(1)]));
+    assert_eq!(subscriber.read_reply().await, array(vec![bulk("subscribe"), bulk("weather"), int(2)]));
+
+    assert_eq!(
+        subscriber.cmd(&["GET", "key"]).await,
+        error("ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")
+    );
+    assert_eq!(subscriber.cmd(&["PING"]).await, array(vec![bulk("pong"), bulk("")]));
+
+    assert_eq!(publisher.cmd(&["PUBLISH", "news", "hello"]).await, int(1));
+    assert_eq!(subscriber.read_reply().await, array(vec![bulk("message"), bulk("news"), bulk("hello")]));
+
+    assert_eq!(
+        subscriber.cmd(&["PSUBSCRIBE", "w*"]).await,
+        array(vec![bulk("psubscribe"), bulk("w*"), int(3)])
+    );
+    assert_eq!(publisher.cmd(&["PUBLISH", "weather", "sunny"]).await, int(2));
+    let mut messages = vec![subscriber.read_reply().await, subscriber.read_reply().await];
+    messages.sort_by_key(|message| format!("{:?}", message));
+    assert_eq!(
+        messages,
+        vec![
+            array(vec![bulk("message"), bulk("weather"), bulk("sunny")]),
+            array(vec![bulk("pmessage"), bulk("w*"), bulk("weather"), bulk("sunny")]),
+        ]
+    );
+
+    subscriber.send(&["UNSUBSCRIBE"]).await;
+    assert_eq!(subscriber.read_reply().await, array(vec![bulk("unsubscribe"), bulk("news"), int(2)]));
+    assert_eq!(subscriber.read_reply().await, array(vec![bulk("unsubscribe"), bulk("weather"), int(1)]));
+    assert_eq!(
+        subscriber.cmd(&["PUNSUBSCRIBE"]).await,
+        array(vec![bulk("punsubscribe"), bulk("w*"), int(0)])
+    );
+    assert_eq!(
+        subscriber.cmd(&["UNSUBSCRIBE"]).await,
+        array(vec![bulk("unsubscribe"), RespData::Null, int(0)])
+    );
+
+    // Back to a regular client
+    assert_eq!(subscriber.cmd(&["GET", "key"]).await, RespData::Null);
+    assert_eq!(subscriber.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+    assert_eq!(publisher.cmd(&["PUBLISH", "news", "again"]).await, int(0));
+}
+
+async fn client_list(client: &mut TestClient) -> Vec<String> {
+    match client.cmd(&["CLIENT", "LIST"]).await {
+        RespData::BulkString(list) => list.lines().map(str::to_string).collect(),
+        other => panic!("CLIENT LIST: {:?}", other),
+    }
+}
+
+fn id_of(reply: RespData) -> i64 {
+    match reply {
+        RespData::Integer(id) => id,
+        other => panic!("CLIENT ID: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn client_list_shows_every_connection() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let mut other = server.client().await;
+
+    let id = id_of(client.cmd(&["CLIENT", "ID"]).await);
+    let other_id = id_of(other.cmd(&["CLIENT", "ID"]).await);
+    assert_ne!(id, other_id);
+
+    assert_eq!(client.cmd(&["CLIENT", "GETNAME"]).await, RespData::Null);
+    assert_eq!(
+        client.cmd(&["CLIENT", "SETNAME", "has space"]).await,
+        error("ERR Client names cannot contain spaces, newlines or special characters.")
+    );
+    assert_eq!(client.cmd(&["CLIENT", "SETNAME", "worker"]).await, ok());
+    assert_eq!(client.cmd(&["CLIENT", "GETNAME"]).await, bulk("worker"));
+    client.cmd(&["SUBSCRIBE", "jobs"]).await;
+
+    let list = client_list(&mut other).await;
+    assert_eq!(list.len(), 2, "{:?}", list);
+    let line = list.iter().find(|line| line.starts_with(&format!("id={} ", id))).expect("listed");
+    for field in ["name=worker", "flags=P", "sub=1", "psub=0", "db=0", "cmd=subscribe"] {
+        assert!(line.split(' ').any(|f| f == field), "{} in {}", field, line);
+    }
+    let own = list.iter().find(|line| line.starts_with(&format!("id={} ", other_id))).expect("listed");
+    assert!(own.contains(" cmd=client "), "{}", own);
+
+    other.cmd(&["MULTI"]).await;
+    other.cmd(&["SET", "key", "value"]).await;
+    let mut third = server.client().await;
+    match third.cmd(&["CLIENT", "INFO"]).await {
+        RespData::BulkString(info) => assert!(info.contains(" flags=N ") && info.contains(" multi=-1 "), "{}", info),
+        reply => panic!("CLIENT INFO: {:?}", reply),
+    }
+    let list = client_list(&mut third).await;
+    let queueing = list.iter().find(|line| line.starts_with(&format!("id={} ", other_id))).expect("listed");
+    assert!(queueing.contains(" flags=x ") && queueing.contains(" multi=1 "), "{}", queueing);
+    assert_eq!(other.cmd(&["DISCARD"]).await, ok());
+    drop(third);
+
+    // Disconnected clients leave the list
+    drop(client);
+    for _ in 0..50 {
+        if client_list(&mut other).await.len() == 1 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("closed client still listed");
+}
+
+#[tokio::test]
+async fn clients_over_maxclients_are_told_and_turned_away() {
+    let server = spawn_server().await;
+    let mut admin = server.client().await;
+    assert_eq!(
+        admin.cmd(&["CONFIG", "SET", "maxclients", "0"]).await,
+        error("ERR CONFIG SET failed (possibly related to argument 'maxclients') - argument must be between 1 and 4294967295 inclusive")
+    );
+    assert_eq!(admin.cmd(&["CONFIG", "SET", "maxclients", "10"]).await, ok());
+
+    // The admin connection counts towards the limit. Turned away clients
+    // are told why without having to send anything.
+    let mut accepted = Vec::new();
+    for _ in 0..9 {
+        let mut client = server.client().await;
+        assert_eq!(client.cmd(&["PING"]).await, pong());
+        accepted.push(client);
+    }
+    for _ in 0..5 {
+        let mut client = server.client().await;
+        assert_eq!(client.read_to_close().await, b"-ERR max number of clients reached\r\n");
+    }
+    assert_eq!(admin.info_field("clients", "connected_clients").await, "10");
+    assert_eq!(admin.info_field("clients", "maxclients").await, "10");
+    assert_eq!(admin.info_field("stats", "rejected_connections").await, "5");
+
+    // A freed slot is handed to the next client
+    drop(accepted.pop());
+    for _ in 0..50 {
+        if admin.info_field("clients", "connected_clients").await == "9" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(server.client().await.cmd(&["PING"]).await, pong());
+
+    // Raising the limit applies to the next accept
+    assert_eq!(admin.cmd(&["CONFIG", "SET", "maxclients", "20"]).await, ok());
+    assert_eq!(server.client().await.cmd(&["PING"]).await, pong());
+}
+
+#[tokio::test]
+async fn idle_clients_are_disconnected_after_the_timeout() {
+    let server = spawn_server().await;
+    // Clients already connected pick up the new timeout too
+    let mut idle = server.client().await;
+    let mut active = server.client().await;
+    let mut subscriber = server.client().await;
+    subscriber.cmd(&["SUBSCRIBE", "news"]).await;
+    assert_eq!(active.cmd(&["CONFIG", "SET", "timeout", "1"]).await, ok());
+
+    for _ in 0..8 {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(active.cmd(&["PING"]).await, pong());
+    }
+    assert_eq!(idle.read_to_close().await, b"");
+    assert_eq!(client_list(&mut active).await.len(), 2);
+
+    // Subscribers are never idle
+    let mut publisher = server.client().await;
+    assert_eq!(publisher.cmd(&["PUBLISH", "news", "still here"]).await, int(1));
+    assert_eq!(
+        subscriber.read_reply().await,
+        array(vec![bulk("message"), bulk("news"), bulk("still here")])
+    );
+
+    // 0 switches the timeout off
+    assert_eq!(active.cmd(&["CONFIG", "SET", "timeout", "0"]).await, ok());
+    let mut idle = server.client().await;
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(idle.cmd(&["PING"]).await, pong());
+}
+
+fn invalidate(keys: &[&str]) -> RespData {
+    RespData::Push(vec![bulk("invalidate"), array(keys.iter().map(|key| bulk(key)).collect())])
+}
+
+fn pong() -> RespData {
+    RespData::SimpleString("PONG".to_string())
+}
+
+#[tokio::test]
+async fn tracking_clients_are_told_when_keys_they_read_change() {
+    let server = spawn_server().await;
+    let mut reader = server.client().await;
+    let mut writer = server.client().await;
+
+    match reader.cmd(&["HELLO", "3"]).await {
+        RespData::Map(fields) => assert!(fields.contains(&(bulk("proto"), int(3))), "{:?}", fields),
+        reply => panic!("HELLO: {:?}", reply),
+    }
+    assert_eq!(reader.cmd(&["CLIENT", "GETREDIR"]).await, int(-1));
+    assert_eq!(reader.cmd(&["CLIENT", "TRACKING", "ON"]).await, ok());
+    assert_eq!(reader.cmd(&["CLIENT", "GETREDIR"]).await, int(0));
+    writer.cmd(&["SET", "key", "1"]).await;
+    assert_eq!(reader.cmd(&["GET", "key"]).await, bulk("1"));
+
+    assert_eq!(writer.cmd(&["SET", "key", "2"]).await, ok());
+    assert_eq!(reader.read_reply().await, invalidate(&["key"]));
+
+    // The key is forgotten until read again
+    writer.cmd(&["SET", "key", "3"]).await;
+    assert_eq!(reader.cmd(&["PING"]).await, pong());
+    reader.cmd(&["GET", "key"]).await;
+    writer.cmd(&["DEL", "key"]).await;
+    assert_eq!(reader.read_reply().await, invalidate(&["key"]));
+
+    // Expiry counts as a change
+    writer.cmd(&["SET", "short", "lived", "PX", "50"]).await;
+    reader.cmd(&["GET", "short"]).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(reader.read_reply().await, invalidate(&["short"]));
+
+    reader.cmd(&["GET", "other"]).await;
+    assert_eq!(reader.cmd(&["CLIENT", "TRACKING", "OFF"]).await, ok());
+    writer.cmd(&["SET", "other", "x"]).await;
+    assert_eq!(reader.cmd(&["PING"]).await, pong());
+}
+
+#[tokio::test]
+async fn resp2_clients_receive_invalidations_through_a_redirect() {
+    let server = spawn_server().await;
+    let mut reader = server.client().await;
+    let mut redirect = server.client().await;
+    let mut writer = server.client().await;
+
+    let redirect_id = id_of(redirect.cmd(&["CLIENT", "ID"]).await);
+    redirect.cmd(&["SUBSCRIBE", "__redis__:invalidate"]).await;
+    assert_eq!(reader.cmd(&["CLIENT", "TRACKING", "ON", "REDIRECT", &redirect_id.to_string()]).await, ok());
+    assert_eq!(reader.cmd(&["CLIENT", "GETREDIR"]).await, int(redirect_id));
+    reader.cmd(&["GET", "a"]).await;
+
+    writer.cmd(&["SET", "a", "1"]).await;
+    assert_eq!(
+        redirect.read_reply().await,
+        array(vec![bulk("message"), bulk("__redis__:invalidate"), array(vec![bulk("a")])])
+    );
+}
+
+#[tokio::test]
+async fn tracking_modes_choose_which_keys_are_reported() {
+    let server = spawn_server().await;
+    let mut bcast = server.client().await;
+    let mut optin = server.client().await;
+    let mut writer = server.client().await;
+
+    // BCAST reports every key under the prefixes, read or not
+    bcast.cmd(&["HELLO", "3"]).await;
+    assert_eq!(bcast.cmd(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:"]).await, ok());
+    writer.cmd(&["SET", "user:1", "x"]).await;
+    assert_eq!(bcast.read_reply().await, invalidate(&["user:1"]));
+    writer.cmd(&["SET", "item:1", "x"]).await;
+    assert_eq!(bcast.cmd(&["PING"]).await, pong());
+
+    // NOLOOP leaves out the client's own writes
+    assert_eq!(bcast.cmd(&["CLIENT", "TRACKING", "OFF"]).await, ok());
+    assert_eq!(bcast.cmd(&["CLIENT", "TRACKING", "ON", "BCAST", "NOLOOP"]).await, ok());
+    bcast.cmd(&["SET", "mine", "x"]).await;
+    assert_eq!(bcast.cmd(&["PING"]).await, pong());
+    writer.cmd(&["SET", "theirs", "x"]).await;
+    assert_eq!(bcast.read_reply().await, invalidate(&["theirs"]));
+
+    // OPTIN only tracks the command after CLIENT CACHING YES
+    optin.cmd(&["HELLO", "3"]).await;
+    assert_eq!(optin.cmd(&["CLIENT", "TRACKING", "ON", "OPTIN"]).await, ok());
+    optin.cmd(&["GET", "skipped"]).await;
+    assert_eq!(optin.cmd(&["CLIENT", "CACHING", "YES"]).await, ok());
+    optin.cmd(&["GET", "cached"]).await;
+    optin.cmd(&["GET", "skipped"]).await;
+    writer.cmd(&["SET", "skipped", "1"]).await;
+    writer.cmd(&["SET", "cached", "1"]).await;
+    assert_eq!(optin.read_reply().await, invalidate(&["cached"]));
+    assert_eq!(optin.cmd(&["PING"]).await, pong());
+}
+
+#[tokio::test]
+async fn client_tracking_rejects_conflicting_options() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    for (args, message) in [
+        (&["CLIENT", "TRACKING", "MAYBE"][..], "ERR syntax error"),
+        (&["CLIENT", "TRACKING", "ON", "PREFIX", "a"], "ERR PREFIX option requires BCAST mode to be enabled"),
+        (&["CLIENT", "TRACKING", "ON", "OPTIN", "OPTOUT"], "ERR You can't use both OPTIN and OPTOUT"),
+        (&["CLIENT", "TRACKING", "ON", "BCAST", "OPTIN"], "ERR OPTIN and OPTOUT are not compatible with BCAST"),
+        (&["CLIENT", "TRACKING", "ON", "REDIRECT", "9999"], "ERR The client ID you want redirect to does not exist"),
+        (
+            &["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "a", "PREFIX", "ab"],
+            "ERR Prefix 'ab' overlaps with an existing prefix 'a'. Prefixes for a single client must not overlap.",
+        ),
+        (
+            &["CLIENT", "CACHING", "YES"],
+            "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled",
+        ),
+    ] {
+        assert_eq!(client.cmd(args).await, error(message), "{:?}", args);
+    }
+
+    assert_eq!(client.cmd(&["CLIENT", "TRACKING", "ON", "OPTOUT"]).await, ok());
+    assert_eq!(
+        client.cmd(&["CLIENT", "CACHING", "YES"]).await,
+        error("ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.")
+    );
+    assert_eq!(client.cmd(&["CLIENT", "CACHING", "NO"]).await, ok());
+    assert_eq!(client.cmd(&["HELLO", "4"]).await, error("NOPROTO unsupported protocol version"));
+}
+
+#[tokio::test]
+async fn nulls_are_encoded_for_the_protocol_in_use() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["RPUSH", "list", "a"]).await;
+    client.cmd(&["HSET", "h", "f", "v"]).await;
+
+    let cases: [(&[&str], &[u8], &[u8]); 6] = [
+        (&["GET", "missing"], b"$-1\r\n", b"_\r\n"),
+        (&["HGET", "h", "missing"], b"$-1\r\n", b"_\r\n"),
+        (&["CLIENT", "GETNAME"], b"$-1\r\n", b"_\r\n"),
+        (&["MEMORY", "USAGE", "missing"], b"$-1\r\n", b"_\r\n"),
+        (&["SORT", "list", "BY", "nosort", "GET", "nope"], b"*1\r\n$-1\r\n", b"*1\r\n_\r\n"),
+        (&["HGETDEL", "h", "FIELDS", "2", "f", "g"], b"*2\r\n$1\r\nv\r\n$-1\r\n", b"*2\r\n$1\r\nv\r\n_\r\n"),
+    ];
+    for (command, resp2, _) in &cases {
+        assert_eq!(client.cmd_raw(command).await, *resp2, "{:?}", command);
+    }
+    client.cmd(&["HSET", "h", "f", "v"]).await;
+    client.cmd(&["HELLO", "3"]).await;
+    for (command, _, resp3) in &cases {
+        assert_eq!(client.cmd_raw(command).await, *resp3, "{:?}", command);
+    }
+    assert_eq!(client.cmd_raw(&["UNSUBSCRIBE"]).await, b">3\r\n$11\r\nunsubscribe\r\n_\r\n:0\r\n");
+
+    // Back on RESP2 the unsubscribe confirmation is an array
+    client.cmd(&["HELLO", "2"]).await;
+    assert_eq!(client.cmd_raw(&["UNSUBSCRIBE"]).await, b"*3\r\n$11\r\nunsubscribe\r\n$-1\r\n:0\r\n");
+}
+
+async fn output_buffer_peak(client: &mut TestClient) -> usize {
+    match client.cmd(&["INFO", "clients"]).await {
+        RespData::BulkString(info) => info
+            .lines()
+            .find_map(|line| line.strip_prefix("client_recent_max_output_buffer:"))
+            .and_then(|value| value.parse::<usize>().ok())
+            .expect("client_recent_max_output_buffer in INFO"),
+        reply => panic!("INFO: {:?}", reply),
+    }
+}
+
+fn list_element(i: usize) -> String {
+    format!("{:08}{}", i, "x".repeat(1992))
+}
+
+#[tokio::test]
+async fn large_lrange_replies_are_streamed_in_bounded_memory() {
+    const ELEMENTS: usize = 100_000;
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    for batch in (0..ELEMENTS).collect::<Vec<_>>().chunks(500) {
+        let elements: Vec<String> = batch.iter().map(|&i| list_element(i)).collect();
+        let mut command = vec!["RPUSH", "big"];
+        command.extend(elements.iter().map(String::as_str));
+        client.cmd(&command).await;
+    }
+
+    // 200mb of elements, read back as they arrive
+    client.send(&["LRANGE", "big", "0", "-1"]).await;
+    assert_eq!(client.read_exact(9).await, format!("*{}\r\n", ELEMENTS).as_bytes());
+    for i in 0..ELEMENTS {
+        let element = list_element(i);
+        let expected = format!("${}\r\n{}\r\n", element.len(), element);
+        assert_eq!(client.read_exact(expected.len()).await, expected.as_bytes(), "element {}", i);
+    }
+    assert_eq!(client.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+    let peak = output_buffer_peak(&mut client).await;
+    assert!(peak < 512 * 1024, "output buffer peaked at {} bytes", peak);
+}
+
+#[tokio::test]
+async fn streamed_lrange_replies_match_built_ones() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let elements: Vec<String> = (0..3000).map(|i| i.to_string()).collect();
+    let mut command = vec!["RPUSH", "list"];
+    command.extend(elements.iter().map(String::as_str));
+    client.cmd(&command).await;
+
+    for (start, stop, expected) in [("0", "-1", &elements[..]), ("10", "1500", &elements[10..1501]), ("-5", "-1", &elements[2995..])] {
+        let expected = array(expected.iter().map(|element| bulk(element)).collect());
+        assert_eq!(client.cmd(&["LRANGE", "list", start, stop]).await, expected);
+        // EXEC builds the reply whole
+        client.cmd(&["MULTI"]).await;
+        client.cmd(&["LRANGE", "list", start, stop]).await;
+        assert_eq!(client.cmd(&["EXEC"]).await, array(vec![expected]));
+    }
+}
+
+#[tokio::test]
+async fn streamed_replies_show_the_value_as_it_was_when_the_command_ran() {
+    const ELEMENTS: usize = 10_000;
+    let server = spawn_server().await;
+    let mut reader = server.client().await;
+    let mut writer = server.client().await;
+    for batch in (0..ELEMENTS).collect::<Vec<_>>().chunks(500) {
+        let elements: Vec<String> = batch.iter().map(|&i| list_element(i)).collect();
+        let mut command = vec!["RPUSH", "big"];
+        command.extend(elements.iter().map(String::as_str));
+        writer.cmd(&command).await;
+    }
+
+    // 20mb is more than the socket buffers hold, so the reply is still
+    // being sent while the list changes and then goes away
+    reader.send(&["LRANGE", "big", "0", "-1"]).await;
+    assert_eq!(reader.read_exact(8).await, format!("*{}\r\n", ELEMENTS).as_bytes());
+    assert_eq!(writer.cmd(&["LPUSH", "big", "new"]).await, int(ELEMENTS as i64 + 1));
+    assert_eq!(writer.cmd(&["DEL", "big"]).await, int(1));
+    for i in 0..ELEMENTS {
+        let element = list_element(i);
+        let expected = format!("${}\r\n{}\r\n", element.len(), element);
+        assert_eq!(reader.read_exact(expected.len()).await, expected.as_bytes(), "element {}", i);
+    }
+    assert_eq!(reader.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn streamed_hgetall_and_keys_replies_match_built_ones() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let names: Vec<String> = (0..3000).map(|i| format!("k{}", i)).collect();
+    let mut hset = vec!["HSET", "hash"];
+    for name in &names {
+        hset.extend([name.as_str(), name.as_str()]);
+    }
+    client.cmd(&hset).await;
+    for name in &names[..1500] {
+        client.cmd(&["SET", name, "v"]).await;
+    }
+
+    for (command, len) in [(&["HGETALL", "hash"][..], 6000), (&["KEYS", "k*"], 1500), (&["KEYS", "*"], 1501)] {
+        let streamed = client.cmd(command).await;
+        assert!(matches!(&streamed, RespData::Array(items) if items.len() == len), "{:?}", command);
+        client.cmd(&["MULTI"]).await;
+        client.cmd(command).await;
+        assert_eq!(client.cmd(&["EXEC"]).await, array(vec![streamed]), "{:?}", command);
+    }
+    let fields = match client.cmd(&["HGETALL", "hash"]).await {
+        RespData::Array(items) => items,
+        reply => panic!("HGETALL: {:?}", reply),
+    };
+    for pair in fields.chunks(2) {
+        assert_eq!(pair[0], pair[1]);
+    }
+}
+
+#[tokio::test]
+async fn large_hgetall_replies_are_streamed_from_a_snapshot() {
+    const FIELDS: usize = 10_000;
+    let server = spawn_server().await;
+    let mut reader = server.client().await;
+    let mut writer = server.client().await;
+    for batch in (0..FIELDS).collect::<Vec<_>>().chunks(500) {
+        let pairs: Vec<(String, String)> = batch.iter().map(|&i| (format!("field:{:05}", i), list_element(i))).collect();
+        let mut command = vec!["HSET", "big"];
+        for (name, value) in &pairs {
+            command.extend([name.as_str(), value.as_str()]);
+        }
+        writer.cmd(&command).await;
+    }
+
+    reader.send(&["HGETALL", "big"]).await;
+    assert_eq!(reader.read_exact(8).await, format!("*{}\r\n", 2 * FIELDS).as_bytes());
+    assert_eq!(writer.cmd(&["HSET", "big", "field:new", "v"]).await, int(1));
+    assert_eq!(writer.cmd(&["DEL", "big"]).await, int(1));
+    // Every field arrives once, names and values being of fixed lengths
+    let mut seen = vec![false; FIELDS];
+    for _ in 0..FIELDS {
+        let name = reader.read_exact(b"$11\r\nfield:00000\r\n".len()).await;
+        let i: usize = String::from_utf8_lossy(&name[11..16]).parse().unwrap();
+        assert!(!std::mem::replace(&mut seen[i], true), "field {} sent twice", i);
+        let element = list_element(i);
+        let expected = format!("${}\r\n{}\r\n", element.len(), element);
+        assert_eq!(reader.read_exact(expected.len()).await, expected.as_bytes(), "field {}", i);
+    }
+    assert_eq!(reader.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+    let peak = output_buffer_peak(&mut reader).await;
+    assert!(peak < 512 * 1024, "output buffer peaked at {} bytes", peak);
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{bulk, error, int, ok, spawn_server, TestClient};
+use redis::resp::RespData;
+
+fn oom() -> RespData {
+    error("OOM command not allowed when used memory > 'maxmemory'.")
+}
+
+async fn used_memory(client: &mut TestClient) -> u64 {
+    client.info_field("memory", "used_memory").await.parse().unwrap()
+}
+
+// Sets keys key:0..count with 100 byte values, then caps maxmemory at
+// half of what they take
+async fn fill_and_cap(client: &mut TestClient, count: usize, ttl: bool) -> u64 {
+    let value = "x".repeat(100);
+    for i in 0..count {
+        let key = format!("key:{}", i);
+        let reply = if ttl {
+            client.cmd(&["SET", &key, &value, "EX", "1000"]).await
+        } else {
+            client.cmd(&["SET", &key, &value]).await
+        };
+        assert_eq!(reply, ok());
+    }
+    let maxmemory = used_memory(client).await / 2;
+    assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory", &maxmemory.to_string()]).await, ok());
+    maxmemory
+}
+
+#[tokio::test]
+async fn noeviction_refuses_writes_that_need_memory() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    fill_and_cap(&mut client, 100, false).await;
+
+    assert_eq!(client.cmd(&["SET", "new", "v"]).await, oom());
+    assert_eq!(client.cmd(&["LPUSH", "list", "v"]).await, oom());
+    assert_eq!(client.cmd(&["GET", "key:0"]).await, bulk(&"x".repeat(100)));
+    assert_eq!(client.cmd(&["DEL", "key:0"]).await, int(1));
+    assert_eq!(client.info_field("stats", "evicted_keys").await, "0");
+    assert_eq!(client.cmd(&["DBSIZE"]).await, int(99));
+}
+
+#[tokio::test]
+async fn allkeys_policies_evict_until_under_maxmemory() {
+    for policy in ["allkeys-lru", "allkeys-random"] {
+        let server = spawn_server().await;
+        let mut client = server.client().await;
+        assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory-policy", policy]).await, ok());
+        let maxmemory = fill_and_cap(&mut client, 100, false).await;
+        assert_eq!(client.info_field("memory", "maxmemory_policy").await, policy);
+
+        // Evicting comes before the write, which may then go over a little
+        assert_eq!(client.cmd(&["SET", "new", "v"]).await, ok(), "{}", policy);
+        assert!(used_memory(&mut client).await <= maxmemory + 200, "{}", policy);
+        let evicted: i64 = client.info_field("stats", "evicted_keys").await.parse().unwrap();
+        assert!(evicted > 0, "{}", policy);
+        assert_eq!(client.cmd(&["DBSIZE"]).await, int(101 - evicted), "{}", policy);
+        assert_eq!(client.cmd(&["GET", "new"]).await, bulk("v"), "{}", policy);
+    }
+}
+
+#[tokio::test]
+async fn lru_eviction_spares_recently_used_keys() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory-policy", "allkeys-lru"]).await, ok());
+    assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory-samples", "10"]).await, ok());
+    let value = "x".repeat(100);
+    for i in 0..100 {
+        client.cmd(&["SET", &format!("key:{}", i), &value]).await;
+    }
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert_eq!(client.cmd(&["GET", "key:42"]).await, bulk(&value));
+
+    let maxmemory = used_memory(&mut client).await / 2;
+    assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory", &maxmemory.to_string()]).await, ok());
+    assert_eq!(client.cmd(&["SET", "new", "v"]).await, ok());
+    assert_eq!(client.cmd(&["EXISTS", "key:42"]).await, int(1));
+}
+
+#[tokio::test]
+async fn volatile_policies_only_evict_keys_with_a_ttl() {
+    for policy in ["volatile-lru", "volatile-random", "volatile-ttl"] {
+        let server = spawn_server().await;
+        let mut client = server.client().await;
+        assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory-policy", policy]).await, ok());
+        let value = "x".repeat(100);
+        for i in 0..50 {
+            client.cmd(&["SET", &format!("persistent:{}", i), &value]).await;
+        }
+        fill_and_cap(&mut client, 100, true).await;
+
+        assert_eq!(client.cmd(&["SET", "new", "v"]).await, ok(), "{}", policy);
+        assert!(client.info_field("stats", "evicted_keys").await != "0", "{}", policy);
+
+        // Once nothing with a TTL is left to evict, writes are refused
+        assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory", "1"]).await, ok());
+        assert_eq!(client.cmd(&["SET", "newer", "v"]).await, oom(), "{}", policy);
+        for i in 0..50 {
+            assert_eq!(client.cmd(&["EXISTS", &format!("persistent:{}", i)]).await, int(1), "{}", policy);
+        }
+    }
+}
+
+#[tokio::test]
+async fn evictions_are_published_as_keyspace_events() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let mut subscriber = server.client().await;
+    assert_eq!(client.cmd(&["CONFIG", "SET", "notify-keyspace-events", "KEe"]).await, ok());
+    assert_eq!(client.cmd(&["CONFIG", "GET", "notify-keyspace-events"]).await, RespData::Array(vec![bulk("notify-keyspace-events"), bulk("eKE")]));
+    assert_eq!(
+        client.cmd(&["CONFIG", "SET", "notify-keyspace-events", "Kq"]).await,
+        RespData::Error(
+            "ERR CONFIG SET failed (possibly related to argument 'notify-keyspace-events') - Invalid event class character. \
+             Use 'Ag$lshzxeKEtmdn'."
+                .to_string()
+        )
+    );
+    subscriber.send(&["SUBSCRIBE", "__keyevent@0__:evicted"]).await;
+    subscriber.read_reply().await;
+    subscriber.send(&["PSUBSCRIBE", "__keyspace@0__:*"]).await;
+    subscriber.read_reply().await;
+
+    assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory-policy", "allkeys-random"]).await, ok());
+    fill_and_cap(&mut client, 100, false).await;
+    assert_eq!(client.cmd(&["SET", "new", "v"]).await, ok());
+    let evicted: usize = client.info_field("stats", "evicted_keys").await.parse().unwrap();
+    assert!(evicted > 0);
+
+    // Each eviction is published on both channels
+    let mut keys = Vec::new();
+    for _ in 0..evicted * 2 {
+        match subscriber.read_reply().await {
+            RespData::Array(message) if message[0] == bulk("message") => {
+                assert_eq!(message[1], bulk("__keyevent@0__:evicted"));
+                keys.push(message[2].clone());
+            }
+            RespData::Array(message) => {
+                assert_eq!(message[3], bulk("evicted"));
+                match &message[2] {
+                    RespData::BulkString(channel) => assert!(channel.starts_with("__keyspace@0__:key:"), "{}", channel),
+                    other => panic!("channel: {:?}", other),
+                }
+            }
+            other => panic!("notification: {:?}", other),
+        }
+    }
+    assert_eq!(keys.len(), evicted);
+    for key in keys {
+        let key = match key {
+            RespData::BulkString(key) => key,
+            other => panic!("key: {:?}", other),
+        };
+        assert_eq!(client.cmd(&["EXISTS", &key]).await, int(0));
+    }
+}
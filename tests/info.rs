@@ -0,0 +1,157 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{bulk, error, int, ok, spawn_server};
+use redis::resp::RespData;
+
+#[tokio::test]
+async fn info_stats_count_lookups_expirations_and_traffic() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["SET", "k", "v"]).await, ok());
+    assert_eq!(client.cmd(&["GET", "k"]).await, bulk("v"));
+    assert_eq!(client.cmd(&["GET", "missing"]).await, RespData::Null);
+    assert_eq!(client.cmd(&["EXISTS", "k"]).await, int(1));
+    assert_eq!(client.cmd(&["EXISTS", "missing"]).await, int(0));
+    let mut other = server.client().await;
+    assert_eq!(other.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+
+    // The INFO asking counts as a command too
+    assert_eq!(client.info_field("stats", "total_commands_processed").await, "7");
+    assert_eq!(client.info_field("stats", "total_connections_received").await, "2");
+    assert_eq!(client.info_field("stats", "keyspace_hits").await, "2");
+    assert_eq!(client.info_field("stats", "keyspace_misses").await, "2");
+    let input: u64 = client.info_field("stats", "total_net_input_bytes").await.parse().unwrap();
+    let output: u64 = client.info_field("stats", "total_net_output_bytes").await.parse().unwrap();
+    assert!(input > 0 && output > 0, "input {} output {}", input, output);
+
+    assert_eq!(client.cmd(&["SET", "short", "v", "PX", "1"]).await, ok());
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(client.cmd(&["GET", "short"]).await, RespData::Null);
+    assert_eq!(client.info_field("stats", "expired_keys").await, "1");
+
+    assert_eq!(client.cmd(&["CONFIG", "RESETSTAT"]).await, ok());
+    assert_eq!(client.info_field("stats", "keyspace_hits").await, "0");
+    assert_eq!(client.info_field("stats", "expired_keys").await, "0");
+    // RESETSTAT's own call isn't counted, the two INFOs since are
+    assert_eq!(client.info_field("stats", "total_commands_processed").await, "3");
+}
+
+#[tokio::test]
+async fn commandstats_count_calls_failures_and_rejections() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["SET", "k", "v"]).await, ok());
+    assert_eq!(client.cmd(&["SET", "k", "w"]).await, ok());
+    assert_eq!(client.cmd(&["LPUSH", "list", "x"]).await, int(1));
+    // Failed: the handler ran and answered an error
+    assert!(matches!(client.cmd(&["GET", "list"]).await, RespData::Error(_)));
+    // Rejected: refused before running
+    assert!(matches!(client.cmd(&["GET"]).await, RespData::Error(_)));
+
+    let set = client.info_field("commandstats", "cmdstat_set").await;
+    assert!(set.starts_with("calls=2,usec="), "{}", set);
+    assert!(set.ends_with(",rejected_calls=0,failed_calls=0"), "{}", set);
+    let get = client.info_field("commandstats", "cmdstat_get").await;
+    assert!(get.starts_with("calls=1,usec="), "{}", get);
+    assert!(get.ends_with(",rejected_calls=1,failed_calls=1"), "{}", get);
+
+    let latency = client.info_field("latencystats", "latency_percentiles_usec_set").await;
+    let percentiles: Vec<&str> = latency.split(',').map(|p| p.split('=').next().unwrap()).collect();
+    assert_eq!(percentiles, ["p50", "p99", "p99.9"]);
+
+    assert_eq!(client.cmd(&["CONFIG", "RESETSTAT"]).await, ok());
+    match client.cmd(&["INFO", "commandstats"]).await {
+        RespData::BulkString(info) => assert!(!info.contains("cmdstat_set"), "{}", info),
+        reply => panic!("INFO: {:?}", reply),
+    }
+}
+
+fn integer(reply: RespData) -> i64 {
+    match reply {
+        RespData::Integer(n) => n,
+        reply => panic!("expected an integer, got {:?}", reply),
+    }
+}
+
+#[tokio::test]
+async fn memory_usage_grows_with_the_value() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["MEMORY", "USAGE", "missing"]).await, RespData::Null);
+
+    client.cmd(&["SET", "small", "v"]).await;
+    client.cmd(&["SET", "large", &"v".repeat(1000)]).await;
+    let small = integer(client.cmd(&["MEMORY", "USAGE", "small"]).await);
+    let large = integer(client.cmd(&["MEMORY", "USAGE", "large"]).await);
+    assert!(small > 1, "{}", small);
+    assert!(large >= small + 999, "{} vs {}", large, small);
+
+    // Elements of the same size estimate the same whether sampled or not
+    let elements: Vec<String> = (0..1000).map(|i| format!("element:{:04}", i)).collect();
+    let mut rpush = vec!["RPUSH", "list"];
+    rpush.extend(elements.iter().map(String::as_str));
+    client.cmd(&rpush).await;
+    let sampled = integer(client.cmd(&["MEMORY", "USAGE", "list"]).await);
+    let exact = integer(client.cmd(&["MEMORY", "USAGE", "list", "SAMPLES", "0"]).await);
+    assert!(sampled > 12 * 1000, "{}", sampled);
+    assert!(sampled.abs_diff(exact) * 10 < exact as u64, "{} vs {}", sampled, exact);
+
+    client.cmd(&["SET", "short", "v", "PX", "1"]).await;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(client.cmd(&["MEMORY", "USAGE", "short"]).await, RespData::Null);
+
+    assert_eq!(
+        client.cmd(&["MEMORY", "USAGE", "small", "SAMPLES", "many"]).await,
+        error("ERR value is not an integer or out of range")
+    );
+    assert_eq!(
+        client.cmd(&["MEMORY", "USAGE", "small", "BOGUS"]).await,
+        error("ERR syntax error")
+    );
+}
+
+// The value of `name` in a MEMORY STATS reply
+fn memory_stat(stats: &RespData, name: &str) -> RespData {
+    match stats {
+        RespData::Array(fields) => fields
+            .chunks(2)
+            .find(|pair| pair[0] == bulk(name))
+            .map(|pair| pair[1].clone())
+            .unwrap_or_else(|| panic!("no {} in MEMORY STATS", name)),
+        reply => panic!("MEMORY STATS: {:?}", reply),
+    }
+}
+
+#[tokio::test]
+async fn memory_stats_and_doctor_describe_the_dataset() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    match client.cmd(&["MEMORY", "DOCTOR"]).await {
+        RespData::BulkString(report) => assert!(report.contains("this instance is empty"), "{}", report),
+        reply => panic!("MEMORY DOCTOR: {:?}", reply),
+    }
+
+    for i in 0..10 {
+        client.cmd(&["SET", &format!("key:{}", i), &"v".repeat(1000)]).await;
+    }
+    let used: i64 = client.info_field("memory", "used_memory").await.parse().unwrap();
+    let stats = client.cmd(&["MEMORY", "STATS"]).await;
+    assert_eq!(memory_stat(&stats, "keys.count"), int(10));
+    assert_eq!(memory_stat(&stats, "total.allocated"), int(used));
+    let overhead = integer(memory_stat(&stats, "overhead.total"));
+    let dataset = integer(memory_stat(&stats, "dataset.bytes"));
+    assert_eq!(overhead + dataset, used);
+    assert_eq!(memory_stat(&stats, "keys.bytes-per-key"), int(used / 10));
+
+    match client.cmd(&["MEMORY", "DOCTOR"]).await {
+        RespData::BulkString(report) => assert!(report.contains("Maxmemory is not set"), "{}", report),
+        reply => panic!("MEMORY DOCTOR: {:?}", reply),
+    }
+    assert_eq!(client.cmd(&["CONFIG", "SET", "maxmemory", "100mb"]).await, ok());
+    match client.cmd(&["MEMORY", "DOCTOR"]).await {
+        RespData::BulkString(report) => assert!(report.contains("can't find any memory issue"), "{}", report),
+        reply => panic!("MEMORY DOCTOR: {:?}", reply),
+    }
+}
@@ -0,0 +1,71 @@
+// Heap use of small aggregates in each encoding, measured by counting what
+// the allocator hands out
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use redis::config::ServerConfig;
+use redis::store::RedisStore;
+
+struct Counting;
+
+static ALLOCATED: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size as isize - layout.size() as isize, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const KEYS: usize = 10_000;
+
+// Bytes still allocated after filling a store configured by `settings`
+// with small lists or hashes
+fn heap_used(settings: &[(&str, &str)], hashes: bool) -> isize {
+    let mut config = ServerConfig::default();
+    for (name, value) in settings {
+        config.set(name, value).unwrap();
+    }
+    let store = RedisStore::with_config(config);
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    for i in 0..KEYS {
+        let key = format!("key:{}", i);
+        if hashes {
+            let pairs = (0..10).map(|field| (format!("field{}", field), format!("value{}", i))).collect();
+            store.hset(&key, pairs).unwrap();
+        } else {
+            store.rpush(&key, (0..10).map(|item| format!("item{}:{}", item, i)).collect()).unwrap();
+        }
+    }
+    let used = ALLOCATED.load(Ordering::Relaxed) - before;
+    drop(store);
+    used
+}
+
+// One test, as concurrent tests would skew each other's counts. Keys and
+// entries cost the same in both encodings, so the saving on the elements
+// themselves is larger than asserted.
+#[test]
+fn compact_encoding_shrinks_small_aggregates() {
+    let compact = heap_used(&[], false);
+    let general = heap_used(&[("list-max-listpack-size", "1")], false);
+    assert!(compact * 3 < general * 2, "lists: {} bytes compact, {} bytes general", compact, general);
+
+    let compact = heap_used(&[], true);
+    let general = heap_used(&[("hash-max-listpack-entries", "0")], true);
+    assert!(compact * 3 < general * 2, "hashes: {} bytes compact, {} bytes general", compact, general);
+}
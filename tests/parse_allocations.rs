@@ -0,0 +1,68 @@
+// Bytes allocated while parsing arrays of growing length, counted by the
+// allocator: parsing must not copy the rest of the buffer for each element
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use redis::resp::{parse_resp, serialize_resp, RequestParser, RespData};
+
+struct Counting;
+
+// Everything handed out, freed or not, since copies are freed right away
+static HANDED_OUT: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        HANDED_OUT.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        HANDED_OUT.fetch_add(new_size, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Bytes allocated by `parse` per element of an encoded `len` element
+// array, whose elements are themselves two element arrays if `nested`
+fn bytes_per_element(len: usize, nested: bool, parse: impl Fn(&[u8]) -> RespData) -> usize {
+    let element = |i: usize| RespData::BulkString(format!("element:{}", i));
+    let elements = (0..len).map(|i| if nested { RespData::Array(vec![element(i), element(i)]) } else { element(i) });
+    let encoded = serialize_resp(&RespData::Array(elements.collect()));
+
+    let before = HANDED_OUT.load(Ordering::Relaxed);
+    let parsed = parse(&encoded);
+    let handed_out = HANDED_OUT.load(Ordering::Relaxed) - before;
+    assert!(matches!(&parsed, RespData::Array(elements) if elements.len() == len));
+    drop(parsed);
+    handed_out / len
+}
+
+// What growing the array of elements may add per element: doubling hands
+// out up to twice the final capacity, itself up to twice the length.
+// Copying the rest of the buffer for each element would instead add half
+// the encoded array per element.
+const GROWTH: usize = 4 * std::mem::size_of::<RespData>();
+
+// One test, as concurrent tests would skew each other's counts
+#[test]
+fn parsing_allocates_the_same_per_element_at_any_length() {
+    let replies = |buffer: &[u8]| parse_resp(buffer).unwrap().unwrap().1;
+    let requests = |buffer: &[u8]| RequestParser::default().parse(buffer, 512 * 1024 * 1024).unwrap().unwrap().1;
+
+    for nested in [false, true] {
+        let short = bytes_per_element(10, nested, replies);
+        let long = bytes_per_element(10_000, nested, replies);
+        assert!(long <= short + GROWTH, "nested: {}: {} bytes per element for 10, {} for 10k", nested, short, long);
+    }
+    let short = bytes_per_element(10, false, requests);
+    let long = bytes_per_element(10_000, false, requests);
+    assert!(long <= short + GROWTH, "{} bytes per element for 10, {} for 10k", short, long);
+}
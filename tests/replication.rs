@@ -0,0 +1,252 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{bulk, error, int, ok, spawn_server, TestClient};
+use redis::resp::RespData;
+
+fn readonly() -> RespData {
+    error("READONLY You can't write against a read only replica.")
+}
+
+// Polls `args` until it answers `expected`, since replication is asynchronous
+async fn wait_for(client: &mut TestClient, args: &[&str], expected: &RespData) {
+    for _ in 0..100 {
+        if client.cmd(args).await == *expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{:?} never answered {:?}", args, expected);
+}
+
+#[tokio::test]
+async fn replicas_only_take_writes_from_their_master() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let mut to_master = master.client().await;
+    let mut to_replica = replica.client().await;
+    to_replica.cmd(&["SET", "local", "1"]).await;
+
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+    assert_eq!(to_replica.cmd(&["SET", "k", "from-client"]).await, readonly());
+    assert_eq!(to_replica.cmd(&["DEL", "local"]).await, readonly());
+
+    // Writes from the master are applied while the replica's clients can read
+    assert_eq!(to_master.cmd(&["SET", "k", "from-master"]).await, ok());
+    wait_for(&mut to_replica, &["GET", "k"], &bulk("from-master")).await;
+    assert_eq!(to_replica.cmd(&["LPUSH", "list", "x"]).await, readonly());
+    assert_eq!(to_replica.cmd(&["GET", "k"]).await, bulk("from-master"));
+
+    // A refused write discards the transaction it was queued in
+    assert_eq!(to_replica.cmd(&["MULTI"]).await, ok());
+    assert_eq!(to_replica.cmd(&["SET", "k", "v"]).await, readonly());
+    assert_eq!(
+        to_replica.cmd(&["EXEC"]).await,
+        error("EXECABORT Transaction discarded because of previous errors.")
+    );
+
+    // The rare opt-out
+    assert_eq!(to_replica.cmd(&["CONFIG", "SET", "replica-read-only", "no"]).await, ok());
+    assert_eq!(to_replica.cmd(&["SET", "scratch", "1"]).await, ok());
+    assert_eq!(to_replica.cmd(&["CONFIG", "SET", "slave-read-only", "yes"]).await, ok());
+    assert_eq!(to_replica.cmd(&["SET", "scratch", "2"]).await, readonly());
+
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "NO", "ONE"]).await, ok());
+    assert_eq!(to_replica.cmd(&["SET", "k", "from-client"]).await, ok());
+    assert_eq!(to_replica.cmd(&["GET", "k"]).await, bulk("from-client"));
+}
+
+#[tokio::test]
+async fn readonly_and_readwrite_are_accepted() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["READONLY"]).await, ok());
+    assert_eq!(client.cmd(&["READWRITE"]).await, ok());
+    assert_eq!(
+        client.cmd(&["READONLY", "extra"]).await,
+        error("ERR wrong number of arguments for 'readonly' command")
+    );
+}
+
+#[tokio::test]
+async fn replicas_add_stream_entries_under_the_masters_ids() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let mut to_master = master.client().await;
+    let mut to_replica = replica.client().await;
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+
+    let id = to_master.cmd(&["XADD", "s", "*", "f", "v"]).await;
+    let entry = RespData::Array(vec![RespData::Array(vec![id, RespData::Array(vec![bulk("f"), bulk("v")])])]);
+    wait_for(&mut to_replica, &["XRANGE", "s", "-", "+"], &entry).await;
+}
+
+#[tokio::test]
+async fn migrated_keys_reach_replicas_as_deletions() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let target = spawn_server().await;
+    let mut to_master = master.client().await;
+    let mut to_replica = replica.client().await;
+    let mut to_target = target.client().await;
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+    to_master.cmd(&["SET", "k", "v"]).await;
+    wait_for(&mut to_replica, &["GET", "k"], &bulk("v")).await;
+
+    let target_port = target.addr.port().to_string();
+    assert_eq!(to_master.cmd(&["MIGRATE", "127.0.0.1", &target_port, "k", "0", "1000"]).await, ok());
+    wait_for(&mut to_replica, &["EXISTS", "k"], &int(0)).await;
+    assert_eq!(to_target.cmd(&["GET", "k"]).await, bulk("v"));
+    // A replica doesn't migrate anything on its own
+    assert_eq!(to_replica.cmd(&["MIGRATE", "127.0.0.1", &target_port, "k", "0", "1000"]).await, readonly());
+}
+
+// With a replica attached writes are serialized, so a MIGRATE waiting on a
+// slow target must not hold them up
+#[tokio::test]
+async fn writes_go_on_while_migrate_waits_for_its_target() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let mut to_master = master.client().await;
+    let mut to_replica = replica.client().await;
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+    to_master.cmd(&["SET", "k", "v"]).await;
+    wait_for(&mut to_replica, &["GET", "k"], &bulk("v")).await;
+
+    // A target that takes the connection and never answers
+    let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target_port = target.local_addr().unwrap().port().to_string();
+    let mut migrating = master.client().await;
+    migrating.send(&["MIGRATE", "127.0.0.1", &target_port, "k", "0", "2000"]).await;
+    let (_connection, _) = target.accept().await.unwrap();
+
+    let write = to_master.cmd(&["SET", "other", "v"]);
+    assert_eq!(tokio::time::timeout(Duration::from_millis(500), write).await.expect("SET waited on MIGRATE"), ok());
+    assert_eq!(
+        migrating.read_reply().await,
+        error("IOERR error or timeout reading to target instance")
+    );
+    assert_eq!(to_master.cmd(&["GET", "k"]).await, bulk("v"));
+}
+
+#[tokio::test]
+async fn migrate_inside_a_transaction_removes_the_key() {
+    let server = spawn_server().await;
+    let target = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["SET", "k", "v"]).await;
+    let target_port = target.addr.port().to_string();
+    assert_eq!(client.cmd(&["MULTI"]).await, ok());
+    client.cmd(&["MIGRATE", "127.0.0.1", &target_port, "k", "0", "1000"]).await;
+    assert_eq!(client.cmd(&["EXEC"]).await, RespData::Array(vec![ok()]));
+    assert_eq!(client.cmd(&["EXISTS", "k"]).await, int(0));
+    assert_eq!(target.client().await.cmd(&["GET", "k"]).await, bulk("v"));
+}
+
+#[tokio::test]
+async fn replicas_attached_during_writes_end_up_with_the_masters_data() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let mut to_replica = replica.client().await;
+
+    let mut writers = Vec::new();
+    for i in 0..4 {
+        let mut client = master.client().await;
+        writers.push(tokio::spawn(async move {
+            let list = format!("list{}", i);
+            for _ in 0..200 {
+                client.cmd(&["INCR", "counter"]).await;
+                client.cmd(&["RPUSH", &list, "x"]).await;
+            }
+        }));
+    }
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+    for writer in writers {
+        writer.await.unwrap();
+    }
+
+    // Neither write is idempotent, so one that made it into the snapshot
+    // and was streamed as well would be counted twice
+    wait_for(&mut to_replica, &["GET", "counter"], &bulk("800")).await;
+    for i in 0..4 {
+        assert_eq!(to_replica.cmd(&["LLEN", &format!("list{}", i)]).await, int(200));
+    }
+}
+
+#[tokio::test]
+async fn sort_only_counts_as_a_write_with_store() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let mut to_master = master.client().await;
+    let mut to_replica = replica.client().await;
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+    to_master.cmd(&["RPUSH", "list", "3", "1", "2"]).await;
+    wait_for(&mut to_replica, &["LLEN", "list"], &int(3)).await;
+
+    let sorted = RespData::Array(vec![bulk("1"), bulk("2"), bulk("3")]);
+    assert_eq!(to_replica.cmd(&["SORT", "list"]).await, sorted);
+    assert_eq!(to_replica.cmd(&["SORT", "list", "BY", "store", "GET", "#"]).await, RespData::Array(vec![bulk("3"), bulk("1"), bulk("2")]));
+    assert_eq!(to_replica.cmd(&["SORT", "list", "STORE", "dst"]).await, readonly());
+    assert_eq!(to_replica.cmd(&["EXISTS", "dst"]).await, int(0));
+
+    assert_eq!(to_master.cmd(&["SORT", "list", "DESC", "STORE", "dst"]).await, int(3));
+    wait_for(&mut to_replica, &["LRANGE", "dst", "0", "-1"], &RespData::Array(vec![bulk("3"), bulk("2"), bulk("1")])).await;
+}
+
+#[tokio::test]
+async fn wait_counts_the_replicas_that_acknowledged() {
+    let master = spawn_server().await;
+    let replica = spawn_server().await;
+    let mut to_master = master.client().await;
+    let mut to_replica = replica.client().await;
+
+    assert_eq!(to_master.cmd(&["WAIT", "0", "-1"]).await, error("ERR timeout is negative"));
+    assert_eq!(to_master.cmd(&["WAIT", "0", "soon"]).await, error("ERR timeout is not an integer or out of range"));
+    assert_eq!(to_master.cmd(&["WAIT", "one", "0"]).await, error("ERR value is not an integer or out of range"));
+    assert_eq!(to_master.cmd(&["WAIT", "0"]).await, error("ERR wrong number of arguments for 'wait' command"));
+    // Like any other command, WAIT is counted and kept out of subscriber mode
+    let stats = to_master.info_field("commandstats", "cmdstat_wait").await;
+    assert!(stats.starts_with("calls=3,") && stats.ends_with(",rejected_calls=1,failed_calls=3"), "{}", stats);
+    let mut subscriber = master.client().await;
+    subscriber.cmd(&["SUBSCRIBE", "news"]).await;
+    assert_eq!(
+        subscriber.cmd(&["WAIT", "0", "0"]).await,
+        error("ERR Can't execute 'wait': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")
+    );
+
+    let port = master.addr.port().to_string();
+    assert_eq!(to_replica.cmd(&["REPLICAOF", "127.0.0.1", &port]).await, ok());
+    for _ in 0..100 {
+        if to_master.info_field("replication", "connected_slaves").await == "1" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(to_master.info_field("replication", "connected_slaves").await, "1");
+    assert_eq!(to_master.cmd(&["SET", "k", "v"]).await, ok());
+    assert_eq!(to_master.cmd(&["WAIT", "1", "0"]).await, int(1));
+    assert_eq!(to_replica.cmd(&["GET", "k"]).await, bulk("v"));
+
+    // Replies queued ahead of a WAIT that blocks are sent straight away
+    to_master.send_raw(b"*1\r\n$4\r\nPING\r\n*3\r\n$4\r\nWAIT\r\n$1\r\n2\r\n$3\r\n500\r\n").await;
+    let started = std::time::Instant::now();
+    assert_eq!(to_master.read_reply().await, RespData::SimpleString("PONG".to_string()));
+    assert!(started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+    assert_eq!(to_master.read_reply().await, int(1));
+    assert!(started.elapsed() >= Duration::from_millis(400), "{:?}", started.elapsed());
+
+    assert_eq!(
+        to_replica.cmd(&["WAIT", "0", "0"]).await,
+        error(
+            "ERR WAIT cannot be used with replica instances. Please also note that since Redis 4.0 if a replica is \
+             configured to be writable (which is not the default) writes to replicas are just local and are not propagated."
+        )
+    );
+}
@@ -0,0 +1,119 @@
+use redis::resp::{parse_resp, serialize_resp, RequestParser, RespData};
+
+fn bulk(s: &str) -> RespData {
+    RespData::BulkString(s.to_string())
+}
+
+fn samples() -> Vec<RespData> {
+    vec![
+        RespData::SimpleString("OK".to_string()),
+        RespData::Error("ERR something went wrong".to_string()),
+        RespData::Integer(0),
+        RespData::Integer(i64::MIN),
+        RespData::Integer(i64::MAX),
+        bulk(""),
+        bulk("contains\r\nCRLF"),
+        RespData::BulkBytes(vec![0xff, 0x00, b'\r', b'\n', 0xfe]),
+        RespData::Null,
+        RespData::NullArray,
+        RespData::Array(vec![]),
+        RespData::Array(vec![
+            RespData::Integer(1),
+            RespData::Array(vec![bulk("nested"), RespData::Null]),
+            RespData::NullArray,
+        ]),
+        RespData::Map(vec![(bulk("key"), RespData::Array(vec![RespData::Integer(-1)]))]),
+        RespData::Push(vec![bulk("invalidate"), RespData::Array(vec![bulk("k")])]),
+    ]
+}
+
+#[test]
+fn values_survive_a_round_trip() {
+    for value in samples() {
+        let encoded = serialize_resp(&value);
+        assert_eq!(parse_resp(&encoded).unwrap(), Some((encoded.len(), value)));
+    }
+}
+
+#[test]
+fn replies_are_incomplete_until_their_last_byte() {
+    let mut stream = Vec::new();
+    for value in samples() {
+        stream.extend(serialize_resp(&value));
+    }
+    // Cut anywhere, a value is either complete or waits for more input
+    let mut pos = 0;
+    for value in samples() {
+        let len = serialize_resp(&value).len();
+        for cut in pos..pos + len {
+            assert_eq!(parse_resp(&stream[pos..cut]).unwrap(), None, "{:?} cut at {}", value, cut - pos);
+        }
+        assert_eq!(parse_resp(&stream[pos..]).unwrap(), Some((len, value)));
+        pos += len;
+    }
+}
+
+#[test]
+fn malformed_replies_are_errors() {
+    for input in [&b"?\r\n"[..], b":twelve\r\n", b"$3\r\nabcd\r\n", b"*x\r\n", b"_x\r\n", b"*1\r\n!\r\n"] {
+        let error = parse_resp(input).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{:?}", String::from_utf8_lossy(input));
+    }
+}
+
+#[test]
+fn requests_are_parsed_as_their_bytes_arrive() {
+    let request = RespData::Array(vec![
+        bulk("SET"),
+        bulk("key"),
+        RespData::BulkBytes(vec![b'\r', b'\n', 0xff]),
+    ]);
+    let mut input = serialize_resp(&request);
+    input.extend(serialize_resp(&RespData::Array(vec![bulk("PING")])));
+
+    // Fed one byte at a time, the buffer only grows until a request is out
+    let mut parser = RequestParser::default();
+    let mut buffer = Vec::new();
+    let mut requests = Vec::new();
+    for &byte in &input {
+        buffer.push(byte);
+        if let Some((consumed, request)) = parser.parse(&buffer, 512 * 1024 * 1024).unwrap() {
+            buffer.drain(..consumed);
+            requests.push(request);
+        }
+    }
+    assert!(buffer.is_empty());
+    assert_eq!(requests, vec![request, RespData::Array(vec![bulk("PING")])]);
+}
+
+#[test]
+fn inline_requests_are_split_into_arguments() {
+    let mut parser = RequestParser::default();
+    let cases: [(&[u8], RespData); 4] = [
+        (b"PING\r\n", RespData::Array(vec![bulk("PING")])),
+        (b"SET  key \"two words\"\n", RespData::Array(vec![bulk("SET"), bulk("key"), bulk("two words")])),
+        (b"\r\n", RespData::Array(vec![])),
+        (
+            b"GET \"open\r\n",
+            RespData::Error("ERR Protocol error: unbalanced quotes in request".to_string()),
+        ),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(parser.parse(input, 1024).unwrap(), Some((input.len(), expected)));
+    }
+    assert_eq!(parser.parse(b"PIN", 1024).unwrap(), None);
+}
+
+#[test]
+fn requests_over_the_limits_are_protocol_errors() {
+    let cases: [(&[u8], &str); 4] = [
+        (b"*1\r\n$11\r\n", "invalid bulk length"),
+        (b"*-2\r\n", "invalid multibulk length"),
+        (b"*1\r\n+PING\r\n", "expected '$', got '+'"),
+        (b"*1\r\n$4\r\nPINGPONG\r\n", "expected '\\r\\n'"),
+    ];
+    for (input, detail) in cases {
+        let error = RequestParser::default().parse(input, 10).unwrap_err();
+        assert_eq!(error.to_string(), detail);
+    }
+}
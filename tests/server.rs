@@ -0,0 +1,765 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{bulk, command, error, int, ok, spawn_server, spawn_server_with, TempDir, TestClient};
+use redis::resp::{serialize_resp, RespData};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn set_get_del_round_trip() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["SET", "greeting", "hello"]).await, ok());
+    assert_eq!(client.cmd(&["GET", "greeting"]).await, bulk("hello"));
+    assert_eq!(client.cmd(&["DEL", "greeting"]).await, int(1));
+    assert_eq!(client.cmd(&["GET", "greeting"]).await, RespData::Null);
+    assert_eq!(client.cmd(&["DEL", "greeting"]).await, int(0));
+}
+
+#[tokio::test]
+async fn keys_expire_after_px() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["SET", "session", "abc", "PX", "100"]).await, ok());
+    assert_eq!(client.cmd(&["GET", "session"]).await, bulk("abc"));
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(client.cmd(&["GET", "session"]).await, RespData::Null);
+}
+
+#[tokio::test]
+async fn expired_keys_are_removed_without_being_read() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["CONFIG", "SET", "hz", "100"]).await, ok());
+
+    // Switched off, expired keys stay until something reads them
+    assert_eq!(client.cmd(&["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await, ok());
+    for i in 0..100 {
+        client.cmd(&["SET", &format!("key:{}", i), "v", "PX", "20"]).await;
+    }
+    client.cmd(&["SET", "kept", "v"]).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(client.cmd(&["DBSIZE"]).await, int(101));
+
+    assert_eq!(client.cmd(&["DEBUG", "SET-ACTIVE-EXPIRE", "1"]).await, ok());
+    for _ in 0..100 {
+        if client.cmd(&["DBSIZE"]).await == int(1) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(client.cmd(&["DBSIZE"]).await, int(1));
+    assert_eq!(client.info_field("stats", "expired_keys").await, "100");
+    assert_eq!(client.cmd(&["GET", "kept"]).await, bulk("v"));
+}
+
+// Sets keys `prefix`0..count, with `options` after the value, a batch of
+// pipelined commands at a time
+async fn set_many(client: &mut TestClient, prefix: &str, count: usize, options: &[&str]) {
+    for batch in (0..count).collect::<Vec<_>>().chunks(1000) {
+        let mut pipeline = Vec::new();
+        for i in batch {
+            let key = format!("{}{}", prefix, i);
+            let args: Vec<&str> = ["SET", key.as_str(), "v"].into_iter().chain(options.iter().copied()).collect();
+            pipeline.extend(serialize_resp(&command(&args)));
+        }
+        client.send_raw(&pipeline).await;
+        for _ in batch {
+            assert_eq!(client.read_reply().await, ok());
+        }
+    }
+}
+
+// Polls DBSIZE until it is `expected`, for up to `within`
+async fn wait_for_dbsize(client: &mut TestClient, expected: i64, within: Duration) {
+    let deadline = tokio::time::Instant::now() + within;
+    while client.cmd(&["DBSIZE"]).await != int(expected) {
+        assert!(tokio::time::Instant::now() < deadline, "DBSIZE never got to {}", expected);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn short_lived_keys_all_expire_on_an_idle_server() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    set_many(&mut client, "key:", 10_000, &["PX", "50"]).await;
+    wait_for_dbsize(&mut client, 0, Duration::from_secs(10)).await;
+    assert_eq!(client.info_field("stats", "expired_keys").await, "10000");
+}
+
+// Sampling only keys with a TTL, expired keys don't linger among many
+// persistent ones
+#[tokio::test]
+async fn expired_keys_among_persistent_ones_are_removed() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    set_many(&mut client, "persistent:", 10_000, &[]).await;
+    set_many(&mut client, "volatile:", 100, &["PX", "50"]).await;
+    wait_for_dbsize(&mut client, 10_000, Duration::from_secs(5)).await;
+    assert_eq!(client.info_field("stats", "expired_keys").await, "100");
+
+    // Keys that lost their TTL or went away meanwhile are left alone
+    set_many(&mut client, "volatile:", 100, &["PX", "100000"]).await;
+    set_many(&mut client, "volatile:", 50, &[]).await;
+    for i in 50..100 {
+        client.cmd(&["DEL", &format!("volatile:{}", i)]).await;
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(client.cmd(&["DBSIZE"]).await, int(10_050));
+}
+
+#[tokio::test]
+async fn expirations_are_published_as_keyspace_events() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let mut subscriber = server.client().await;
+    assert_eq!(client.cmd(&["CONFIG", "SET", "notify-keyspace-events", "Ex"]).await, ok());
+    subscriber.send(&["SUBSCRIBE", "__keyevent@0__:expired"]).await;
+    subscriber.read_reply().await;
+
+    client.cmd(&["SET", "session", "v", "PX", "20"]).await;
+    assert_eq!(
+        subscriber.read_reply().await,
+        RespData::Array(vec![bulk("message"), bulk("__keyevent@0__:expired"), bulk("session")])
+    );
+}
+
+#[tokio::test]
+async fn pipelined_commands_are_answered_in_order() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    let mut batch = Vec::new();
+    for _ in 0..100 {
+        batch.extend(serialize_resp(&command(&["INCR", "counter"])));
+    }
+    client.send_raw(&batch).await;
+    for expected in 1..=100 {
+        assert_eq!(client.read_reply().await, int(expected));
+    }
+}
+
+#[tokio::test]
+async fn pipelined_batches_are_answered_faster_than_round_trips() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let incr = serialize_resp(&command(&["INCR", "counter"]));
+    let reply_len = |n: i64| format!(":{}\r\n", n).len();
+
+    let started = std::time::Instant::now();
+    for expected in 1..=2000 {
+        assert_eq!(client.cmd(&["INCR", "counter"]).await, int(expected));
+    }
+    let one_by_one = started.elapsed();
+
+    let started = std::time::Instant::now();
+    client.send_raw(&incr.repeat(2000)).await;
+    let len = (2001..=4000).map(reply_len).sum();
+    client.read_exact(len).await;
+    let pipelined = started.elapsed();
+    assert!(pipelined * 2 < one_by_one, "pipelined {:?}, one by one {:?}", pipelined, one_by_one);
+
+    // A batch ending part way through a command answers what it can and
+    // keeps the rest for the next read
+    let mut batch = incr.repeat(3);
+    batch.extend(&incr[..5]);
+    client.send_raw(&batch).await;
+    for expected in 4001..=4003 {
+        assert_eq!(client.read_reply().await, int(expected));
+    }
+    client.send_raw(&incr[5..]).await;
+    assert_eq!(client.read_reply().await, int(4004));
+}
+
+#[tokio::test]
+async fn replies_are_encoded_byte_for_byte() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["SET", "max", &(i64::MAX - 1).to_string()]).await;
+    client.cmd(&["SET", "min", &(i64::MIN + 1).to_string()]).await;
+    client.cmd(&["SET", "empty", ""]).await;
+    client.cmd(&["RPUSH", "list", "a", "bc"]).await;
+
+    let cases: [(&[&str], &[u8]); 9] = [
+        (&["INCR", "max"], b":9223372036854775807\r\n"),
+        (&["DECR", "min"], b":-9223372036854775808\r\n"),
+        (&["DECR", "counter"], b":-1\r\n"),
+        (&["INCR", "counter"], b":0\r\n"),
+        (&["GET", "empty"], b"$0\r\n\r\n"),
+        (&["GET", "missing"], b"$-1\r\n"),
+        (&["LRANGE", "list", "0", "-1"], b"*2\r\n$1\r\na\r\n$2\r\nbc\r\n"),
+        (&["LRANGE", "missing", "0", "-1"], b"*0\r\n"),
+        (&["SET", "k", "v"], b"+OK\r\n"),
+    ];
+    for (command, expected) in &cases {
+        assert_eq!(client.cmd_raw(command).await, *expected, "{:?}", command);
+    }
+
+    // A pipelined batch is encoded into one reused buffer, so a long reply
+    // followed by short ones mustn't leave any of its bytes behind
+    let long = "x".repeat(10_000);
+    client.cmd(&["SET", "long", &long]).await;
+    let mut batch = Vec::new();
+    let mut expected = Vec::new();
+    for i in 1..=3 {
+        batch.extend(serialize_resp(&command(&["GET", "long"])));
+        expected.extend(format!("$10000\r\n{}\r\n", long).into_bytes());
+        batch.extend(serialize_resp(&command(&["DECR", "negative"])));
+        expected.extend(format!(":-{}\r\n", i).into_bytes());
+        batch.extend(serialize_resp(&command(&["GET", "missing"])));
+        expected.extend(b"$-1\r\n");
+    }
+    client.send_raw(&batch).await;
+    assert_eq!(client.read_exact(expected.len()).await, expected);
+    assert_eq!(client.cmd_raw(&["PING"]).await, b"+PONG\r\n");
+}
+
+#[tokio::test]
+async fn protocol_errors_are_reported_before_closing() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    client.send_raw(b"*1\r\n$x\r\n").await;
+    assert_eq!(client.read_to_close().await, b"-ERR Protocol error: invalid bulk length\r\n");
+
+    // A payload longer than its declared length
+    let mut client = server.client().await;
+    client.send_raw(b"*1\r\n$4\r\nPINGS\r\n").await;
+    assert_eq!(client.read_to_close().await, b"-ERR Protocol error: expected '\\r\\n'\r\n");
+}
+
+#[tokio::test]
+async fn each_protocol_error_is_named_before_closing() {
+    let server = spawn_server().await;
+    let cases: [(&[u8], &[u8]); 3] = [
+        (b"*abc\r\n", b"-ERR Protocol error: invalid multibulk length\r\n"),
+        (b"*1\r\n:1\r\n", b"-ERR Protocol error: expected '$', got ':'\r\n"),
+        (b"*1\r\n$-5\r\n", b"-ERR Protocol error: invalid bulk length\r\n"),
+    ];
+    for (request, reply) in cases {
+        let mut client = server.client().await;
+        client.send_raw(request).await;
+        assert_eq!(client.read_to_close().await, reply);
+    }
+
+    // Commands ahead of the bad one are still answered
+    let mut client = server.client().await;
+    client.send_raw(b"*1\r\n$4\r\nPING\r\n*x\r\n").await;
+    assert_eq!(client.read_to_close().await, b"+PONG\r\n-ERR Protocol error: invalid multibulk length\r\n");
+
+    // Unbalanced quotes only fail the inline command they're in
+    let mut client = server.client().await;
+    client.send_raw(b"SET k \"v\r\n").await;
+    assert_eq!(client.read_reply().await, error("ERR Protocol error: unbalanced quotes in request"));
+    assert_eq!(client.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn oversized_requests_are_refused() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(
+        client.cmd(&["CONFIG", "SET", "proto-max-bulk-len", "1000"]).await,
+        RespData::Error(
+            "ERR CONFIG SET failed (possibly related to argument 'proto-max-bulk-len') - argument must be a memory value of at least 1mb"
+                .to_string()
+        )
+    );
+    assert_eq!(client.cmd(&["CONFIG", "SET", "proto-max-bulk-len", "1mb"]).await, ok());
+    assert_eq!(client.cmd(&["CONFIG", "SET", "client-query-buffer-limit", "1mb"]).await, ok());
+
+    // A bulk over the limit is refused from its header
+    let mut client = server.client().await;
+    client.send_raw(format!("*2\r\n$3\r\nGET\r\n${}\r\n", 1024 * 1024 + 1).as_bytes()).await;
+    assert_eq!(client.read_to_close().await, b"-ERR Protocol error: invalid bulk length\r\n");
+
+    // Arguments within the limit that add up to more than the query buffer
+    // may hold close the connection
+    let mut client = server.client().await;
+    let arg = "x".repeat(530 * 1000);
+    let mut request = serialize_resp(&command(&["SET", &arg, &arg]));
+    request.pop();
+    client.send_raw(&request).await;
+    assert_eq!(client.read_to_close().await, b"");
+
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["DBSIZE"]).await, int(0));
+}
+
+#[tokio::test]
+async fn requests_arriving_in_pieces_are_put_back_together() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    // The headers and CRLFs around the value arrive a byte at a time, so
+    // the request is split at every offset inside them, and the value in
+    // 997 byte pieces
+    let value = "v".repeat(100_000);
+    let mut request = serialize_resp(&command(&["SET", "big", &value]));
+    request.extend_from_slice(&serialize_resp(&command(&["GET", "big"])));
+    let value_start = request.len() - value.len() - serialize_resp(&command(&["GET", "big"])).len() - 2;
+    let (head, rest) = request.split_at(value_start);
+    let (value_part, tail) = rest.split_at(value.len());
+    assert_eq!(value_part, value.as_bytes());
+    let pieces = head.chunks(1).chain(value_part.chunks(997)).chain(tail.chunks(1));
+    for piece in pieces {
+        client.send_raw(piece).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    assert_eq!(client.read_reply().await, ok());
+    assert_eq!(client.read_reply().await, bulk(&value));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn concurrent_increments_are_not_lost() {
+    let server = spawn_server().await;
+    let mut tasks = Vec::new();
+    for _ in 0..2 {
+        let mut client = server.client().await;
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..500 {
+                client.cmd(&["INCR", "hits"]).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["GET", "hits"]).await, bulk("1000"));
+}
+
+#[tokio::test]
+async fn commands_against_the_wrong_type_fail() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["RPUSH", "list", "a"]).await, int(1));
+    let wrongtype = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+    assert_eq!(client.cmd(&["INCR", "list"]).await, wrongtype);
+    assert_eq!(client.cmd(&["GET", "list"]).await, wrongtype);
+}
+
+#[tokio::test]
+async fn every_typed_command_checks_the_type_before_touching_the_key() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    let wrongtype = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+    assert_eq!(client.cmd(&["SET", "string", "v"]).await, ok());
+    assert_eq!(client.cmd(&["RPUSH", "list", "a"]).await, int(1));
+    assert_eq!(client.cmd(&["HSET", "hash", "f", "v"]).await, int(1));
+    assert!(matches!(client.cmd(&["XADD", "stream", "1-1", "f", "v"]).await, RespData::BulkString(_)));
+
+    let commands: &[(&str, &[&str])] = &[
+        ("string", &["GET", "INCR", "DECR", "LCS"]),
+        ("list", &["LLEN", "LRANGE", "LPUSH", "RPUSH"]),
+        ("hash", &["HGET", "HSET", "HDEL", "HLEN", "HGETALL"]),
+        ("stream", &["XLEN", "XRANGE", "XADD"]),
+    ];
+    for (key, _) in commands {
+        for (kind, names) in commands {
+            if key == kind {
+                continue;
+            }
+            for name in *names {
+                let args: &[&str] = match *name {
+                    "GET" | "INCR" | "DECR" | "LLEN" | "HLEN" | "HGETALL" | "XLEN" => &[name, key],
+                    "LCS" => &[name, key, "string"],
+                    "LRANGE" => &[name, key, "0", "-1"],
+                    "XRANGE" => &[name, key, "-", "+"],
+                    "HSET" => &[name, key, "f", "w"],
+                    "XADD" => &[name, key, "*", "f", "w"],
+                    _ => &[name, key, "x"],
+                };
+                assert_eq!(client.cmd(args).await, wrongtype, "{:?}", args);
+            }
+        }
+    }
+
+    // Nothing was changed along the way
+    assert_eq!(client.cmd(&["GET", "string"]).await, bulk("v"));
+    assert_eq!(client.cmd(&["LRANGE", "list", "0", "-1"]).await, RespData::Array(vec![bulk("a")]));
+    assert_eq!(client.cmd(&["HGETALL", "hash"]).await, RespData::Array(vec![bulk("f"), bulk("v")]));
+    assert_eq!(client.cmd(&["XLEN", "stream"]).await, int(1));
+}
+
+#[tokio::test]
+async fn saved_data_is_loaded_after_restart() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["SET", "persisted", "yes"]).await, ok());
+    assert_eq!(client.cmd(&["RPUSH", "list", "a", "b"]).await, int(2));
+    assert_eq!(client.cmd(&["SAVE"]).await, ok());
+    assert!(server.dir.path().join("redis-data.json").exists());
+
+    let server = server.restart().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["GET", "persisted"]).await, bulk("yes"));
+    assert_eq!(client.cmd(&["DBSIZE"]).await, int(2));
+}
+
+#[tokio::test]
+async fn serves_clients_over_a_unix_socket() {
+    let socket_dir = TempDir::new();
+    let path = socket_dir.path().join("redis.sock");
+    let server = spawn_server_with(|config| {
+        config.set("unixsocket", path.to_str().unwrap()).unwrap();
+    })
+    .await;
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").await.unwrap();
+    let mut reply = vec![0; 12];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, b"+OK\r\n$1\r\nv\r\n");
+
+    server.shutdown().await;
+    assert!(!path.exists());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn save_does_not_stall_other_clients() {
+    // A single worker thread, so a blocking SAVE would stall every client
+    let server = spawn_server().await;
+    let mut writer = server.client().await;
+    let value = "x".repeat(100);
+    for batch in 0..20 {
+        let mut args = vec!["RPUSH".to_string(), format!("list:{}", batch)];
+        args.extend(std::iter::repeat_n(value.clone(), 10_000));
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        assert_eq!(writer.cmd(&args).await, int(10_000));
+    }
+
+    let saving = tokio::spawn(async move {
+        assert_eq!(writer.cmd(&["SAVE"]).await, ok());
+    });
+    let mut client = server.client().await;
+    let mut slowest = Duration::ZERO;
+    while !saving.is_finished() {
+        let started = std::time::Instant::now();
+        assert_eq!(client.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+        slowest = slowest.max(started.elapsed());
+    }
+    saving.await.unwrap();
+    assert!(slowest < Duration::from_millis(50), "PING took {:?} during SAVE", slowest);
+}
+
+#[tokio::test]
+async fn renamed_and_disabled_commands_answer_to_their_new_names_only() {
+    let server = spawn_server_with(|config| {
+        config.set("rename-command", "debug \"\"").unwrap();
+        config.set("rename-command", "CONFIG cfg").unwrap();
+        config.set("rename-command", "get FETCH").unwrap();
+        // A renamed command can be renamed again, freeing the name it had
+        config.set("rename-command", "fetch read").unwrap();
+    })
+    .await;
+    let mut client = server.client().await;
+    let unknown = |name: &str, args: &str| {
+        RespData::Error(format!("ERR unknown command '{}', with args beginning with: {}", name, args))
+    };
+
+    assert_eq!(client.cmd(&["DEBUG", "SET-ACTIVE-EXPIRE", "0"]).await, unknown("DEBUG", "'SET-ACTIVE-EXPIRE' '0' "));
+    assert_eq!(client.cmd(&["CONFIG", "GET", "hz"]).await, unknown("CONFIG", "'GET' 'hz' "));
+    assert_eq!(client.cmd(&["cfg", "GET", "hz"]).await, RespData::Array(vec![bulk("hz"), bulk("10")]));
+    assert_eq!(client.cmd(&["CfG", "SET", "hz", "20"]).await, ok());
+
+    assert_eq!(client.cmd(&["SET", "k", "v"]).await, ok());
+    assert_eq!(client.cmd(&["GET", "k"]).await, unknown("GET", "'k' "));
+    assert_eq!(client.cmd(&["FETCH", "k"]).await, unknown("FETCH", "'k' "));
+    assert_eq!(client.cmd(&["READ", "k"]).await, bulk("v"));
+    assert_eq!(client.cmd(&["read", "k"]).await, bulk("v"));
+
+    // Queued commands are looked up the same way
+    assert_eq!(client.cmd(&["MULTI"]).await, ok());
+    assert_eq!(client.cmd(&["read", "k"]).await, RespData::SimpleString("QUEUED".to_string()));
+    assert_eq!(client.cmd(&["EXEC"]).await, RespData::Array(vec![bulk("v")]));
+    assert_eq!(client.cmd(&["MULTI"]).await, ok());
+    assert_eq!(client.cmd(&["GET", "k"]).await, unknown("GET", "'k' "));
+    assert!(matches!(client.cmd(&["EXEC"]).await, RespData::Error(e) if e.starts_with("EXECABORT")));
+
+    assert_eq!(
+        client.cmd(&["cfg", "SET", "rename-command", "set put"]).await,
+        error("ERR CONFIG SET failed (possibly related to argument 'rename-command') - can't set immutable config")
+    );
+}
+
+#[test]
+fn conflicting_renames_are_rejected() {
+    let mut config = redis::config::ServerConfig::default();
+    assert_eq!(config.set("rename-command", "nosuch foo"), Err("No such command in rename-command".to_string()));
+    assert_eq!(config.set("rename-command", "get"), Err("Bad directive or wrong number of arguments".to_string()));
+    assert_eq!(config.set("rename-command", "get SET"), Err("Target command name already exists".to_string()));
+    config.set("rename-command", "get fetch").unwrap();
+    assert_eq!(config.set("rename-command", "get other"), Err("No such command in rename-command".to_string()));
+    assert_eq!(config.set("rename-command", "set FETCH"), Err("Target command name already exists".to_string()));
+    // Once renamed away, a command's own name can be taken
+    config.set("rename-command", "set get").unwrap();
+    config.set("rename-command", "del \"\"").unwrap();
+    assert_eq!(config.set("rename-command", "\"\" foo"), Err("No such command in rename-command".to_string()));
+}
+
+#[tokio::test]
+async fn the_server_refuses_to_start_with_conflicting_renames() {
+    let args = ["--port", "0", "--rename-command", "get", "same", "--rename-command", "set", "SAME"];
+    let (code, _, err) = common::run_binary(env!("CARGO_BIN_EXE_redis"), &args, b"").await;
+    assert_eq!(code, 1);
+    assert_eq!(err.trim(), "Error in argument '--rename-command': Target command name already exists");
+
+    let args = ["--port", "0", "--rename-command", "get", "", "--rename-command", "get", "x"];
+    let (code, _, err) = common::run_binary(env!("CARGO_BIN_EXE_redis"), &args, b"").await;
+    assert_eq!(code, 1);
+    assert_eq!(err.trim(), "Error in argument '--rename-command': No such command in rename-command");
+}
+
+#[tokio::test]
+async fn command_lists_commands_under_the_names_they_are_called_by() {
+    let server = spawn_server_with(|config| {
+        config.set("rename-command", "debug \"\"").unwrap();
+        config.set("rename-command", "get fetch").unwrap();
+    })
+    .await;
+    let mut client = server.client().await;
+
+    let names = match client.cmd(&["COMMAND", "LIST"]).await {
+        RespData::Array(names) => names,
+        other => panic!("COMMAND LIST: {:?}", other),
+    };
+    assert!(names.contains(&bulk("fetch")) && names.contains(&bulk("set")));
+    assert!(!names.contains(&bulk("get")) && !names.contains(&bulk("debug")));
+    assert_eq!(client.cmd(&["COMMAND", "COUNT"]).await, int(names.len() as i64));
+    match client.cmd(&["COMMAND"]).await {
+        RespData::Array(entries) => assert_eq!(entries.len(), names.len()),
+        other => panic!("COMMAND: {:?}", other),
+    }
+
+    let flags = |flags: &[&str]| RespData::Array(flags.iter().map(|flag| RespData::SimpleString(flag.to_string())).collect());
+    let entry = |name: &str, arity: i64, flag_names: &[&str], keys: [i64; 3]| {
+        RespData::Array(vec![
+            bulk(name),
+            int(arity),
+            flags(flag_names),
+            int(keys[0]),
+            int(keys[1]),
+            int(keys[2]),
+            RespData::Array(vec![]),
+        ])
+    };
+    assert_eq!(
+        client.cmd(&["COMMAND", "INFO", "FETCH", "get", "debug", "del"]).await,
+        RespData::Array(vec![
+            entry("fetch", 2, &["readonly"], [1, 1, 1]),
+            RespData::NullArray,
+            RespData::NullArray,
+            entry("del", -2, &["write"], [1, -1, 1]),
+        ])
+    );
+    assert_eq!(
+        client.cmd(&["COMMAND", "INFO", "wait"]).await,
+        RespData::Array(vec![entry("wait", 3, &["no_multi"], [0, 0, 0])])
+    );
+    assert_eq!(
+        client.cmd(&["COMMAND", "BOGUS"]).await,
+        error("ERR unknown subcommand or wrong number of arguments for 'BOGUS'. Try COMMAND HELP.")
+    );
+}
+
+#[tokio::test]
+async fn config_files_are_applied_before_the_command_line() {
+    let mut config = redis::config::ServerConfig::default();
+    let contents = "# Comments and blank lines are skipped\n\n\
+        hz 20\n\
+        maxmemory 1mb\n\
+        rename-command debug \"\"\n\
+        rename-command GET fetch\n\
+        bind 127.0.0.1 ::1\n";
+    config.load_file(contents).unwrap();
+    assert_eq!(config.get("hz"), Some("20".to_string()));
+    assert_eq!(config.get("maxmemory"), Some("1048576".to_string()));
+    assert_eq!(config.get("bind"), Some("127.0.0.1 ::1".to_string()));
+
+    let server = spawn_server_with(|defaults| defaults.load_file(contents).unwrap()).await;
+    let mut client = server.client().await;
+    client.cmd(&["SET", "k", "v"]).await;
+    assert_eq!(client.cmd(&["FETCH", "k"]).await, bulk("v"));
+    assert!(matches!(client.cmd(&["GET", "k"]).await, RespData::Error(e) if e.starts_with("ERR unknown command")));
+
+    assert_eq!(
+        config.load_file("hz 10\nnosuch directive\n"),
+        Err("Reading the configuration file, at line 2\n>>> 'nosuch directive'\nBad directive or wrong number of arguments".to_string())
+    );
+    assert_eq!(
+        config.load_file("maxmemory lots"),
+        Err("Reading the configuration file, at line 1\n>>> 'maxmemory lots'\nargument must be a memory value".to_string())
+    );
+
+    // The binary takes the file as its first argument
+    let dir = TempDir::new();
+    let path = dir.path().join("redis.conf");
+    std::fs::write(&path, "port 0\nrename-command get same\nrename-command set SAME\n").unwrap();
+    let (code, _, err) = common::run_binary(env!("CARGO_BIN_EXE_redis"), &[path.to_str().unwrap()], b"").await;
+    assert_eq!(code, 1);
+    assert!(err.contains("at line 3\n>>> 'rename-command set SAME'\nTarget command name already exists"), "{}", err);
+    let missing = dir.path().join("missing.conf");
+    let (code, _, err) = common::run_binary(env!("CARGO_BIN_EXE_redis"), &[missing.to_str().unwrap()], b"").await;
+    assert_eq!(code, 1);
+    assert!(err.starts_with("Fatal error, can't open config file"), "{}", err);
+}
+
+// The nodelay flag and keepalive time and interval of the server's end of
+// `client`'s connection. The server runs in this process, so its sockets
+// are among our own descriptors.
+fn accepted_socket_options(server_addr: std::net::SocketAddr, client: &common::TestClient) -> (bool, Option<(Duration, Duration)>) {
+    let peer = client.local_addr();
+    for entry in std::fs::read_dir("/proc/self/fd").unwrap() {
+        let Ok(fd) = entry.unwrap().file_name().to_string_lossy().parse() else { continue };
+        // Other tests may close the descriptor meanwhile, which at worst makes
+        // the lookups below fail, and only a socket with both ends matching
+        // is inspected
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) };
+        let socket = socket2::SockRef::from(&fd);
+        let local = socket.local_addr().ok().and_then(|addr| addr.as_socket());
+        let remote = socket.peer_addr().ok().and_then(|addr| addr.as_socket());
+        if local == Some(server_addr) && remote == Some(peer) {
+            let keepalive = socket.keepalive().unwrap().then(|| {
+                (socket.keepalive_time().unwrap(), socket.keepalive_interval().unwrap())
+            });
+            return (socket.nodelay().unwrap(), keepalive);
+        }
+    }
+    panic!("no accepted socket for {}", peer);
+}
+
+#[tokio::test]
+async fn accepted_sockets_get_the_tcp_options() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    for (name, default) in [("tcp-keepalive", "300"), ("tcp-backlog", "511"), ("tcp-nodelay", "yes")] {
+        assert_eq!(client.cmd(&["CONFIG", "GET", name]).await, RespData::Array(vec![bulk(name), bulk(default)]));
+    }
+    client.cmd(&["PING"]).await;
+    assert_eq!(
+        accepted_socket_options(server.addr, &client),
+        (true, Some((Duration::from_secs(300), Duration::from_secs(100))))
+    );
+
+    // Changes apply to the connections accepted afterwards
+    assert_eq!(client.cmd(&["CONFIG", "SET", "tcp-keepalive", "60"]).await, ok());
+    assert_eq!(client.cmd(&["CONFIG", "SET", "tcp-nodelay", "no"]).await, ok());
+    let mut other = server.client().await;
+    other.cmd(&["PING"]).await;
+    assert_eq!(
+        accepted_socket_options(server.addr, &other),
+        (false, Some((Duration::from_secs(60), Duration::from_secs(20))))
+    );
+    assert_eq!(client.cmd(&["CONFIG", "SET", "tcp-keepalive", "0"]).await, ok());
+    let mut other = server.client().await;
+    other.cmd(&["PING"]).await;
+    assert_eq!(accepted_socket_options(server.addr, &other), (false, None));
+
+    // The backlog is fixed once the listener exists
+    assert_eq!(
+        client.cmd(&["CONFIG", "SET", "tcp-backlog", "1024"]).await,
+        error("ERR CONFIG SET failed (possibly related to argument 'tcp-backlog') - can't set immutable config")
+    );
+    let mut config = redis::config::ServerConfig::default();
+    assert_eq!(
+        config.load_file("tcp-keepalive -1"),
+        Err("Reading the configuration file, at line 1\n>>> 'tcp-keepalive -1'\nargument couldn't be parsed into an integer".to_string())
+    );
+    assert_eq!(
+        config.load_file("tcp-nodelay maybe"),
+        Err("Reading the configuration file, at line 1\n>>> 'tcp-nodelay maybe'\nargument must be 'yes' or 'no'".to_string())
+    );
+}
+
+// An address of this host that isn't loopback, if it has a route out
+fn external_ip() -> Option<std::net::IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("203.0.113.1:9").ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+}
+
+#[tokio::test]
+async fn protected_mode_only_lets_loopback_clients_in() {
+    let Some(external) = external_ip() else {
+        eprintln!("skipping: no non-loopback address to connect from");
+        return;
+    };
+    let dir = TempDir::new();
+    let mut config = redis::config::ServerConfig::default();
+    config.set("dir", dir.path().to_str().unwrap()).unwrap();
+    let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = redis::server::Server::with_listener(config, listener).await.unwrap();
+    tokio::spawn(server.run_until(std::future::pending()));
+
+    let loopback = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+    let mut local = common::TestClient::connect(loopback).await;
+    assert_eq!(local.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+
+    let outside = std::net::SocketAddr::new(external, port);
+    let mut remote = common::TestClient::connect(outside).await;
+    let denied = String::from_utf8(remote.read_to_close().await).unwrap();
+    assert!(denied.starts_with("-DENIED Redis is running in protected mode"), "{}", denied);
+    assert!(denied.ends_with("to start accepting connections from the outside.\r\n"), "{}", denied);
+    assert_eq!(local.info_field("clients", "connected_clients").await, "1");
+
+    assert_eq!(local.cmd(&["CONFIG", "SET", "protected-mode", "no"]).await, ok());
+    let mut remote = common::TestClient::connect(outside).await;
+    assert_eq!(remote.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+
+    assert_eq!(local.cmd(&["CONFIG", "SET", "protected-mode", "yes"]).await, ok());
+    let mut remote = common::TestClient::connect(outside).await;
+    assert!(remote.read_to_close().await.starts_with(b"-DENIED"));
+
+    // With the addresses chosen deliberately anyone who can reach them is let in
+    let mut config = redis::config::ServerConfig::default();
+    config.set("dir", dir.path().to_str().unwrap()).unwrap();
+    config.set("bind", "0.0.0.0").unwrap();
+    let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+    let outside = std::net::SocketAddr::new(external, listener.local_addr().unwrap().port());
+    let server = redis::server::Server::with_listener(config, listener).await.unwrap();
+    tokio::spawn(server.run_until(std::future::pending()));
+    let mut remote = common::TestClient::connect(outside).await;
+    assert_eq!(remote.cmd(&["PING"]).await, RespData::SimpleString("PONG".to_string()));
+}
+
+#[tokio::test]
+async fn every_bind_address_gets_a_listener() {
+    let dir = TempDir::new();
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut config = redis::config::ServerConfig::default();
+    config.set("dir", dir.path().to_str().unwrap()).unwrap();
+    config.set("port", &port.to_string()).unwrap();
+    config.set("bind", "127.0.0.1 127.0.0.2").unwrap();
+    assert_eq!(config.get("bind"), Some("127.0.0.1 127.0.0.2".to_string()));
+    let server = redis::server::Server::bind(config.clone()).await.unwrap();
+    tokio::spawn(server.run_until(std::future::pending()));
+
+    let mut first = common::TestClient::connect(([127, 0, 0, 1], port).into()).await;
+    let mut second = common::TestClient::connect(([127, 0, 0, 2], port).into()).await;
+    assert_eq!(first.cmd(&["SET", "k", "v"]).await, ok());
+    assert_eq!(second.cmd(&["GET", "k"]).await, bulk("v"));
+
+    // Every address has to be bound, and the one that can't be is named
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    config.set("port", &port.to_string()).unwrap();
+    config.set("bind", "127.0.0.1 203.0.113.1").unwrap();
+    let error = redis::server::Server::bind(config.clone()).await.err().expect("bind fails");
+    assert!(
+        error.to_string().starts_with(&format!("Could not create server TCP listening socket 203.0.113.1:{}: ", port)),
+        "{}",
+        error
+    );
+    assert_eq!(
+        config.set("bind", "127.0.0.1 nowhere"),
+        Err("invalid bind address 'nowhere'".to_string())
+    );
+}
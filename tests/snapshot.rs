@@ -0,0 +1,246 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::TempDir;
+use redis::clock::ManualClock;
+use redis::config::ServerConfig;
+use redis::server::Server;
+use redis::store::{LoadSummary, RedisStore, RedisValueType, SaveSummary, SetOptions};
+
+fn config_in(dir: &TempDir) -> ServerConfig {
+    let mut config = ServerConfig::default();
+    config.set("dir", dir.path().to_str().unwrap()).unwrap();
+    config
+}
+
+// Far enough from zero that clocks can be skewed backwards
+const NOW: u64 = 1_700_000_000_000;
+
+fn string(s: &str) -> RedisValueType {
+    RedisValueType::String(s.to_string())
+}
+
+fn write_snapshot(dir: &TempDir, contents: &str) {
+    std::fs::write(dir.path().join("redis-data.json"), contents).unwrap();
+}
+
+#[test]
+fn snapshots_round_trip_with_a_version() {
+    let dir = TempDir::new();
+    let store = RedisStore::with_config(config_in(&dir));
+    store.set_with_options("plain".to_string(), string("1"), SetOptions::None);
+    store.set_with_options("volatile".to_string(), string("2"), SetOptions::EX(100));
+    store.save().unwrap();
+
+    let contents = std::fs::read_to_string(dir.path().join("redis-data.json")).unwrap();
+    assert!(contents.starts_with(r#"{"version":1,"#));
+
+    let loaded = RedisStore::with_config(config_in(&dir));
+    assert_eq!(loaded.load().unwrap(), Some(LoadSummary { version: 1, loaded: 2, expired: 0 }));
+    assert!(loaded.exists("plain"));
+    assert!(loaded.ttl("volatile").unwrap().is_some());
+}
+
+#[test]
+fn unversioned_snapshots_are_still_read() {
+    let dir = TempDir::new();
+    write_snapshot(&dir, r#"[["greeting",{"data":{"String":"hello"},"expiry":null}]]"#);
+
+    let store = RedisStore::with_config(config_in(&dir));
+    assert_eq!(store.load().unwrap(), Some(LoadSummary { version: 0, loaded: 1, expired: 0 }));
+    assert!(matches!(store.get("greeting").unwrap().data, RedisValueType::String(s) if s == "hello"));
+}
+
+#[test]
+fn expired_keys_are_skipped_when_loading() {
+    let dir = TempDir::new();
+    write_snapshot(
+        &dir,
+        r#"{"version":1,"entries":[
+            ["dead",{"data":{"String":"x"},"expiry":1}],
+            ["alive",{"data":{"String":"y"},"expiry":null}]
+        ]}"#,
+    );
+
+    let store = RedisStore::with_config(config_in(&dir));
+    assert_eq!(store.load().unwrap(), Some(LoadSummary { version: 1, loaded: 1, expired: 1 }));
+    assert!(!store.exists("dead"));
+    assert!(store.exists("alive"));
+}
+
+#[test]
+fn damaged_or_newer_snapshots_are_rejected() {
+    let dir = TempDir::new();
+    let store = RedisStore::with_config(config_in(&dir));
+    assert_eq!(store.load().unwrap(), None);
+
+    write_snapshot(&dir, r#"{"version":1,"entries":[["truncated""#);
+    let error = store.load().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("corrupt snapshot"), "{}", error);
+
+    write_snapshot(&dir, r#"{"version":99,"entries":{"a":"new layout"}}"#);
+    let error = store.load().unwrap_err();
+    assert!(error.to_string().contains("unknown snapshot format version 99"), "{}", error);
+}
+
+#[tokio::test]
+async fn startup_fails_on_a_corrupt_snapshot_unless_told_to_ignore_it() {
+    let dir = TempDir::new();
+    write_snapshot(&dir, "not json");
+    let mut config = config_in(&dir);
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(Server::with_listener(config.clone(), listener).await.is_err());
+
+    config.set("ignore-corrupt-dump", "yes").unwrap();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = Server::with_listener(config, listener).await.unwrap();
+    assert!(!server.store().exists("anything"));
+}
+
+#[test]
+fn expired_keys_are_left_out_of_snapshots() {
+    let dir = TempDir::new();
+    let clock = Arc::new(ManualClock::new(NOW));
+    let store = RedisStore::with_clock(config_in(&dir), clock.clone());
+    store.set_with_options("short".to_string(), string("x"), SetOptions::EX(10));
+    store.set_with_options("long".to_string(), string("y"), SetOptions::EX(100));
+    // Expired, but never touched since, so still in memory
+    clock.advance(Duration::from_secs(10));
+    store.save().unwrap();
+
+    let contents = std::fs::read_to_string(dir.path().join("redis-data.json")).unwrap();
+    assert!(!contents.contains("short"));
+    assert!(contents.contains(&format!(r#""expiry":{}"#, NOW + 100_000)));
+}
+
+#[test]
+fn ttls_survive_a_restart_as_absolute_times() {
+    // Clock offset of the loading store relative to the saving one, and the
+    // TTL left on a key saved with 100 seconds to live
+    let cases: [(i64, Option<u64>); 5] = [
+        (0, Some(100)),
+        (60_000, Some(40)),
+        (100_000, None),
+        (3_600_000, None),
+        (-3_600_000, Some(3700)),
+    ];
+    for (skew, remaining) in cases {
+        let dir = TempDir::new();
+        let store = RedisStore::with_clock(config_in(&dir), Arc::new(ManualClock::new(NOW)));
+        store.set_with_options("volatile".to_string(), string("x"), SetOptions::EX(100));
+        store.save().unwrap();
+
+        let clock = Arc::new(ManualClock::new(NOW.checked_add_signed(skew).unwrap()));
+        let loaded = RedisStore::with_clock(config_in(&dir), clock);
+        let summary = loaded.load().unwrap().unwrap();
+        assert_eq!(summary.expired, usize::from(remaining.is_none()), "skew {}", skew);
+        let ttl = loaded.ttl("volatile").map(|ttl| ttl.unwrap().as_secs());
+        assert_eq!(ttl, remaining, "skew {}", skew);
+    }
+}
+
+// Enough repetitive data to span several compressed blocks
+fn fill_dataset(store: &RedisStore) -> Vec<String> {
+    let mut keys = Vec::new();
+    for i in 0..5000 {
+        let key = format!("user:{}:profile", i);
+        let value = format!(r#"{{"name":"user {}","plan":"{}","visits":{}}}"#, i, ["free", "pro"][i % 2], i * 7);
+        store.set_with_options(key.clone(), string(&value), if i % 3 == 0 { SetOptions::EX(1000) } else { SetOptions::None });
+        keys.push(key);
+    }
+    for i in 0..200 {
+        let key = format!("events:{}", i);
+        let events = (0..50).map(|n| format!("event-{}-{}", i, n)).collect();
+        store.set_with_options(key.clone(), RedisValueType::List(events), SetOptions::None);
+        keys.push(key);
+    }
+    keys
+}
+
+#[test]
+fn compressed_snapshots_round_trip_and_are_detected_when_loading() {
+    let dir = TempDir::new();
+    let mut config = config_in(&dir);
+    config.set("rdbcompression", "yes").unwrap();
+    let store = RedisStore::with_config(config.clone());
+    let keys = fill_dataset(&store);
+
+    let summary = store.save().unwrap();
+    let contents = std::fs::read(dir.path().join("redis-data.json")).unwrap();
+    assert_eq!(&contents[..2], &[0x1f, 0x8b]);
+    assert_eq!(summary.bytes, contents.len() as u64);
+    assert!(summary.compressed);
+    assert!(summary.uncompressed > 4 * summary.bytes, "{:?}", summary);
+
+    // Loading doesn't depend on rdbcompression
+    let loaded = RedisStore::with_config(config_in(&dir));
+    assert_eq!(loaded.load().unwrap(), Some(LoadSummary { version: 1, loaded: keys.len(), expired: 0 }));
+    for key in &keys {
+        assert_eq!(loaded.dump(key), store.dump(key), "{}", key);
+    }
+    assert!(loaded.ttl("user:0:profile").unwrap().is_some());
+
+    // Saved plain again, it still loads where compression is on
+    let plain = loaded.save().unwrap();
+    assert_eq!(plain, SaveSummary { bytes: summary.uncompressed, uncompressed: summary.uncompressed, compressed: false });
+    let reloaded = RedisStore::with_config(config);
+    assert_eq!(reloaded.load().unwrap().unwrap().loaded, keys.len());
+}
+
+#[test]
+fn damaged_compressed_snapshots_are_rejected() {
+    let dir = TempDir::new();
+    let mut config = config_in(&dir);
+    config.set("rdbcompression", "yes").unwrap();
+    let store = RedisStore::with_config(config);
+    fill_dataset(&store);
+    store.save().unwrap();
+    let path = dir.path().join("redis-data.json");
+    let contents = std::fs::read(&path).unwrap();
+
+    let truncated = &contents[..contents.len() / 2];
+    let mut flipped = contents.clone();
+    let middle = flipped.len() / 2;
+    flipped[middle] ^= 0x55;
+    for damaged in [truncated, &flipped[..], &contents[..5]] {
+        std::fs::write(&path, damaged).unwrap();
+        let error = RedisStore::with_config(config_in(&dir)).load().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("corrupt snapshot"), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn the_binary_takes_ignore_corrupt_dump_without_a_value() {
+    let dir = TempDir::new();
+    write_snapshot(&dir, "not json");
+    // Loading comes before listening, so a port already taken ends the
+    // run once the snapshot has been dealt with
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port().to_string();
+    let dir_arg = dir.path().to_str().unwrap();
+    let redis = env!("CARGO_BIN_EXE_redis");
+
+    let (code, _, err) = common::run_binary(redis, &["--bind", "127.0.0.1", "--port", &port, "--dir", dir_arg], b"").await;
+    assert_eq!(code, 1);
+    assert!(err.contains("Error loading data from"), "{}", err);
+
+    for args in [
+        ["--ignore-corrupt-dump", "--bind", "127.0.0.1", "--port", &port, "--dir", dir_arg],
+        ["--bind", "127.0.0.1", "--port", &port, "--dir", dir_arg, "--ignore-corrupt-dump"],
+    ] {
+        let (code, out, err) = common::run_binary(redis, &args, b"").await;
+        assert_eq!(code, 1, "{:?}", args);
+        assert!(out.contains("Starting empty (ignore-corrupt-dump)"), "{:?}: {}", args, out);
+        assert!(!err.contains("Invalid argument"), "{:?}: {}", args, err);
+    }
+
+    // Settings that aren't yes/no still need their value
+    let (code, _, err) = common::run_binary(redis, &["--port"], b"").await;
+    assert_eq!(code, 1);
+    assert_eq!(err.trim(), "Invalid argument '--port', expected --<option> <value>");
+}
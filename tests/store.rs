@@ -0,0 +1,28 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use redis::store::RedisStore;
+
+#[test]
+fn readers_of_a_key_do_not_wait_for_each_other() {
+    let store = RedisStore::new();
+    store.rpush("list", vec!["a".to_string()]).unwrap();
+    let (first_in, first_entered) = mpsc::channel();
+    let (second_in, second_entered) = mpsc::channel();
+
+    let store = &store;
+    thread::scope(|scope| {
+        // The first reader stays on the key until the second one is on it too
+        let first = scope.spawn(move || {
+            store.read("list", |_| {
+                first_in.send(()).unwrap();
+                second_entered.recv_timeout(Duration::from_secs(2)).is_ok()
+            })
+        });
+        first_entered.recv().unwrap();
+        store.get("list").unwrap();
+        assert_eq!(store.read("list", |_| second_in.send(()).unwrap()), Some(()));
+        assert_eq!(first.join().unwrap(), Some(true));
+    });
+}
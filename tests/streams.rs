@@ -0,0 +1,284 @@
+mod common;
+
+use common::{bulk, bulks, error, int, ok, spawn_server, TestClient};
+use redis::resp::{serialize_resp, RespData};
+
+fn entry(id: &str, fields: &[&str]) -> RespData {
+    RespData::Array(vec![bulk(id), bulks(fields)])
+}
+
+fn entries(items: &[(&str, &[&str])]) -> RespData {
+    RespData::Array(items.iter().map(|(id, fields)| entry(id, fields)).collect())
+}
+
+// A field of a reply that reads as a map, in either protocol
+fn field(reply: &RespData, name: &str) -> RespData {
+    let found = match reply {
+        RespData::Map(pairs) => pairs.iter().find(|(key, _)| *key == bulk(name)).map(|(_, value)| value.clone()),
+        RespData::Array(items) => items.chunks(2).find(|pair| pair[0] == bulk(name)).map(|pair| pair[1].clone()),
+        _ => None,
+    };
+    found.unwrap_or_else(|| panic!("no {} in {:?}", name, reply))
+}
+
+fn only(reply: RespData) -> RespData {
+    match reply {
+        RespData::Array(mut items) if items.len() == 1 => items.remove(0),
+        other => panic!("expected one element: {:?}", other),
+    }
+}
+
+async fn group_info(client: &mut TestClient, key: &str) -> RespData {
+    only(client.cmd(&["XINFO", "GROUPS", key]).await)
+}
+
+#[tokio::test]
+async fn xadd_generates_and_checks_ids() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+
+    assert_eq!(client.cmd(&["XADD", "s", "5-*", "a", "1"]).await, bulk("5-0"));
+    assert_eq!(client.cmd(&["XADD", "s", "5-*", "a", "2"]).await, bulk("5-1"));
+    assert_eq!(client.cmd(&["XADD", "s", "7", "a", "3"]).await, bulk("7-0"));
+    assert_eq!(
+        client.cmd(&["XADD", "s", "6-0", "a", "4"]).await,
+        error("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+    );
+    assert_eq!(
+        client.cmd(&["XADD", "new", "0-0", "a", "1"]).await,
+        error("ERR The ID specified in XADD must be greater than 0-0")
+    );
+    assert_eq!(client.cmd(&["EXISTS", "new"]).await, int(0));
+    assert_eq!(
+        client.cmd(&["XADD", "s", "bogus", "a", "1"]).await,
+        error("ERR Invalid stream ID specified as stream command argument")
+    );
+    assert_eq!(client.cmd(&["XADD", "s", "*", "a"]).await, error("ERR wrong number of arguments for 'xadd' command"));
+    // Generated IDs are the current time, past the last ID
+    match client.cmd(&["XADD", "s", "*", "a", "5"]).await {
+        RespData::BulkString(id) => assert!(id.split('-').next().unwrap().parse::<u64>().unwrap() > 7),
+        other => panic!("XADD *: {:?}", other),
+    }
+    assert_eq!(client.cmd(&["XLEN", "s"]).await, int(4));
+
+    assert_eq!(client.cmd(&["XADD", "missing", "NOMKSTREAM", "*", "a", "1"]).await, RespData::Null);
+    assert_eq!(client.cmd(&["EXISTS", "missing"]).await, int(0));
+    client.cmd(&["SET", "string", "v"]).await;
+    assert_eq!(
+        client.cmd(&["XADD", "string", "*", "a", "1"]).await,
+        error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    );
+}
+
+#[tokio::test]
+async fn xadd_trims_and_xrange_reads_intervals() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    for id in ["1-0", "2-0", "3-0", "4-0"] {
+        client.cmd(&["XADD", "s", id, "f", id]).await;
+    }
+    assert_eq!(client.cmd(&["XADD", "s", "MAXLEN", "3", "5-0", "f", "5-0"]).await, bulk("5-0"));
+    assert_eq!(client.cmd(&["XLEN", "s"]).await, int(3));
+    assert_eq!(client.cmd(&["XADD", "s", "MINID", "~", "4", "6-0", "f", "6-0"]).await, bulk("6-0"));
+    assert_eq!(
+        client.cmd(&["XRANGE", "s", "-", "+"]).await,
+        entries(&[("4-0", &["f", "4-0"]), ("5-0", &["f", "5-0"]), ("6-0", &["f", "6-0"])])
+    );
+    assert_eq!(client.cmd(&["XRANGE", "s", "(4-0", "5"]).await, entries(&[("5-0", &["f", "5-0"])]));
+    assert_eq!(client.cmd(&["XRANGE", "s", "-", "+", "COUNT", "1"]).await, entries(&[("4-0", &["f", "4-0"])]));
+    assert_eq!(client.cmd(&["XRANGE", "s", "6", "4"]).await, RespData::Array(vec![]));
+    assert_eq!(
+        client.cmd(&["XADD", "s", "MAXLEN", "1", "LIMIT", "10", "*", "f", "v"]).await,
+        error("ERR syntax error, LIMIT cannot be used without the special ~ option")
+    );
+
+    assert_eq!(client.cmd(&["XDEL", "s", "4-0", "9-0"]).await, int(1));
+    let info = client.cmd(&["XINFO", "STREAM", "s"]).await;
+    assert_eq!(field(&info, "length"), int(2));
+    assert_eq!(field(&info, "entries-added"), int(6));
+    assert_eq!(field(&info, "max-deleted-entry-id"), bulk("4-0"));
+    assert_eq!(field(&info, "recorded-first-entry-id"), bulk("5-0"));
+    assert_eq!(field(&info, "last-generated-id"), bulk("6-0"));
+    assert_eq!(field(&info, "groups"), int(0));
+    assert_eq!(field(&info, "first-entry"), entry("5-0", &["f", "5-0"]));
+    assert_eq!(field(&info, "last-entry"), entry("6-0", &["f", "6-0"]));
+}
+
+#[tokio::test]
+async fn groups_read_acknowledge_and_report_lag() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    for id in ["1-0", "2-0", "3-0"] {
+        client.cmd(&["XADD", "s", id, "f", id]).await;
+    }
+    assert_eq!(client.cmd(&["XGROUP", "CREATE", "s", "g", "0"]).await, ok());
+    assert_eq!(client.cmd(&["XGROUP", "CREATE", "s", "g", "$"]).await, error("BUSYGROUP Consumer Group name already exists"));
+    let group = group_info(&mut client, "s").await;
+    assert_eq!(field(&group, "entries-read"), RespData::Null);
+    assert_eq!(field(&group, "lag"), int(3));
+
+    let read = client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "COUNT", "2", "STREAMS", "s", ">"]).await;
+    assert_eq!(
+        read,
+        RespData::Array(vec![RespData::Array(vec![bulk("s"), entries(&[("1-0", &["f", "1-0"]), ("2-0", &["f", "2-0"])])])])
+    );
+    let group = group_info(&mut client, "s").await;
+    assert_eq!(field(&group, "last-delivered-id"), bulk("2-0"));
+    assert_eq!(field(&group, "entries-read"), int(2));
+    assert_eq!(field(&group, "lag"), int(1));
+    assert_eq!(field(&group, "pending"), int(2));
+    assert_eq!(field(&group, "consumers"), int(1));
+
+    assert_eq!(client.cmd(&["XACK", "s", "g", "1-0", "1-0", "9-0"]).await, int(1));
+    // The consumer's history holds what it hasn't acknowledged
+    assert_eq!(
+        client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"]).await,
+        RespData::Array(vec![RespData::Array(vec![bulk("s"), entries(&[("2-0", &["f", "2-0"])])])])
+    );
+    let consumer = only(client.cmd(&["XINFO", "CONSUMERS", "s", "g"]).await);
+    assert_eq!(field(&consumer, "name"), bulk("alice"));
+    assert_eq!(field(&consumer, "pending"), int(1));
+    assert!(matches!(field(&consumer, "inactive"), RespData::Integer(ms) if ms >= 0));
+
+    // Once an entry past the group's position is deleted, its lag can't be
+    // told from the counters
+    assert_eq!(client.cmd(&["XDEL", "s", "3-0"]).await, int(1));
+    assert_eq!(field(&group_info(&mut client, "s").await, "lag"), RespData::Null);
+    // Deleted entries come back from the history without their fields
+    client.cmd(&["XDEL", "s", "2-0"]).await;
+    assert_eq!(
+        client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"]).await,
+        RespData::Array(vec![RespData::Array(vec![bulk("s"), RespData::Array(vec![RespData::Array(vec![bulk("2-0"), RespData::NullArray])])])])
+    );
+
+    // Nothing new to read
+    assert_eq!(client.cmd(&["XREADGROUP", "GROUP", "g", "bob", "STREAMS", "s", ">"]).await, RespData::NullArray);
+    // BLOCK is checked but doesn't wait
+    let started = std::time::Instant::now();
+    let blocking = ["XREADGROUP", "GROUP", "g", "bob", "BLOCK", "5000", "STREAMS", "s", ">"];
+    assert_eq!(client.cmd(&blocking).await, RespData::NullArray);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    assert_eq!(client.cmd(&["XREADGROUP", "GROUP", "g", "bob", "BLOCK", "-1", "STREAMS", "s", ">"]).await, error("ERR timeout is negative"));
+    assert_eq!(client.cmd(&["XADD", "s", "4-0", "f", "4-0"]).await, bulk("4-0"));
+    client.cmd(&["XREADGROUP", "GROUP", "g", "bob", "NOACK", "STREAMS", "s", ">"]).await;
+    let group = group_info(&mut client, "s").await;
+    assert_eq!(field(&group, "last-delivered-id"), bulk("4-0"));
+    assert_eq!(field(&group, "pending"), int(1));
+    assert_eq!(field(&group, "consumers"), int(2));
+
+    assert_eq!(
+        client.cmd(&["XREADGROUP", "GROUP", "nope", "alice", "STREAMS", "s", ">"]).await,
+        error("NOGROUP No such consumer group 'nope' for key name 's'")
+    );
+    assert_eq!(client.cmd(&["XGROUP", "DELCONSUMER", "s", "g", "alice"]).await, int(1));
+    assert_eq!(client.cmd(&["XGROUP", "CREATECONSUMER", "s", "g", "carol"]).await, int(1));
+    assert_eq!(client.cmd(&["XGROUP", "SETID", "s", "g", "$"]).await, ok());
+    assert_eq!(field(&group_info(&mut client, "s").await, "lag"), int(0));
+    assert_eq!(client.cmd(&["XGROUP", "DESTROY", "s", "g"]).await, int(1));
+    assert_eq!(client.cmd(&["XINFO", "GROUPS", "s"]).await, RespData::Array(vec![]));
+    assert_eq!(
+        client.cmd(&["XGROUP", "CREATE", "missing", "g", "$"]).await,
+        error(
+            "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the \
+             MKSTREAM option to create an empty stream automatically."
+        )
+    );
+    assert_eq!(client.cmd(&["XGROUP", "CREATE", "empty", "g", "$", "MKSTREAM"]).await, ok());
+    assert_eq!(client.cmd(&["XLEN", "empty"]).await, int(0));
+}
+
+#[tokio::test]
+async fn xinfo_full_and_resp3_replies() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["XADD", "s", "1-0", "a", "1"]).await;
+    client.cmd(&["XADD", "s", "2-0", "b", "2"]).await;
+    client.cmd(&["XGROUP", "CREATE", "s", "g", "0"]).await;
+    client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "COUNT", "1", "STREAMS", "s", ">"]).await;
+
+    let full = client.cmd(&["XINFO", "STREAM", "s", "FULL", "COUNT", "1"]).await;
+    assert_eq!(field(&full, "length"), int(2));
+    assert_eq!(field(&full, "entries"), entries(&[("1-0", &["a", "1"])]));
+    let group = only(field(&full, "groups"));
+    assert_eq!(field(&group, "name"), bulk("g"));
+    assert_eq!(field(&group, "entries-read"), int(1));
+    assert_eq!(field(&group, "lag"), int(1));
+    assert_eq!(field(&group, "pel-count"), int(1));
+    match only(field(&group, "pending")) {
+        RespData::Array(pending) => {
+            assert_eq!(pending[..2], [bulk("1-0"), bulk("alice")]);
+            assert_eq!(pending[3], int(1));
+        }
+        other => panic!("pending entry: {:?}", other),
+    }
+    let consumer = only(field(&group, "consumers"));
+    assert_eq!(field(&consumer, "name"), bulk("alice"));
+    assert_eq!(field(&consumer, "pel-count"), int(1));
+
+    client.cmd(&["HELLO", "3"]).await;
+    assert!(matches!(client.cmd(&["XINFO", "STREAM", "s"]).await, RespData::Map(_)));
+    assert!(matches!(only(client.cmd(&["XINFO", "GROUPS", "s"]).await), RespData::Map(_)));
+    assert_eq!(
+        client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"]).await,
+        RespData::Map(vec![(bulk("s"), entries(&[("2-0", &["b", "2"])]))])
+    );
+    assert_eq!(client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", ">"]).await, RespData::Null);
+}
+
+#[tokio::test]
+async fn xinfo_reports_missing_and_mistyped_keys() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["SET", "s", "v"]).await;
+
+    let no_such_key = error("ERR no such key");
+    let wrongtype = error("WRONGTYPE Operation against a key holding the wrong kind of value");
+    assert_eq!(client.cmd(&["XINFO", "STREAM", "missing"]).await, no_such_key);
+    assert_eq!(client.cmd(&["XINFO", "STREAM", "missing", "FULL", "COUNT", "10"]).await, no_such_key);
+    assert_eq!(client.cmd(&["XINFO", "GROUPS", "missing"]).await, no_such_key);
+    assert_eq!(client.cmd(&["XINFO", "CONSUMERS", "missing", "group"]).await, no_such_key);
+    assert_eq!(client.cmd(&["XINFO", "STREAM", "s"]).await, wrongtype);
+    assert_eq!(client.cmd(&["XINFO", "GROUPS", "s"]).await, wrongtype);
+    assert_eq!(client.cmd(&["XINFO", "STREAM", "s", "BOGUS"]).await, error("ERR syntax error"));
+    assert_eq!(
+        client.cmd(&["XINFO", "CONSUMERS", "s"]).await,
+        error("ERR unknown subcommand or wrong number of arguments for 'CONSUMERS'. Try XINFO HELP.")
+    );
+    client.cmd(&["XADD", "stream", "1-0", "a", "1"]).await;
+    assert_eq!(
+        client.cmd(&["XINFO", "CONSUMERS", "stream", "group"]).await,
+        error("NOGROUP No such consumer group 'group' for key name 'stream'")
+    );
+    assert!(matches!(client.cmd(&["XINFO", "HELP"]).await, RespData::Array(lines) if lines.len() == 9));
+}
+
+#[tokio::test]
+async fn streams_survive_dump_and_restart() {
+    let server = spawn_server().await;
+    let mut client = server.client().await;
+    client.cmd(&["XADD", "s", "1-0", "a", "1"]).await;
+    client.cmd(&["XADD", "s", "2-0", "b", "2"]).await;
+    client.cmd(&["XGROUP", "CREATE", "s", "g", "0"]).await;
+    client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "COUNT", "1", "STREAMS", "s", ">"]).await;
+    assert_eq!(client.cmd(&["OBJECT", "ENCODING", "s"]).await, bulk("stream"));
+
+    let payload = match client.cmd(&["DUMP", "s"]).await {
+        RespData::BulkBytes(payload) => payload,
+        RespData::BulkString(payload) => payload.into_bytes(),
+        other => panic!("DUMP: {:?}", other),
+    };
+    let restore = RespData::Array(vec![bulk("RESTORE"), bulk("copy"), bulk("0"), RespData::BulkBytes(payload)]);
+    client.send_raw(&serialize_resp(&restore)).await;
+    assert_eq!(client.read_reply().await, ok());
+    let before = client.cmd(&["XINFO", "STREAM", "s", "FULL"]).await;
+    assert_eq!(client.cmd(&["XINFO", "STREAM", "copy", "FULL"]).await, before);
+
+    client.cmd(&["SAVE"]).await;
+    let server = server.restart().await;
+    let mut client = server.client().await;
+    assert_eq!(client.cmd(&["XINFO", "STREAM", "s", "FULL"]).await, before);
+    assert_eq!(
+        client.cmd(&["XREADGROUP", "GROUP", "g", "alice", "STREAMS", "s", "0"]).await,
+        RespData::Array(vec![RespData::Array(vec![bulk("s"), entries(&[("1-0", &["a", "1"])])])])
+    );
+}